@@ -4,6 +4,12 @@
  * This header is designed for use with Dart's ffigen tool.
  * All strings are NUL-terminated UTF-8. Callers must free returned
  * strings with monty_string_free() and byte buffers with monty_bytes_free().
+ *
+ * A handle must be used from one thread at a time. Every call taking a
+ * handle claims it for its duration. A call made while another holds the
+ * claim, from a callback or another thread, fails with "handle already in
+ * use" instead of corrupting state; calls without an error out-parameter
+ * do nothing, and accessors return their NULL-handle value (NULL, -1, ...).
  */
 
 #ifndef DART_MONTY_H
@@ -22,6 +28,7 @@ extern "C" {
 
 /** Opaque handle to a compiled Python program. */
 typedef struct MontyHandle MontyHandle;
+typedef struct MontyInterruptToken MontyInterruptToken;
 
 /* ------------------------------------------------------------------ */
 /* Enums                                                              */
@@ -39,8 +46,56 @@ typedef enum {
     MONTY_PROGRESS_PENDING         = 1,
     MONTY_PROGRESS_ERROR           = 2,
     MONTY_PROGRESS_RESOLVE_FUTURES = 3,
+    MONTY_PROGRESS_OS_CALL         = 4,
 } MontyProgressTag;
 
+/** Lifecycle state reported by monty_state(). */
+typedef enum {
+    MONTY_STATE_READY    = 0,
+    MONTY_STATE_PAUSED   = 1,
+    MONTY_STATE_FUTURES  = 2,
+    MONTY_STATE_COMPLETE = 3,
+    MONTY_STATE_CONSUMED = 4,
+    MONTY_STATE_OS_CALL  = 5,
+} MontyStateTag;
+
+/**
+ * Category of the last failed call, reported by monty_last_error_code().
+ * Values are stable; new categories are only appended.
+ */
+typedef enum {
+    MONTY_ERROR_NONE             = 0,
+    MONTY_ERROR_WRONG_STATE      = 1,
+    MONTY_ERROR_INVALID_JSON     = 2,
+    MONTY_ERROR_NULL_ARGUMENT    = 3,
+    MONTY_ERROR_NON_UTF8         = 4,
+    MONTY_ERROR_PANIC            = 5,
+    MONTY_ERROR_VM_EXCEPTION     = 6,
+    MONTY_ERROR_INVALID_ARGUMENT = 7,
+} MontyErrorCode;
+
+/* ------------------------------------------------------------------ */
+/* Library info                                                       */
+/* ------------------------------------------------------------------ */
+
+/**
+ * Get the C ABI version of the loaded library. Bumped when an existing
+ * function changes signature or behavior; new functions are announced in
+ * monty_capabilities_json() instead.
+ */
+uint32_t monty_abi_version(void);
+
+/**
+ * Describe the loaded library for binding-side compatibility checks.
+ *
+ * @return  Heap-allocated JSON object
+ *          {"abi_version", "crate_version", "capabilities": [...]}, where
+ *          capabilities names supported features such as "async",
+ *          "snapshot-paused", "os-call" and "typed-conversions".
+ *          Caller frees with monty_string_free().
+ */
+char *monty_capabilities_json(void);
+
 /* ------------------------------------------------------------------ */
 /* Lifecycle                                                          */
 /* ------------------------------------------------------------------ */
@@ -62,10 +117,225 @@ MontyHandle *monty_create(const char *code,
                            char **out_error);
 
 /**
- * Free a handle. Safe to call with NULL.
+ * Create a new handle with host values bound as Python globals.
+ *
+ * @param globals_json    NUL-terminated JSON object mapping global names to
+ *                        values, or NULL for none. Names must be valid,
+ *                        non-keyword identifiers not starting with "__monty_".
+ *
+ * Other parameters and the return value are as for monty_create().
+ */
+MontyHandle *monty_create_with_globals(const char *code,
+                                        const char *ext_fns,
+                                        const char *script_name,
+                                        const char *globals_json,
+                                        char **out_error);
+
+/**
+ * Create a handle with every creation option.
+ *
+ * With a compile time budget, for hosts compiling untrusted source: if
+ * parsing and compiling take longer than compile_timeout_ms, returns NULL
+ * with out_error set to "TimeoutError: compile timed out after N ms". The
+ * engine cannot interrupt a compile, so an over-budget compile keeps
+ * running on a background thread until it finishes and is then discarded;
+ * only the caller is released on time. At most 8 such compile threads run
+ * at once, abandoned ones included; past that, creation fails at once
+ * with "RuntimeError: too many compiles in progress (max 8)". The budget
+ * also applies to every later recompile of the handle (monty_add_external,
+ * monty_remove_external, monty_set_stdin, monty_register_constant,
+ * monty_reset).
+ *
+ * @param globals_json        As for monty_create_with_globals(), or NULL.
+ * @param compile_timeout_ms  Budget in milliseconds, or 0 for no limit.
+ * @param out_error_json      If creation fails with a Python exception
+ *                            (e.g. a SyntaxError), receives it as JSON in
+ *                            the shape of a runtime error: exc_type,
+ *                            message, line_number, column_number,
+ *                            traceback. Caller frees with
+ *                            monty_string_free(). May be NULL. Not set for
+ *                            other failures; out_error always gets the
+ *                            message.
+ *
+ * Other parameters and the return value are as for monty_create().
+ */
+MontyHandle *monty_create_ex(const char *code,
+                             const char *ext_fns,
+                             const char *script_name,
+                             const char *globals_json,
+                             uint64_t compile_timeout_ms,
+                             char **out_error,
+                             char **out_error_json);
+
+/**
+ * Free a handle. Safe to call with NULL. Does nothing while another call
+ * holds the handle.
  */
 void monty_free(MontyHandle *handle);
 
+/**
+ * Set a free-form label identifying the handle in panic messages.
+ * Pass NULL to clear it. Safe with a NULL handle.
+ */
+void monty_set_label(MontyHandle *handle, const char *label);
+
+/**
+ * Set an opaque id embedded as "correlation_id" in every result JSON the
+ * handle produces, success or error, so logs can tie VM errors to
+ * application requests. Pass NULL to clear it. Safe with a NULL handle.
+ */
+void monty_set_correlation_id(MontyHandle *handle, const char *id);
+
+/**
+ * Get the handle's label.
+ *
+ * @return  Heap-allocated string, or NULL if unset. Caller frees with monty_string_free().
+ */
+char *monty_get_label(const MontyHandle *handle);
+
+/**
+ * Empty the accumulated print output and reset usage to defaults without
+ * changing the handle's execution state. Safe with NULL.
+ */
+void monty_clear_output(MontyHandle *handle);
+
+/**
+ * Return the handle to Ready so monty_run()/monty_start() can execute the
+ * same program again, clearing print output, usage, the external call
+ * count and the event log. Fails for a restored handle that has left Ready
+ * state (no compiled program is retained).
+ *
+ * @param handle     Valid handle.
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_reset(MontyHandle *handle, char **out_error);
+
+/**
+ * Duplicate a handle in Ready state. The clone keeps the limits, options,
+ * label and externals but starts with empty print output.
+ *
+ * @param handle     Handle in Ready state.
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           New handle (free with monty_free()), or NULL on error.
+ */
+MontyHandle *monty_clone(const MontyHandle *handle, char **out_error);
+
+/* ------------------------------------------------------------------ */
+/* External functions                                                 */
+/* ------------------------------------------------------------------ */
+
+/**
+ * Declare an additional external function. Only valid in Ready state;
+ * the program is recompiled with the updated external set.
+ *
+ * @param handle     Handle in Ready state.
+ * @param name       NUL-terminated external function name; must be a
+ *                   Python identifier.
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_add_external(MontyHandle *handle,
+                                   const char *name,
+                                   char **out_error);
+
+/**
+ * Remove a declared external function. Only valid in Ready state. Any
+ * constant, stdin text or serviceable entry registered for the name is
+ * dropped with it.
+ *
+ * @param handle     Handle in Ready state.
+ * @param name       NUL-terminated external function name.
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_remove_external(MontyHandle *handle,
+                                      const char *name,
+                                      char **out_error);
+
+/**
+ * Pre-load text for input() to read during iterative execution, one line
+ * per call without the newline. Declares "input" as an external that the
+ * handle answers itself; once the text is used up input() raises
+ * OSError("EOF when reading a line").
+ *
+ * @param handle     Handle in Ready state, or one with "input" declared.
+ * @param data       NUL-terminated UTF-8 text.
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_set_stdin(MontyHandle *handle,
+                               const char *data,
+                               char **out_error);
+
+/**
+ * Answer every call to the external name with a fixed value, whatever its
+ * arguments, without surfacing MONTY_PROGRESS_PENDING. Declares name as an
+ * external; registering it again replaces the value. Answered calls are
+ * not counted by monty_ext_call_count.
+ *
+ * @param handle      Handle in Ready state, or one with name declared.
+ * @param name        NUL-terminated function name.
+ * @param value_json  NUL-terminated JSON return value, decoded like a
+ *                    monty_resume value (tags included).
+ * @param out_error   Receives error message on failure. Caller frees.
+ * @return            MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_register_constant(MontyHandle *handle,
+                                       const char *name,
+                                       const char *value_json,
+                                       char **out_error);
+
+/**
+ * Declare which externals the host services. Guest calls to a declared
+ * external outside this set raise NotImplementedError without pausing.
+ *
+ * @param handle     Valid handle.
+ * @param csv        NUL-terminated comma-separated declared names, or NULL
+ *                   to service every declared external (the default).
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_set_serviceable_externals(MontyHandle *handle,
+                                               const char *csv,
+                                               char **out_error);
+
+/**
+ * Get the declared external function names as a JSON array, reflecting
+ * monty_add_external()/monty_remove_external(). Empty for handles restored
+ * from a Ready snapshot.
+ *
+ * @return  Heap-allocated JSON string, or NULL if handle is NULL or in use.
+ *          Caller frees with monty_string_free().
+ */
+char *monty_ext_fn_names(const MontyHandle *handle);
+
+/* ------------------------------------------------------------------ */
+/* Validation                                                         */
+/* ------------------------------------------------------------------ */
+
+/**
+ * Compile Python code without creating a handle or running anything, e.g.
+ * for an editor's "does this compile?" check.
+ *
+ * Diagnostics are objects {"severity": "error", "exc_type", "message",
+ * "filename", "line", "column", "end_line", "end_column"}, position fields
+ * present when known. The engine stops at the first error and reports no
+ * warnings; undefined names only fail at runtime.
+ *
+ * @param code                  NUL-terminated UTF-8 Python source.
+ * @param ext_fns               Comma-separated external names, or NULL.
+ * @param script_name           Script name for diagnostics, or NULL.
+ * @param out_diagnostics_json  Receives a JSON array, "[]" when clean.
+ *                              Caller frees with monty_string_free(). May be NULL.
+ * @return                      0 if clean, 1 if there are diagnostics, -1 if
+ *                              an argument is NULL or not UTF-8.
+ */
+int monty_validate(const char *code,
+                   const char *ext_fns,
+                   const char *script_name,
+                   char **out_diagnostics_json);
+
 /* ------------------------------------------------------------------ */
 /* Run to completion                                                  */
 /* ------------------------------------------------------------------ */
@@ -84,6 +354,49 @@ MontyResultTag monty_run(MontyHandle *handle,
                           char **result_json,
                           char **error_msg);
 
+/**
+ * Run to completion with new values for the globals given to
+ * monty_create_with_globals(), matched positionally in their original
+ * order. The handle's own globals are unchanged, so a program compiled once
+ * can run with different inputs, calling monty_reset() in between.
+ *
+ * @param handle       Handle in Ready state.
+ * @param args_json    NUL-terminated JSON array with one value per global.
+ * @param result_json  As for monty_run().
+ * @param error_msg    As for monty_run(); e.g. "expected 1 args, got 2".
+ * @return             MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_run_with_args(MontyHandle *handle,
+                                   const char *args_json,
+                                   char **result_json,
+                                   char **error_msg);
+
+/**
+ * Create a handle, run it to completion and free it in one call, for code
+ * that needs no answers from the host.
+ *
+ * Compile errors, an invalid limits_json and calls to declared externals
+ * all come back as error results in out_result_json. The first external
+ * call ends the run with RuntimeError instead of pausing, and a panic
+ * comes back as a RuntimeError carrying the panic message.
+ *
+ * @param code             NUL-terminated UTF-8 Python source.
+ * @param ext_fns          Comma-separated external names, or NULL.
+ * @param script_name      Script name for tracebacks, or NULL.
+ * @param limits_json      JSON object with any keys monty_get_limits_json()
+ *                         reports, or NULL for no limits.
+ * @param out_result_json  Receives the result JSON. Caller frees with
+ *                         monty_string_free(). May be NULL.
+ * @return                 MONTY_RESULT_OK or MONTY_RESULT_ERROR. If an
+ *                         argument is NULL or not UTF-8, out_result_json is
+ *                         left untouched.
+ */
+MontyResultTag monty_eval(const char *code,
+                          const char *ext_fns,
+                          const char *script_name,
+                          const char *limits_json,
+                          char **out_result_json);
+
 /* ------------------------------------------------------------------ */
 /* Iterative execution                                                */
 /* ------------------------------------------------------------------ */
@@ -101,6 +414,10 @@ MontyProgressTag monty_start(MontyHandle *handle,
 /**
  * Resume execution with a return value.
  *
+ * When the value is passed straight into another external call, e.g.
+ * `outer(inner())`, this returns MONTY_PROGRESS_PENDING again; the pending
+ * accessors then describe the new call, whose call ID is higher.
+ *
  * @param handle      Handle in PENDING state.
  * @param value_json  NUL-terminated JSON value to return to Python.
  * @param out_error   Receives error message on failure. Caller frees.
@@ -110,6 +427,27 @@ MontyProgressTag monty_resume(MontyHandle *handle,
                                const char *value_json,
                                char **out_error);
 
+/**
+ * Resume with each value of a JSON array in turn, answering successive
+ * external calls without returning to the host in between.
+ *
+ * @param handle       Handle in PENDING state.
+ * @param values_json  NUL-terminated JSON array of return values.
+ * @param out_error    Receives error message on failure. Caller frees.
+ * @return             The progress after the last value used:
+ *                     MONTY_PROGRESS_PENDING if the values ran out.
+ */
+MontyProgressTag monty_resume_many(MontyHandle *handle,
+                                    const char *values_json,
+                                    char **out_error);
+
+/**
+ * Number of values the last monty_resume_many() call consumed.
+ *
+ * @return  Count, or 0 if handle is NULL or in use.
+ */
+size_t monty_last_batch_consumed(const MontyHandle *handle);
+
 /**
  * Resume execution with an error (raises RuntimeError in Python).
  *
@@ -122,6 +460,74 @@ MontyProgressTag monty_resume_with_error(MontyHandle *handle,
                                           const char *error_message,
                                           char **out_error);
 
+/**
+ * Resume by raising a typed exception at the pending call, so Python
+ * `except` clauses can match it.
+ *
+ * @param handle     Handle in PENDING state.
+ * @param exc_type   NUL-terminated exception class name (e.g. "ValueError").
+ *                   Unknown names raise RuntimeError.
+ * @param message    NUL-terminated exception message.
+ * @param out_error  Receives FFI error message on failure. Caller frees.
+ * @return           MONTY_PROGRESS_COMPLETE, _PENDING, or _ERROR.
+ */
+MontyProgressTag monty_resume_with_typed_error(MontyHandle *handle,
+                                                const char *exc_type,
+                                                const char *message,
+                                                char **out_error);
+
+/**
+ * Resume by raising an exception described as JSON:
+ * {"type": "ValueError", "message": "..."}, or {"type": ..., "args": ["..."]}.
+ * The engine keeps only a type and an optional message, so Python sees
+ * e.args as () or (message,); other args and unknown types fail without
+ * resuming.
+ *
+ * @param handle     Handle in PENDING state.
+ * @param exc_json   NUL-terminated exception JSON.
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_PROGRESS_COMPLETE, _PENDING, or _ERROR.
+ */
+MontyProgressTag monty_resume_with_exception(MontyHandle *handle,
+                                             const char *exc_json,
+                                             char **out_error);
+
+/**
+ * Resume an OS call (e.g. os.getenv) with the host's result.
+ *
+ * @param handle      Handle paused at MONTY_PROGRESS_OS_CALL.
+ * @param value_json  NUL-terminated JSON value to return to Python.
+ * @param out_error   Receives error message on failure. Caller frees.
+ * @return            MONTY_PROGRESS_COMPLETE, _PENDING, _OS_CALL, or _ERROR.
+ */
+MontyProgressTag monty_resume_os_call(MontyHandle *handle,
+                                       const char *value_json,
+                                       char **out_error);
+
+/**
+ * Pull callback for monty_resume_reader(). Writes up to cap bytes of JSON
+ * into buf and returns the count written, 0 at end of input, or a negative
+ * value on error.
+ */
+typedef intptr_t (*MontyReadCallback)(void *user_data, uint8_t *buf, size_t cap);
+
+/**
+ * Resume execution with a return value streamed as JSON through read_cb.
+ * The value is decoded incrementally, so the full JSON text is never held
+ * in memory. read_cb is called on this thread until it returns 0 or a
+ * negative value, and must not call back into the handle.
+ *
+ * @param handle     Handle in PENDING state.
+ * @param read_cb    Pull callback supplying JSON bytes.
+ * @param user_data  Passed through to read_cb.
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_PROGRESS_COMPLETE, _PENDING, or _ERROR.
+ */
+MontyProgressTag monty_resume_reader(MontyHandle *handle,
+                                     MontyReadCallback read_cb,
+                                     void *user_data,
+                                     char **out_error);
+
 /* ------------------------------------------------------------------ */
 /* Async / Futures                                                    */
 /* ------------------------------------------------------------------ */
@@ -147,10 +553,27 @@ MontyProgressTag monty_resume_as_future(MontyHandle *handle,
  */
 char *monty_pending_future_call_ids(const MontyHandle *handle);
 
+/**
+ * Describe the pending futures: which external function each call_id
+ * belongs to and its arguments.
+ * Only valid after progress returned MONTY_PROGRESS_RESOLVE_FUTURES.
+ *
+ * @return  Heap-allocated JSON array of objects
+ *          {"call_id", "fn_name", "args", "kwargs"} in the order of
+ *          monty_pending_future_call_ids(), or NULL.
+ *          Caller frees with monty_string_free().
+ */
+char *monty_pending_futures_json(const MontyHandle *handle);
+
 /**
  * Resume futures with results and errors.
  * Only valid when handle is in RESOLVE_FUTURES state.
  *
+ * The results and errors may cover only some of the pending call_ids. If
+ * coroutines are still blocked on the rest, MONTY_PROGRESS_RESOLVE_FUTURES
+ * is returned again with the remaining ids, and this can be called again as
+ * more answers arrive. A call_id that is not pending is an error.
+ *
  * @param handle        Handle in RESOLVE_FUTURES state.
  * @param results_json  JSON object mapping call_id (string) to value,
  *                      e.g. {"0": "value0", "1": 42}.
@@ -165,6 +588,38 @@ MontyProgressTag monty_resume_futures(MontyHandle *handle,
                                        const char *errors_json,
                                        char **out_error);
 
+/**
+ * Resume futures like monty_resume_futures(), raising TimeoutError in the
+ * guest for each listed call_id, as asyncio.wait_for does on timeout.
+ *
+ * @param handle         Handle in RESOLVE_FUTURES state.
+ * @param results_json   As for monty_resume_futures().
+ * @param errors_json    As for monty_resume_futures().
+ * @param timeouts_json  JSON array of timed-out call_ids, e.g. [3, 4].
+ * @param out_error      Receives error message on failure. Caller frees.
+ * @return               MONTY_PROGRESS_COMPLETE, _RESOLVE_FUTURES, _PENDING,
+ *                       or _ERROR.
+ */
+MontyProgressTag monty_resume_futures_with_timeouts(MontyHandle *handle,
+                                                    const char *results_json,
+                                                    const char *errors_json,
+                                                    const char *timeouts_json,
+                                                    char **out_error);
+
+/**
+ * Fail every pending future with the same typed exception.
+ *
+ * @param handle     Handle in RESOLVE_FUTURES state.
+ * @param exc_type   NUL-terminated exception class name (e.g. "ConnectionError").
+ * @param message    NUL-terminated exception message.
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_PROGRESS_COMPLETE, _PENDING, _RESOLVE_FUTURES, or _ERROR.
+ */
+MontyProgressTag monty_fail_all_futures(MontyHandle *handle,
+                                        const char *exc_type,
+                                        const char *message,
+                                        char **out_error);
+
 /* ------------------------------------------------------------------ */
 /* State accessors                                                    */
 /* ------------------------------------------------------------------ */
@@ -180,11 +635,39 @@ char *monty_pending_fn_name(const MontyHandle *handle);
 /**
  * Get the pending function arguments as a JSON array.
  * Only valid after monty_start/monty_resume returned MONTY_PROGRESS_PENDING.
+ * Arguments are encoded on the first call and cached, so a host that only
+ * reads monty_pending_fn_name never pays for serialization. They are
+ * checked against the conversion limits at the pause and encoded under the
+ * options in effect then; later option changes don't apply to this call.
  *
- * @return  Heap-allocated JSON string, or NULL. Caller frees with monty_string_free().
+ * @return  Heap-allocated JSON string, or NULL if not paused.
+ *          Caller frees with monty_string_free().
  */
 char *monty_pending_fn_args_json(const MontyHandle *handle);
 
+/**
+ * Get the number of positional arguments of the pending call, counting any
+ * left out under monty_set_max_call_args_bytes.
+ * Only valid after monty_start/monty_resume returned MONTY_PROGRESS_PENDING.
+ *
+ * @return  Argument count, or -1 if handle is NULL, in use or not paused.
+ */
+int monty_pending_fn_args_count(const MontyHandle *handle);
+
+/**
+ * Get one pending argument as JSON with Python container types tagged
+ * ({"__tuple__": [...]}, {"__set__": [...]}, {"__bigint__": "..."}, ...),
+ * regardless of monty_set_typed_containers().
+ * Only valid after monty_start/monty_resume returned MONTY_PROGRESS_PENDING.
+ *
+ * @param index  Zero-based argument index.
+ * @return       Heap-allocated JSON string, or NULL if not paused, index
+ *               is out of range or the argument was left out under
+ *               monty_set_max_call_args_bytes. Caller frees with
+ *               monty_string_free().
+ */
+char *monty_pending_fn_arg_tagged_json(const MontyHandle *handle, int index);
+
 /**
  * Get the pending function keyword arguments as a JSON object.
  * Only valid after monty_start/monty_resume returned MONTY_PROGRESS_PENDING.
@@ -210,6 +693,62 @@ uint32_t monty_pending_call_id(const MontyHandle *handle);
  */
 int monty_pending_method_call(const MontyHandle *handle);
 
+/**
+ * Whether arguments of the pending call were left out of its metadata for
+ * exceeding monty_set_max_call_args_bytes. The call itself is unaffected.
+ *
+ * @return  1 if truncated, 0 if not, -1 if not in Paused state.
+ */
+int monty_pending_args_truncated(const MontyHandle *handle);
+
+/**
+ * Get the pending OS call's function name (e.g. "os.getenv").
+ * Only valid after a call returned MONTY_PROGRESS_OS_CALL.
+ *
+ * @return  Heap-allocated string, or NULL. Caller frees with monty_string_free().
+ */
+char *monty_pending_os_call_name(const MontyHandle *handle);
+
+/**
+ * Get the pending OS call's arguments as a JSON array.
+ * Only valid after a call returned MONTY_PROGRESS_OS_CALL.
+ *
+ * @return  Heap-allocated JSON string, or NULL. Caller frees with monty_string_free().
+ */
+char *monty_pending_os_call_args_json(const MontyHandle *handle);
+
+/**
+ * Get the usage accumulated by the current execution, in any state
+ * (e.g. while paused at an external call). Same shape as the result's
+ * "usage" object.
+ *
+ * @return  Heap-allocated JSON object string ("{}" if nothing has run yet),
+ *          or NULL if handle is NULL or in use. Caller frees with
+ *          monty_string_free().
+ */
+char *monty_usage_json(const MontyHandle *handle);
+
+/**
+ * Start recording each pause and how the host answered it, for replay and
+ * audit. Keeps the latest max_entries events, counting older ones as
+ * dropped. Replaces any earlier log; the log is cleared by monty_start.
+ * No-op if handle is NULL.
+ */
+void monty_enable_event_log(MontyHandle *handle, size_t max_entries);
+
+/**
+ * Get the event log: {"events": [...], "dropped": n}, oldest first. Each
+ * event is {"call_id", "fn_name", "args", "kwargs", "timestamp_ms"}, with
+ * "os_call": true for OS calls and, once answered, one of "result",
+ * "error" ({"exc_type", "message"}) or "future": true, plus "resumed_ms".
+ * Timestamps are Unix milliseconds. Calls the handle answers itself are
+ * not logged.
+ *
+ * @return  Heap-allocated JSON string, or NULL if handle is NULL, in use or the
+ *          log isn't enabled. Caller frees with monty_string_free().
+ */
+char *monty_event_log_json(const MontyHandle *handle);
+
 /**
  * Get the completed result as a JSON string.
  * Only valid after execution reached COMPLETE state.
@@ -218,6 +757,33 @@ int monty_pending_method_call(const MontyHandle *handle);
  */
 char *monty_complete_result_json(const MontyHandle *handle);
 
+/**
+ * Query the handle's current lifecycle state.
+ *
+ * @return  A MontyStateTag value, or -1 if handle is NULL or in use.
+ */
+int monty_state(const MontyHandle *handle);
+
+/**
+ * Whether the handle has spent its program: it completed (MONTY_STATE_COMPLETE)
+ * or an execution consumed it (MONTY_STATE_CONSUMED). Tells a spent handle
+ * apart from one that has not reached a queried state yet; monty_reset()
+ * makes it runnable again.
+ *
+ * @return  1 if consumed, 0 if not, or -1 if handle is NULL or in use.
+ */
+int monty_is_consumed(const MontyHandle *handle);
+
+/**
+ * Category of the last failed call on the handle, set alongside the error
+ * message by every call that takes an out_error / error_msg parameter
+ * (except monty_create*, which has no handle yet).
+ *
+ * @return  A MontyErrorCode value (MONTY_ERROR_NONE if the last such call
+ *          succeeded), or -1 if handle is NULL or in use.
+ */
+int monty_last_error_code(const MontyHandle *handle);
+
 /**
  * Check whether the completed result is an error.
  *
@@ -225,13 +791,25 @@ char *monty_complete_result_json(const MontyHandle *handle);
  */
 int monty_complete_is_error(const MontyHandle *handle);
 
+/**
+ * Check whether the completed result is a retryable error (see
+ * monty_set_retryable_exc_types()).
+ *
+ * @return  1 = retryable error, 0 = not retryable or success,
+ *          -1 = not in Complete state.
+ */
+int monty_complete_is_retryable(const MontyHandle *handle);
+
 /* ------------------------------------------------------------------ */
 /* Snapshots                                                          */
 /* ------------------------------------------------------------------ */
 
 /**
  * Serialize compiled code to a byte buffer (snapshot).
- * Only valid in Ready state.
+ * Valid in Ready state, or Paused at an external function call; a paused
+ * snapshot includes the VM state and restores ready for monty_resume(),
+ * with the handle's globals, limits, options, label, stdin and constants.
+ * Callbacks, OS call handlers and the event log must be set again.
  *
  * @param handle   Valid handle.
  * @param out_len  Receives byte count.
@@ -240,9 +818,45 @@ int monty_complete_is_error(const MontyHandle *handle);
 uint8_t *monty_snapshot(const MontyHandle *handle,
                          size_t *out_len);
 
+/** Compression for monty_snapshot_ex(). */
+typedef enum {
+    MONTY_SNAPSHOT_CODEC_NONE = 0,
+    MONTY_SNAPSHOT_CODEC_ZSTD = 1,
+    MONTY_SNAPSHOT_CODEC_GZIP = 2,
+} MontySnapshotCodec;
+
+/**
+ * Like monty_snapshot(), compressed with a MontySnapshotCodec.
+ * monty_restore() detects the codec from the frame magic, so compressed
+ * and uncompressed snapshots restore the same way.
+ *
+ * @param handle   Valid handle.
+ * @param codec    A MontySnapshotCodec value.
+ * @param out_len  Receives byte count.
+ * @return         Heap-allocated byte buffer, or NULL on error or an unknown
+ *                 codec. Caller frees with monty_bytes_free().
+ */
+uint8_t *monty_snapshot_ex(const MontyHandle *handle,
+                           int codec,
+                           size_t *out_len);
+
+/**
+ * Get the byte length monty_snapshot() would return. The bytes are cached
+ * so the following monty_snapshot() does not serialize again.
+ *
+ * @param handle  Valid handle in Ready or Paused state.
+ * @return        Byte length, or -1 if not in Ready or Paused state or on
+ *                error.
+ */
+int64_t monty_snapshot_size(const MontyHandle *handle);
+
 /**
  * Restore a handle from a snapshot byte buffer.
  *
+ * Snapshots carry a format version; bytes from an incompatible crate
+ * version fail with "snapshot version mismatch: got N, expected M".
+ * Compressed snapshots that inflate past 256 MiB are rejected.
+ *
  * @param data       Pointer to snapshot bytes.
  * @param len        Byte count.
  * @param out_error  Receives error message on failure. Caller frees.
@@ -262,9 +876,257 @@ void monty_set_memory_limit(MontyHandle *handle, size_t bytes);
 /** Set execution time limit in milliseconds. */
 void monty_set_time_limit_ms(MontyHandle *handle, uint64_t ms);
 
+/**
+ * Set a time limit in milliseconds that only counts time the VM is running.
+ * Time paused for the host (PENDING, RESOLVE_FUTURES, OS_CALL) is excluded.
+ * Replaces any limit set by monty_set_time_limit_ms().
+ */
+void monty_set_compute_time_limit_ms(MontyHandle *handle, uint64_t ms);
+
+/**
+ * Create a token that interrupts the handle's executions. Create it before
+ * handing the handle to the thread that runs it: the token shares only the
+ * handle's usage counters, so it is safe to use from any thread, even after
+ * the handle is freed.
+ *
+ * @return  Token to free with monty_token_free(), or NULL if handle is
+ *          NULL or in use.
+ */
+MontyInterruptToken *monty_interrupt_token(const MontyHandle *handle);
+
+/**
+ * Ask the current execution of the token's handle to stop at its next time
+ * check. It then fails with KeyboardInterrupt like any other error. Cleared
+ * when a new execution starts. No-op if token is NULL.
+ */
+void monty_token_interrupt(const MontyInterruptToken *token);
+
+/** Free a token from monty_interrupt_token(). Safe to call with NULL. */
+void monty_token_free(MontyInterruptToken *token);
+
 /** Set stack depth limit. */
 void monty_set_stack_limit(MontyHandle *handle, size_t depth);
 
+/**
+ * Abort an execution with RuntimeError("external call limit exceeded: N")
+ * once it makes more than max external function calls.
+ */
+void monty_set_max_external_calls(MontyHandle *handle, uint64_t max);
+
+/**
+ * Cap the JSON bytes a pending external call's arguments may take.
+ * Positional then keyword arguments are kept up to the first that would
+ * pass the cap; it and every later argument are left out of
+ * monty_pending_fn_args_json and friends, encoding stops at the cap, and
+ * monty_pending_args_truncated reports 1. monty_pending_fn_args_count
+ * still reports every positional argument.
+ */
+void monty_set_max_call_args_bytes(MontyHandle *handle, size_t max);
+
+/**
+ * Number of external function calls handed to the host in the current
+ * execution (reset by monty_start, monty_run and monty_reset). Returns 0
+ * if handle is NULL or in use.
+ */
+uint64_t monty_ext_call_count(const MontyHandle *handle);
+
+/** Remove every resource limit; the next run is unlimited. */
+void monty_clear_limits(MontyHandle *handle);
+
+/** Remove the memory limit. */
+void monty_clear_memory_limit(MontyHandle *handle);
+
+/** Remove the time limit, whether set as wall or compute time. */
+void monty_clear_time_limit(MontyHandle *handle);
+
+/** Restore the default stack depth limit (1000). */
+void monty_clear_stack_limit(MontyHandle *handle);
+
+/**
+ * Read back the configured limits as a JSON object:
+ * {"max_memory", "max_duration_ms", "max_recursion_depth", "max_compute_ms"},
+ * each a number or null if unset. Once any limit is set,
+ * max_recursion_depth defaults to 1000.
+ *
+ * @return  Heap-allocated JSON string, or NULL if handle is NULL or in use.
+ *          Caller frees with monty_string_free().
+ */
+char *monty_get_limits_json(const MontyHandle *handle);
+
+/**
+ * Set several limits in one call from a JSON object with any subset of the
+ * keys monty_get_limits_json() reports, e.g.
+ * {"max_memory": 1048576, "max_duration_ms": 500, "max_recursion_depth": 200}.
+ * A null value leaves that limit as it is.
+ *
+ * Every entry is checked first: an unknown key (e.g. a typo) or a value
+ * that is not a non-negative integer fails the call and applies nothing.
+ *
+ * @param handle       Valid handle.
+ * @param limits_json  NUL-terminated JSON object.
+ * @param out_error    Receives error message on failure. Caller frees.
+ * @return             MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_set_limits_json(MontyHandle *handle,
+                                     const char *limits_json,
+                                     char **out_error);
+
+/**
+ * Cap the length in characters of any single string in a result or external
+ * call argument, keyword names included. Enforced when the value is
+ * serialized, since the engine has no per-object string cap: the run fails
+ * with a host-side serialization error (reported as MemoryError) that the
+ * guest cannot catch.
+ */
+void monty_set_max_string_length(MontyHandle *handle, size_t chars);
+
+/**
+ * Cap buffered print output at bytes. Output past the cap is dropped and
+ * the result JSON reports "print_output_truncated": true. Unlimited by
+ * default; does not apply to monty_set_print_callback() output.
+ */
+void monty_set_output_limit(MontyHandle *handle, size_t bytes);
+
+/**
+ * Truncate any single collection in a result or external call argument to
+ * n elements, ending it with a {"__monty_elided__": remaining} marker.
+ */
+void monty_set_max_collection_width(MontyHandle *handle, size_t n);
+
+/**
+ * Reject any single value passed to a resume call (monty_resume,
+ * monty_resume_many, monty_resume_reader, monty_resume_os_call,
+ * monty_resume_futures) that would decode into more than n values. Each
+ * JSON value counts once; object keys don't. The handle stays paused.
+ */
+void monty_set_max_resume_elements(MontyHandle *handle, size_t n);
+
+/* ------------------------------------------------------------------ */
+/* Result options                                                     */
+/* ------------------------------------------------------------------ */
+
+/**
+ * Always include "print_output" in result JSON (empty string when nothing
+ * was printed). Non-zero enables; the default omits the key when empty.
+ */
+void monty_set_always_emit_print(MontyHandle *handle, int enabled);
+
+/**
+ * Include "value_repr", the Python repr() of the result value (e.g.
+ * "(1, 2)" for a tuple), in successful result JSON. Non-zero enables; the
+ * default omits the key.
+ */
+void monty_set_value_repr(MontyHandle *handle, int enabled);
+
+/**
+ * Pause with MONTY_PROGRESS_OS_CALL (state MONTY_STATE_OS_CALL) on an OS
+ * call no registered handler answers, for the host to answer with
+ * monty_resume_os_call(). Non-zero enables; by default such a call ends
+ * the run with NotImplementedError, as it always does in monty_run().
+ */
+void monty_set_pause_os_calls(MontyHandle *handle, int enabled);
+
+/**
+ * Print callback: receives len bytes of UTF-8 output at text. The text is
+ * not NUL-terminated and is only valid during the call.
+ */
+typedef void (*MontyPrintCallback)(const char *text, size_t len, void *user_data);
+
+/**
+ * Stream print output to cb as the guest writes it, instead of buffering it
+ * into the result's "print_output". Pass NULL to restore buffering. Takes
+ * effect from the next execution call, so it may be changed while paused.
+ * Calls into the handle from cb, accessors included, are refused.
+ *
+ * @param handle     Valid handle (NULL is a no-op).
+ * @param cb         Callback, or NULL.
+ * @param user_data  Passed through to cb unchanged.
+ */
+void monty_set_print_callback(MontyHandle *handle,
+                              MontyPrintCallback cb,
+                              void *user_data);
+
+/**
+ * Emit only "exc_type" and "message" for guest exceptions, skipping
+ * traceback serialization. Non-zero enables; the default keeps tracebacks.
+ */
+void monty_set_minimal_errors(MontyHandle *handle, int enabled);
+
+/**
+ * Tag tuples, sets, frozensets, bytes, non-finite floats, integers beyond
+ * i64 and non-string-keyed dicts as {"__tuple__": [...]}, {"__set__": [...]},
+ * {"__frozenset__": [...]}, {"__bytes__": "<base64>"}, {"__float__": "NaN"},
+ * {"__bigint__": "<digits>"} and {"__dict__": [[k, v], ...]} in result JSON
+ * and external call arguments. Non-zero enables; the default emits plain
+ * arrays and strings.
+ *
+ * Values from the host (resume values, run arguments, constants) decode
+ * these tags only while this is enabled; otherwise {"__tuple__": [1]} is an
+ * ordinary one-key dict. When enabled, a dict whose only key is a tag is
+ * written as {"__dict__": [[key, value]]}, which is also how a host passes
+ * such a dict, or a literal {"__monty_type__": "missing"} dict, back in.
+ */
+void monty_set_typed_containers(MontyHandle *handle, int enabled);
+
+/**
+ * Emit dicts keyed by ints and bools as JSON objects, keys stringified as
+ * Python's json.dumps does ({1: "a"} -> {"1": "a"}), plus a
+ * "__key_types__" entry ({"1": "int"}) that restores them on resume.
+ * Non-zero enables; the default emits [[k, v], ...] pairs. Takes
+ * precedence over the "__dict__" tag; colliding keys, or a dict that
+ * already has a "__key_types__" key, keep the pairs form. Host values
+ * restore "__key_types__" entries only while this is enabled.
+ */
+void monty_set_stringify_int_keys(MontyHandle *handle, int enabled);
+
+/**
+ * Emit -0.0 as-is in result JSON and external call arguments. Non-zero
+ * enables; the default normalizes it to 0.0.
+ */
+void monty_set_preserve_negative_zero(MontyHandle *handle, int enabled);
+
+/**
+ * How floats with no fractional part (3.0) are emitted in result JSON and
+ * external call arguments. Unknown values are ignored. Under PRESERVE,
+ * host values decode the "__float__" tag even without typed containers.
+ */
+typedef enum {
+    MONTY_INTEGRAL_FLOAT_AS_NUMBER = 0, /**< 3.0 (default) */
+    MONTY_INTEGRAL_FLOAT_PRESERVE  = 1, /**< {"__float__": 3.0} */
+    MONTY_INTEGRAL_FLOAT_AS_INT    = 2, /**< 3, when it fits in int64 */
+} MontyIntegralFloatPolicy;
+
+void monty_set_integral_float_policy(MontyHandle *handle, int policy);
+
+/**
+ * Replace the exception types reported as "retryable" in error JSON.
+ * An error the host injected at the last resume (resume with error,
+ * futures errors and timeouts, monty_fail_all_futures()) is retryable
+ * regardless, when that exception itself ends the run.
+ *
+ * @param handle     Valid handle.
+ * @param csv        NUL-terminated comma-separated exception class names,
+ *                   or NULL to restore the default ("TimeoutError").
+ * @param out_error  Receives error message on failure. Caller frees.
+ * @return           MONTY_RESULT_OK or MONTY_RESULT_ERROR.
+ */
+MontyResultTag monty_set_retryable_exc_types(MontyHandle *handle,
+                                             const char *csv,
+                                             char **out_error);
+
+/* ------------------------------------------------------------------ */
+/* Value helpers                                                      */
+/* ------------------------------------------------------------------ */
+
+/**
+ * Estimate the memory footprint of a JSON value once converted to a
+ * Python object. Useful to check a resume value against a memory limit.
+ *
+ * @param json  NUL-terminated JSON value.
+ * @return      Estimated bytes, or -1 if json is NULL or invalid.
+ */
+int64_t monty_estimate_value_memory(const char *json);
+
 /* ------------------------------------------------------------------ */
 /* Memory management                                                  */
 /* ------------------------------------------------------------------ */
@@ -272,7 +1134,17 @@ void monty_set_stack_limit(MontyHandle *handle, size_t depth);
 /** Free a string returned by any monty_* function. Safe with NULL. */
 void monty_string_free(char *ptr);
 
-/** Free a byte buffer returned by monty_snapshot(). Safe with NULL. */
+/**
+ * Free several strings returned by monty_* functions in one call.
+ * NULL entries, and a NULL array, are skipped. The array itself is not
+ * freed.
+ */
+void monty_string_free_many(char *const *ptrs, size_t count);
+
+/**
+ * Free a byte buffer returned by monty_snapshot() or monty_snapshot_ex().
+ * Safe with NULL.
+ */
 void monty_bytes_free(uint8_t *ptr, size_t len);
 
 #ifdef __cplusplus
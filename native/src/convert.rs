@@ -1,400 +1,2592 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use monty::MontyObject;
 use num_bigint::BigInt;
 use num_traits::ToPrimitive;
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, SerializeMap, SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
 use serde_json::{Number, Value, json};
 
-/// Convert a `MontyObject` to a JSON `Value`.
+/// Convert a `MontyObject` to JSON with default options.
+#[cfg(test)]
+pub fn monty_object_to_json(obj: &MontyObject) -> Value {
+    monty_object_to_json_with(obj, &ConversionOptions::default()).unwrap_or(Value::Null)
+}
+
+/// Options controlling `MontyObject` → JSON conversion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionOptions {
+    /// Maximum characters in a single string. The engine has no per-object
+    /// cap, so oversized strings are rejected here, at serialization time.
+    pub max_string_length: Option<usize>,
+    /// Maximum elements emitted per collection. Longer collections are
+    /// truncated and end with a `{"__monty_elided__": remaining}` marker
+    /// (for string-keyed dicts, an extra `"__monty_elided__"` key).
+    pub max_collection_width: Option<usize>,
+    /// Emit `-0.0` as-is instead of normalizing it to `0.0`. JSON parsers
+    /// disagree on whether the sign survives, so it is dropped by default.
+    pub preserve_negative_zero: bool,
+    /// How finite floats with no fractional part (`3.0`) are emitted.
+    pub integral_float_policy: IntegralFloatPolicy,
+    /// Tag types plain JSON would lose: tuples, sets and frozensets become
+    /// `{"__tuple__": [...]}`, `{"__set__": [...]}` and
+    /// `{"__frozenset__": [...]}`, bytes `{"__bytes__": "<base64>"}`,
+    /// non-finite floats `{"__float__": "NaN"}` (or `"Infinity"`,
+    /// `"-Infinity"`), integers beyond i64 `{"__bigint__": "<digits>"}`, and
+    /// dicts with non-string keys `{"__dict__": [[k, v], ...]}`. A
+    /// string-keyed dict whose one key is a tag is written as `__dict__`
+    /// pairs too, so it reads back as a dict. `decode_options` recognizes
+    /// these tags on input only while this is set. Output-only tags keep the
+    /// class name of named tuples and dataclasses
+    /// (`{"__namedtuple__": {"name", "values"}}`,
+    /// `{"__dataclass__": {"name", "fields"}}`), split type objects into
+    /// their bare name and repr (`{"__type__": {"name", "repr"}}`) and
+    /// describe cycle markers (`{"__cycle__": {"id", "repr"}}`).
+    pub typed_containers: bool,
+    /// Emit dicts keyed by ints, bools and strings as JSON objects, with the
+    /// int and bool keys stringified as `json.dumps` does (`1` → `"1"`,
+    /// `True` → `"true"`) and recorded in a `{"__key_types__": {"1": "int"}}`
+    /// entry so `decode_options` restores them. Dicts whose keys collide
+    /// once stringified, or that already have a `__key_types__` key, keep
+    /// the pairs form. Takes precedence over the `__dict__` tag.
+    pub stringify_int_keys: bool,
+    /// Maximum nesting depth, counting a top-level scalar as 1. Deeper
+    /// values fail with "conversion depth exceeded" instead of overflowing
+    /// the stack.
+    pub max_depth: usize,
+}
+
+/// How `ConversionOptions` emits a finite float with no fractional part.
+///
+/// Many JSON parsers read `3.0` back as an integer, so plain numbers cannot
+/// tell `3.0` from `3`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntegralFloatPolicy {
+    /// Tag it as `{"__float__": 3.0}`, which decodes back to a float.
+    Preserve,
+    /// Emit a JSON number (`3.0`).
+    #[default]
+    AsNumber,
+    /// Emit a JSON integer (`3`) when it fits in i64. `-0.0` stays a float
+    /// when `preserve_negative_zero` is set.
+    AsInt,
+}
+
+impl IntegralFloatPolicy {
+    /// Map the C values `0` (`AsNumber`), `1` (`Preserve`) and `2`
+    /// (`AsInt`).
+    pub fn from_c_int(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::AsNumber),
+            1 => Some(Self::Preserve),
+            2 => Some(Self::AsInt),
+            _ => None,
+        }
+    }
+}
+
+/// Default `ConversionOptions::max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+/// Which tags decoding host JSON into a `MontyObject` recognizes, and how
+/// many values it may build.
+///
+/// A tag is only decoded while the matching output option is on, so a
+/// plain dict that happens to use a tag key (`{"__tuple__": 1}`) stays a
+/// dict.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeOptions {
+    /// Decode the `typed_containers` tags: `__tuple__`, `__set__`,
+    /// `__frozenset__`, `__bytes__`, `__float__`, `__bigint__` and
+    /// `__dict__`.
+    pub typed_containers: bool,
+    /// Decode `__float__` even without `typed_containers`, as written under
+    /// `IntegralFloatPolicy::Preserve`.
+    pub float_tag: bool,
+    /// Restore the int and bool keys a `__key_types__` entry names.
+    pub key_types: bool,
+    /// Fail once more than this many values have been built. Every JSON
+    /// value counts once, containers and scalars alike; object keys don't.
+    pub max_elements: Option<usize>,
+    /// Maximum nesting depth, counting a top-level scalar as 1, as in
+    /// `ConversionOptions::max_depth`.
+    pub max_depth: usize,
+}
+
+impl DecodeOptions {
+    /// Every tag recognized and no element cap, for values supplied before
+    /// a handle has options, such as globals.
+    pub const TAGGED: Self = Self {
+        typed_containers: true,
+        float_tag: true,
+        key_types: true,
+        max_elements: None,
+        max_depth: DEFAULT_MAX_DEPTH,
+    };
+}
+
+impl ConversionOptions {
+    /// Decode settings recognizing exactly the tags these options write.
+    pub fn decode_options(&self, max_elements: Option<usize>) -> DecodeOptions {
+        DecodeOptions {
+            typed_containers: self.typed_containers,
+            float_tag: self.typed_containers
+                || self.integral_float_policy == IntegralFloatPolicy::Preserve,
+            key_types: self.stringify_int_keys,
+            max_elements,
+            max_depth: self.max_depth,
+        }
+    }
+
+    /// Whether a one-key string-keyed dict with this key would read back
+    /// as a tag.
+    fn is_tag_key(&self, key: &str) -> bool {
+        let float_tag =
+            self.typed_containers || self.integral_float_policy == IntegralFloatPolicy::Preserve;
+        (self.typed_containers && TAG_KEYS.contains(&key)) || (float_tag && key == FLOAT_KEY)
+    }
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        Self {
+            max_string_length: None,
+            max_collection_width: None,
+            preserve_negative_zero: false,
+            integral_float_policy: IntegralFloatPolicy::AsNumber,
+            typed_containers: false,
+            stringify_int_keys: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// Marker key recording how many collection elements were elided.
+const ELIDED_KEY: &str = "__monty_elided__";
+
+/// Tag keys wrapping container items under `typed_containers`.
+const TUPLE_KEY: &str = "__tuple__";
+const SET_KEY: &str = "__set__";
+const FROZENSET_KEY: &str = "__frozenset__";
+const BYTES_KEY: &str = "__bytes__";
+const FLOAT_KEY: &str = "__float__";
+const BIGINT_KEY: &str = "__bigint__";
+const DICT_KEY: &str = "__dict__";
+const CYCLE_KEY: &str = "__cycle__";
+const NAMEDTUPLE_KEY: &str = "__namedtuple__";
+const DATACLASS_KEY: &str = "__dataclass__";
+const TYPE_KEY: &str = "__type__";
+/// Sidecar entry recording the types of keys stringified under
+/// `stringify_int_keys`.
+const KEY_TYPES_KEY: &str = "__key_types__";
+const TAG_KEYS: [&str; 7] = [
+    TUPLE_KEY,
+    SET_KEY,
+    FROZENSET_KEY,
+    BYTES_KEY,
+    FLOAT_KEY,
+    BIGINT_KEY,
+    DICT_KEY,
+];
+
+/// Convert a `MontyObject` to JSON under `options`.
+///
+/// Dicts keep Python's insertion order: `DictPairs` iterates in insertion
+/// order, and `serde_json`'s `preserve_order` feature backs `Map` with an
+/// `IndexMap` instead of a sorted `BTreeMap`.
 ///
 /// Key mappings:
 /// - `None` → `null`
 /// - `Bool` → `true`/`false`
 /// - `Int` → number
-/// - `BigInt` → number if fits i64, else string
-/// - `Float` → number
+/// - `BigInt` → number if fits i64, else string (`{"__bigint__": "..."}`
+///   under `typed_containers`)
+/// - `Float` → number; `"NaN"`/`"Infinity"`/`"-Infinity"` when non-finite
+///   (`{"__float__": "NaN"}` etc. under `typed_containers`)
 /// - `String` → string
-/// - `List`/`Tuple` → array
+/// - `List`/`Tuple` → array (tuples `{"__tuple__": [...]}` under
+///   `typed_containers`)
 /// - `Dict` → object (string keys) or array of `[k, v]` pairs
+///   (`{"__dict__": [[k, v], ...]}` under `typed_containers`)
 /// - `Ellipsis` → `"..."`
-/// - `Bytes` → array of ints
-/// - `Set`/`FrozenSet` → array
-pub fn monty_object_to_json(obj: &MontyObject) -> Value {
+/// - `Bytes` → array of ints (`{"__bytes__": "<base64>"}` under
+///   `typed_containers`)
+/// - `Set`/`FrozenSet` → array (`{"__set__": [...]}` /
+///   `{"__frozenset__": [...]}` under `typed_containers`)
+/// - `NamedTuple` → array, `Dataclass` → object (`{"__namedtuple__": ...}` /
+///   `{"__dataclass__": ...}` with the class name under `typed_containers`)
+/// - `Type` → repr string like `"<class 'int'>"` (`{"__type__": {"name",
+///   "repr"}}` under `typed_containers`)
+/// - `Cycle` → description string (`{"__cycle__": {"id", "repr"}}` under
+///   `typed_containers`)
+///
+/// Returns an error message if the value violates a limit in `options`.
+pub fn monty_object_to_json_with(
+    obj: &MontyObject,
+    options: &ConversionOptions,
+) -> Result<Value, String> {
+    let encoder = Encoder::new(options);
+    encoder
+        .emit(Node::Object(obj))
+        .serialize(serde_json::value::Serializer)
+        .map_err(|e| encoder.into_error(e))
+}
+
+/// Write `obj` as JSON text under `options`, as `monty_object_to_json_with`
+/// would encode it but without building a `Value` tree first.
+///
+/// Returns whether the output is JS-safe: every number is representable by
+/// a JavaScript `number` (no integer beyond ±(2^53 − 1), no
+/// `NaN`/`Infinity`). Returns an error message instead if the value
+/// violates a limit in `options`; `writer` may then hold partial output.
+pub fn monty_object_to_writer(
+    obj: &MontyObject,
+    options: &ConversionOptions,
+    writer: impl Write,
+) -> Result<bool, String> {
+    let encoder = Encoder::new(options);
+    let mut serializer = serde_json::Serializer::new(writer);
+    match encoder.emit(Node::Object(obj)).serialize(&mut serializer) {
+        Ok(()) => Ok(encoder.js_safe.get()),
+        Err(e) => Err(encoder.into_error(e)),
+    }
+}
+
+/// Check `obj` against the depth and string-length limits in `options`
+/// without encoding it, failing with the message `monty_object_to_writer`
+/// would. Only the elements `max_collection_width` keeps are checked.
+pub fn check_conversion_limits(
+    obj: &MontyObject,
+    options: &ConversionOptions,
+) -> Result<(), String> {
+    check_limits(obj, options, 0)
+}
+
+fn check_limits(
+    obj: &MontyObject,
+    options: &ConversionOptions,
+    depth: usize,
+) -> Result<(), String> {
+    if depth >= options.max_depth {
+        return Err(depth_exceeded(options.max_depth));
+    }
+    let width = options.max_collection_width.unwrap_or(usize::MAX);
     match obj {
-        MontyObject::None => Value::Null,
-        MontyObject::Bool(b) => Value::Bool(*b),
-        MontyObject::Int(n) => json!(n),
-        MontyObject::BigInt(n) => bigint_to_json(n),
-        MontyObject::Float(f) => float_to_json(*f),
-        MontyObject::String(s) => Value::String(s.clone()),
-        MontyObject::List(items) | MontyObject::Tuple(items) => {
-            Value::Array(items.iter().map(monty_object_to_json).collect())
-        }
-        MontyObject::Dict(pairs) => dict_to_json(pairs),
-        MontyObject::Set(items) | MontyObject::FrozenSet(items) => {
-            Value::Array(items.iter().map(monty_object_to_json).collect())
-        }
-        MontyObject::Ellipsis => Value::String("...".into()),
-        MontyObject::Bytes(bytes) => Value::Array(bytes.iter().map(|b| json!(*b)).collect()),
-        MontyObject::NamedTuple { values, .. } => {
-            Value::Array(values.iter().map(monty_object_to_json).collect())
-        }
-        MontyObject::Path(p) => Value::String(p.clone()),
-        MontyObject::Dataclass { attrs, .. } => dict_to_json(attrs),
-        MontyObject::Type(t) => Value::String(format!("{t}")),
-        MontyObject::BuiltinFunction(f) => Value::String(format!("{f:?}")),
-        MontyObject::Exception { exc_type, arg } => {
-            let msg = match arg {
-                Some(a) => format!("{exc_type}: {a}"),
-                None => format!("{exc_type}"),
-            };
-            Value::String(msg)
+        MontyObject::String(text) => check_string_length(text, options),
+        MontyObject::List(items)
+        | MontyObject::Tuple(items)
+        | MontyObject::Set(items)
+        | MontyObject::FrozenSet(items)
+        | MontyObject::NamedTuple { values: items, .. } => items
+            .iter()
+            .take(width)
+            .try_for_each(|item| check_limits(item, options, depth + 1)),
+        MontyObject::Dict(pairs) | MontyObject::Dataclass { attrs: pairs, .. } => {
+            pairs.into_iter().take(width).try_for_each(|(k, v)| {
+                check_limits(k, options, depth + 1)?;
+                check_limits(v, options, depth + 1)
+            })
         }
-        MontyObject::Repr(r) => Value::String(r.clone()),
-        MontyObject::Cycle(_, desc) => Value::String(desc.clone()),
+        _ => Ok(()),
     }
 }
 
-/// Convert a JSON `Value` back to a `MontyObject` (for resume values).
-pub fn json_to_monty_object(val: &Value) -> MontyObject {
-    match val {
-        Value::Null => MontyObject::None,
-        Value::Bool(b) => MontyObject::Bool(*b),
-        Value::Number(n) => number_to_monty_object(n),
-        Value::String(s) => MontyObject::String(s.clone()),
-        Value::Array(items) => MontyObject::List(items.iter().map(json_to_monty_object).collect()),
-        Value::Object(map) => {
-            let pairs: Vec<(MontyObject, MontyObject)> = map
-                .iter()
-                .map(|(k, v)| (MontyObject::String(k.clone()), json_to_monty_object(v)))
-                .collect();
-            MontyObject::dict(pairs)
+fn depth_exceeded(max: usize) -> String {
+    format!("conversion depth exceeded (max_depth {max})")
+}
+
+fn check_string_length(s: &str, options: &ConversionOptions) -> Result<(), String> {
+    if let Some(max) = options.max_string_length {
+        let len = s.chars().count();
+        if len > max {
+            return Err(format!(
+                "string of {len} characters exceeds max_string_length of {max}"
+            ));
         }
     }
+    Ok(())
 }
 
-fn bigint_to_json(n: &BigInt) -> Value {
-    if let Some(i) = n.to_i64() {
-        json!(i)
-    } else {
-        Value::String(n.to_string())
-    }
+#[cfg(test)]
+thread_local! {
+    /// Values handed to an `Encoder` on this thread, for tests checking
+    /// that a value is not encoded before it is read.
+    pub(crate) static ENCODER_RUNS: Cell<usize> = const { Cell::new(0) };
 }
 
-fn float_to_json(f: f64) -> Value {
-    if f.is_finite() {
-        Number::from_f64(f)
-            .map(Value::Number)
-            .unwrap_or(Value::Null)
-    } else if f.is_nan() {
-        Value::String("NaN".into())
-    } else if f.is_sign_positive() {
-        Value::String("Infinity".into())
-    } else {
-        Value::String("-Infinity".into())
-    }
+/// Largest integer a JavaScript `number` represents exactly (2^53 − 1).
+const JS_MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Recursive `MontyObject` → JSON encoder, tracking JS-safety and the first
+/// limit violation as it goes.
+///
+/// Encoding drives a serde `Serializer`, so the same walk builds a `Value`
+/// tree (`monty_object_to_json_with`) or writes JSON text directly
+/// (`monty_object_to_writer`). A limit violation aborts the walk.
+struct Encoder<'a> {
+    options: &'a ConversionOptions,
+    js_safe: Cell<bool>,
+    error: RefCell<Option<String>>,
+    depth: Cell<usize>,
 }
 
-fn number_to_monty_object(n: &Number) -> MontyObject {
-    if let Some(i) = n.as_i64() {
-        MontyObject::Int(i)
-    } else if let Some(f) = n.as_f64() {
-        MontyObject::Float(f)
-    } else {
-        // u64 that doesn't fit i64
-        MontyObject::BigInt(BigInt::from(n.as_u64().unwrap_or(0)))
+/// A part of the value being encoded, serialized through its `Encoder`.
+#[derive(Clone, Copy)]
+enum Node<'e> {
+    Object(&'e MontyObject),
+    Array(&'e [MontyObject]),
+    Dict(&'e monty::DictPairs),
+    /// Non-string-keyed dict items as `[k, v]` pairs, then the elided count.
+    Pairs(&'e [&'e (MontyObject, MontyObject)], usize),
+    Pair(&'e MontyObject, &'e MontyObject),
+    /// `{"name": name, <field>: body}` inside a named tuple or dataclass tag.
+    Named {
+        name: &'e str,
+        field: &'static str,
+        body: &'e Node<'e>,
+    },
+}
+
+struct Emit<'e, 'a>(&'e Encoder<'a>, Node<'e>);
+
+impl Serialize for Emit<'_, '_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let Emit(encoder, node) = self;
+        match node {
+            Node::Object(obj) => encoder.encode(obj, s),
+            Node::Array(items) => encoder.encode_array(items, s),
+            Node::Dict(pairs) => encoder.encode_dict(pairs, s),
+            Node::Pairs(items, elided) => encoder.encode_pairs(items, *elided, s),
+            Node::Pair(k, v) => {
+                let mut seq = s.serialize_seq(Some(2))?;
+                seq.serialize_element(&encoder.emit(Node::Object(k)))?;
+                seq.serialize_element(&encoder.emit(Node::Object(v)))?;
+                seq.end()
+            }
+            Node::Named { name, field, body } => {
+                let mut map = s.serialize_map(Some(2))?;
+                map.serialize_entry("name", name)?;
+                map.serialize_entry(field, &Emit(encoder, **body))?;
+                map.end()
+            }
+        }
     }
 }
 
-fn dict_to_json(pairs: &monty::DictPairs) -> Value {
-    // Collect pairs via the &DictPairs IntoIterator impl.
-    let items: Vec<&(MontyObject, MontyObject)> = pairs.into_iter().collect();
-    let all_string_keys = items
-        .iter()
-        .all(|(k, _)| matches!(k, MontyObject::String(_)));
+impl<'a> Encoder<'a> {
+    fn new(options: &'a ConversionOptions) -> Self {
+        #[cfg(test)]
+        ENCODER_RUNS.with(|n| n.set(n.get() + 1));
+        Self {
+            options,
+            js_safe: Cell::new(true),
+            error: RefCell::new(None),
+            depth: Cell::new(0),
+        }
+    }
+
+    fn emit<'e>(&'e self, node: Node<'e>) -> Emit<'e, 'a> {
+        Emit(self, node)
+    }
+
+    /// Record a limit violation and abort serialization with it.
+    fn fail<E: ser::Error>(&self, msg: String) -> E {
+        let err = E::custom(&msg);
+        self.error.borrow_mut().get_or_insert(msg);
+        err
+    }
+
+    /// The recorded limit violation, or else the serializer's own error.
+    fn into_error(self, err: impl fmt::Display) -> String {
+        self.error.into_inner().unwrap_or_else(|| err.to_string())
+    }
+
+    fn check_string<E: ser::Error>(&self, s: &str) -> Result<(), E> {
+        check_string_length(s, self.options).map_err(|msg| self.fail(msg))
+    }
+
+    fn encode<S: Serializer>(&self, obj: &MontyObject, s: S) -> Result<S::Ok, S::Error> {
+        let max = self.options.max_depth;
+        let depth = self.depth.get();
+        if depth >= max {
+            return Err(self.fail(depth_exceeded(max)));
+        }
+        self.depth.set(depth + 1);
+        let result = self.encode_value(obj, s);
+        self.depth.set(depth);
+        result
+    }
+
+    fn encode_value<S: Serializer>(&self, obj: &MontyObject, s: S) -> Result<S::Ok, S::Error> {
+        let typed = self.options.typed_containers;
+        match obj {
+            MontyObject::None => s.serialize_unit(),
+            MontyObject::Bool(b) => s.serialize_bool(*b),
+            MontyObject::Int(n) => {
+                if n.unsigned_abs() > JS_MAX_SAFE_INTEGER {
+                    self.js_safe.set(false);
+                }
+                n.serialize(s)
+            }
+            MontyObject::BigInt(n) => {
+                let fits_i64 = n.to_i64();
+                if fits_i64.is_none_or(|i| i.unsigned_abs() > JS_MAX_SAFE_INTEGER) {
+                    self.js_safe.set(false);
+                }
+                if fits_i64.is_none() && typed {
+                    return tagged(s, BIGINT_KEY, &n.to_string());
+                }
+                bigint_to_json(n).serialize(s)
+            }
+            MontyObject::Float(f) => {
+                if !f.is_finite() {
+                    self.js_safe.set(false);
+                    if typed {
+                        return tagged(s, FLOAT_KEY, &float_to_json(*f));
+                    }
+                }
+                let f = if *f == 0.0 && !self.options.preserve_negative_zero {
+                    0.0
+                } else {
+                    *f
+                };
+                if f.is_finite() && f.fract() == 0.0 {
+                    match self.options.integral_float_policy {
+                        IntegralFloatPolicy::Preserve => {
+                            return tagged(s, FLOAT_KEY, &float_to_json(f));
+                        }
+                        IntegralFloatPolicy::AsInt
+                            if f.abs() < I64_FLOAT_BOUND && !(f == 0.0 && f.is_sign_negative()) =>
+                        {
+                            return s.serialize_i64(f as i64);
+                        }
+                        _ => {}
+                    }
+                }
+                float_to_json(f).serialize(s)
+            }
+            MontyObject::String(text) => {
+                self.check_string(text)?;
+                s.serialize_str(text)
+            }
+            MontyObject::List(items) => self.encode_array(items, s),
+            MontyObject::Tuple(items) if typed => {
+                tagged(s, TUPLE_KEY, &self.emit(Node::Array(items)))
+            }
+            MontyObject::Tuple(items) => self.encode_array(items, s),
+            MontyObject::Dict(pairs) => self.encode_dict(pairs, s),
+            MontyObject::Set(items) if typed => tagged(s, SET_KEY, &self.emit(Node::Array(items))),
+            MontyObject::FrozenSet(items) if typed => {
+                tagged(s, FROZENSET_KEY, &self.emit(Node::Array(items)))
+            }
+            MontyObject::Set(items) | MontyObject::FrozenSet(items) => self.encode_array(items, s),
+            MontyObject::Ellipsis => s.serialize_str("..."),
+            MontyObject::Bytes(bytes) if typed => tagged(s, BYTES_KEY, &BASE64.encode(bytes)),
+            MontyObject::Bytes(bytes) => s.collect_seq(bytes),
+            MontyObject::NamedTuple {
+                type_name, values, ..
+            } if typed => {
+                let body = Node::Named {
+                    name: type_name,
+                    field: "values",
+                    body: &Node::Array(values),
+                };
+                tagged(s, NAMEDTUPLE_KEY, &self.emit(body))
+            }
+            MontyObject::NamedTuple { values, .. } => self.encode_array(values, s),
+            MontyObject::Path(p) => s.serialize_str(p),
+            MontyObject::Dataclass { name, attrs, .. } if typed => {
+                let body = Node::Named {
+                    name,
+                    field: "fields",
+                    body: &Node::Dict(attrs),
+                };
+                tagged(s, DATACLASS_KEY, &self.emit(body))
+            }
+            MontyObject::Dataclass { attrs, .. } => self.encode_dict(attrs, s),
+            MontyObject::Type(t) if typed => {
+                let repr = t.to_string();
+                tagged(
+                    s,
+                    TYPE_KEY,
+                    &json!({"name": type_name(&repr), "repr": repr}),
+                )
+            }
+            MontyObject::Type(t) => s.collect_str(t),
+            MontyObject::BuiltinFunction(f) => s.serialize_str(&format!("{f:?}")),
+            MontyObject::Exception { exc_type, arg } => match arg {
+                Some(a) => s.serialize_str(&format!("{exc_type}: {a}")),
+                None => s.collect_str(exc_type),
+            },
+            MontyObject::Repr(r) => s.serialize_str(r),
+            MontyObject::Cycle(id, desc) if typed => {
+                let id = serde_json::to_value(id).unwrap_or(Value::Null);
+                tagged(s, CYCLE_KEY, &json!({"id": id, "repr": desc}))
+            }
+            MontyObject::Cycle(_, desc) => s.serialize_str(desc),
+        }
+    }
+
+    fn encode_array<S: Serializer>(&self, items: &[MontyObject], s: S) -> Result<S::Ok, S::Error> {
+        let (kept, elided) = self.split_width(items.len());
+        let mut seq = s.serialize_seq(Some(kept + usize::from(elided > 0)))?;
+        for item in &items[..kept] {
+            seq.serialize_element(&self.emit(Node::Object(item)))?;
+        }
+        if elided > 0 {
+            seq.serialize_element(&json!({ELIDED_KEY: elided}))?;
+        }
+        seq.end()
+    }
+
+    fn encode_dict<S: Serializer>(
+        &self,
+        pairs: &monty::DictPairs,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        // Collect pairs via the &DictPairs IntoIterator impl.
+        let mut items: Vec<&(MontyObject, MontyObject)> = pairs.into_iter().collect();
+        let (kept, elided) = self.split_width(items.len());
+        items.truncate(kept);
+        let all_string_keys = items
+            .iter()
+            .all(|(k, _)| matches!(k, MontyObject::String(_)));
+
+        if all_string_keys && !self.keys_collide(&items, elided) {
+            let mut map = s.serialize_map(Some(kept + usize::from(elided > 0)))?;
+            for (k, v) in items {
+                let MontyObject::String(key) = k else {
+                    unreachable!()
+                };
+                self.check_string(key)?;
+                map.serialize_entry(key, &self.emit(Node::Object(v)))?;
+            }
+            if elided > 0 {
+                map.serialize_entry(ELIDED_KEY, &elided)?;
+            }
+            map.end()
+        } else if let Some(keys) = self.stringified_keys(&items, elided) {
+            let mut key_types = serde_json::Map::new();
+            let mut map = s.serialize_map(Some(kept + 1 + usize::from(elided > 0)))?;
+            for ((k, v), key) in items.iter().zip(keys) {
+                match k {
+                    MontyObject::String(_) => self.check_string(&key)?,
+                    MontyObject::Bool(_) => {
+                        key_types.insert(key.clone(), "bool".into());
+                    }
+                    _ => {
+                        key_types.insert(key.clone(), "int".into());
+                    }
+                }
+                map.serialize_entry(&key, &self.emit(Node::Object(v)))?;
+            }
+            map.serialize_entry(KEY_TYPES_KEY, &key_types)?;
+            if elided > 0 {
+                map.serialize_entry(ELIDED_KEY, &elided)?;
+            }
+            map.end()
+        } else if self.options.typed_containers {
+            tagged(s, DICT_KEY, &self.emit(Node::Pairs(&items, elided)))
+        } else {
+            self.encode_pairs(&items, elided, s)
+        }
+    }
+
+    fn encode_pairs<S: Serializer>(
+        &self,
+        items: &[&(MontyObject, MontyObject)],
+        elided: usize,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = s.serialize_seq(Some(items.len() + usize::from(elided > 0)))?;
+        for (k, v) in items {
+            seq.serialize_element(&self.emit(Node::Pair(k, v)))?;
+        }
+        if elided > 0 {
+            seq.serialize_element(&json!({ELIDED_KEY: elided}))?;
+        }
+        seq.end()
+    }
+
+    /// Whether a string-keyed dict written as a plain object would read
+    /// back as something else: a lone tag key, or a key matching a marker
+    /// entry this encoding adds or decodes.
+    fn keys_collide(&self, items: &[&(MontyObject, MontyObject)], elided: usize) -> bool {
+        fn key<'k>(item: &&'k (MontyObject, MontyObject)) -> &'k str {
+            match &item.0 {
+                MontyObject::String(k) => k,
+                _ => "",
+            }
+        }
+        if let [item] = items
+            && elided == 0
+            && self.options.is_tag_key(key(item))
+        {
+            return true;
+        }
+        items.iter().map(key).any(|k| {
+            (elided > 0 && k == ELIDED_KEY)
+                || (self.options.stringify_int_keys && k == KEY_TYPES_KEY)
+        })
+    }
 
-    if all_string_keys {
-        let map: serde_json::Map<String, Value> = items
-            .into_iter()
-            .map(|(k, v)| {
+    /// Object keys for a dict under `stringify_int_keys`, or `None` if the
+    /// option is off, a key is not an int, bool or string, or two keys
+    /// (or a key and a marker entry) collide once stringified.
+    fn stringified_keys(
+        &self,
+        items: &[&(MontyObject, MontyObject)],
+        elided: usize,
+    ) -> Option<Vec<String>> {
+        if !self.options.stringify_int_keys {
+            return None;
+        }
+        let mut seen = HashSet::with_capacity(items.len() + 2);
+        seen.insert(KEY_TYPES_KEY.to_string());
+        if elided > 0 {
+            seen.insert(ELIDED_KEY.to_string());
+        }
+        items
+            .iter()
+            .map(|(k, _)| {
                 let key = match k {
                     MontyObject::String(s) => s.clone(),
-                    _ => unreachable!(),
+                    MontyObject::Int(n) => n.to_string(),
+                    MontyObject::BigInt(n) => n.to_string(),
+                    MontyObject::Bool(b) => b.to_string(),
+                    _ => return None,
                 };
-                (key, monty_object_to_json(v))
+                seen.insert(key.clone()).then_some(key)
             })
-            .collect();
-        Value::Object(map)
-    } else {
-        Value::Array(
-            items
-                .into_iter()
-                .map(|(k, v)| json!([monty_object_to_json(k), monty_object_to_json(v)]))
-                .collect(),
-        )
+            .collect()
+    }
+
+    /// Split a collection length into `(kept, elided)` under
+    /// `max_collection_width`.
+    fn split_width(&self, len: usize) -> (usize, usize) {
+        match self.options.max_collection_width {
+            Some(max) if len > max => (max, len - max),
+            _ => (len, 0),
+        }
+    }
+}
+
+/// Serialize `{key: value}`, the shape of every type tag.
+fn tagged<S: Serializer>(s: S, key: &str, value: &impl Serialize) -> Result<S::Ok, S::Error> {
+    let mut map = s.serialize_map(Some(1))?;
+    map.serialize_entry(key, value)?;
+    map.end()
+}
+
+/// The bare class name in a type's repr: `int` for `<class 'int'>`.
+fn type_name(repr: &str) -> &str {
+    repr.strip_prefix("<class '")
+        .and_then(|rest| rest.strip_suffix("'>"))
+        .unwrap_or(repr)
+}
+
+/// Convert a JSON `Value` back to a `MontyObject`, decoding every tag.
+///
+/// Returns an error message for a malformed tagged value, such as a
+/// `__bytes__` payload that is neither base64 nor a list of bytes, or for
+/// nesting deeper than `DEFAULT_MAX_DEPTH`.
+pub fn json_to_monty_object(val: &Value) -> Result<MontyObject, String> {
+    json_to_monty_object_with(val, &DecodeOptions::TAGGED)
+}
+
+/// `json_to_monty_object`, recognizing only the tags `options` enables,
+/// building at most `options.max_elements` values and nesting at most
+/// `options.max_depth` levels.
+pub fn json_to_monty_object_with(
+    val: &Value,
+    options: &DecodeOptions,
+) -> Result<MontyObject, String> {
+    Decoder::new(options).value(val, 1)
+}
+
+/// A value the host answered an external call with.
+#[derive(Debug)]
+pub enum HostValue {
+    Value(MontyObject),
+    /// The `{"__monty_type__": "missing"}` sentinel, signalling "no value"
+    /// (distinct from `None`). Under `typed_containers` a dict of that
+    /// shape can still be passed as
+    /// `{"__dict__": [["__monty_type__", "missing"]]}`.
+    Missing,
+}
+
+/// Decode a host answer: `json_to_monty_object_with`, except that a
+/// top-level missing sentinel becomes `HostValue::Missing`.
+#[cfg(test)]
+pub fn host_value_from_json(val: &Value, options: &DecodeOptions) -> Result<HostValue, String> {
+    let decoder = Decoder::new(options);
+    let obj = decoder.value(val, 1)?;
+    Ok(decoder.host_value(obj))
+}
+
+/// State for decoding one value: the options, the values built so far
+/// against `max_elements`, whether the top level was the missing sentinel
+/// and, when decoding JSON text, the text of the last number read.
+struct Decoder<'a> {
+    options: &'a DecodeOptions,
+    used: Cell<usize>,
+    missing: Cell<bool>,
+    numbers: Option<&'a NumberText>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(options: &'a DecodeOptions) -> Self {
+        Self {
+            options,
+            used: Cell::new(0),
+            missing: Cell::new(false),
+            numbers: None,
+        }
+    }
+
+    /// A decoder for JSON text read through a `NumberTextReader`.
+    fn reading(options: &'a DecodeOptions, numbers: &'a NumberText) -> Self {
+        Self {
+            numbers: Some(numbers),
+            ..Self::new(options)
+        }
+    }
+
+    /// Count one more value against `max_elements`.
+    fn take(&self) -> Result<(), String> {
+        let used = self.used.get() + 1;
+        self.used.set(used);
+        match self.options.max_elements {
+            Some(max) if used > max => Err(format!(
+                "value exceeds max_resume_elements ({max} elements)"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn host_value(&self, obj: MontyObject) -> HostValue {
+        if self.missing.get() {
+            HostValue::Missing
+        } else {
+            HostValue::Value(obj)
+        }
+    }
+
+    /// Fail if a value at `depth` would nest past `max_depth`.
+    fn enter(&self, depth: usize) -> Result<(), String> {
+        let max = self.options.max_depth;
+        if depth > max {
+            return Err(format!("conversion depth exceeded (max_depth {max})"));
+        }
+        Ok(())
+    }
+
+    fn value(&self, val: &Value, depth: usize) -> Result<MontyObject, String> {
+        self.enter(depth)?;
+        self.take()?;
+        Ok(match val {
+            Value::Null => MontyObject::None,
+            Value::Bool(b) => MontyObject::Bool(*b),
+            Value::Number(n) => number_to_monty_object(n),
+            Value::String(s) => MontyObject::String(s.clone()),
+            Value::Array(items) => MontyObject::List(
+                items
+                    .iter()
+                    .map(|item| self.value(item, depth + 1))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(map) => {
+                let pairs = map
+                    .iter()
+                    .map(|(k, v)| {
+                        let value = self.value(v, depth + 1)?;
+                        Ok((MontyObject::String(k.clone()), value))
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                return self.object(pairs, depth);
+            }
+        })
+    }
+
+    /// Build the `MontyObject` for a decoded JSON object: a tagged value
+    /// such as `{"__tuple__": [...]}` becomes that type when its tag is
+    /// enabled, anything else a dict. Set items are de-duplicated, keeping
+    /// the first occurrence.
+    fn object(
+        &self,
+        mut pairs: Vec<(MontyObject, MontyObject)>,
+        depth: usize,
+    ) -> Result<MontyObject, String> {
+        let key_types = pairs
+            .iter()
+            .position(|(k, _)| matches!(k, MontyObject::String(k) if k == KEY_TYPES_KEY));
+        if self.options.key_types
+            && let Some(index) = key_types
+        {
+            let (_, key_types) = pairs.remove(index);
+            return restore_key_types(pairs, key_types).map(MontyObject::dict);
+        }
+        if depth == 1 && is_missing_sentinel(&pairs) {
+            self.missing.set(true);
+        }
+        let tagged = matches!(
+            pairs.as_slice(),
+            [(MontyObject::String(key), _)] if self.decodes_tag(key)
+        );
+        if !tagged {
+            return Ok(MontyObject::dict(pairs));
+        }
+        let Some((MontyObject::String(tag), payload)) = pairs.pop() else {
+            unreachable!()
+        };
+        Ok(match (tag.as_str(), payload) {
+            (TUPLE_KEY, MontyObject::List(items)) => MontyObject::Tuple(items),
+            (SET_KEY, MontyObject::List(items)) => MontyObject::Set(dedup_items(items)),
+            (FROZENSET_KEY, MontyObject::List(items)) => MontyObject::FrozenSet(dedup_items(items)),
+            (BYTES_KEY, payload) => MontyObject::Bytes(decode_bytes(payload)?),
+            (FLOAT_KEY, payload) => MontyObject::Float(decode_float(payload)?),
+            (BIGINT_KEY, payload) => decode_bigint(payload)?,
+            (DICT_KEY, payload) => MontyObject::dict(decode_dict_pairs(payload)?),
+            (_, payload) => MontyObject::dict(vec![(MontyObject::String(tag), payload)]),
+        })
+    }
+
+    fn decodes_tag(&self, key: &str) -> bool {
+        if key == FLOAT_KEY {
+            self.options.typed_containers || self.options.float_tag
+        } else {
+            self.options.typed_containers && TAG_KEYS.contains(&key)
+        }
+    }
+}
+
+/// Turn the keys a `__key_types__` entry names back into ints and bools.
+fn restore_key_types(
+    pairs: Vec<(MontyObject, MontyObject)>,
+    key_types: MontyObject,
+) -> Result<Vec<(MontyObject, MontyObject)>, String> {
+    let MontyObject::Dict(key_types) = key_types else {
+        return Err(format!("{KEY_TYPES_KEY} must be an object"));
+    };
+    let mut types = HashMap::new();
+    for (key, ty) in &key_types {
+        let (MontyObject::String(key), MontyObject::String(ty)) = (key, ty) else {
+            return Err(format!(
+                "{KEY_TYPES_KEY} values must be \"int\" or \"bool\""
+            ));
+        };
+        types.insert(key.as_str(), ty.as_str());
+    }
+    pairs
+        .into_iter()
+        .map(|(k, v)| {
+            let MontyObject::String(key) = &k else {
+                return Ok((k, v));
+            };
+            let key = match (types.get(key.as_str()), key.as_str()) {
+                (None, _) => k,
+                (Some(&"bool"), "true") => MontyObject::Bool(true),
+                (Some(&"bool"), "false") => MontyObject::Bool(false),
+                (Some(&"int"), _) => decode_bigint(k.clone())
+                    .map_err(|_| format!("invalid {KEY_TYPES_KEY} int key: {key:?}"))?,
+                (Some(ty), _) => {
+                    return Err(format!("invalid {KEY_TYPES_KEY} entry {ty:?} for {key:?}"));
+                }
+            };
+            Ok((key, v))
+        })
+        .collect()
+}
+
+/// Decode a `__bytes__` payload: a base64 string, or a list of ints 0–255.
+fn decode_bytes(payload: MontyObject) -> Result<Vec<u8>, String> {
+    match payload {
+        MontyObject::String(s) => BASE64
+            .decode(s)
+            .map_err(|e| format!("invalid {BYTES_KEY} base64: {e}")),
+        MontyObject::List(items) => items
+            .iter()
+            .map(|item| match item {
+                MontyObject::Int(n) => u8::try_from(*n)
+                    .map_err(|_| format!("{BYTES_KEY} value out of range 0-255: {n}")),
+                _ => Err(format!("{BYTES_KEY} values must be ints")),
+            })
+            .collect(),
+        _ => Err(format!(
+            "{BYTES_KEY} must be a base64 string or a list of ints"
+        )),
+    }
+}
+
+/// Decode a `__float__` payload: `"NaN"`, `"Infinity"`, `"-Infinity"`, or a
+/// number.
+fn decode_float(payload: MontyObject) -> Result<f64, String> {
+    match payload {
+        MontyObject::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            _ => Err(format!("invalid {FLOAT_KEY} value: {s:?}")),
+        },
+        MontyObject::Float(f) => Ok(f),
+        MontyObject::Int(n) => Ok(n as f64),
+        _ => Err(format!(
+            "{FLOAT_KEY} must be a number or \"NaN\"/\"Infinity\"/\"-Infinity\""
+        )),
+    }
+}
+
+/// Decode a `__bigint__` payload: a string of decimal digits with an
+/// optional leading `-`. Values that fit i64 become `Int`.
+fn decode_bigint(payload: MontyObject) -> Result<MontyObject, String> {
+    let MontyObject::String(s) = payload else {
+        return Err(format!("{BIGINT_KEY} must be a string of decimal digits"));
+    };
+    let digits = s.strip_prefix('-').unwrap_or(&s);
+    let n = (!digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| BigInt::parse_bytes(s.as_bytes(), 10))
+        .flatten()
+        .ok_or_else(|| format!("invalid {BIGINT_KEY} digits: {s:?}"))?;
+    Ok(n.to_i64().map_or(MontyObject::BigInt(n), MontyObject::Int))
+}
+
+/// Decode a `__dict__` payload: a list of `[key, value]` pairs whose keys
+/// may be any decoded value, not just strings.
+fn decode_dict_pairs(payload: MontyObject) -> Result<Vec<(MontyObject, MontyObject)>, String> {
+    let MontyObject::List(items) = payload else {
+        return Err(format!("{DICT_KEY} must be a list of [key, value] pairs"));
+    };
+    items
+        .into_iter()
+        .map(|item| match item {
+            MontyObject::List(pair) if pair.len() == 2 => {
+                let mut pair = pair.into_iter();
+                Ok((pair.next().unwrap(), pair.next().unwrap()))
+            }
+            _ => Err(format!("{DICT_KEY} entries must be [key, value] pairs")),
+        })
+        .collect()
+}
+
+/// Drop repeated items, keeping the first occurrence of each.
+///
+/// Hashable items are bucketed by `item_hash`, so a large set decodes in
+/// linear time; only unhashable items (which Python would reject) are
+/// compared against each other one by one.
+fn dedup_items(items: Vec<MontyObject>) -> Vec<MontyObject> {
+    let mut unique: Vec<MontyObject> = Vec::with_capacity(items.len());
+    let mut by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut unhashable: Vec<usize> = Vec::new();
+    for item in items {
+        let seen = match item_hash(&item) {
+            Some(hash) => by_hash.entry(hash).or_default(),
+            None => &mut unhashable,
+        };
+        if !seen.iter().any(|&i| unique[i] == item) {
+            seen.push(unique.len());
+            unique.push(item);
+        }
+    }
+    unique
+}
+
+/// Hash of an item consistent with `MontyObject` equality, or `None` for
+/// mutable containers and other values Python cannot hash.
+fn item_hash(item: &MontyObject) -> Option<u64> {
+    let mut hasher = std::hash::DefaultHasher::new();
+    hash_item(item, &mut hasher).then(|| hasher.finish())
+}
+
+fn hash_item(item: &MontyObject, hasher: &mut impl Hasher) -> bool {
+    std::mem::discriminant(item).hash(hasher);
+    match item {
+        MontyObject::Ellipsis | MontyObject::None => {}
+        MontyObject::Bool(b) => b.hash(hasher),
+        MontyObject::Int(i) => i.hash(hasher),
+        MontyObject::BigInt(i) => i.hash(hasher),
+        // `-0.0 == 0.0`, so both hash as `0.0`.
+        MontyObject::Float(f) => (if *f == 0.0 { 0.0 } else { *f }).to_bits().hash(hasher),
+        MontyObject::String(s) | MontyObject::Path(s) => s.hash(hasher),
+        MontyObject::Bytes(b) => b.hash(hasher),
+        MontyObject::Tuple(items) | MontyObject::FrozenSet(items) => {
+            items.len().hash(hasher);
+            return items.iter().all(|item| hash_item(item, hasher));
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Whether an object's pairs are exactly the `{"__monty_type__":
+/// "missing"}` sentinel.
+fn is_missing_sentinel(pairs: &[(MontyObject, MontyObject)]) -> bool {
+    matches!(
+        pairs,
+        [(MontyObject::String(k), MontyObject::String(v))]
+            if k == "__monty_type__" && v == "missing"
+    )
+}
+
+/// Decode one JSON value from `reader` directly into a `MontyObject`,
+/// under `options` as in `json_to_monty_object_with`.
+///
+/// Unlike `serde_json::from_str` + `json_to_monty_object_with`, neither the
+/// full JSON text nor an intermediate `Value` tree is held in memory.
+/// Object keys keep their input order; a repeated key keeps its last value.
+#[cfg(test)]
+pub fn monty_object_from_json_reader(
+    reader: impl Read,
+    options: &DecodeOptions,
+) -> Result<MontyObject, serde_json::Error> {
+    let numbers = NumberText::default();
+    let decoder = Decoder::reading(options, &numbers);
+    read_json(reader, &numbers, JsonObjectSeed(&decoder, 1))
+}
+
+/// `monty_object_from_json_reader` for a host answer, turning a top-level
+/// missing sentinel into `HostValue::Missing`.
+pub fn host_value_from_json_reader(
+    reader: impl Read,
+    options: &DecodeOptions,
+) -> Result<HostValue, serde_json::Error> {
+    let numbers = NumberText::default();
+    read_json(reader, &numbers, HostValueSeed(options, &numbers))
+}
+
+/// Decode a JSON array of host answers from `reader`, each decoded as by
+/// `host_value_from_json_reader` with its own `max_elements` budget.
+pub fn host_values_from_json_reader(
+    reader: impl Read,
+    options: &DecodeOptions,
+) -> Result<Vec<HostValue>, serde_json::Error> {
+    let numbers = NumberText::default();
+    read_json(reader, &numbers, HostValuesSeed(options, &numbers))
+}
+
+/// Decode a JSON object of host answers from `reader`, keeping each key
+/// with its answer decoded as by `host_value_from_json_reader`. Keys keep
+/// their input order; a repeated key keeps its last value.
+pub fn keyed_host_values_from_json_reader(
+    reader: impl Read,
+    options: &DecodeOptions,
+) -> Result<Vec<(String, HostValue)>, serde_json::Error> {
+    let numbers = NumberText::default();
+    read_json(reader, &numbers, KeyedHostValuesSeed(options, &numbers))
+}
+
+/// Deserialize exactly one JSON value from `reader` with `seed`, recording
+/// number text in `numbers` for the seed's decoders.
+fn read_json<T>(
+    reader: impl Read,
+    numbers: &NumberText,
+    seed: impl for<'de> DeserializeSeed<'de, Value = T>,
+) -> Result<T, serde_json::Error> {
+    let reader = NumberTextReader {
+        inner: reader,
+        numbers,
+    };
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    // serde_json stops at 128 levels; the seed enforces `max_depth` instead.
+    de.disable_recursion_limit();
+    let value = seed.deserialize(&mut de)?;
+    de.end()?;
+    Ok(value)
+}
+
+/// Text of the JSON number most recently read through a
+/// `NumberTextReader`.
+///
+/// serde_json hands an integer literal beyond the u64/i64 range to the
+/// visitor as a rounded `f64`; the visitor uses this text to decode it as
+/// an exact `BigInt` instead.
+#[derive(Default)]
+struct NumberText {
+    /// Number characters read since the last other byte.
+    run: RefCell<String>,
+    /// The last completed run.
+    last: RefCell<String>,
+}
+
+impl NumberText {
+    fn push(&self, byte: u8) {
+        let mut run = self.run.borrow_mut();
+        if matches!(byte, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') {
+            run.push(char::from(byte));
+        } else if !run.is_empty() {
+            *self.last.borrow_mut() = std::mem::take(&mut *run);
+        }
+    }
+
+    /// The exact integer for `f` if the number just read was an integer
+    /// literal too large to arrive as an `i64` or `u64`.
+    fn big_int(&self, f: f64) -> Option<MontyObject> {
+        if f.abs() < I64_FLOAT_BOUND {
+            return None;
+        }
+        // serde_json reads one byte past a number, so the number's run is
+        // complete unless the input ended right after it.
+        let run = self.run.borrow();
+        let last = self.last.borrow();
+        let text = if run.is_empty() { &*last } else { &*run };
+        if text.contains(['.', 'e', 'E']) {
+            return None;
+        }
+        text.parse::<BigInt>().ok().map(MontyObject::BigInt)
+    }
+}
+
+/// Passes bytes through from `inner`, recording number text in `numbers`.
+/// serde_json pulls bytes one at a time, so the record keeps pace with the
+/// parser.
+struct NumberTextReader<'n, R> {
+    inner: R,
+    numbers: &'n NumberText,
+}
+
+impl<R: Read> Read for NumberTextReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for &byte in &buf[..n] {
+            self.numbers.push(byte);
+        }
+        Ok(n)
+    }
+}
+
+/// Decodes one host answer under a fresh `Decoder`.
+#[derive(Clone, Copy)]
+struct HostValueSeed<'a>(&'a DecodeOptions, &'a NumberText);
+
+impl<'de> DeserializeSeed<'de> for HostValueSeed<'_> {
+    type Value = HostValue;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<HostValue, D::Error> {
+        let decoder = Decoder::reading(self.0, self.1);
+        let obj = JsonObjectSeed(&decoder, 1).deserialize(deserializer)?;
+        Ok(decoder.host_value(obj))
+    }
+}
+
+/// Decodes a JSON array of host answers.
+struct HostValuesSeed<'a>(&'a DecodeOptions, &'a NumberText);
+
+impl<'de> DeserializeSeed<'de> for HostValuesSeed<'_> {
+    type Value = Vec<HostValue>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl<'de> Visitor<'de> for HostValuesSeed<'_> {
+    type Value = Vec<HostValue>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element_seed(HostValueSeed(self.0, self.1))? {
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+/// Decodes a JSON object of host answers keyed by string.
+struct KeyedHostValuesSeed<'a>(&'a DecodeOptions, &'a NumberText);
+
+impl<'de> DeserializeSeed<'de> for KeyedHostValuesSeed<'_> {
+    type Value = Vec<(String, HostValue)>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeyedHostValuesSeed<'_> {
+    type Value = Vec<(String, HostValue)>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entries: Vec<(String, HostValue)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = map.next_value_seed(HostValueSeed(self.0, self.1))?;
+            match index.get(&key) {
+                Some(&i) => entries[i].1 = value,
+                None => {
+                    index.insert(key.clone(), entries.len());
+                    entries.push((key, value));
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Decodes a `MontyObject` at `depth` straight from JSON, charging each
+/// value to the decoder's budget.
+#[derive(Clone, Copy)]
+struct JsonObjectSeed<'d, 'a>(&'d Decoder<'a>, usize);
+
+impl<'de> DeserializeSeed<'de> for JsonObjectSeed<'_, '_> {
+    type Value = MontyObject;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<MontyObject, D::Error> {
+        self.0.enter(self.1).map_err(de::Error::custom)?;
+        self.0.take().map_err(de::Error::custom)?;
+        deserializer.deserialize_any(JsonObjectVisitor(self.0, self.1))
+    }
+}
+
+struct JsonObjectVisitor<'d, 'a>(&'d Decoder<'a>, usize);
+
+impl<'de> Visitor<'de> for JsonObjectVisitor<'_, '_> {
+    type Value = MontyObject;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<MontyObject, E> {
+        Ok(MontyObject::None)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<MontyObject, E> {
+        Ok(MontyObject::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<MontyObject, E> {
+        Ok(MontyObject::Int(n))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<MontyObject, E> {
+        Ok(
+            i64::try_from(n)
+                .map_or_else(|_| MontyObject::BigInt(BigInt::from(n)), MontyObject::Int),
+        )
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<MontyObject, E> {
+        let exact = self.0.numbers.and_then(|numbers| numbers.big_int(f));
+        Ok(exact.unwrap_or(MontyObject::Float(f)))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<MontyObject, E> {
+        Ok(MontyObject::String(s.to_owned()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<MontyObject, E> {
+        Ok(MontyObject::String(s))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MontyObject, A::Error> {
+        let JsonObjectVisitor(decoder, depth) = self;
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element_seed(JsonObjectSeed(decoder, depth + 1))? {
+            items.push(item);
+        }
+        Ok(MontyObject::List(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MontyObject, A::Error> {
+        let JsonObjectVisitor(decoder, depth) = self;
+        let mut pairs: Vec<(MontyObject, MontyObject)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = map.next_value_seed(JsonObjectSeed(decoder, depth + 1))?;
+            match index.get(&key) {
+                Some(&i) => pairs[i].1 = value,
+                None => {
+                    index.insert(key.clone(), pairs.len());
+                    pairs.push((MontyObject::String(key), value));
+                }
+            }
+        }
+        decoder.object(pairs, depth).map_err(de::Error::custom)
+    }
+}
+
+/// Estimate the heap footprint in bytes of the `MontyObject` graph that
+/// `json_to_monty_object` would build from `val`, without building it.
+///
+/// Counts one `MontyObject` slot per value and object key, plus string and
+/// big-integer payloads. Tagged values are sized as the objects they are
+/// written as. It is an approximation for budget checks, not an exact
+/// figure from the engine's allocator. Walks with an explicit stack, so
+/// deep nesting can't overflow.
+pub fn estimate_json_memory(val: &Value) -> usize {
+    let slot = std::mem::size_of::<MontyObject>();
+    let mut total = 0;
+    let mut stack = vec![val];
+    while let Some(val) = stack.pop() {
+        total += slot;
+        match val {
+            Value::Number(n) if n.as_i64().is_none() && n.as_u64().is_some() => {
+                total += std::mem::size_of::<u64>();
+            }
+            Value::String(s) => total += s.len(),
+            Value::Array(items) => stack.extend(items),
+            Value::Object(map) => {
+                for (k, v) in map {
+                    total += slot + k.len();
+                    stack.push(v);
+                }
+            }
+            _ => {}
+        }
+    }
+    total
+}
+
+fn bigint_to_json(n: &BigInt) -> Value {
+    if let Some(i) = n.to_i64() {
+        json!(i)
+    } else {
+        Value::String(n.to_string())
+    }
+}
+
+/// 2^63: integral floats below this in magnitude convert to i64 exactly.
+const I64_FLOAT_BOUND: f64 = 9_223_372_036_854_775_808.0;
+
+fn float_to_json(f: f64) -> Value {
+    if f.is_finite() {
+        Number::from_f64(f)
+            .map(Value::Number)
+            .unwrap_or(Value::Null)
+    } else if f.is_nan() {
+        Value::String("NaN".into())
+    } else if f.is_sign_positive() {
+        Value::String("Infinity".into())
+    } else {
+        Value::String("-Infinity".into())
+    }
+}
+
+fn number_to_monty_object(n: &Number) -> MontyObject {
+    // Cheapest check first: most JSON numbers are small ints.
+    if let Some(i) = n.as_i64() {
+        MontyObject::Int(i)
+    } else if let Some(u) = n.as_u64() {
+        // Only u64 values above i64::MAX reach here.
+        MontyObject::BigInt(BigInt::from(u))
+    } else {
+        MontyObject::Float(n.as_f64().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_none() {
+        assert_eq!(monty_object_to_json(&MontyObject::None), Value::Null);
+    }
+
+    #[test]
+    fn test_bool() {
+        assert_eq!(monty_object_to_json(&MontyObject::Bool(true)), json!(true));
+        assert_eq!(
+            monty_object_to_json(&MontyObject::Bool(false)),
+            json!(false)
+        );
+    }
+
+    #[test]
+    fn test_int() {
+        assert_eq!(monty_object_to_json(&MontyObject::Int(42)), json!(42));
+        assert_eq!(monty_object_to_json(&MontyObject::Int(-1)), json!(-1));
+        assert_eq!(monty_object_to_json(&MontyObject::Int(0)), json!(0));
+    }
+
+    #[test]
+    fn test_bigint_fits_i64() {
+        let n = BigInt::from(123_456_789i64);
+        assert_eq!(
+            monty_object_to_json(&MontyObject::BigInt(n)),
+            json!(123_456_789)
+        );
+    }
+
+    #[test]
+    fn test_bigint_too_large() {
+        let n = BigInt::parse_bytes(b"99999999999999999999999", 10).unwrap();
+        let val = monty_object_to_json(&MontyObject::BigInt(n.clone()));
+        assert_eq!(val, Value::String(n.to_string()));
+    }
+
+    #[test]
+    fn test_float() {
+        assert_eq!(
+            monty_object_to_json(&MontyObject::Float(3.125)),
+            json!(3.125)
+        );
+    }
+
+    #[test]
+    fn test_float_negative_zero() {
+        let val = monty_object_to_json(&MontyObject::Float(-0.0));
+        assert_eq!(serde_json::to_string(&val).unwrap(), "0.0");
+
+        let options = ConversionOptions {
+            preserve_negative_zero: true,
+            ..Default::default()
+        };
+        let val = monty_object_to_json_with(&MontyObject::Float(-0.0), &options).unwrap();
+        assert_eq!(serde_json::to_string(&val).unwrap(), "-0.0");
+        let back = json_to_monty_object(&val).unwrap();
+        assert!(matches!(back, MontyObject::Float(f) if f == 0.0 && f.is_sign_negative()));
+    }
+
+    #[test]
+    fn test_float_nan() {
+        assert_eq!(
+            monty_object_to_json(&MontyObject::Float(f64::NAN)),
+            Value::String("NaN".into())
+        );
+    }
+
+    #[test]
+    fn test_float_infinity() {
+        assert_eq!(
+            monty_object_to_json(&MontyObject::Float(f64::INFINITY)),
+            Value::String("Infinity".into())
+        );
+        assert_eq!(
+            monty_object_to_json(&MontyObject::Float(f64::NEG_INFINITY)),
+            Value::String("-Infinity".into())
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(
+            monty_object_to_json(&MontyObject::String("hello".into())),
+            json!("hello")
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        let list = MontyObject::List(vec![MontyObject::Int(1), MontyObject::Int(2)]);
+        assert_eq!(monty_object_to_json(&list), json!([1, 2]));
+    }
+
+    #[test]
+    fn test_tuple() {
+        let tuple = MontyObject::Tuple(vec![MontyObject::Bool(true), MontyObject::None]);
+        assert_eq!(monty_object_to_json(&tuple), json!([true, null]));
+    }
+
+    #[test]
+    fn test_dict_string_keys() {
+        let pairs = vec![
+            (MontyObject::String("a".into()), MontyObject::Int(1)),
+            (MontyObject::String("b".into()), MontyObject::Int(2)),
+        ];
+        let dict = MontyObject::dict(pairs);
+        let val = monty_object_to_json(&dict);
+        assert_eq!(val["a"], json!(1));
+        assert_eq!(val["b"], json!(2));
+    }
+
+    #[test]
+    fn test_dict_insertion_order() {
+        let dict = MontyObject::dict(
+            ["z", "a", "m"]
+                .map(|k| (MontyObject::String(k.into()), MontyObject::None))
+                .to_vec(),
+        );
+        let val = monty_object_to_json(&dict);
+        let keys: Vec<&str> = val
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(keys, ["z", "a", "m"]);
+        assert_eq!(val.to_string(), r#"{"z":null,"a":null,"m":null}"#);
+
+        let mut out = Vec::new();
+        monty_object_to_writer(&dict, &ConversionOptions::default(), &mut out).unwrap();
+        assert_eq!(out, br#"{"z":null,"a":null,"m":null}"#);
+        assert_eq!(json_to_monty_object(&val).unwrap(), dict);
+    }
+
+    #[test]
+    fn test_dict_non_string_keys() {
+        let pairs = vec![
+            (MontyObject::Int(1), MontyObject::String("a".into())),
+            (MontyObject::Int(2), MontyObject::String("b".into())),
+        ];
+        let dict = MontyObject::dict(pairs);
+        let val = monty_object_to_json(&dict);
+        assert_eq!(val, json!([[1, "a"], [2, "b"]]));
+    }
+
+    #[test]
+    fn test_set() {
+        let set = MontyObject::Set(vec![MontyObject::Int(1), MontyObject::Int(2)]);
+        assert_eq!(monty_object_to_json(&set), json!([1, 2]));
+    }
+
+    #[test]
+    fn test_ellipsis() {
+        assert_eq!(monty_object_to_json(&MontyObject::Ellipsis), json!("..."));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let bytes = MontyObject::Bytes(vec![72, 105]);
+        assert_eq!(monty_object_to_json(&bytes), json!([72, 105]));
+    }
+
+    // Round-trip tests
+    #[test]
+    fn test_round_trip_null() {
+        let original = MontyObject::None;
+        let json = monty_object_to_json(&original);
+        let back = json_to_monty_object(&json).unwrap();
+        assert!(matches!(back, MontyObject::None));
+    }
+
+    #[test]
+    fn test_round_trip_bool() {
+        let json = monty_object_to_json(&MontyObject::Bool(true));
+        let back = json_to_monty_object(&json).unwrap();
+        assert!(matches!(back, MontyObject::Bool(true)));
+    }
+
+    #[test]
+    fn test_round_trip_int() {
+        let json = monty_object_to_json(&MontyObject::Int(42));
+        let back = json_to_monty_object(&json).unwrap();
+        assert!(matches!(back, MontyObject::Int(42)));
+    }
+
+    #[test]
+    fn test_json_number_variants() {
+        assert_eq!(
+            json_to_monty_object(&json!(-7)).unwrap(),
+            MontyObject::Int(-7)
+        );
+        assert_eq!(
+            json_to_monty_object(&json!(i64::MAX)).unwrap(),
+            MontyObject::Int(i64::MAX)
+        );
+        assert_eq!(
+            json_to_monty_object(&json!(u64::MAX)).unwrap(),
+            MontyObject::BigInt(BigInt::from(u64::MAX))
+        );
+        // Integer literals beyond u64 decode exactly from JSON text; a
+        // `Value` has already rounded them to f64.
+        let digits = "-1234567890123456789012345";
+        let exact = MontyObject::BigInt(digits.parse().unwrap());
+        let read =
+            |json: &str| monty_object_from_json_reader(json.as_bytes(), &DecodeOptions::TAGGED);
+        assert_eq!(read(digits).unwrap(), exact);
+        let big = MontyObject::BigInt("98765432109876543210".parse().unwrap());
+        assert_eq!(
+            read(r#"{"a": "12", "b": [98765432109876543210, 1e30, "3"]}"#).unwrap(),
+            MontyObject::dict(vec![
+                (
+                    MontyObject::String("a".into()),
+                    MontyObject::String("12".into())
+                ),
+                (
+                    MontyObject::String("b".into()),
+                    MontyObject::List(vec![
+                        big,
+                        MontyObject::Float(1e30),
+                        MontyObject::String("3".into()),
+                    ]),
+                ),
+            ])
+        );
+        let val: Value = serde_json::from_str(digits).unwrap();
+        assert_eq!(
+            json_to_monty_object(&val).unwrap(),
+            MontyObject::Float(-1.2345678901234568e24)
+        );
+        assert_eq!(
+            monty_object_from_json_reader(&b"[7, 2.5, 1e3]"[..], &DecodeOptions::TAGGED).unwrap(),
+            MontyObject::List(vec![
+                MontyObject::Int(7),
+                MontyObject::Float(2.5),
+                MontyObject::Float(1000.0),
+            ])
+        );
+        assert_eq!(
+            json_to_monty_object(&json!(1.5)).unwrap(),
+            MontyObject::Float(1.5)
+        );
+    }
+
+    /// Micro-benchmark for the resume decode path; run with
+    /// `cargo test --release -- --ignored --nocapture bench_json_to_monty_object`.
+    #[test]
+    #[ignore]
+    fn bench_json_to_monty_object() {
+        let items: Vec<Value> = (0..1_000_000)
+            .map(|i| if i % 2 == 0 { json!(i) } else { json!("item") })
+            .collect();
+        let value = Value::Array(items);
+
+        let start = std::time::Instant::now();
+        for _ in 0..10 {
+            std::hint::black_box(json_to_monty_object(std::hint::black_box(&value)).unwrap());
+        }
+        println!(
+            "json_to_monty_object: {:?} per 1M-element array",
+            start.elapsed() / 10
+        );
+    }
+
+    #[test]
+    fn test_round_trip_string() {
+        let json = monty_object_to_json(&MontyObject::String("hello".into()));
+        let back = json_to_monty_object(&json).unwrap();
+        assert!(matches!(back, MontyObject::String(ref s) if s == "hello"));
+    }
+
+    #[test]
+    fn test_round_trip_list() {
+        let list = MontyObject::List(vec![MontyObject::Int(1), MontyObject::None]);
+        let json = monty_object_to_json(&list);
+        let back = json_to_monty_object(&json).unwrap();
+        match back {
+            MontyObject::List(items) => {
+                assert_eq!(items.len(), 2);
+                assert!(matches!(items[0], MontyObject::Int(1)));
+                assert!(matches!(items[1], MontyObject::None));
+            }
+            _ => panic!("expected list"),
+        }
+    }
+
+    #[test]
+    fn test_json_to_monty_object_object() {
+        let val = json!({"key": "value"});
+        let obj = json_to_monty_object(&val).unwrap();
+        match obj {
+            MontyObject::Dict(pairs) => {
+                let items: Vec<_> = pairs.into_iter().collect::<Vec<_>>();
+                assert_eq!(items.len(), 1);
+            }
+            _ => panic!("expected dict"),
+        }
+    }
+
+    #[test]
+    fn test_named_tuple() {
+        let nt = MontyObject::NamedTuple {
+            type_name: "Point".into(),
+            field_names: vec!["x".into(), "y".into()],
+            values: vec![MontyObject::Int(1), MontyObject::Int(2)],
+        };
+        assert_eq!(monty_object_to_json(&nt), json!([1, 2]));
+    }
+
+    #[test]
+    fn test_path() {
+        let p = MontyObject::Path("/tmp/foo".into());
+        assert_eq!(monty_object_to_json(&p), json!("/tmp/foo"));
+    }
+
+    #[test]
+    fn test_dataclass() {
+        let dc = MontyObject::Dataclass {
+            name: "MyClass".into(),
+            type_id: 1,
+            field_names: vec!["a".into()],
+            attrs: vec![(MontyObject::String("a".into()), MontyObject::Int(42))].into(),
+            frozen: false,
+        };
+        let val = monty_object_to_json(&dc);
+        assert_eq!(val["a"], json!(42));
+    }
+
+    #[test]
+    fn test_exception_with_arg() {
+        let exc = MontyObject::Exception {
+            exc_type: monty::ExcType::ValueError,
+            arg: Some("bad value".into()),
+        };
+        assert_eq!(
+            monty_object_to_json(&exc),
+            Value::String("ValueError: bad value".into())
+        );
+    }
+
+    #[test]
+    fn test_exception_no_arg() {
+        let exc = MontyObject::Exception {
+            exc_type: monty::ExcType::RuntimeError,
+            arg: None,
+        };
+        assert_eq!(
+            monty_object_to_json(&exc),
+            Value::String("RuntimeError".into())
+        );
+    }
+
+    #[test]
+    fn test_repr() {
+        let r = MontyObject::Repr("<object at 0x123>".into());
+        assert_eq!(
+            monty_object_to_json(&r),
+            Value::String("<object at 0x123>".into())
+        );
+    }
+
+    #[test]
+    fn test_frozen_set() {
+        let fs = MontyObject::FrozenSet(vec![MontyObject::Int(3), MontyObject::Int(4)]);
+        assert_eq!(monty_object_to_json(&fs), json!([3, 4]));
+    }
+
+    fn encode_default(obj: &MontyObject) -> (Value, bool) {
+        let mut out = Vec::new();
+        let js_safe = monty_object_to_writer(obj, &ConversionOptions::default(), &mut out).unwrap();
+        (serde_json::from_slice(&out).unwrap(), js_safe)
+    }
+
+    #[test]
+    fn test_js_safe_plain_value() {
+        let list = MontyObject::List(vec![MontyObject::Int(1), MontyObject::Float(2.5)]);
+        let (val, js_safe) = encode_default(&list);
+        assert_eq!(val, json!([1, 2.5]));
+        assert!(js_safe);
+    }
+
+    #[test]
+    fn test_js_safe_large_int() {
+        let n = BigInt::parse_bytes(b"99999999999999999999999", 10).unwrap();
+        let (_, js_safe) = encode_default(&MontyObject::BigInt(n));
+        assert!(!js_safe);
+
+        let (_, js_safe) = encode_default(&MontyObject::Int(1 << 53));
+        assert!(!js_safe);
+        let (_, js_safe) = encode_default(&MontyObject::Int((1 << 53) - 1));
+        assert!(js_safe);
+    }
+
+    #[test]
+    fn test_js_safe_nested_nan() {
+        let pairs = vec![(
+            MontyObject::String("x".into()),
+            MontyObject::List(vec![MontyObject::Float(f64::NAN)]),
+        )];
+        let (_, js_safe) = encode_default(&MontyObject::dict(pairs));
+        assert!(!js_safe);
+    }
+
+    #[test]
+    fn test_writer_matches_value_tree() {
+        let mut obj = MontyObject::List(vec![MontyObject::Int(1), MontyObject::Float(f64::NAN)]);
+        for i in 0..100 {
+            let leaf = MontyObject::Tuple(vec![
+                MontyObject::BigInt(BigInt::from(u64::MAX)),
+                MontyObject::Bytes(vec![1, 2]),
+                MontyObject::Set(vec![MontyObject::String("s".into())]),
+            ]);
+            obj = if i % 2 == 0 {
+                MontyObject::dict(vec![
+                    (MontyObject::String(format!("k{i}")), obj),
+                    (MontyObject::String("leaf".into()), leaf),
+                ])
+            } else {
+                MontyObject::dict(vec![(MontyObject::Int(i), obj), (MontyObject::None, leaf)])
+            };
+        }
+
+        let typed = ConversionOptions {
+            typed_containers: true,
+            max_collection_width: Some(1),
+            ..ConversionOptions::default()
+        };
+        for options in [ConversionOptions::default(), typed] {
+            let tree = monty_object_to_json_with(&obj, &options).unwrap();
+            let mut out = Vec::new();
+            monty_object_to_writer(&obj, &options, &mut out).unwrap();
+            // Nested past serde_json's default 128-level parse limit.
+            let mut de = serde_json::Deserializer::from_slice(&out);
+            de.disable_recursion_limit();
+            assert_eq!(Value::deserialize(&mut de).unwrap(), tree);
+        }
+
+        let shallow = ConversionOptions {
+            max_depth: 10,
+            ..ConversionOptions::default()
+        };
+        let err = monty_object_to_json_with(&obj, &shallow).unwrap_err();
+        let writer_err = monty_object_to_writer(&obj, &shallow, Vec::new()).unwrap_err();
+        assert_eq!(err, writer_err);
+        assert!(err.contains("conversion depth exceeded"));
+    }
+
+    #[test]
+    fn test_json_to_monty_float() {
+        let val = json!(3.125);
+        let obj = json_to_monty_object(&val).unwrap();
+        match obj {
+            MontyObject::Float(f) => assert!((f - 3.125).abs() < f64::EPSILON),
+            _ => panic!("expected Float"),
+        }
+    }
+
+    #[test]
+    fn test_max_string_length() {
+        let options = ConversionOptions {
+            max_string_length: Some(5),
+            ..Default::default()
+        };
+        let at_cap = MontyObject::String("héllo".into());
+        assert_eq!(
+            monty_object_to_json_with(&at_cap, &options).unwrap(),
+            json!("héllo")
+        );
+
+        let nested = MontyObject::List(vec![MontyObject::String("abcdef".into())]);
+        let err = monty_object_to_json_with(&nested, &options).unwrap_err();
+        assert_eq!(err, "string of 6 characters exceeds max_string_length of 5");
+
+        let key = MontyObject::dict(vec![(
+            MontyObject::String("long_key".into()),
+            MontyObject::Int(1),
+        )]);
+        assert!(monty_object_to_json_with(&key, &options).is_err());
+    }
+
+    #[test]
+    fn test_max_collection_width() {
+        let options = ConversionOptions {
+            max_collection_width: Some(2),
+            ..Default::default()
+        };
+        let encode = |obj: &MontyObject| monty_object_to_json_with(obj, &options).unwrap();
+
+        let list = MontyObject::List((0..5).map(MontyObject::Int).collect());
+        assert_eq!(encode(&list), json!([0, 1, {"__monty_elided__": 3}]));
+
+        let fits = MontyObject::Tuple(vec![MontyObject::Int(1), MontyObject::Int(2)]);
+        assert_eq!(encode(&fits), json!([1, 2]));
+
+        let dict = MontyObject::dict(
+            ["a", "b", "c"]
+                .map(|k| (MontyObject::String(k.into()), MontyObject::None))
+                .to_vec(),
+        );
+        assert_eq!(
+            encode(&dict),
+            json!({"a": null, "b": null, "__monty_elided__": 1})
+        );
+
+        let int_keys = MontyObject::dict(
+            (0..3)
+                .map(|i| (MontyObject::Int(i), MontyObject::Int(i)))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            encode(&int_keys),
+            json!([[0, 0], [1, 1], {"__monty_elided__": 1}])
+        );
+    }
+
+    #[test]
+    fn test_typed_containers_tuple() {
+        let typed = ConversionOptions {
+            typed_containers: true,
+            ..Default::default()
+        };
+        let obj = MontyObject::Tuple(vec![
+            MontyObject::List(vec![MontyObject::Tuple(vec![
+                MontyObject::Int(1),
+                MontyObject::Int(2),
+            ])]),
+            MontyObject::List(vec![]),
+        ]);
+
+        assert_eq!(monty_object_to_json(&obj), json!([[[1, 2]], []]));
+
+        let val = monty_object_to_json_with(&obj, &typed).unwrap();
+        assert_eq!(val, json!({"__tuple__": [[{"__tuple__": [1, 2]}], []]}));
+        assert_eq!(json_to_monty_object(&val).unwrap(), obj);
+
+        let text = val.to_string();
+        assert_eq!(
+            monty_object_from_json_reader(text.as_bytes(), &DecodeOptions::TAGGED).unwrap(),
+            obj
+        );
+    }
 
     #[test]
-    fn test_none() {
-        assert_eq!(monty_object_to_json(&MontyObject::None), Value::Null);
+    fn test_typed_containers_sets() {
+        let typed = ConversionOptions {
+            typed_containers: true,
+            ..Default::default()
+        };
+        let set = MontyObject::Set(vec![MontyObject::Int(1), MontyObject::Int(2)]);
+        let frozen = MontyObject::FrozenSet(vec![MontyObject::String("a".into())]);
+
+        assert_eq!(monty_object_to_json(&set), json!([1, 2]));
+        let val = monty_object_to_json_with(&set, &typed).unwrap();
+        assert_eq!(val, json!({"__set__": [1, 2]}));
+        assert_eq!(json_to_monty_object(&val).unwrap(), set);
+
+        let val = monty_object_to_json_with(&frozen, &typed).unwrap();
+        assert_eq!(val, json!({"__frozenset__": ["a"]}));
+        assert_eq!(json_to_monty_object(&val).unwrap(), frozen);
     }
 
     #[test]
-    fn test_bool() {
-        assert_eq!(monty_object_to_json(&MontyObject::Bool(true)), json!(true));
+    fn test_tagged_set_dedups_and_empty() {
         assert_eq!(
-            monty_object_to_json(&MontyObject::Bool(false)),
-            json!(false)
+            json_to_monty_object(&json!({"__set__": [1, 1, 2]})).unwrap(),
+            MontyObject::Set(vec![MontyObject::Int(1), MontyObject::Int(2)])
+        );
+        assert_eq!(
+            monty_object_from_json_reader(
+                &br#"{"__frozenset__": [1, 1, 2]}"#[..],
+                &DecodeOptions::TAGGED
+            )
+            .unwrap(),
+            MontyObject::FrozenSet(vec![MontyObject::Int(1), MontyObject::Int(2)])
+        );
+
+        assert_eq!(
+            json_to_monty_object(
+                &json!({"__set__": [[1], [1], 0.0, -0.0, {"__tuple__": [1]}, {"__tuple__": [1]}]})
+            )
+            .unwrap(),
+            MontyObject::Set(vec![
+                MontyObject::List(vec![MontyObject::Int(1)]),
+                MontyObject::Float(0.0),
+                MontyObject::Tuple(vec![MontyObject::Int(1)]),
+            ])
         );
+
+        let many: Vec<i64> = (0..50_000).chain(0..50_000).collect();
+        let MontyObject::Set(items) = json_to_monty_object(&json!({"__set__": many})).unwrap()
+        else {
+            panic!("expected a set");
+        };
+        assert_eq!(items.len(), 50_000);
+
+        let empty = json_to_monty_object(&json!({"__set__": []})).unwrap();
+        assert_eq!(empty, MontyObject::Set(vec![]));
+        assert_ne!(empty, json_to_monty_object(&json!([])).unwrap());
     }
 
     #[test]
-    fn test_int() {
-        assert_eq!(monty_object_to_json(&MontyObject::Int(42)), json!(42));
-        assert_eq!(monty_object_to_json(&MontyObject::Int(-1)), json!(-1));
-        assert_eq!(monty_object_to_json(&MontyObject::Int(0)), json!(0));
+    fn test_typed_containers_bytes() {
+        let typed = ConversionOptions {
+            typed_containers: true,
+            ..Default::default()
+        };
+        let bytes = MontyObject::Bytes(vec![0x00, 0xff, 0xe2, 0x82, 0xac]);
+        assert_eq!(monty_object_to_json(&bytes), json!([0, 255, 226, 130, 172]));
+
+        let val = monty_object_to_json_with(&bytes, &typed).unwrap();
+        assert_eq!(val, json!({"__bytes__": "AP/igqw="}));
+        assert_eq!(json_to_monty_object(&val).unwrap(), bytes);
+        assert_eq!(
+            monty_object_from_json_reader(val.to_string().as_bytes(), &DecodeOptions::TAGGED)
+                .unwrap(),
+            bytes
+        );
+
+        let empty = MontyObject::Bytes(vec![]);
+        let val = monty_object_to_json_with(&empty, &typed).unwrap();
+        assert_eq!(val, json!({"__bytes__": ""}));
+        assert_eq!(json_to_monty_object(&val).unwrap(), empty);
     }
 
     #[test]
-    fn test_bigint_fits_i64() {
-        let n = BigInt::from(123_456_789i64);
+    fn test_bytes_tag_int_list_and_validation() {
         assert_eq!(
-            monty_object_to_json(&MontyObject::BigInt(n)),
-            json!(123_456_789)
+            json_to_monty_object(&json!({"__bytes__": [104, 105]})).unwrap(),
+            MontyObject::Bytes(b"hi".to_vec())
+        );
+
+        let err = json_to_monty_object(&json!({"__bytes__": [1, 256]})).unwrap_err();
+        assert!(err.contains("out of range 0-255: 256"), "{err}");
+        let err = json_to_monty_object(&json!({"__bytes__": [-1]})).unwrap_err();
+        assert!(err.contains("out of range"), "{err}");
+        let err = json_to_monty_object(&json!([{"__bytes__": "not base64!"}])).unwrap_err();
+        assert!(err.contains("base64"), "{err}");
+        assert!(
+            monty_object_from_json_reader(&br#"{"__bytes__": 7}"#[..], &DecodeOptions::TAGGED)
+                .is_err()
         );
     }
 
     #[test]
-    fn test_bigint_too_large() {
-        let n = BigInt::parse_bytes(b"99999999999999999999999", 10).unwrap();
-        let val = monty_object_to_json(&MontyObject::BigInt(n.clone()));
-        assert_eq!(val, Value::String(n.to_string()));
+    fn test_typed_non_finite_floats() {
+        let typed = ConversionOptions {
+            typed_containers: true,
+            ..Default::default()
+        };
+        for (f, tag) in [
+            (f64::INFINITY, "Infinity"),
+            (f64::NEG_INFINITY, "-Infinity"),
+        ] {
+            let val = monty_object_to_json_with(&MontyObject::Float(f), &typed).unwrap();
+            assert_eq!(val, json!({"__float__": tag}));
+            assert_eq!(json_to_monty_object(&val).unwrap(), MontyObject::Float(f));
+        }
+        let val = monty_object_to_json_with(&MontyObject::Float(f64::NAN), &typed).unwrap();
+        assert_eq!(val, json!({"__float__": "NaN"}));
+        assert!(matches!(json_to_monty_object(&val).unwrap(), MontyObject::Float(f) if f.is_nan()));
+
+        let val = monty_object_to_json_with(&MontyObject::Float(1.5), &typed).unwrap();
+        assert_eq!(val, json!(1.5));
     }
 
     #[test]
-    fn test_float() {
+    fn test_integral_float_policy() {
+        let encode = |f: f64, policy| {
+            let options = ConversionOptions {
+                integral_float_policy: policy,
+                ..Default::default()
+            };
+            serde_json::to_string(
+                &monty_object_to_json_with(&MontyObject::Float(f), &options).unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(encode(3.0, IntegralFloatPolicy::AsNumber), "3.0");
+        assert_eq!(encode(3.5, IntegralFloatPolicy::AsNumber), "3.5");
         assert_eq!(
-            monty_object_to_json(&MontyObject::Float(3.125)),
-            json!(3.125)
+            encode(3.0, IntegralFloatPolicy::Preserve),
+            r#"{"__float__":3.0}"#
         );
-    }
+        assert_eq!(encode(3.5, IntegralFloatPolicy::Preserve), "3.5");
+        assert_eq!(encode(3.0, IntegralFloatPolicy::AsInt), "3");
+        assert_eq!(encode(3.5, IntegralFloatPolicy::AsInt), "3.5");
+        // Too large for an int, so it stays a float (serde_json picks the
+        // exponent form).
+        let huge = encode(1e300, IntegralFloatPolicy::AsInt);
+        assert_eq!(
+            serde_json::from_str::<Value>(&huge).unwrap().as_f64(),
+            Some(1e300)
+        );
+        assert!(huge.contains('e'), "{huge}");
 
-    #[test]
-    fn test_float_nan() {
+        let tagged: Value = serde_json::from_str(r#"{"__float__":3.0}"#).unwrap();
         assert_eq!(
-            monty_object_to_json(&MontyObject::Float(f64::NAN)),
-            Value::String("NaN".into())
+            json_to_monty_object(&tagged).unwrap(),
+            MontyObject::Float(3.0)
         );
     }
 
     #[test]
-    fn test_float_infinity() {
+    fn test_float_tag_decoding() {
         assert_eq!(
-            monty_object_to_json(&MontyObject::Float(f64::INFINITY)),
-            Value::String("Infinity".into())
+            json_to_monty_object(&json!({"__float__": 2})).unwrap(),
+            MontyObject::Float(2.0)
         );
         assert_eq!(
-            monty_object_to_json(&MontyObject::Float(f64::NEG_INFINITY)),
-            Value::String("-Infinity".into())
+            json_to_monty_object(&json!("Infinity")).unwrap(),
+            MontyObject::String("Infinity".into())
         );
+        let err = json_to_monty_object(&json!({"__float__": "inf"})).unwrap_err();
+        assert!(err.contains("invalid __float__ value"), "{err}");
     }
 
     #[test]
-    fn test_string() {
+    fn test_typed_bigint_round_trip() {
+        let typed = ConversionOptions {
+            typed_containers: true,
+            ..Default::default()
+        };
+        for digits in ["999999999999999999999", "-999999999999999999999"] {
+            let obj = MontyObject::BigInt(BigInt::parse_bytes(digits.as_bytes(), 10).unwrap());
+            assert_eq!(monty_object_to_json(&obj), json!(digits));
+            let val = monty_object_to_json_with(&obj, &typed).unwrap();
+            assert_eq!(val, json!({"__bigint__": digits}));
+            assert_eq!(json_to_monty_object(&val).unwrap(), obj);
+        }
+
+        let small = MontyObject::BigInt(BigInt::from(7));
+        assert_eq!(monty_object_to_json_with(&small, &typed).unwrap(), json!(7));
         assert_eq!(
-            monty_object_to_json(&MontyObject::String("hello".into())),
-            json!("hello")
+            json_to_monty_object(&json!({"__bigint__": "7"})).unwrap(),
+            MontyObject::Int(7)
         );
     }
 
     #[test]
-    fn test_list() {
-        let list = MontyObject::List(vec![MontyObject::Int(1), MontyObject::Int(2)]);
-        assert_eq!(monty_object_to_json(&list), json!([1, 2]));
+    fn test_bigint_tag_rejects_malformed_digits() {
+        for bad in [json!("12a"), json!(""), json!("-"), json!("+5"), json!(5)] {
+            let err = json_to_monty_object(&json!({"__bigint__": bad})).unwrap_err();
+            assert!(err.contains("__bigint__"), "{err}");
+        }
     }
 
     #[test]
-    fn test_tuple() {
-        let tuple = MontyObject::Tuple(vec![MontyObject::Bool(true), MontyObject::None]);
-        assert_eq!(monty_object_to_json(&tuple), json!([true, null]));
-    }
+    fn test_typed_dict_non_string_keys() {
+        let typed = ConversionOptions {
+            typed_containers: true,
+            ..Default::default()
+        };
+        let obj = MontyObject::dict(vec![
+            (MontyObject::Int(1), MontyObject::String("one".into())),
+            (MontyObject::String("a".into()), MontyObject::Int(2)),
+            (
+                MontyObject::Tuple(vec![MontyObject::Int(1), MontyObject::Int(2)]),
+                MontyObject::None,
+            ),
+        ]);
+        assert_eq!(
+            monty_object_to_json(&obj),
+            json!([[1, "one"], ["a", 2], [[1, 2], null]])
+        );
+        let val = monty_object_to_json_with(&obj, &typed).unwrap();
+        assert_eq!(
+            val,
+            json!({"__dict__": [[1, "one"], ["a", 2], [{"__tuple__": [1, 2]}, null]]})
+        );
+        assert_eq!(json_to_monty_object(&val).unwrap(), obj);
 
-    #[test]
-    fn test_dict_string_keys() {
-        let pairs = vec![
-            (MontyObject::String("a".into()), MontyObject::Int(1)),
-            (MontyObject::String("b".into()), MontyObject::Int(2)),
-        ];
-        let dict = MontyObject::dict(pairs);
-        let val = monty_object_to_json(&dict);
-        assert_eq!(val["a"], json!(1));
-        assert_eq!(val["b"], json!(2));
+        let string_keyed =
+            MontyObject::dict(vec![(MontyObject::String("a".into()), MontyObject::Int(1))]);
+        assert_eq!(
+            monty_object_to_json_with(&string_keyed, &typed).unwrap(),
+            json!({"a": 1})
+        );
     }
 
     #[test]
-    fn test_dict_non_string_keys() {
-        let pairs = vec![
+    fn test_stringify_int_keys() {
+        let stringify = ConversionOptions {
+            stringify_int_keys: true,
+            ..Default::default()
+        };
+        let int_keyed = MontyObject::dict(vec![
             (MontyObject::Int(1), MontyObject::String("a".into())),
-            (MontyObject::Int(2), MontyObject::String("b".into())),
-        ];
-        let dict = MontyObject::dict(pairs);
-        let val = monty_object_to_json(&dict);
-        assert_eq!(val, json!([[1, "a"], [2, "b"]]));
+            (MontyObject::String("b".into()), MontyObject::Int(2)),
+        ]);
+        assert_eq!(
+            monty_object_to_json(&int_keyed),
+            json!([[1, "a"], ["b", 2]])
+        );
+        let val = monty_object_to_json_with(&int_keyed, &stringify).unwrap();
+        assert_eq!(
+            val,
+            json!({"1": "a", "b": 2, "__key_types__": {"1": "int"}})
+        );
+        assert_eq!(json_to_monty_object(&val).unwrap(), int_keyed);
+
+        let bool_keyed = MontyObject::dict(vec![
+            (MontyObject::Bool(true), MontyObject::Int(1)),
+            (MontyObject::Bool(false), MontyObject::Int(0)),
+        ]);
+        assert_eq!(
+            monty_object_to_json(&bool_keyed),
+            json!([[true, 1], [false, 0]])
+        );
+        let val = monty_object_to_json_with(&bool_keyed, &stringify).unwrap();
+        assert_eq!(
+            val,
+            json!({"true": 1, "false": 0, "__key_types__": {"true": "bool", "false": "bool"}})
+        );
+        assert_eq!(json_to_monty_object(&val).unwrap(), bool_keyed);
+
+        // Keys that collide once stringified keep the pairs form.
+        let colliding = MontyObject::dict(vec![
+            (MontyObject::Int(1), MontyObject::None),
+            (MontyObject::String("1".into()), MontyObject::None),
+        ]);
+        assert_eq!(
+            monty_object_to_json_with(&colliding, &stringify).unwrap(),
+            json!([[1, null], ["1", null]])
+        );
     }
 
     #[test]
-    fn test_set() {
-        let set = MontyObject::Set(vec![MontyObject::Int(1), MontyObject::Int(2)]);
-        assert_eq!(monty_object_to_json(&set), json!([1, 2]));
+    fn test_key_types_rejects_malformed_entries() {
+        let stringify = ConversionOptions {
+            stringify_int_keys: true,
+            ..Default::default()
+        }
+        .decode_options(None);
+        for bad in [
+            json!({"x": 1, "__key_types__": 5}),
+            json!({"x": 1, "__key_types__": {"x": "int"}}),
+            json!({"x": 1, "__key_types__": {"x": "float"}}),
+            json!({"x": 1, "__key_types__": {"x": "bool"}}),
+        ] {
+            let err = json_to_monty_object_with(&bad, &stringify).unwrap_err();
+            assert!(err.contains("__key_types__"), "{err}");
+            // Without stringify_int_keys the entry is an ordinary key.
+            let plain = ConversionOptions::default().decode_options(None);
+            assert!(json_to_monty_object_with(&bad, &plain).is_ok());
+        }
     }
 
     #[test]
-    fn test_ellipsis() {
-        assert_eq!(monty_object_to_json(&MontyObject::Ellipsis), json!("..."));
+    fn test_dict_tag_rejects_malformed_pairs() {
+        for bad in [json!(1), json!([[1]]), json!([[1, 2, 3]]), json!([5])] {
+            let err = json_to_monty_object(&json!({"__dict__": bad})).unwrap_err();
+            assert!(err.contains("__dict__"), "{err}");
+        }
     }
 
     #[test]
-    fn test_bytes() {
-        let bytes = MontyObject::Bytes(vec![72, 105]);
-        assert_eq!(monty_object_to_json(&bytes), json!([72, 105]));
+    fn test_typed_named_types() {
+        let typed = ConversionOptions {
+            typed_containers: true,
+            ..Default::default()
+        };
+        let point = MontyObject::Dataclass {
+            name: "Point".into(),
+            type_id: 1,
+            field_names: vec!["x".into(), "y".into()],
+            attrs: vec![
+                (MontyObject::String("x".into()), MontyObject::Int(1)),
+                (MontyObject::String("y".into()), MontyObject::Int(2)),
+            ]
+            .into(),
+            frozen: false,
+        };
+        assert_eq!(monty_object_to_json(&point), json!({"x": 1, "y": 2}));
+        assert_eq!(
+            monty_object_to_json_with(&point, &typed).unwrap(),
+            json!({"__dataclass__": {"name": "Point", "fields": {"x": 1, "y": 2}}})
+        );
+
+        let pair = MontyObject::NamedTuple {
+            type_name: "Pair".into(),
+            field_names: vec!["a".into(), "b".into()],
+            values: vec![MontyObject::Int(1), MontyObject::String("b".into())],
+        };
+        assert_eq!(monty_object_to_json(&pair), json!([1, "b"]));
+        assert_eq!(
+            monty_object_to_json_with(&pair, &typed).unwrap(),
+            json!({"__namedtuple__": {"name": "Pair", "values": [1, "b"]}})
+        );
     }
 
-    // Round-trip tests
     #[test]
-    fn test_round_trip_null() {
-        let original = MontyObject::None;
-        let json = monty_object_to_json(&original);
-        let back = json_to_monty_object(&json);
-        assert!(matches!(back, MontyObject::None));
+    fn test_max_depth() {
+        let nested = |levels: usize| {
+            (0..levels).fold(MontyObject::Int(1), |inner, _| {
+                MontyObject::List(vec![inner])
+            })
+        };
+        let options = ConversionOptions {
+            max_depth: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            monty_object_to_json_with(&nested(2), &options).unwrap(),
+            json!([[1]])
+        );
+        let err = monty_object_to_json_with(&nested(3), &options).unwrap_err();
+        assert_eq!(err, "conversion depth exceeded (max_depth 3)");
+
+        // Each encoded level takes several KiB of stack in debug builds, so
+        // a full `DEFAULT_MAX_DEPTH` value would overflow a test thread.
+        let options = ConversionOptions {
+            max_depth: 100,
+            ..Default::default()
+        };
+        let err = monty_object_to_json_with(&nested(600), &options).unwrap_err();
+        assert_eq!(err, "conversion depth exceeded (max_depth 100)");
+
+        let deep = (0..600).fold(json!(1), |inner, _| json!([inner]));
+        let err = json_to_monty_object(&deep).unwrap_err();
+        assert!(err.starts_with("conversion depth exceeded"));
     }
 
     #[test]
-    fn test_round_trip_bool() {
-        let json = monty_object_to_json(&MontyObject::Bool(true));
-        let back = json_to_monty_object(&json);
-        assert!(matches!(back, MontyObject::Bool(true)));
+    fn test_check_conversion_limits_matches_encoder() {
+        let nested = |levels: usize| {
+            (0..levels).fold(MontyObject::String("abcdef".into()), |inner, _| {
+                MontyObject::Tuple(vec![MontyObject::Int(1), inner])
+            })
+        };
+        let keyed = MontyObject::dict(vec![
+            (MontyObject::String("k".into()), MontyObject::Int(1)),
+            (MontyObject::String("long_key".into()), MontyObject::Int(2)),
+        ]);
+        let values = [nested(0), nested(2), nested(4), keyed];
+        for options in [
+            ConversionOptions::default(),
+            ConversionOptions {
+                max_depth: 3,
+                ..Default::default()
+            },
+            ConversionOptions {
+                max_string_length: Some(5),
+                ..Default::default()
+            },
+            ConversionOptions {
+                max_string_length: Some(5),
+                max_collection_width: Some(1),
+                ..Default::default()
+            },
+        ] {
+            for obj in &values {
+                let encoded = monty_object_to_writer(obj, &options, std::io::sink()).map(drop);
+                assert_eq!(check_conversion_limits(obj, &options), encoded, "{obj:?}");
+            }
+        }
     }
 
     #[test]
-    fn test_round_trip_int() {
-        let json = monty_object_to_json(&MontyObject::Int(42));
-        let back = json_to_monty_object(&json);
-        assert!(matches!(back, MontyObject::Int(42)));
+    fn test_decode_depth_past_serde_limit() {
+        // Deeper than serde_json's own 128-level limit, within max_depth.
+        let nested = |levels: usize| "[".repeat(levels) + &"]".repeat(levels);
+        let obj =
+            monty_object_from_json_reader(nested(300).as_bytes(), &DecodeOptions::TAGGED).unwrap();
+        let mut depth = 1;
+        let mut cur = &obj;
+        while let MontyObject::List(items) = cur
+            && let [inner] = items.as_slice()
+        {
+            depth += 1;
+            cur = inner;
+        }
+        assert_eq!(depth, 300);
+
+        let options = DecodeOptions {
+            max_depth: 200,
+            ..DecodeOptions::TAGGED
+        };
+        let err = monty_object_from_json_reader(nested(300).as_bytes(), &options).unwrap_err();
+        assert!(err.to_string().contains("max_depth 200"), "{err}");
+        let err = monty_object_from_json_reader(nested(5000).as_bytes(), &DecodeOptions::TAGGED)
+            .unwrap_err();
+        assert!(err.to_string().contains("max_depth 512"), "{err}");
     }
 
     #[test]
-    fn test_round_trip_string() {
-        let json = monty_object_to_json(&MontyObject::String("hello".into()));
-        let back = json_to_monty_object(&json);
-        assert!(matches!(back, MontyObject::String(ref s) if s == "hello"));
+    fn test_tuple_tag_with_extra_keys_stays_dict() {
+        let obj = json_to_monty_object(&json!({"__tuple__": [1], "x": 2})).unwrap();
+        assert!(matches!(obj, MontyObject::Dict(_)));
+        let obj = json_to_monty_object(&json!({"__tuple__": 1})).unwrap();
+        assert!(matches!(obj, MontyObject::Dict(_)));
     }
 
     #[test]
-    fn test_round_trip_list() {
-        let list = MontyObject::List(vec![MontyObject::Int(1), MontyObject::None]);
-        let json = monty_object_to_json(&list);
-        let back = json_to_monty_object(&json);
-        match back {
-            MontyObject::List(items) => {
-                assert_eq!(items.len(), 2);
-                assert!(matches!(items[0], MontyObject::Int(1)));
-                assert!(matches!(items[1], MontyObject::None));
-            }
-            _ => panic!("expected list"),
-        }
+    fn test_estimate_json_memory_scalars() {
+        let slot = std::mem::size_of::<MontyObject>();
+        assert_eq!(estimate_json_memory(&json!(null)), slot);
+        assert_eq!(estimate_json_memory(&json!(42)), slot);
+        assert_eq!(estimate_json_memory(&json!("hello")), slot + 5);
     }
 
     #[test]
-    fn test_json_to_monty_object_object() {
-        let val = json!({"key": "value"});
-        let obj = json_to_monty_object(&val);
-        match obj {
-            MontyObject::Dict(pairs) => {
-                let items: Vec<_> = pairs.into_iter().collect::<Vec<_>>();
-                assert_eq!(items.len(), 1);
-            }
-            _ => panic!("expected dict"),
-        }
+    fn test_estimate_json_memory_nested() {
+        let slot = std::mem::size_of::<MontyObject>();
+        // list + 2 ints
+        assert_eq!(estimate_json_memory(&json!([1, 2])), 3 * slot);
+        // dict + key "ab" + list + "xyz"
+        assert_eq!(
+            estimate_json_memory(&json!({"ab": ["xyz"]})),
+            4 * slot + 2 + 3
+        );
+        let small = estimate_json_memory(&json!({"a": [1]}));
+        let large = estimate_json_memory(&json!({"a": [1, 2, 3, 4], "b": "long string"}));
+        assert!(large > small);
+
+        // Nesting far past `max_depth` is still measured.
+        let deep = (0..600).fold(json!(1), |inner, _| json!([inner]));
+        assert_eq!(estimate_json_memory(&deep), 601 * slot);
     }
 
     #[test]
-    fn test_named_tuple() {
-        let nt = MontyObject::NamedTuple {
-            type_name: "Point".into(),
-            field_names: vec!["x".into(), "y".into()],
-            values: vec![MontyObject::Int(1), MontyObject::Int(2)],
+    fn test_is_missing_sentinel() {
+        let options = ConversionOptions::default().decode_options(None);
+        let missing = |v: Value| {
+            matches!(
+                host_value_from_json(&v, &options).unwrap(),
+                HostValue::Missing
+            )
         };
-        assert_eq!(monty_object_to_json(&nt), json!([1, 2]));
-    }
+        assert!(missing(json!({"__monty_type__": "missing"})));
+        assert!(!missing(json!(null)));
+        assert!(!missing(json!({"__monty_type__": "other"})));
+        assert!(!missing(json!({"__monty_type__": "missing", "extra": 1})));
+        assert!(!missing(json!([{"__monty_type__": "missing"}])));
 
-    #[test]
-    fn test_path() {
-        let p = MontyObject::Path("/tmp/foo".into());
-        assert_eq!(monty_object_to_json(&p), json!("/tmp/foo"));
+        // Under typed containers the `__dict__` form passes the dict itself.
+        let escaped = json!({"__dict__": [["__monty_type__", "missing"]]});
+        let HostValue::Value(obj) = host_value_from_json(&escaped, &DecodeOptions::TAGGED).unwrap()
+        else {
+            panic!("escaped sentinel decoded as missing");
+        };
+        assert_eq!(
+            obj,
+            MontyObject::dict(vec![(
+                MontyObject::String("__monty_type__".into()),
+                MontyObject::String("missing".into()),
+            )])
+        );
+        let reader =
+            host_value_from_json_reader(&br#"{"__monty_type__": "missing"}"#[..], &options);
+        assert!(matches!(reader.unwrap(), HostValue::Missing));
     }
 
     #[test]
-    fn test_dataclass() {
-        let dc = MontyObject::Dataclass {
-            name: "MyClass".into(),
-            type_id: 1,
-            field_names: vec!["a".into()],
-            attrs: vec![(MontyObject::String("a".into()), MontyObject::Int(42))].into(),
-            frozen: false,
+    fn test_tags_ignored_when_disabled() {
+        let plain = ConversionOptions::default().decode_options(None);
+        for val in [
+            json!({"__tuple__": [1, 2]}),
+            json!({"__set__": [1, 1]}),
+            json!({"__bytes__": "not base64!"}),
+            json!({"__bigint__": 5}),
+            json!({"__float__": "inf"}),
+            json!({"__dict__": 1}),
+            json!({"x": 1, "__key_types__": {"x": "int"}}),
+            json!({"x": 1, "__key_types__": 5}),
+        ] {
+            let obj = json_to_monty_object_with(&val, &plain).unwrap();
+            assert!(matches!(obj, MontyObject::Dict(_)), "{val}");
+            let back = monty_object_to_json_with(&obj, &ConversionOptions::default()).unwrap();
+            assert_eq!(back, val);
+            let text = val.to_string();
+            assert_eq!(
+                monty_object_from_json_reader(text.as_bytes(), &plain).unwrap(),
+                obj
+            );
+        }
+
+        // `__float__` alone is read under the Preserve policy.
+        let preserve = ConversionOptions {
+            integral_float_policy: IntegralFloatPolicy::Preserve,
+            ..Default::default()
         };
-        let val = monty_object_to_json(&dc);
-        assert_eq!(val["a"], json!(42));
+        let obj =
+            json_to_monty_object_with(&json!({"__float__": 3.0}), &preserve.decode_options(None))
+                .unwrap();
+        assert_eq!(obj, MontyObject::Float(3.0));
     }
 
     #[test]
-    fn test_exception_with_arg() {
-        let exc = MontyObject::Exception {
-            exc_type: monty::ExcType::ValueError,
-            arg: Some("bad value".into()),
+    fn test_tag_keyed_dicts_round_trip() {
+        let typed = ConversionOptions {
+            typed_containers: true,
+            ..Default::default()
         };
+        let dict = MontyObject::dict(vec![(
+            MontyObject::String("__tuple__".into()),
+            MontyObject::List(vec![MontyObject::Int(1)]),
+        )]);
+        let val = monty_object_to_json_with(&dict, &typed).unwrap();
+        assert_eq!(val, json!({"__dict__": [["__tuple__", [1]]]}));
         assert_eq!(
-            monty_object_to_json(&exc),
-            Value::String("ValueError: bad value".into())
+            json_to_monty_object_with(&val, &typed.decode_options(None)).unwrap(),
+            dict
+        );
+
+        let stringify = ConversionOptions {
+            stringify_int_keys: true,
+            ..Default::default()
+        };
+        let dict = MontyObject::dict(vec![
+            (
+                MontyObject::String("__key_types__".into()),
+                MontyObject::Int(1),
+            ),
+            (MontyObject::Int(2), MontyObject::Int(3)),
+        ]);
+        let val = monty_object_to_json_with(&dict, &stringify).unwrap();
+        assert_eq!(val, json!([["__key_types__", 1], [2, 3]]));
+
+        let width = ConversionOptions {
+            max_collection_width: Some(1),
+            ..Default::default()
+        };
+        let dict = MontyObject::dict(vec![
+            (
+                MontyObject::String("__monty_elided__".into()),
+                MontyObject::Int(1),
+            ),
+            (MontyObject::String("b".into()), MontyObject::Int(2)),
+        ]);
+        let val = monty_object_to_json_with(&dict, &width).unwrap();
+        assert_eq!(
+            val,
+            json!([["__monty_elided__", 1], {"__monty_elided__": 1}])
         );
     }
 
     #[test]
-    fn test_exception_no_arg() {
-        let exc = MontyObject::Exception {
-            exc_type: monty::ExcType::RuntimeError,
-            arg: None,
-        };
+    fn test_from_json_reader() {
+        let text = r#"{"b": [1, -2, 2.5, "x", null, true], "a": 18446744073709551615}"#;
+        let obj = monty_object_from_json_reader(text.as_bytes(), &DecodeOptions::TAGGED).unwrap();
+        // Keys keep input order.
+        let big = BigInt::from(u64::MAX);
         assert_eq!(
-            monty_object_to_json(&exc),
-            Value::String("RuntimeError".into())
+            obj,
+            MontyObject::dict(vec![
+                (
+                    MontyObject::String("b".into()),
+                    MontyObject::List(vec![
+                        MontyObject::Int(1),
+                        MontyObject::Int(-2),
+                        MontyObject::Float(2.5),
+                        MontyObject::String("x".into()),
+                        MontyObject::None,
+                        MontyObject::Bool(true),
+                    ])
+                ),
+                (MontyObject::String("a".into()), MontyObject::BigInt(big)),
+            ])
         );
     }
 
     #[test]
-    fn test_repr() {
-        let r = MontyObject::Repr("<object at 0x123>".into());
+    fn test_from_json_reader_duplicate_key() {
+        let obj =
+            monty_object_from_json_reader(r#"{"k": 1, "k": 2}"#.as_bytes(), &DecodeOptions::TAGGED)
+                .unwrap();
         assert_eq!(
-            monty_object_to_json(&r),
-            Value::String("<object at 0x123>".into())
+            obj,
+            MontyObject::dict(vec![(MontyObject::String("k".into()), MontyObject::Int(2))])
         );
     }
 
+    fn capped(max_elements: usize) -> DecodeOptions {
+        DecodeOptions {
+            max_elements: Some(max_elements),
+            ..DecodeOptions::TAGGED
+        }
+    }
+
     #[test]
-    fn test_frozen_set() {
-        let fs = MontyObject::FrozenSet(vec![MontyObject::Int(3), MontyObject::Int(4)]);
-        assert_eq!(monty_object_to_json(&fs), json!([3, 4]));
+    fn test_element_cap() {
+        let val = json!([1, [2, 3], {"k": 4}]);
+        // 1 outer list + 1 + (1 + 2) + (1 + 1) = 7 values.
+        assert!(json_to_monty_object_with(&val, &capped(7)).is_ok());
+        let err = json_to_monty_object_with(&val, &capped(6)).unwrap_err();
+        assert!(err.contains("max_resume_elements"), "{err}");
+
+        let text = val.to_string();
+        assert!(monty_object_from_json_reader(text.as_bytes(), &capped(7)).is_ok());
+        assert!(monty_object_from_json_reader(text.as_bytes(), &capped(6)).is_err());
     }
 
     #[test]
-    fn test_json_to_monty_float() {
-        let val = json!(3.125);
-        let obj = json_to_monty_object(&val);
-        match obj {
-            MontyObject::Float(f) => assert!((f - 3.125).abs() < f64::EPSILON),
-            _ => panic!("expected Float"),
-        }
+    fn test_from_json_reader_invalid() {
+        assert!(monty_object_from_json_reader("[1, 2".as_bytes(), &DecodeOptions::TAGGED).is_err());
+        assert!(monty_object_from_json_reader("1 2".as_bytes(), &DecodeOptions::TAGGED).is_err());
     }
 }
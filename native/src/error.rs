@@ -1,7 +1,9 @@
+use std::cell::RefCell;
 use std::ffi::{CStr, CString, c_char};
-use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::panic::{self, AssertUnwindSafe, catch_unwind};
+use std::sync::Once;
 
-use monty::MontyException;
+use monty::{ExcType, MontyException};
 use serde_json::{Value, json};
 
 /// Allocate a C string from a Rust `&str`. Caller must free with `monty_string_free`.
@@ -9,24 +11,79 @@ pub fn to_c_string(s: &str) -> *mut c_char {
     CString::new(s).unwrap_or_default().into_raw()
 }
 
+thread_local! {
+    /// `file:line:column` of the last panic on this thread.
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Install (once per process) a panic hook that records each panic's
+/// location before running the previous hook, which still prints as usual.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(loc) = info.location() {
+                let loc = format!("{}:{}:{}", loc.file(), loc.line(), loc.column());
+                PANIC_LOCATION.with(|cell| *cell.borrow_mut() = Some(loc));
+            }
+            previous(info);
+        }));
+    });
+}
+
 /// Wrap a closure in `catch_unwind`, returning `Err(message)` on panic.
+///
+/// The message ends with ` (at file:line:column)` when the panic location
+/// is known, since backtraces don't reach FFI callers.
 pub fn catch_ffi_panic<F, T>(f: F) -> Result<T, String>
 where
     F: FnOnce() -> T,
 {
+    install_panic_hook();
+    PANIC_LOCATION.with(|cell| cell.borrow_mut().take());
     catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
-        if let Some(s) = payload.downcast_ref::<&str>() {
+        let msg = if let Some(s) = payload.downcast_ref::<&str>() {
             s.to_string()
         } else if let Some(s) = payload.downcast_ref::<String>() {
             s.clone()
         } else {
             "unknown panic".to_string()
+        };
+        match PANIC_LOCATION.with(|cell| cell.borrow_mut().take()) {
+            Some(loc) => format!("{msg} (at {loc})"),
+            None => msg,
         }
     })
 }
 
+/// Category of the last failure on a handle, reported by
+/// `monty_last_error_code` — matches `MontyErrorCode` in the C header.
+///
+/// The values are stable; new categories are only ever appended.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MontyErrorCode {
+    #[default]
+    None = 0,
+    /// The handle is not in a state that allows the call.
+    WrongState = 1,
+    /// A JSON argument failed to parse.
+    InvalidJson = 2,
+    /// A required pointer argument was NULL.
+    NullArgument = 3,
+    /// A string argument was not valid UTF-8.
+    NonUtf8 = 4,
+    /// The call panicked inside the library.
+    Panic = 5,
+    /// Python code raised an exception (the result carries its JSON).
+    VmException = 6,
+    /// Any other rejected argument, e.g. well-formed JSON of the wrong shape.
+    InvalidArgument = 7,
+}
+
 /// Parse a C string pointer, writing to `out_error` on failure.
-/// Returns `Ok(&str)` or `Err(())` if null or invalid UTF-8.
+/// Returns `Ok(&str)`, or the error code if null or invalid UTF-8.
 ///
 /// # Safety
 /// `ptr` must be a valid NUL-terminated C string if non-null.
@@ -34,12 +91,12 @@ pub unsafe fn parse_c_str<'a>(
     ptr: *const c_char,
     name: &str,
     out_error: *mut *mut c_char,
-) -> Result<&'a str, ()> {
+) -> Result<&'a str, MontyErrorCode> {
     if ptr.is_null() {
         if !out_error.is_null() {
             unsafe { *out_error = to_c_string(&format!("{name} is NULL")) };
         }
-        return Err(());
+        return Err(MontyErrorCode::NullArgument);
     }
     match unsafe { CStr::from_ptr(ptr) }.to_str() {
         Ok(s) => Ok(s),
@@ -47,16 +104,23 @@ pub unsafe fn parse_c_str<'a>(
             if !out_error.is_null() {
                 unsafe { *out_error = to_c_string(&format!("{name} is not valid UTF-8")) };
             }
-            Err(())
+            Err(MontyErrorCode::NonUtf8)
         }
     }
 }
 
+/// Parse a Python exception class name (e.g. `"ValueError"`) into an `ExcType`.
+pub fn parse_exc_type(name: &str) -> Result<ExcType, String> {
+    name.parse()
+        .map_err(|_| format!("unknown exception type: {name}"))
+}
+
 /// Convert a `MontyException` to a snake_case JSON value matching Dart's
 /// `MontyException.fromJson`.
 ///
 /// Includes `exc_type` (e.g. `"ValueError"`) and full `traceback` array
-/// with all frames from the upstream exception.
+/// with all frames from the upstream exception. `error_line_preview` is the
+/// source line of the innermost frame that has one, i.e. the line that raised.
 pub fn monty_exception_to_json(e: &MontyException) -> Value {
     let mut obj = json!({
         "message": e.summary(),
@@ -73,9 +137,21 @@ pub fn monty_exception_to_json(e: &MontyException) -> Value {
         map.insert("column_number".into(), json!(frame.start.column));
         if let Some(ref preview) = frame.preview_line {
             map.insert("source_code".into(), json!(preview));
+            if e.exc_type() == ExcType::AssertionError
+                && let Some(expr) = assert_expression(preview)
+            {
+                map.insert(
+                    "assertion".into(),
+                    json!({"expr": expr, "line": frame.start.line}),
+                );
+            }
         }
     }
 
+    if let Some(preview) = traceback.iter().rev().find_map(|f| f.preview_line.as_ref()) {
+        map.insert("error_line_preview".into(), json!(preview));
+    }
+
     // Full traceback array
     if !traceback.is_empty() {
         let frames: Vec<Value> = traceback
@@ -94,6 +170,7 @@ pub fn monty_exception_to_json(e: &MontyException) -> Value {
                 }
                 if let Some(ref preview) = frame.preview_line {
                     fm.insert("preview_line".into(), json!(preview));
+                    fm.insert("source_code".into(), json!(preview));
                 }
                 if frame.hide_caret {
                     fm.insert("hide_caret".into(), json!(true));
@@ -110,10 +187,55 @@ pub fn monty_exception_to_json(e: &MontyException) -> Value {
     obj
 }
 
+/// Extract the asserted expression from an `assert` source line, dropping
+/// the optional `, message` part.
+///
+/// The engine does not record assertion context, so this reads the source
+/// line from the traceback: `"assert x == 2, 'bad'"` → `"x == 2"`.
+fn assert_expression(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("assert")?;
+    if !rest.starts_with([' ', '(']) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in rest.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => return Some(rest[..i].trim_end()),
+            '#' => return Some(rest[..i].trim_end()),
+            _ => {}
+        }
+    }
+    Some(rest.trim_end())
+}
+
+/// Convert a `MontyException` to a minimal JSON value with only `message`
+/// and `exc_type`, skipping traceback serialization.
+pub fn monty_exception_to_minimal_json(e: &MontyException) -> Value {
+    json!({
+        "message": e.summary(),
+        "exc_type": e.exc_type().to_string(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use monty::ExcType;
     use std::ffi::CStr;
     use std::ptr;
 
@@ -153,14 +275,25 @@ mod tests {
 
     #[test]
     fn test_catch_ffi_panic_str() {
-        let result = catch_ffi_panic(|| panic!("boom"));
-        assert_eq!(result, Err("boom".to_string()));
+        let msg = catch_ffi_panic(|| panic!("boom")).unwrap_err();
+        assert!(msg.starts_with("boom (at "), "{msg}");
     }
 
     #[test]
     fn test_catch_ffi_panic_string() {
-        let result = catch_ffi_panic(|| panic!("{}", "formatted boom"));
-        assert_eq!(result, Err("formatted boom".to_string()));
+        let msg = catch_ffi_panic(|| panic!("{}", "formatted boom")).unwrap_err();
+        assert!(msg.starts_with("formatted boom (at "), "{msg}");
+    }
+
+    #[test]
+    fn test_catch_ffi_panic_location() {
+        let line = line!() + 1;
+        let msg = catch_ffi_panic(|| panic!("boom")).unwrap_err();
+        assert!(msg.contains(&format!("src/error.rs:{line}:")), "{msg}");
+
+        // A later panic without a location doesn't reuse the old one.
+        let msg = catch_ffi_panic(|| std::panic::resume_unwind(Box::new("quiet"))).unwrap_err();
+        assert_eq!(msg, "quiet");
     }
 
     #[test]
@@ -214,12 +347,66 @@ mod tests {
             "expected at least one frame with frame_name"
         );
 
+        // Each frame carries its own source line; the top-level preview is
+        // the innermost (raising) frame's
+        let inner = tb.last().unwrap();
+        assert_eq!(inner["source_code"], inner["preview_line"]);
+        assert!(inner["source_code"].as_str().unwrap().contains("1/0"));
+        assert_eq!(obj["error_line_preview"], inner["preview_line"]);
+        let outer_call = &tb[tb.len() - 2];
+        assert!(
+            outer_call["source_code"]
+                .as_str()
+                .unwrap()
+                .contains("inner()")
+        );
+
         // Legacy single-frame fields should match last frame
         assert!(obj.get("filename").is_some());
         assert!(obj.get("line_number").is_some());
         assert!(obj.get("column_number").is_some());
     }
 
+    #[test]
+    fn test_monty_exception_to_json_assertion() {
+        use monty::{MontyRun, NoLimitTracker, PrintWriter};
+
+        let code = "x = 1\nassert x == 2, 'x should be two'";
+        let compiled = MontyRun::new(code.into(), "<test>", vec![], vec![]).unwrap();
+        let mut print = PrintWriter::Disabled;
+        let err = compiled
+            .run(vec![], NoLimitTracker, &mut print)
+            .unwrap_err();
+
+        let json = monty_exception_to_json(&err);
+        assert_eq!(json["exc_type"], "AssertionError");
+        assert_eq!(json["assertion"], json!({"expr": "x == 2", "line": 2}));
+    }
+
+    #[test]
+    fn test_assert_expression() {
+        assert_eq!(assert_expression("assert x == 2"), Some("x == 2"));
+        assert_eq!(assert_expression("    assert x, 'msg'"), Some("x"));
+        assert_eq!(
+            assert_expression("assert f(a, b) == [1, 2], f'{a}, {b}'"),
+            Some("f(a, b) == [1, 2]")
+        );
+        assert_eq!(assert_expression("assert s == 'a,b'"), Some("s == 'a,b'"));
+        assert_eq!(assert_expression("assert(ok)  # check"), Some("(ok)"));
+        assert_eq!(assert_expression("asserted = 1"), None);
+        assert_eq!(assert_expression("raise AssertionError()"), None);
+    }
+
+    #[test]
+    fn test_monty_exception_to_minimal_json() {
+        let exc = MontyException::new(ExcType::ValueError, Some("bad".into()));
+        let json = monty_exception_to_minimal_json(&exc);
+        assert_eq!(
+            json,
+            json!({"message": "ValueError: bad", "exc_type": "ValueError"})
+        );
+    }
+
     #[test]
     fn test_catch_ffi_panic_non_string_payload() {
         // Panic with a non-string payload (Box<i32>) → "unknown panic" branch
@@ -242,10 +429,29 @@ mod tests {
     fn test_parse_c_str_null() {
         let mut err: *mut c_char = ptr::null_mut();
         let result = unsafe { parse_c_str(ptr::null(), "arg", &mut err) };
-        assert!(result.is_err());
+        assert_eq!(result, Err(MontyErrorCode::NullArgument));
         assert!(!err.is_null());
         let msg = unsafe { CStr::from_ptr(err) }.to_str().unwrap();
         assert_eq!(msg, "arg is NULL");
         unsafe { drop(CString::from_raw(err)) };
     }
+
+    #[test]
+    fn test_parse_c_str_non_utf8() {
+        let c = CString::new(vec![0xff, 0xfe]).unwrap();
+        let result = unsafe { parse_c_str(c.as_ptr(), "arg", ptr::null_mut()) };
+        assert_eq!(result, Err(MontyErrorCode::NonUtf8));
+    }
+
+    #[test]
+    fn test_parse_exc_type() {
+        assert_eq!(
+            parse_exc_type("ConnectionError").unwrap(),
+            ExcType::ConnectionError
+        );
+        assert_eq!(
+            parse_exc_type("NotAnError").unwrap_err(),
+            "unknown exception type: NotAnError"
+        );
+    }
 }
@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+
+/// Opt-in record of the calls a handle paused on and how the host answered
+/// each, kept for replay and audit.
+///
+/// Holds at most `max_entries` events; once full, the oldest are dropped
+/// and counted in `dropped`.
+#[derive(Debug)]
+pub struct EventLog {
+    max_entries: usize,
+    entries: VecDeque<Value>,
+    dropped: u64,
+}
+
+impl EventLog {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// An empty log with the same cap.
+    pub fn emptied(&self) -> Self {
+        Self::new(self.max_entries)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.dropped = 0;
+    }
+
+    /// Record a pause at `call_id`. `args` and `kwargs` are `null` when
+    /// they break a conversion limit.
+    pub fn record_pause(
+        &mut self,
+        call_id: u32,
+        fn_name: &str,
+        args: Value,
+        kwargs: Value,
+        os_call: bool,
+    ) {
+        let mut entry = json!({
+            "call_id": call_id,
+            "fn_name": fn_name,
+            "args": args,
+            "kwargs": kwargs,
+            "timestamp_ms": unix_ms(),
+        });
+        if os_call {
+            entry["os_call"] = Value::Bool(true);
+        }
+        if self.entries.len() >= self.max_entries {
+            self.dropped += 1;
+            if self.entries.pop_front().is_none() {
+                return;
+            }
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Record how the host answered `call_id`: `field` is `"result"`,
+    /// `"error"` or `"future"`. A no-op if the pause was dropped.
+    pub fn record_answer(&mut self, call_id: u32, field: &str, value: Value) {
+        let entry = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|e| e["call_id"] == call_id && e.get("resumed_ms").is_none());
+        if let Some(entry) = entry {
+            entry[field] = value;
+            entry["resumed_ms"] = Value::from(unix_ms());
+        }
+    }
+
+    /// `{"events": [...], "dropped": n}`, oldest event first.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "events": self.entries,
+            "dropped": self.dropped,
+        })
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_log_drops_oldest() {
+        let mut log = EventLog::new(2);
+        for id in 0..3 {
+            log.record_pause(id, "f", json!([id]), json!({}), false);
+        }
+        log.record_answer(0, "result", json!(0));
+        log.record_answer(2, "result", json!(20));
+
+        let json = log.to_json();
+        assert_eq!(json["dropped"], 1);
+        let events = json["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["call_id"], 1);
+        assert!(events[0].get("result").is_none());
+        assert_eq!(events[1]["result"], 20);
+        assert!(
+            events[1]["resumed_ms"].as_u64().unwrap()
+                >= events[1]["timestamp_ms"].as_u64().unwrap()
+        );
+
+        let mut empty = EventLog::new(0);
+        empty.record_pause(0, "f", json!([]), json!({}), false);
+        assert_eq!(empty.to_json(), json!({"events": [], "dropped": 1}));
+    }
+}
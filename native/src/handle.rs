@@ -1,24 +1,43 @@
-use std::time::Duration;
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use monty::{
     ExternalResult, FutureSnapshot, LimitedTracker, MontyException, MontyRun, NoLimitTracker,
-    PrintWriter, ResourceLimits, RunProgress, Snapshot,
+    PrintWriter, PrintWriterCallback, ResourceLimits, RunProgress, Snapshot,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::convert::{json_to_monty_object, monty_object_to_json};
-use crate::error::monty_exception_to_json;
+use crate::convert::{
+    ConversionOptions, DecodeOptions, HostValue, IntegralFloatPolicy, check_conversion_limits,
+    host_value_from_json_reader, host_values_from_json_reader, json_to_monty_object_with,
+    keyed_host_values_from_json_reader, monty_object_to_json_with, monty_object_to_writer,
+};
+use crate::error::{
+    MontyErrorCode, monty_exception_to_json, monty_exception_to_minimal_json, parse_exc_type,
+};
+use crate::event_log::EventLog;
+use crate::usage::{MeteredTracker, MontyInterruptToken, StopReason, UsageStats};
 
 /// Maps a `ResourceTracker` type to its `HandleState` variants.
 trait TrackerExt: monty::ResourceTracker + Sized {
     fn into_paused(snapshot: Snapshot<Self>, meta: PendingMeta) -> HandleState;
+    fn into_os_call(snapshot: Snapshot<Self>, meta: PendingMeta) -> HandleState;
     fn into_futures(snapshot: FutureSnapshot<Self>, call_ids_json: String) -> HandleState;
 }
 
-impl TrackerExt for LimitedTracker {
+impl TrackerExt for MeteredTracker<LimitedTracker> {
     fn into_paused(snapshot: Snapshot<Self>, meta: PendingMeta) -> HandleState {
         HandleState::PausedLimited { snapshot, meta }
     }
+    fn into_os_call(snapshot: Snapshot<Self>, meta: PendingMeta) -> HandleState {
+        HandleState::PausedOsCallLimited { snapshot, meta }
+    }
     fn into_futures(snapshot: FutureSnapshot<Self>, call_ids_json: String) -> HandleState {
         HandleState::FuturesLimited {
             snapshot,
@@ -27,10 +46,13 @@ impl TrackerExt for LimitedTracker {
     }
 }
 
-impl TrackerExt for NoLimitTracker {
+impl TrackerExt for MeteredTracker<NoLimitTracker> {
     fn into_paused(snapshot: Snapshot<Self>, meta: PendingMeta) -> HandleState {
         HandleState::PausedNoLimit { snapshot, meta }
     }
+    fn into_os_call(snapshot: Snapshot<Self>, meta: PendingMeta) -> HandleState {
+        HandleState::PausedOsCallNoLimit { snapshot, meta }
+    }
     fn into_futures(snapshot: FutureSnapshot<Self>, call_ids_json: String) -> HandleState {
         HandleState::FuturesNoLimit {
             snapshot,
@@ -56,51 +78,444 @@ pub enum MontyProgressTag {
     Pending = 1,
     Error = 2,
     ResolveFutures = 3,
+    OsCall = 4,
+}
+
+/// Lifecycle state reported by `monty_state` — matches `MontyStateTag` in
+/// the C header.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MontyStateTag {
+    Ready = 0,
+    Paused = 1,
+    Futures = 2,
+    Complete = 3,
+    Consumed = 4,
+    OsCall = 5,
 }
 
-/// Metadata captured when paused at a `FunctionCall`.
+/// Metadata captured when paused at a `FunctionCall` or `OsCall`.
+///
+/// Arguments are kept as the engine passed them and only encoded to JSON
+/// when the host first asks for them, so a host routing on the function
+/// name alone never pays for serialization. They were checked against the
+/// conversion limits at the pause, and are encoded under the options
+/// captured then, so a later option change can't make a read fail.
+/// Encodings are cached.
+#[derive(Clone, Serialize, Deserialize)]
 struct PendingMeta {
     fn_name: String,
-    args_json: String,
-    kwargs_json: String,
+    args: Vec<monty::MontyObject>,
+    kwargs: Vec<(monty::MontyObject, monty::MontyObject)>,
     call_id: u32,
     method_call: bool,
+    /// Conversion options in effect at the pause.
+    options: ConversionOptions,
+    /// Positional arguments the call was made with, including any dropped.
+    args_count: usize,
+    /// Whether arguments were dropped for breaking `max_call_args_bytes`.
+    args_truncated: bool,
+    #[serde(skip)]
+    args_json: OnceCell<Option<String>>,
+    /// Each argument encoded with `typed_containers`, so tuples, sets and
+    /// big ints keep their Python type.
+    #[serde(skip)]
+    args_tagged: OnceCell<Option<Vec<String>>>,
+    #[serde(skip)]
+    kwargs_json: OnceCell<Option<String>>,
+}
+
+impl PendingMeta {
+    fn args_json(&self) -> Option<&str> {
+        self.args_json
+            .get_or_init(|| {
+                let args = self
+                    .args
+                    .iter()
+                    .map(|obj| monty_object_to_json_with(obj, &self.options))
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()?;
+                serde_json::to_string(&args).ok()
+            })
+            .as_deref()
+    }
+
+    fn arg_tagged_json(&self, index: usize) -> Option<&str> {
+        let tagged = ConversionOptions {
+            typed_containers: true,
+            ..self.options.clone()
+        };
+        self.args_tagged
+            .get_or_init(|| {
+                self.args
+                    .iter()
+                    .map(|obj| monty_object_to_json_with(obj, &tagged).map(|v| v.to_string()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()
+            })
+            .as_ref()?
+            .get(index)
+            .map(String::as_str)
+    }
+
+    fn kwargs_json(&self) -> Option<&str> {
+        self.kwargs_json
+            .get_or_init(|| {
+                let map = self
+                    .kwargs
+                    .iter()
+                    .map(|(k, v)| {
+                        let key = if let monty::MontyObject::String(s) = k {
+                            s.clone()
+                        } else {
+                            format!("{k}")
+                        };
+                        Ok((key, monty_object_to_json_with(v, &self.options)?))
+                    })
+                    .collect::<Result<serde_json::Map<String, Value>, String>>()
+                    .ok()?;
+                serde_json::to_string(&map).ok()
+            })
+            .as_deref()
+    }
+}
+
+/// Header on every snapshot: this magic, then `SNAPSHOT_FORMAT_VERSION` as
+/// a little-endian u32, then the body. Headerless bytes from older crate
+/// versions are still restored on a best-effort basis.
+const SNAPSHOT_MAGIC: &[u8] = b"MONTY-SNAP";
+/// Bumped whenever the snapshot body changes shape, including a new pinned
+/// engine revision, whose serialized types are unversioned.
+const SNAPSHOT_FORMAT_VERSION: u32 = 3;
+
+/// Prefix marking a snapshot body taken while paused, followed by a tracker
+/// byte (`PAUSED_LIMITED` or `PAUSED_NO_LIMIT`) and a postcard `PausedImage`.
+/// Ready bodies are the bare `MontyRun::dump` bytes.
+const PAUSED_SNAPSHOT_MAGIC: &[u8] = b"MONTY-PAUSED";
+const PAUSED_LIMITED: u8 = b'L';
+const PAUSED_NO_LIMIT: u8 = b'N';
+
+/// Serialized form of a handle paused at an external function call.
+#[derive(Serialize, Deserialize)]
+struct PausedImage<S> {
+    source: String,
+    external_functions: Vec<String>,
+    snapshot: S,
+    meta: PendingMeta,
+    config: PausedConfig,
+}
+
+/// Handle settings a paused snapshot carries, so the restored handle
+/// finishes the run as configured. Callbacks, OS handlers and the event
+/// log are host-side and must be set again.
+#[derive(Serialize, Deserialize)]
+struct PausedConfig {
+    globals: Vec<(String, monty::MontyObject)>,
+    limits: Option<ResourceLimits>,
+    options: HandleOptions,
+    label: Option<String>,
+    stdin: Option<String>,
+    constants: HashMap<String, monty::MontyObject>,
+    compute_time_limit: Option<Duration>,
+    ext_call_count: u64,
+    max_external_calls: Option<u64>,
+    max_call_args_bytes: Option<usize>,
+}
+
+/// Compression applied to a whole snapshot by `snapshot_with_codec`.
+/// `restore` detects compressed bytes by the codec's own frame magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotCodec {
+    None,
+    Zstd,
+    Gzip,
+}
+
+impl SnapshotCodec {
+    /// Map the C values `0` (`None`), `1` (`Zstd`) and `2` (`Gzip`).
+    pub fn from_c_int(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::Zstd),
+            2 => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+}
+
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+
+/// Largest snapshot `restore` will decompress. A frame inflating past it
+/// is rejected as soon as the limit is reached.
+const MAX_SNAPSHOT_BYTES: u64 = 256 << 20;
+
+fn compress_snapshot(bytes: Vec<u8>, codec: SnapshotCodec) -> Result<Vec<u8>, String> {
+    let fail = |e: std::io::Error| format!("snapshot compression failed: {e}");
+    match codec {
+        SnapshotCodec::None => Ok(bytes),
+        SnapshotCodec::Zstd => zstd::encode_all(bytes.as_slice(), 0).map_err(fail),
+        SnapshotCodec::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&bytes).map_err(fail)?;
+            encoder.finish().map_err(fail)
+        }
+    }
+}
+
+/// Decompress snapshot bytes carrying a zstd or gzip frame magic, or
+/// `None` for uncompressed bytes. Fails once the output passes `limit`.
+fn decompress_snapshot(bytes: &[u8], limit: u64) -> Result<Option<Vec<u8>>, String> {
+    let fail = |e: std::io::Error| format!("restore failed: snapshot decompression: {e}");
+    let decoder: Box<dyn Read + '_> = if bytes.starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::new(bytes).map_err(fail)?)
+    } else if bytes.starts_with(GZIP_MAGIC) {
+        Box::new(flate2::read::GzDecoder::new(bytes))
+    } else {
+        return Ok(None);
+    };
+    let mut out = Vec::new();
+    decoder
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut out)
+        .map_err(fail)?;
+    if out.len() as u64 > limit {
+        return Err(format!(
+            "restore failed: decompressed snapshot exceeds {limit} bytes"
+        ));
+    }
+    Ok(Some(out))
+}
+
+/// Start a snapshot buffer with the magic and format version, reserving
+/// room for `body_len` more bytes.
+fn snapshot_header(body_len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 4 + body_len);
+    bytes.extend_from_slice(SNAPSHOT_MAGIC);
+    bytes.extend_from_slice(&SNAPSHOT_FORMAT_VERSION.to_le_bytes());
+    bytes
 }
 
 /// Internal state of a running handle.
 enum HandleState {
     Ready(MontyRun),
     PausedLimited {
-        snapshot: Snapshot<LimitedTracker>,
+        snapshot: Snapshot<MeteredTracker<LimitedTracker>>,
         meta: PendingMeta,
     },
     PausedNoLimit {
-        snapshot: Snapshot<NoLimitTracker>,
+        snapshot: Snapshot<MeteredTracker<NoLimitTracker>>,
+        meta: PendingMeta,
+    },
+    PausedOsCallLimited {
+        snapshot: Snapshot<MeteredTracker<LimitedTracker>>,
+        meta: PendingMeta,
+    },
+    PausedOsCallNoLimit {
+        snapshot: Snapshot<MeteredTracker<NoLimitTracker>>,
         meta: PendingMeta,
     },
     FuturesLimited {
-        snapshot: FutureSnapshot<LimitedTracker>,
+        snapshot: FutureSnapshot<MeteredTracker<LimitedTracker>>,
         call_ids_json: String,
     },
     FuturesNoLimit {
-        snapshot: FutureSnapshot<NoLimitTracker>,
+        snapshot: FutureSnapshot<MeteredTracker<NoLimitTracker>>,
         call_ids_json: String,
     },
     Complete {
-        result_json: String,
+        /// Shared with `run`'s caller instead of copied.
+        result_json: Arc<str>,
         is_error: bool,
+        /// Decided when the error is reported, for `complete_is_retryable`.
+        retryable: bool,
     },
     Consumed,
 }
 
+/// Type and message of an exception the host raised into the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InjectedExc {
+    exc_type: monty::ExcType,
+    message: Option<String>,
+}
+
+impl InjectedExc {
+    fn of(exc: &MontyException) -> Self {
+        Self {
+            exc_type: exc.exc_type(),
+            message: exc.message().map(str::to_owned),
+        }
+    }
+}
+
+/// Rust-side handler answering an `OsCall` without pausing for the host.
+pub type OsHandler =
+    Box<dyn FnMut(&[monty::MontyObject]) -> Result<monty::MontyObject, MontyException> + Send>;
+
+/// Host sink receiving print output chunks as the guest writes them.
+pub type PrintCallback = Box<dyn FnMut(&str) + Send>;
+
+/// Forwards engine print output to a `PrintCallback`.
+struct CallbackWriter<'a>(&'a mut PrintCallback);
+
+impl PrintWriterCallback for CallbackWriter<'_> {
+    fn stdout_write(&mut self, output: Cow<'_, str>) -> Result<(), MontyException> {
+        (self.0)(&output);
+        Ok(())
+    }
+
+    fn stdout_push(&mut self, end: char) -> Result<(), MontyException> {
+        (self.0)(end.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+}
+
+/// Appends engine print output to a buffer, dropping whatever would take it
+/// past `limit` bytes.
+struct BufferWriter<'a> {
+    out: &'a mut String,
+    limit: Option<usize>,
+    truncated: &'a mut bool,
+}
+
+impl BufferWriter<'_> {
+    fn push(&mut self, text: &str) {
+        let room = self
+            .limit
+            .map_or(usize::MAX, |limit| limit.saturating_sub(self.out.len()));
+        if text.len() <= room {
+            self.out.push_str(text);
+            return;
+        }
+        let mut end = room;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.out.push_str(&text[..end]);
+        *self.truncated = true;
+    }
+}
+
+impl PrintWriterCallback for BufferWriter<'_> {
+    fn stdout_write(&mut self, output: Cow<'_, str>) -> Result<(), MontyException> {
+        self.push(&output);
+        Ok(())
+    }
+
+    fn stdout_push(&mut self, end: char) -> Result<(), MontyException> {
+        self.push(end.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+}
+
 /// Opaque handle exposed to C callers.
 pub struct MontyHandle {
     state: HandleState,
+    /// Script name used at compile time, or `None` for restored handles
+    /// (whose source can't be recompiled).
+    script_name: Option<String>,
+    /// Source code the handle was compiled from.
+    source: String,
+    external_functions: Vec<String>,
+    /// Host values bound as globals; their names are compiled as inputs.
+    globals: Vec<(String, monty::MontyObject)>,
     limits: Option<ResourceLimits>,
-    usage_json: String,
+    /// Usage as of the last engine step; `None` until something runs.
+    usage_json: Option<String>,
+    /// Counters fed by the tracker of the current execution.
+    usage: Arc<UsageStats>,
+    /// Milliseconds spent compiling the current program, reported as
+    /// `usage.compile_ms`. `None` for restored handles.
+    compile_ms: Option<f64>,
+    /// Budget from `new_with_compile_timeout`, applied to every later
+    /// recompile too (`add_external`, `reset`, ...).
+    compile_timeout: Option<Duration>,
     print_output: String,
+    /// Whether `print_output` stopped growing at `output_limit`.
+    print_output_truncated: bool,
+    options: HandleOptions,
+    /// Caller-chosen label identifying the handle in panic messages.
+    label: Option<String>,
+    /// OS call handlers keyed by function name (e.g. `"os.getenv"`).
+    os_handlers: HashMap<String, OsHandler>,
+    /// Receives print output as it is written instead of `print_output`.
+    print_callback: Option<PrintCallback>,
+    /// Text `input()` reads from, a line per call; `None` leaves `input`
+    /// calls to the host.
+    stdin: Option<String>,
+    /// Fixed return values of externals the handle answers itself.
+    constants: HashMap<String, monty::MontyObject>,
+    /// Values consumed by the last `resume_many` call.
+    last_batch_consumed: usize,
+    /// Unresolved calls turned into futures by `resume_as_future` in the
+    /// current execution, by call id, reported by `pending_futures_json`.
+    future_calls: HashMap<u32, PendingMeta>,
+    /// Time limit that only counts time spent executing guest code.
+    compute_time_limit: Option<Duration>,
+    /// External function calls handed to the host in the current execution.
+    ext_call_count: u64,
+    /// Calls allowed per execution before it is aborted; `None` for no cap.
+    max_external_calls: Option<u64>,
+    /// Bytes of JSON a pending call's arguments may take before the rest
+    /// are dropped; `None` for no cap.
+    max_call_args_bytes: Option<usize>,
+    /// When the current execution last paused for the host.
+    paused_at: Option<Instant>,
+    /// Category of the last FFI failure; a `Cell` so `&self` calls can set it.
+    last_error_code: Cell<MontyErrorCode>,
+    /// Exceptions the host raised into the guest at the last resume, as
+    /// type and message. A run ending in one of these is retryable.
+    injected_excs: Vec<InjectedExc>,
+    /// Bytes serialized by `snapshot_size`, handed out by the next `snapshot`.
+    snapshot_cache: RefCell<Option<Vec<u8>>>,
+    /// Pauses of the current execution and their answers, once enabled.
+    event_log: Option<EventLog>,
+}
+
+/// Per-handle knobs set through the `monty_set_*` FFI functions.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct HandleOptions {
+    /// Always include `print_output`, even when nothing was printed.
+    always_emit_print: bool,
+    /// Include `value_repr`, the engine's `repr()` of the result value.
+    value_repr: bool,
+    /// Opaque id echoed as `correlation_id` in every result.
+    correlation_id: Option<String>,
+    /// Omit tracebacks from error JSON (`exc_type` + `message` only).
+    minimal_errors: bool,
+    /// Limits applied when converting results and external call arguments.
+    conversion: ConversionOptions,
+    /// Externals the host services, or `None` for all declared externals.
+    /// Calls to other externals raise `NotImplementedError` in the guest.
+    serviceable_externals: Option<Vec<String>>,
+    /// Exception types reported as retryable, or `None` for
+    /// `DEFAULT_RETRYABLE_EXC_TYPES`.
+    retryable_exc_types: Option<Vec<monty::ExcType>>,
+    /// Cap in bytes on buffered `print_output`, or `None` for unlimited.
+    output_limit: Option<usize>,
+    /// Cap on the values decoded from one host-supplied resume value.
+    max_resume_elements: Option<usize>,
+    /// Pause on OS calls no registered handler answers, instead of ending
+    /// the run with `NotImplementedError`.
+    pause_os_calls: bool,
+}
+
+/// External function name `set_stdin` answers, standing in for the
+/// builtin the engine lacks.
+const STDIN_EXTERNAL: &str = "input";
+
+#[cfg(test)]
+thread_local! {
+    /// Result JSON documents built on this thread, for tests checking that
+    /// a result is serialized once.
+    static RESULT_SERIALIZATIONS: Cell<usize> = const { Cell::new(0) };
 }
 
+/// Exception types retryable by default: the time limit is transient.
+const DEFAULT_RETRYABLE_EXC_TYPES: &[monty::ExcType] = &[monty::ExcType::TimeoutError];
+
 impl MontyHandle {
     /// Create a new handle from Python source code.
     ///
@@ -111,18 +526,255 @@ impl MontyHandle {
         external_functions: Vec<String>,
         script_name: Option<String>,
     ) -> Result<Self, MontyException> {
+        Self::new_with_globals(code, external_functions, script_name, vec![])
+    }
+
+    /// Create a new handle with host values bound as Python globals.
+    ///
+    /// Each name must be a valid identifier that is not a keyword, not
+    /// reserved by the crate (`__monty_*`) and not given twice.
+    pub fn new_with_globals(
+        code: String,
+        external_functions: Vec<String>,
+        script_name: Option<String>,
+        globals: Vec<(String, monty::MontyObject)>,
+    ) -> Result<Self, MontyException> {
+        Self::create(code, external_functions, script_name, globals, None)
+    }
+
+    /// Create a new handle, giving up with `TimeoutError` if compiling takes
+    /// longer than `compile_timeout`.
+    ///
+    /// The engine can't interrupt a compile, so one that runs over is
+    /// abandoned on its worker thread rather than stopped; see
+    /// `compile_within`.
+    pub fn new_with_compile_timeout(
+        code: String,
+        external_functions: Vec<String>,
+        script_name: Option<String>,
+        globals: Vec<(String, monty::MontyObject)>,
+        compile_timeout: Duration,
+    ) -> Result<Self, MontyException> {
+        Self::create(
+            code,
+            external_functions,
+            script_name,
+            globals,
+            Some(compile_timeout),
+        )
+    }
+
+    fn create(
+        code: String,
+        external_functions: Vec<String>,
+        script_name: Option<String>,
+        globals: Vec<(String, monty::MontyObject)>,
+        compile_timeout: Option<Duration>,
+    ) -> Result<Self, MontyException> {
+        for (i, (global, _)) in globals.iter().enumerate() {
+            let invalid = |reason: &str| {
+                MontyException::new(
+                    monty::ExcType::ValueError,
+                    Some(format!("invalid global name '{global}': {reason}")),
+                )
+            };
+            check_global_name(global).map_err(invalid)?;
+            if globals[..i].iter().any(|(other, _)| other == global) {
+                return Err(invalid("given more than once"));
+            }
+        }
         let name = script_name.unwrap_or_else(|| "<input>".into());
-        let compiled = MontyRun::new(code, &name, vec![], external_functions)?;
+        let input_names = globals.iter().map(|(n, _)| n.clone()).collect();
+        let (compiled, compile_ms) = compile_with_budget(
+            code.clone(),
+            &name,
+            input_names,
+            external_functions.clone(),
+            compile_timeout,
+        )?;
         Ok(Self {
             state: HandleState::Ready(compiled),
+            script_name: Some(name),
+            source: code,
+            external_functions,
+            globals,
             limits: None,
-            usage_json: default_usage_json(),
+            usage_json: None,
+            usage: Arc::default(),
+            compile_ms: Some(compile_ms),
+            compile_timeout,
             print_output: String::new(),
+            print_output_truncated: false,
+            options: HandleOptions::default(),
+            label: None,
+            os_handlers: HashMap::new(),
+            print_callback: None,
+            stdin: None,
+            constants: HashMap::new(),
+            last_batch_consumed: 0,
+            future_calls: HashMap::new(),
+            compute_time_limit: None,
+            ext_call_count: 0,
+            max_external_calls: None,
+            max_call_args_bytes: None,
+            paused_at: None,
+            last_error_code: Cell::default(),
+            injected_excs: Vec::new(),
+            snapshot_cache: RefCell::new(None),
+            event_log: None,
         })
     }
 
+    /// Compile `code` without building a handle, returning its diagnostics:
+    /// empty if it compiles.
+    ///
+    /// Each diagnostic is `{"severity", "exc_type", "message", "filename",
+    /// "line", "column", "end_line", "end_column"}`, the position fields
+    /// present when the engine reports one. The engine stops at the first
+    /// error and surfaces no warnings, so at most one diagnostic results.
+    /// Undefined names are resolved at runtime and not diagnosed.
+    pub fn validate(
+        code: String,
+        external_functions: Vec<String>,
+        script_name: Option<String>,
+    ) -> Vec<Value> {
+        let name = script_name.unwrap_or_else(|| "<input>".into());
+        let Err(exc) = MontyRun::new(code, &name, vec![], external_functions) else {
+            return vec![];
+        };
+        let mut diagnostic = serde_json::json!({
+            "severity": "error",
+            "exc_type": exc.exc_type().to_string(),
+            "message": exc.summary(),
+        });
+        if let Some(frame) = exc.traceback().last() {
+            diagnostic["filename"] = Value::from(frame.filename.clone());
+            diagnostic["line"] = Value::from(frame.start.line);
+            diagnostic["column"] = Value::from(frame.start.column);
+            diagnostic["end_line"] = Value::from(frame.end.line);
+            diagnostic["end_column"] = Value::from(frame.end.column);
+        }
+        vec![diagnostic]
+    }
+
+    /// Compile and run `code` in one go, for code that needs nothing from
+    /// the host. Returns `(result_tag, result_json)`.
+    ///
+    /// A compile error or a bad `limits_json` comes back as an error result
+    /// without running anything. Declared externals may still be called,
+    /// but with no host to answer, the first call ends the run with
+    /// `RuntimeError` instead of pausing.
+    pub fn eval(
+        code: String,
+        external_functions: Vec<String>,
+        script_name: Option<String>,
+        limits_json: Option<&str>,
+    ) -> (MontyResultTag, String) {
+        let mut handle = match Self::new(code, external_functions, script_name) {
+            Ok(handle) => handle,
+            Err(exc) => {
+                let mut error = monty_exception_to_json(&exc);
+                error["retryable"] = Value::Bool(false);
+                return (MontyResultTag::Error, standalone_error_result_json(error));
+            }
+        };
+        if let Some(json) = limits_json
+            && let Err(msg) = handle.set_limits_json(json)
+        {
+            handle.handle_exception(MontyException::new(monty::ExcType::ValueError, Some(msg)));
+        } else {
+            let (tag, _) = handle.start();
+            if !matches!(tag, MontyProgressTag::Complete | MontyProgressTag::Error) {
+                let name = handle
+                    .pending_fn_name()
+                    .or(handle.pending_os_call_name())
+                    .unwrap_or_default();
+                let msg = format!("external call to '{name}' cannot be answered in eval");
+                handle
+                    .handle_exception(MontyException::new(monty::ExcType::RuntimeError, Some(msg)));
+            }
+        }
+        let tag = if handle.complete_is_error() == Some(false) {
+            MontyResultTag::Ok
+        } else {
+            MontyResultTag::Error
+        };
+        (
+            tag,
+            handle
+                .complete_result_json()
+                .unwrap_or_default()
+                .to_string(),
+        )
+    }
+
+    /// Result JSON for an `eval` that panicked, reporting `msg` as a
+    /// non-retryable `RuntimeError`.
+    pub fn eval_panic_result_json(msg: String) -> String {
+        let exc = MontyException::new(monty::ExcType::RuntimeError, Some(msg));
+        let mut error = monty_exception_to_json(&exc);
+        error["retryable"] = Value::Bool(false);
+        standalone_error_result_json(error)
+    }
+
     /// Run code to completion. Returns `(result_tag, result_json, error_msg)`.
-    pub fn run(&mut self) -> (MontyResultTag, String, Option<String>) {
+    ///
+    /// `result_json` is the same buffer `complete_result_json` returns.
+    pub fn run(&mut self) -> (MontyResultTag, Arc<str>, Option<String>) {
+        let inputs = self.global_values();
+        self.run_inputs(inputs)
+    }
+
+    /// Run code to completion with `args` in place of the global values,
+    /// matched positionally to the names given at creation.
+    ///
+    /// The engine's `run` takes the program's inputs as positional values,
+    /// so a program compiled once can run with different inputs each time,
+    /// calling `reset` in between. The handle's own globals are unchanged.
+    pub fn run_with_args(
+        &mut self,
+        args: Vec<monty::MontyObject>,
+    ) -> (MontyResultTag, Arc<str>, Option<String>) {
+        if !matches!(self.state, HandleState::Ready(_)) {
+            return (
+                MontyResultTag::Error,
+                Arc::from(""),
+                Some(self.fail(MontyErrorCode::WrongState, "handle not in Ready state")),
+            );
+        }
+        if args.len() != self.globals.len() {
+            return (
+                MontyResultTag::Error,
+                Arc::from(""),
+                Some(format!(
+                    "expected {} args, got {}",
+                    self.globals.len(),
+                    args.len()
+                )),
+            );
+        }
+        self.run_inputs(args)
+    }
+
+    /// `run_with_args` taking a JSON array of arguments.
+    pub fn run_with_args_json(
+        &mut self,
+        args_json: &str,
+    ) -> (MontyResultTag, Arc<str>, Option<String>) {
+        match parse_args_json(args_json, &self.options.conversion.decode_options(None)) {
+            Ok(args) => self.run_with_args(args),
+            Err((code, e)) => (
+                MontyResultTag::Error,
+                Arc::from(""),
+                Some(self.fail(code, e)),
+            ),
+        }
+    }
+
+    fn run_inputs(
+        &mut self,
+        inputs: Vec<monty::MontyObject>,
+    ) -> (MontyResultTag, Arc<str>, Option<String>) {
         let state = std::mem::replace(&mut self.state, HandleState::Consumed);
         let compiled = match state {
             HandleState::Ready(c) => c,
@@ -130,46 +782,46 @@ impl MontyHandle {
                 self.state = state;
                 return (
                     MontyResultTag::Error,
-                    String::new(),
-                    Some("handle not in Ready state".into()),
+                    Arc::from(""),
+                    Some(self.fail(MontyErrorCode::WrongState, "handle not in Ready state")),
                 );
             }
         };
 
-        let mut print = PrintWriter::Collect(String::new());
-
+        self.clear_execution();
+        self.usage.reset();
+        let started = Instant::now();
         let result = if let Some(limits) = self.limits.clone() {
-            let tracker = LimitedTracker::new(limits);
-            compiled.run(vec![], tracker, &mut print)
+            let tracker = self.metered(LimitedTracker::new(limits));
+            let progress = self.collect_print(|print| compiled.start(inputs, tracker, print));
+            self.run_to_completion(progress)
         } else {
-            compiled.run(vec![], NoLimitTracker, &mut print)
+            let tracker = self.metered(NoLimitTracker);
+            let progress = self.collect_print(|print| compiled.start(inputs, tracker, print));
+            self.run_to_completion(progress)
         };
+        self.record_usage(started);
 
-        self.drain_print(print);
-
-        match result {
-            Ok(obj) => {
-                let val = monty_object_to_json(&obj);
-                let result_json =
-                    build_result_json(val, None, &self.usage_json, &self.print_output);
+        match result.and_then(|obj| self.value_result_json(&obj)) {
+            Ok(result_json) => {
+                let result_json = Arc::<str>::from(result_json);
                 self.state = HandleState::Complete {
                     result_json: result_json.clone(),
                     is_error: false,
+                    retryable: false,
                 };
                 (MontyResultTag::Ok, result_json, None)
             }
             Err(exc) => {
-                let err_json = monty_exception_to_json(&exc);
-                let result_json = build_result_json(
-                    Value::Null,
-                    Some(err_json),
-                    &self.usage_json,
-                    &self.print_output,
-                );
+                self.last_error_code.set(MontyErrorCode::VmException);
+                let exc = self.interrupted(exc);
+                let retryable = self.is_retryable(&exc);
+                let result_json = Arc::<str>::from(self.exception_result_json(&exc, retryable));
                 let msg = exc.summary();
                 self.state = HandleState::Complete {
                     result_json: result_json.clone(),
                     is_error: true,
+                    retryable,
                 };
                 (MontyResultTag::Error, result_json, Some(msg))
             }
@@ -185,38 +837,177 @@ impl MontyHandle {
                 self.state = state;
                 return (
                     MontyProgressTag::Error,
-                    Some("handle not in Ready state".into()),
+                    Some(self.fail(MontyErrorCode::WrongState, "handle not in Ready state")),
                 );
             }
         };
 
+        self.clear_execution();
+        self.usage.reset();
+        let inputs = self.global_values();
         if let Some(limits) = self.limits.clone() {
-            let tracker = LimitedTracker::new(limits);
-            self.run_snapshot_op(|print| compiled.start(vec![], tracker, print))
+            let tracker = self.metered(LimitedTracker::new(limits));
+            self.run_snapshot_op(|print| compiled.start(inputs, tracker, print))
         } else {
-            self.run_snapshot_op(|print| compiled.start(vec![], NoLimitTracker, print))
+            let tracker = self.metered(NoLimitTracker);
+            self.run_snapshot_op(|print| compiled.start(inputs, tracker, print))
         }
     }
 
     /// Resume with a return value (JSON string).
+    ///
+    /// The `{"__monty_type__": "missing"}` sentinel raises `KeyError` in the
+    /// guest instead, so "no value" stays distinct from a returned `None`.
+    ///
+    /// If the value flows straight into another external call, as in
+    /// `outer(inner())`, this returns `Pending` again with that call's
+    /// metadata and a higher `pending_call_id`; nothing of the answered call
+    /// carries over. Values are plain data, so the host cannot hand back
+    /// something for the guest to call.
     pub fn resume(&mut self, value_json: &str) -> (MontyProgressTag, Option<String>) {
-        let val: Value = match serde_json::from_str(value_json) {
-            Ok(v) => v,
-            Err(e) => return (MontyProgressTag::Error, Some(format!("invalid JSON: {e}"))),
-        };
-        let obj = json_to_monty_object(&val);
-        let result = ExternalResult::Return(obj);
-        self.resume_with_result(result)
+        self.resume_reader(value_json.as_bytes())
+    }
+
+    /// Resume with a return value streamed as JSON from `reader`.
+    ///
+    /// Decodes straight into the guest value, so the JSON text is never held
+    /// in full. Same semantics as `resume`, including the missing sentinel.
+    pub fn resume_reader(
+        &mut self,
+        reader: impl std::io::Read,
+    ) -> (MontyProgressTag, Option<String>) {
+        if !matches!(
+            self.state,
+            HandleState::PausedLimited { .. } | HandleState::PausedNoLimit { .. }
+        ) {
+            return (
+                MontyProgressTag::Error,
+                Some(self.fail(MontyErrorCode::WrongState, "handle not in Paused state")),
+            );
+        }
+        match host_value_from_json_reader(reader, &self.decode_options()) {
+            Ok(value) => self.resume_with_result(external_result(value), false),
+            Err(e) => (MontyProgressTag::Error, Some(self.decode_error(e, "JSON"))),
+        }
+    }
+
+    /// Resume with each value of a JSON array in turn, answering successive
+    /// external calls without returning to the host in between.
+    ///
+    /// Stops early when execution reaches anything other than another
+    /// pending call, and returns `Pending` if the values run out first.
+    /// `last_batch_consumed` reports how many values were used.
+    pub fn resume_many(&mut self, values_json: &str) -> (MontyProgressTag, Option<String>) {
+        self.last_batch_consumed = 0;
+        let results =
+            match host_values_from_json_reader(values_json.as_bytes(), &self.decode_options()) {
+                Ok(values) => values.into_iter().map(external_result).collect::<Vec<_>>(),
+                Err(e) => return (MontyProgressTag::Error, Some(self.decode_error(e, "JSON"))),
+            };
+        if !matches!(
+            self.state,
+            HandleState::PausedLimited { .. } | HandleState::PausedNoLimit { .. }
+        ) {
+            return (
+                MontyProgressTag::Error,
+                Some(self.fail(MontyErrorCode::WrongState, "handle not in Paused state")),
+            );
+        }
+        let mut progress = (MontyProgressTag::Pending, None);
+        for result in results {
+            progress = self.resume_with_result(result, false);
+            self.last_batch_consumed += 1;
+            if progress.0 != MontyProgressTag::Pending {
+                break;
+            }
+        }
+        progress
+    }
+
+    /// Number of values the last `resume_many` call consumed.
+    pub fn last_batch_consumed(&self) -> usize {
+        self.last_batch_consumed
     }
 
     /// Resume with an error message.
     pub fn resume_with_error(&mut self, error_message: &str) -> (MontyProgressTag, Option<String>) {
-        let exc = MontyException::new(
-            monty::ExcType::RuntimeError,
-            Some(error_message.to_string()),
-        );
-        let result = ExternalResult::Error(exc);
-        self.resume_with_result(result)
+        self.resume_raising(monty::ExcType::RuntimeError, error_message)
+    }
+
+    /// Resume by raising `exc_type` (a Python exception class name such as
+    /// `"ValueError"`) at the pending call, so guest `except` clauses can
+    /// match it. Unknown names fall back to `RuntimeError`.
+    pub fn resume_with_typed_error(
+        &mut self,
+        exc_type: &str,
+        message: &str,
+    ) -> (MontyProgressTag, Option<String>) {
+        let exc_type = parse_exc_type(exc_type).unwrap_or(monty::ExcType::RuntimeError);
+        self.resume_raising(exc_type, message)
+    }
+
+    /// Resume by raising an exception described as JSON:
+    /// `{"type": "ValueError", "message": "..."}` or, in place of
+    /// `message`, `"args": ["..."]`.
+    ///
+    /// `MontyException` holds a type and an optional message only, so the
+    /// guest sees `e.args` as `()` or `(message,)`. Richer `args` and
+    /// unknown types are rejected without resuming.
+    pub fn resume_with_exception(&mut self, exc_json: &str) -> (MontyProgressTag, Option<String>) {
+        match parse_exception_json(exc_json) {
+            Ok((exc_type, message)) => {
+                let exc = MontyException::new(exc_type, message);
+                self.resume_with_result(ExternalResult::Error(exc), true)
+            }
+            Err((code, e)) => (MontyProgressTag::Error, Some(self.fail(code, e))),
+        }
+    }
+
+    fn resume_raising(
+        &mut self,
+        exc_type: monty::ExcType,
+        message: &str,
+    ) -> (MontyProgressTag, Option<String>) {
+        let exc = MontyException::new(exc_type, Some(message.to_string()));
+        self.resume_with_result(ExternalResult::Error(exc), true)
+    }
+
+    /// Resume an OS call with its result (JSON string). Only valid when
+    /// paused at an OS call.
+    pub fn resume_os_call(&mut self, value_json: &str) -> (MontyProgressTag, Option<String>) {
+        let result =
+            match host_value_from_json_reader(value_json.as_bytes(), &self.decode_options()) {
+                Ok(value) => external_result(value),
+                Err(e) => return (MontyProgressTag::Error, Some(self.decode_error(e, "JSON"))),
+            };
+        if let HandleState::PausedOsCallLimited { meta, .. }
+        | HandleState::PausedOsCallNoLimit { meta, .. } = &self.state
+        {
+            let call_id = meta.call_id;
+            self.log_answer(call_id, &result);
+        }
+        let state = std::mem::replace(&mut self.state, HandleState::Consumed);
+
+        match state {
+            HandleState::PausedOsCallLimited { snapshot, .. } => {
+                self.injected_excs.clear();
+                self.run_snapshot_op(|print| snapshot.run(result, print))
+            }
+            HandleState::PausedOsCallNoLimit { snapshot, .. } => {
+                self.injected_excs.clear();
+                self.run_snapshot_op(|print| snapshot.run(result, print))
+            }
+            other => {
+                self.state = other;
+                (
+                    MontyProgressTag::Error,
+                    Some(self.fail(
+                        MontyErrorCode::WrongState,
+                        "handle not paused at an OS call",
+                    )),
+                )
+            }
+        }
     }
 
     /// Resume by creating a future (tells the VM this call returns a future).
@@ -224,20 +1015,27 @@ impl MontyHandle {
     /// The VM continues executing until all coroutines are blocked, then
     /// yields `ResolveFutures`. Only valid in Paused state.
     pub fn resume_as_future(&mut self) -> (MontyProgressTag, Option<String>) {
+        if let Some(call_id) = self.pending_call_id() {
+            self.log_answer(call_id, &ExternalResult::Future);
+        }
         let state = std::mem::replace(&mut self.state, HandleState::Consumed);
 
         match state {
-            HandleState::PausedLimited { snapshot, .. } => {
+            HandleState::PausedLimited { snapshot, meta } => {
+                self.future_calls.insert(meta.call_id, meta);
+                self.injected_excs.clear();
                 self.run_snapshot_op(|print| snapshot.run_pending(print))
             }
-            HandleState::PausedNoLimit { snapshot, .. } => {
+            HandleState::PausedNoLimit { snapshot, meta } => {
+                self.future_calls.insert(meta.call_id, meta);
+                self.injected_excs.clear();
                 self.run_snapshot_op(|print| snapshot.run_pending(print))
             }
             other => {
                 self.state = other;
                 (
                     MontyProgressTag::Error,
-                    Some("handle not in Paused state".into()),
+                    Some(self.fail(MontyErrorCode::WrongState, "handle not in Paused state")),
                 )
             }
         }
@@ -255,85 +1053,189 @@ impl MontyHandle {
         }
     }
 
+    /// Describe the pending futures as a JSON array of
+    /// `{"call_id", "fn_name", "args", "kwargs", "args_truncated"}` objects,
+    /// in the order of
+    /// `pending_future_call_ids`.
+    ///
+    /// Only valid in Futures state. Lets a host dispatch every gathered
+    /// call without having recorded them at each `Pending` step.
+    pub fn pending_futures_json(&self) -> Option<String> {
+        let call_ids = self.future_call_ids()?;
+        let parse = |json: Option<&str>| {
+            json.and_then(|json| serde_json::from_str(json).ok())
+                .unwrap_or(Value::Null)
+        };
+        let futures: Vec<Value> = call_ids
+            .iter()
+            .filter_map(|id| self.future_calls.get(id))
+            .map(|meta| {
+                serde_json::json!({
+                    "call_id": meta.call_id,
+                    "fn_name": meta.fn_name,
+                    "args": parse(meta.args_json()),
+                    "kwargs": parse(meta.kwargs_json()),
+                    "args_truncated": meta.args_truncated,
+                })
+            })
+            .collect();
+        Some(Value::Array(futures).to_string())
+    }
+
+    /// Call ids of the pending futures, or `None` outside Futures state.
+    fn future_call_ids(&self) -> Option<&[u32]> {
+        match &self.state {
+            HandleState::FuturesLimited { snapshot, .. } => Some(snapshot.pending_call_ids()),
+            HandleState::FuturesNoLimit { snapshot, .. } => Some(snapshot.pending_call_ids()),
+            _ => None,
+        }
+    }
+
     /// Resume futures with results and errors.
     ///
     /// - `results_json`: JSON object `{"call_id": value, ...}` (string keys)
     /// - `errors_json`: JSON object `{"call_id": "error_message", ...}` (string keys), or empty
+    ///
+    /// The two may cover only some of the pending call ids. If coroutines
+    /// are still blocked on the rest, the handle re-enters Futures state
+    /// (`ResolveFutures`) with the remaining ids, so answers can be supplied
+    /// as they arrive. A call id that is not pending is an error.
     pub fn resume_futures(
         &mut self,
         results_json: &str,
         errors_json: &str,
     ) -> (MontyProgressTag, Option<String>) {
-        let results_map: serde_json::Map<String, Value> = match serde_json::from_str(results_json) {
+        self.resume_futures_with_timeouts(results_json, errors_json, "[]")
+    }
+
+    /// Resume futures like `resume_futures`, also raising `TimeoutError` for
+    /// each call id in `timeouts_json` (a JSON array such as `[3, 4]`), so
+    /// the guest sees the same error as `asyncio.wait_for` timing out.
+    pub fn resume_futures_with_timeouts(
+        &mut self,
+        results_json: &str,
+        errors_json: &str,
+        timeouts_json: &str,
+    ) -> (MontyProgressTag, Option<String>) {
+        let options = self.decode_options();
+        let results = match keyed_host_values_from_json_reader(results_json.as_bytes(), &options) {
             Ok(v) => v,
             Err(e) => {
                 return (
                     MontyProgressTag::Error,
-                    Some(format!("invalid results JSON: {e}")),
+                    Some(self.decode_error(e, "results JSON")),
                 );
             }
         };
-        let errors_map: serde_json::Map<String, Value> = match serde_json::from_str(errors_json) {
+        let errors = match keyed_host_values_from_json_reader(errors_json.as_bytes(), &options) {
             Ok(v) => v,
             Err(e) => {
                 return (
                     MontyProgressTag::Error,
-                    Some(format!("invalid errors JSON: {e}")),
+                    Some(self.decode_error(e, "errors JSON")),
                 );
             }
         };
+        let timeouts: Vec<u32> = match serde_json::from_str(timeouts_json) {
+            Ok(v) => v,
+            Err(e) => {
+                return (
+                    MontyProgressTag::Error,
+                    Some(self.fail(
+                        MontyErrorCode::InvalidJson,
+                        format!("invalid timeouts JSON: {e}"),
+                    )),
+                );
+            }
+        };
+
+        let Some(pending) = self.future_call_ids().map(<[u32]>::to_vec) else {
+            return (
+                MontyProgressTag::Error,
+                Some(self.fail(MontyErrorCode::WrongState, "handle not in Futures state")),
+            );
+        };
+        let parse_call_id = |key: &str| match key.parse::<u32>() {
+            Ok(id) if pending.contains(&id) => Ok(id),
+            Ok(_) => Err(format!("unknown call_id: {key}")),
+            Err(_) => Err(format!("invalid call_id: {key}")),
+        };
 
         let mut ext_results: Vec<(u32, ExternalResult)> = Vec::new();
+        let mut injected = Vec::new();
 
-        for (key, val) in &results_map {
-            let call_id: u32 = match key.parse() {
+        for (key, value) in results {
+            let call_id = match parse_call_id(&key) {
                 Ok(id) => id,
-                Err(_) => {
-                    return (
-                        MontyProgressTag::Error,
-                        Some(format!("invalid call_id: {key}")),
-                    );
-                }
+                Err(e) => return (MontyProgressTag::Error, Some(e)),
             };
-            let obj = json_to_monty_object(val);
-            ext_results.push((call_id, ExternalResult::Return(obj)));
+            ext_results.push((call_id, external_result(value)));
         }
 
-        for (key, val) in &errors_map {
-            let call_id: u32 = match key.parse() {
+        for (key, value) in errors {
+            let call_id = match parse_call_id(&key) {
                 Ok(id) => id,
-                Err(_) => {
-                    return (
-                        MontyProgressTag::Error,
-                        Some(format!("invalid call_id: {key}")),
-                    );
-                }
+                Err(e) => return (MontyProgressTag::Error, Some(e)),
+            };
+            let msg = match value {
+                HostValue::Value(monty::MontyObject::String(msg)) => msg,
+                _ => "unknown error".to_string(),
             };
-            let msg = val.as_str().unwrap_or("unknown error").to_string();
             let exc = MontyException::new(monty::ExcType::RuntimeError, Some(msg));
+            injected.push(InjectedExc::of(&exc));
             ext_results.push((call_id, ExternalResult::Error(exc)));
         }
 
-        let state = std::mem::replace(&mut self.state, HandleState::Consumed);
-
-        match state {
-            HandleState::FuturesLimited { snapshot, .. } => {
-                self.run_snapshot_op(|print| snapshot.resume(ext_results, print))
-            }
-            HandleState::FuturesNoLimit { snapshot, .. } => {
-                self.run_snapshot_op(|print| snapshot.resume(ext_results, print))
-            }
-            other => {
-                self.state = other;
-                (
+        for call_id in &timeouts {
+            if !pending.contains(call_id) {
+                return (
                     MontyProgressTag::Error,
-                    Some("handle not in Futures state".into()),
-                )
+                    Some(format!("unknown call_id: {call_id}")),
+                );
             }
+            let exc = MontyException::new(
+                monty::ExcType::TimeoutError,
+                Some(format!("future {call_id} timed out")),
+            );
+            injected.push(InjectedExc::of(&exc));
+            ext_results.push((*call_id, ExternalResult::Error(exc)));
         }
+
+        self.resolve_futures(ext_results, injected)
     }
 
-    /// Get the pending function name (only valid in Paused state).
+    /// Fail every pending future with the same typed exception.
+    ///
+    /// `exc_type` is a Python exception class name such as `"ConnectionError"`.
+    /// Only valid in Futures state.
+    pub fn fail_all_futures(
+        &mut self,
+        exc_type: &str,
+        message: &str,
+    ) -> (MontyProgressTag, Option<String>) {
+        let exc_type = match parse_exc_type(exc_type) {
+            Ok(t) => t,
+            Err(e) => return (MontyProgressTag::Error, Some(e)),
+        };
+        let Some(call_ids) = self.future_call_ids().map(<[u32]>::to_vec) else {
+            return (
+                MontyProgressTag::Error,
+                Some(self.fail(MontyErrorCode::WrongState, "handle not in Futures state")),
+            );
+        };
+        let exc = MontyException::new(exc_type, Some(message.to_string()));
+        let injected = vec![InjectedExc::of(&exc)];
+        let ext_results = call_ids
+            .into_iter()
+            .map(|id| {
+                let exc = MontyException::new(exc_type, Some(message.to_string()));
+                (id, ExternalResult::Error(exc))
+            })
+            .collect();
+        self.resolve_futures(ext_results, injected)
+    }
+
+    /// Get the pending function name (only valid in Paused state).
     pub fn pending_fn_name(&self) -> Option<&str> {
         match &self.state {
             HandleState::PausedLimited { meta, .. } | HandleState::PausedNoLimit { meta, .. } => {
@@ -344,10 +1246,38 @@ impl MontyHandle {
     }
 
     /// Get the pending function args as JSON (only valid in Paused state).
+    ///
+    /// Encoded on first read under the conversion options in effect at the
+    /// pause, and cached; later option changes don't apply to this call.
     pub fn pending_fn_args_json(&self) -> Option<&str> {
         match &self.state {
             HandleState::PausedLimited { meta, .. } | HandleState::PausedNoLimit { meta, .. } => {
-                Some(meta.args_json.as_str())
+                meta.args_json()
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of positional arguments the pending call was made with, even
+    /// if some were dropped under `set_max_call_args_bytes` (only valid in
+    /// Paused state).
+    pub fn pending_fn_args_count(&self) -> Option<usize> {
+        match &self.state {
+            HandleState::PausedLimited { meta, .. } | HandleState::PausedNoLimit { meta, .. } => {
+                Some(meta.args_count)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get one pending function argument as JSON encoded with
+    /// `typed_containers`, whatever the handle's own setting (only valid in
+    /// Paused state). `None` if `index` is out of range or the tagged
+    /// encoding breaks a conversion limit.
+    pub fn pending_fn_arg_tagged_json(&self, index: usize) -> Option<&str> {
+        match &self.state {
+            HandleState::PausedLimited { meta, .. } | HandleState::PausedNoLimit { meta, .. } => {
+                meta.arg_tagged_json(index)
             }
             _ => None,
         }
@@ -356,11 +1286,12 @@ impl MontyHandle {
     /// Get the pending function kwargs as JSON (only valid in Paused state).
     ///
     /// Returns a JSON object string like `{"key": value}`, or `"{}"` if no
-    /// keyword arguments were passed.
+    /// keyword arguments were passed. Encoded lazily like
+    /// `pending_fn_args_json`.
     pub fn pending_fn_kwargs_json(&self) -> Option<&str> {
         match &self.state {
             HandleState::PausedLimited { meta, .. } | HandleState::PausedNoLimit { meta, .. } => {
-                Some(meta.kwargs_json.as_str())
+                meta.kwargs_json()
             }
             _ => None,
         }
@@ -391,14 +1322,81 @@ impl MontyHandle {
         }
     }
 
+    /// Whether arguments of the pending call were left out for breaking
+    /// `set_max_call_args_bytes` (only valid in Paused state).
+    pub fn pending_args_truncated(&self) -> Option<bool> {
+        match &self.state {
+            HandleState::PausedLimited { meta, .. } | HandleState::PausedNoLimit { meta, .. } => {
+                Some(meta.args_truncated)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the pending OS call's function name, e.g. `"os.getenv"` (only
+    /// valid when paused at an OS call).
+    pub fn pending_os_call_name(&self) -> Option<&str> {
+        match &self.state {
+            HandleState::PausedOsCallLimited { meta, .. }
+            | HandleState::PausedOsCallNoLimit { meta, .. } => Some(meta.fn_name.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Get the pending OS call's args as JSON (only valid when paused at an
+    /// OS call).
+    pub fn pending_os_call_args_json(&self) -> Option<&str> {
+        match &self.state {
+            HandleState::PausedOsCallLimited { meta, .. }
+            | HandleState::PausedOsCallNoLimit { meta, .. } => meta.args_json(),
+            _ => None,
+        }
+    }
+
+    /// Usage accumulated by the current execution, valid in any state.
+    ///
+    /// Returns `"{}"` if nothing has run since the handle was created or
+    /// its output was cleared.
+    pub fn usage_json(&self) -> &str {
+        self.usage_json.as_deref().unwrap_or("{}")
+    }
+
     /// Get the complete result as JSON (only valid in Complete state).
     pub fn complete_result_json(&self) -> Option<&str> {
         match &self.state {
-            HandleState::Complete { result_json, .. } => Some(result_json.as_str()),
+            HandleState::Complete { result_json, .. } => Some(result_json),
             _ => None,
         }
     }
 
+    /// Whether the handle has spent its program: it completed, or an
+    /// execution consumed it without finishing. Only `reset` makes it
+    /// runnable again.
+    pub fn is_consumed(&self) -> bool {
+        matches!(
+            self.state,
+            HandleState::Complete { .. } | HandleState::Consumed
+        )
+    }
+
+    /// Current lifecycle state of the handle.
+    pub fn state_tag(&self) -> MontyStateTag {
+        match &self.state {
+            HandleState::Ready(_) => MontyStateTag::Ready,
+            HandleState::PausedLimited { .. } | HandleState::PausedNoLimit { .. } => {
+                MontyStateTag::Paused
+            }
+            HandleState::PausedOsCallLimited { .. } | HandleState::PausedOsCallNoLimit { .. } => {
+                MontyStateTag::OsCall
+            }
+            HandleState::FuturesLimited { .. } | HandleState::FuturesNoLimit { .. } => {
+                MontyStateTag::Futures
+            }
+            HandleState::Complete { .. } => MontyStateTag::Complete,
+            HandleState::Consumed => MontyStateTag::Consumed,
+        }
+    }
+
     /// Whether the complete result is an error.
     pub fn complete_is_error(&self) -> Option<bool> {
         match &self.state {
@@ -407,290 +1405,2541 @@ impl MontyHandle {
         }
     }
 
+    /// Whether the completed result is a retryable error (only valid in
+    /// Complete state). Successful results are not retryable.
+    pub fn complete_is_retryable(&self) -> Option<bool> {
+        match &self.state {
+            HandleState::Complete { retryable, .. } => Some(*retryable),
+            _ => None,
+        }
+    }
+
     /// Serialize the compiled code to bytes (snapshot).
+    ///
+    /// Returns the buffer computed by a preceding `snapshot_size` call, if
+    /// any, instead of serializing again.
+    ///
+    /// A handle paused at an external function call is serialized together
+    /// with its VM state, so `restore` yields a handle ready to `resume`.
     pub fn snapshot(&self) -> Result<Vec<u8>, String> {
         match &self.state {
             HandleState::Ready(compiled) => {
-                compiled.dump().map_err(|e| format!("snapshot failed: {e}"))
+                if let Some(bytes) = self.snapshot_cache.take() {
+                    return Ok(bytes);
+                }
+                let body = compiled
+                    .dump()
+                    .map_err(|e| format!("snapshot failed: {e}"))?;
+                let mut bytes = snapshot_header(body.len());
+                bytes.extend_from_slice(&body);
+                Ok(bytes)
+            }
+            HandleState::PausedLimited { snapshot, meta } => {
+                self.paused_snapshot(PAUSED_LIMITED, snapshot, meta)
             }
-            _ => Err("can only snapshot in Ready state".into()),
+            HandleState::PausedNoLimit { snapshot, meta } => {
+                self.paused_snapshot(PAUSED_NO_LIMIT, snapshot, meta)
+            }
+            _ => Err(self.fail(
+                MontyErrorCode::WrongState,
+                "can only snapshot in Ready or Paused state",
+            )),
         }
     }
 
-    /// Restore a handle from serialized bytes.
-    pub fn restore(bytes: &[u8]) -> Result<Self, String> {
-        let compiled = MontyRun::load(bytes).map_err(|e| format!("restore failed: {e}"))?;
-        Ok(Self {
-            state: HandleState::Ready(compiled),
-            limits: None,
-            usage_json: default_usage_json(),
-            print_output: String::new(),
-        })
-    }
-
-    /// Set memory limit in bytes.
-    pub fn set_memory_limit(&mut self, bytes: usize) {
-        let limits = self.limits.get_or_insert_with(ResourceLimits::new);
-        limits.max_memory = Some(bytes);
+    fn paused_snapshot<T: Serialize>(
+        &self,
+        tracker: u8,
+        snapshot: &T,
+        meta: &PendingMeta,
+    ) -> Result<Vec<u8>, String> {
+        let image = PausedImage {
+            source: self.source.clone(),
+            external_functions: self.external_functions.clone(),
+            snapshot,
+            meta: meta.clone(),
+            config: PausedConfig {
+                globals: self.globals.clone(),
+                limits: self.limits.clone(),
+                options: self.options.clone(),
+                label: self.label.clone(),
+                stdin: self.stdin.clone(),
+                constants: self.constants.clone(),
+                compute_time_limit: self.compute_time_limit,
+                ext_call_count: self.ext_call_count,
+                max_external_calls: self.max_external_calls,
+                max_call_args_bytes: self.max_call_args_bytes,
+            },
+        };
+        let body = postcard::to_allocvec(&image).map_err(|e| format!("snapshot failed: {e}"))?;
+        let mut bytes = snapshot_header(PAUSED_SNAPSHOT_MAGIC.len() + 1 + body.len());
+        bytes.extend_from_slice(PAUSED_SNAPSHOT_MAGIC);
+        bytes.push(tracker);
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
     }
 
-    /// Set time limit in milliseconds.
-    pub fn set_time_limit_ms(&mut self, ms: u64) {
-        let limits = self.limits.get_or_insert_with(ResourceLimits::new);
-        limits.max_duration = Some(Duration::from_millis(ms));
+    /// `snapshot`, compressed with `codec`. `restore` detects the codec.
+    pub fn snapshot_with_codec(&self, codec: SnapshotCodec) -> Result<Vec<u8>, String> {
+        compress_snapshot(self.snapshot()?, codec)
     }
 
-    /// Set stack depth limit.
-    pub fn set_stack_limit(&mut self, depth: usize) {
-        let limits = self.limits.get_or_insert_with(ResourceLimits::new);
-        limits.max_recursion_depth = Some(depth);
+    /// Length in bytes of the snapshot `snapshot` would return.
+    ///
+    /// The engine can only measure by serializing, so the bytes are cached
+    /// and handed out by the next `snapshot` call. Paused snapshots are not
+    /// cached since resuming changes them.
+    pub fn snapshot_size(&self) -> Result<usize, String> {
+        if !matches!(self.state, HandleState::Ready(_)) {
+            return self.snapshot().map(|bytes| bytes.len());
+        }
+        if let Some(bytes) = self.snapshot_cache.borrow().as_ref() {
+            return Ok(bytes.len());
+        }
+        let bytes = self.snapshot()?;
+        let len = bytes.len();
+        *self.snapshot_cache.borrow_mut() = Some(bytes);
+        Ok(len)
     }
 
-    // --- private helpers ---
-
-    fn drain_print(&mut self, print: PrintWriter) {
-        if let PrintWriter::Collect(collected) = print {
-            self.print_output.push_str(&collected);
+    /// Restore a handle from serialized bytes.
+    ///
+    /// Bytes taken while paused restore to the same pending call. Bytes
+    /// from a different `SNAPSHOT_FORMAT_VERSION` are rejected up front.
+    /// Compressed snapshots are decompressed first.
+    pub fn restore(bytes: &[u8]) -> Result<Self, String> {
+        if let Some(decompressed) = decompress_snapshot(bytes, MAX_SNAPSHOT_BYTES)? {
+            // Compression wraps a whole headed snapshot, so anything else
+            // inside a frame (another frame included) is rejected.
+            let Some(rest) = decompressed.strip_prefix(SNAPSHOT_MAGIC) else {
+                return Err("restore failed: compressed snapshot has no header".into());
+            };
+            return Self::restore_headed(rest);
         }
+        let Some(rest) = bytes.strip_prefix(SNAPSHOT_MAGIC) else {
+            // Headerless snapshot from before versioning.
+            return Self::restore_body(bytes);
+        };
+        Self::restore_headed(rest)
     }
 
-    fn run_snapshot_op<T: TrackerExt>(
-        &mut self,
-        f: impl FnOnce(&mut PrintWriter) -> Result<RunProgress<T>, MontyException>,
-    ) -> (MontyProgressTag, Option<String>) {
-        let mut print = PrintWriter::Collect(String::new());
-        let result = f(&mut print);
-        self.drain_print(print);
-        match result {
-            Ok(progress) => self.process_progress(progress),
-            Err(exc) => self.handle_exception(exc),
+    /// Restore from the bytes after `SNAPSHOT_MAGIC`: the format version,
+    /// then the body.
+    fn restore_headed(rest: &[u8]) -> Result<Self, String> {
+        let Some((version, body)) = rest.split_first_chunk::<4>() else {
+            return Err("restore failed: snapshot header truncated".into());
+        };
+        let version = u32::from_le_bytes(*version);
+        if version != SNAPSHOT_FORMAT_VERSION {
+            return Err(format!(
+                "snapshot version mismatch: got {version}, expected {SNAPSHOT_FORMAT_VERSION}"
+            ));
         }
+        Self::restore_body(body)
     }
 
-    fn resume_with_result(&mut self, result: ExternalResult) -> (MontyProgressTag, Option<String>) {
-        let state = std::mem::replace(&mut self.state, HandleState::Consumed);
-
-        match state {
-            HandleState::PausedLimited { snapshot, .. } => {
-                self.run_snapshot_op(|print| snapshot.run(result, print))
-            }
-            HandleState::PausedNoLimit { snapshot, .. } => {
-                self.run_snapshot_op(|print| snapshot.run(result, print))
-            }
-            other => {
-                self.state = other;
-                (
-                    MontyProgressTag::Error,
-                    Some("handle not in Paused state".into()),
-                )
-            }
+    fn restore_body(bytes: &[u8]) -> Result<Self, String> {
+        if let Some(rest) = bytes.strip_prefix(PAUSED_SNAPSHOT_MAGIC) {
+            return Self::restore_paused(rest);
         }
+        let compiled = MontyRun::load(bytes).map_err(|e| format!("restore failed: {e}"))?;
+        let source = compiled.code().to_string();
+        Ok(Self::restored(HandleState::Ready(compiled), source, vec![]))
     }
 
-    fn process_progress<T: TrackerExt>(
-        &mut self,
-        progress: RunProgress<T>,
-    ) -> (MontyProgressTag, Option<String>) {
-        match progress {
-            RunProgress::Complete(obj) => {
-                let val = monty_object_to_json(&obj);
-                let result_json =
-                    build_result_json(val, None, &self.usage_json, &self.print_output);
-                self.state = HandleState::Complete {
-                    result_json,
-                    is_error: false,
+    fn restore_paused(bytes: &[u8]) -> Result<Self, String> {
+        let usage;
+        let (state, source, external_functions, config) = match bytes.split_first() {
+            Some((&PAUSED_LIMITED, body)) => {
+                let mut image: PausedImage<Snapshot<MeteredTracker<LimitedTracker>>> =
+                    postcard::from_bytes(body).map_err(|e| format!("restore failed: {e}"))?;
+                usage = image.snapshot.tracker_mut().stats().clone();
+                let state = HandleState::PausedLimited {
+                    snapshot: image.snapshot,
+                    meta: image.meta,
                 };
-                (MontyProgressTag::Complete, None)
-            }
-            RunProgress::FunctionCall {
-                function_name,
-                args,
-                kwargs,
-                call_id,
-                method_call,
-                state: snapshot,
-            } => {
-                let meta = build_pending_meta(function_name, &args, &kwargs, call_id, method_call);
-                self.state = T::into_paused(snapshot, meta);
-                (MontyProgressTag::Pending, None)
-            }
-            RunProgress::ResolveFutures(snapshot) => {
-                let call_ids_json = serde_json::to_string(snapshot.pending_call_ids())
-                    .unwrap_or_else(|_| "[]".into());
-                self.state = T::into_futures(snapshot, call_ids_json);
-                (MontyProgressTag::ResolveFutures, None)
+                (state, image.source, image.external_functions, image.config)
             }
-            RunProgress::OsCall { .. } => {
-                self.state = HandleState::Complete {
-                    result_json: build_result_json(
-                        Value::Null,
-                        Some(serde_json::json!({"message": "unsupported progress type: OsCall"})),
-                        &self.usage_json,
-                        &self.print_output,
-                    ),
-                    is_error: true,
+            Some((&PAUSED_NO_LIMIT, body)) => {
+                let mut image: PausedImage<Snapshot<MeteredTracker<NoLimitTracker>>> =
+                    postcard::from_bytes(body).map_err(|e| format!("restore failed: {e}"))?;
+                usage = image.snapshot.tracker_mut().stats().clone();
+                let state = HandleState::PausedNoLimit {
+                    snapshot: image.snapshot,
+                    meta: image.meta,
                 };
-                (
-                    MontyProgressTag::Error,
-                    Some("unsupported progress type: OsCall".into()),
-                )
+                (state, image.source, image.external_functions, image.config)
             }
+            _ => return Err("restore failed: unknown paused snapshot kind".into()),
+        };
+        let mut handle = Self::restored(state, source, external_functions);
+        handle.usage = usage;
+        handle.globals = config.globals;
+        handle.limits = config.limits;
+        handle.options = config.options;
+        handle.label = config.label;
+        handle.stdin = config.stdin;
+        handle.constants = config.constants;
+        handle.compute_time_limit = config.compute_time_limit;
+        handle.ext_call_count = config.ext_call_count;
+        handle.max_external_calls = config.max_external_calls;
+        handle.max_call_args_bytes = config.max_call_args_bytes;
+        // Restored trackers start with fresh stats: arm the compute limit
+        // again and count the wait for the next resume as host time.
+        handle.usage.set_compute_limit(handle.compute_time_limit);
+        handle.paused_at = Some(Instant::now());
+        Ok(handle)
+    }
+
+    fn restored(state: HandleState, source: String, external_functions: Vec<String>) -> Self {
+        Self {
+            source,
+            state,
+            script_name: None,
+            external_functions,
+            globals: Vec::new(),
+            limits: None,
+            usage_json: None,
+            usage: Arc::default(),
+            compile_ms: None,
+            compile_timeout: None,
+            print_output: String::new(),
+            print_output_truncated: false,
+            options: HandleOptions::default(),
+            label: None,
+            os_handlers: HashMap::new(),
+            print_callback: None,
+            stdin: None,
+            constants: HashMap::new(),
+            last_batch_consumed: 0,
+            future_calls: HashMap::new(),
+            compute_time_limit: None,
+            ext_call_count: 0,
+            max_external_calls: None,
+            max_call_args_bytes: None,
+            paused_at: None,
+            last_error_code: Cell::default(),
+            injected_excs: Vec::new(),
+            snapshot_cache: RefCell::new(None),
+            event_log: None,
         }
     }
 
-    fn handle_exception(&mut self, exc: MontyException) -> (MontyProgressTag, Option<String>) {
-        let err_json = monty_exception_to_json(&exc);
-        let result_json = build_result_json(
-            Value::Null,
-            Some(err_json),
-            &self.usage_json,
-            &self.print_output,
-        );
-        let msg = exc.summary();
-        self.state = HandleState::Complete {
-            result_json,
-            is_error: true,
+    /// Duplicate a Ready handle so the same program can run independently,
+    /// e.g. under different limits.
+    ///
+    /// The clone keeps the limits, options, label and externals but starts
+    /// with empty print output and default usage. OS call handlers are not
+    /// cloned. Paused handles can't be cloned: the engine's snapshots are
+    /// consumed when resumed and expose no copy.
+    pub fn try_clone(&self) -> Result<Self, String> {
+        let HandleState::Ready(compiled) = &self.state else {
+            return Err(self.fail(MontyErrorCode::WrongState, "can only clone in Ready state"));
         };
-        (MontyProgressTag::Error, Some(msg))
+        let bytes = compiled.dump().map_err(|e| format!("clone failed: {e}"))?;
+        let compiled = MontyRun::load(&bytes).map_err(|e| format!("clone failed: {e}"))?;
+        Ok(Self {
+            state: HandleState::Ready(compiled),
+            script_name: self.script_name.clone(),
+            source: self.source.clone(),
+            external_functions: self.external_functions.clone(),
+            globals: self.globals.clone(),
+            limits: self.limits.clone(),
+            usage_json: None,
+            usage: Arc::default(),
+            compile_ms: self.compile_ms,
+            compile_timeout: self.compile_timeout,
+            print_output: String::new(),
+            print_output_truncated: false,
+            options: self.options.clone(),
+            label: self.label.clone(),
+            os_handlers: HashMap::new(),
+            print_callback: None,
+            stdin: self.stdin.clone(),
+            constants: self.constants.clone(),
+            last_batch_consumed: 0,
+            future_calls: HashMap::new(),
+            compute_time_limit: self.compute_time_limit,
+            ext_call_count: 0,
+            max_external_calls: self.max_external_calls,
+            max_call_args_bytes: self.max_call_args_bytes,
+            paused_at: None,
+            last_error_code: Cell::default(),
+            injected_excs: Vec::new(),
+            snapshot_cache: RefCell::new(None),
+            event_log: self.event_log.as_ref().map(EventLog::emptied),
+        })
     }
-}
 
-/// Build a `PendingMeta` from a `FunctionCall` variant's fields.
-fn build_pending_meta(
-    function_name: String,
-    args: &[monty::MontyObject],
-    kwargs: &[(monty::MontyObject, monty::MontyObject)],
-    call_id: u32,
-    method_call: bool,
-) -> PendingMeta {
-    let args_json =
-        serde_json::to_string(&args.iter().map(monty_object_to_json).collect::<Vec<_>>())
-            .unwrap_or_else(|_| "[]".into());
+    /// Declare an additional external function (only valid in Ready state).
+    ///
+    /// The engine fixes the external set at compile time, so the program is
+    /// recompiled from its source with the updated set. Adding a name that
+    /// is already declared is a no-op. `name` must be a Python identifier.
+    pub fn add_external(&mut self, name: &str) -> Result<(), String> {
+        if !is_identifier(name) {
+            return Err(format!("invalid external function name: {name:?}"));
+        }
+        if self.external_functions.iter().any(|f| f == name) {
+            return Ok(());
+        }
+        let mut external_functions = self.external_functions.clone();
+        external_functions.push(name.to_string());
+        self.recompile(external_functions)
+    }
 
-    let kwargs_json = if kwargs.is_empty() {
-        "{}".into()
-    } else {
-        let map: serde_json::Map<String, Value> = kwargs
+    /// Remove a declared external function (only valid in Ready state).
+    ///
+    /// Guest code referencing a removed name raises `NameError` when run.
+    /// Any constant, stdin text or serviceable entry for the name goes too.
+    pub fn remove_external(&mut self, name: &str) -> Result<(), String> {
+        if !self.external_functions.iter().any(|f| f == name) {
+            return Err(format!("external function not declared: {name}"));
+        }
+        let external_functions = self
+            .external_functions
             .iter()
-            .map(|(k, v)| {
-                let key = if let monty::MontyObject::String(s) = k {
-                    s.clone()
-                } else {
-                    format!("{k}")
-                };
-                (key, monty_object_to_json(v))
-            })
+            .filter(|f| *f != name)
+            .cloned()
             .collect();
-        serde_json::to_string(&map).unwrap_or_else(|_| "{}".into())
-    };
-
-    PendingMeta {
-        fn_name: function_name,
-        args_json,
-        kwargs_json,
-        call_id,
-        method_call,
+        self.recompile(external_functions)?;
+        self.constants.remove(name);
+        if name == STDIN_EXTERNAL {
+            self.stdin = None;
+        }
+        if let Some(names) = &mut self.options.serviceable_externals {
+            names.retain(|n| n != name);
+        }
+        Ok(())
     }
-}
 
-fn default_usage_json() -> String {
-    r#"{"memory_bytes_used":0,"time_elapsed_ms":0,"stack_depth_used":0}"#.into()
-}
-
-fn build_result_json(
-    value: Value,
-    error: Option<Value>,
-    usage_json: &str,
-    print_output: &str,
-) -> String {
-    let usage: Value = serde_json::from_str(usage_json).unwrap_or(serde_json::json!({
-        "memory_bytes_used": 0,
-        "time_elapsed_ms": 0,
-        "stack_depth_used": 0,
-    }));
-    let mut result = serde_json::json!({
-        "value": value,
-        "usage": usage,
-    });
-    if let Some(err) = error {
-        result.as_object_mut().unwrap().insert("error".into(), err);
+    /// Names the VM treats as external functions. Empty for handles
+    /// restored from a Ready snapshot, which doesn't record them.
+    pub fn external_functions(&self) -> &[String] {
+        &self.external_functions
     }
-    if !print_output.is_empty() {
-        result
-            .as_object_mut()
-            .unwrap()
-            .insert("print_output".into(), Value::String(print_output.into()));
+
+    /// Emit only `exc_type` and `message` for guest exceptions, skipping
+    /// traceback serialization. Off by default.
+    pub fn set_minimal_errors(&mut self, enabled: bool) {
+        self.options.minimal_errors = enabled;
     }
-    serde_json::to_string(&result).unwrap_or_default()
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Cap the length (in characters) of any single string in a result or
+    /// external call argument, keyword names included. The engine has no
+    /// per-object string cap, so this is enforced when the value is
+    /// serialized: the run fails with a host-side serialization error
+    /// (reported as `MemoryError`) that the guest cannot catch.
+    pub fn set_max_string_length(&mut self, chars: usize) {
+        self.options.conversion.max_string_length = Some(chars);
+    }
 
-    #[test]
-    fn test_create_handle() {
-        let handle = MontyHandle::new("2 + 2".into(), vec![], None);
-        assert!(handle.is_ok());
+    /// Truncate any single collection in a result or external call argument
+    /// to `n` elements, ending it with a `{"__monty_elided__": remaining}`
+    /// marker.
+    pub fn set_max_collection_width(&mut self, n: usize) {
+        self.options.conversion.max_collection_width = Some(n);
     }
 
-    #[test]
-    fn test_create_handle_syntax_error() {
-        let handle = MontyHandle::new("def".into(), vec![], None);
-        assert!(handle.is_err());
+    /// Tag tuples, sets, frozensets, bytes, non-finite floats, integers
+    /// beyond i64 and non-string-keyed dicts (`{"__tuple__": [...]}` etc.)
+    /// in results and external call arguments so they keep their Python
+    /// type. Values from the host (resume values, run arguments and
+    /// constants) decode these tags only while this is on.
+    pub fn set_typed_containers(&mut self, enabled: bool) {
+        self.options.conversion.typed_containers = enabled;
     }
 
-    #[test]
-    fn test_run_simple() {
-        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
-        let (tag, result_json, err) = handle.run();
-        assert_eq!(tag, MontyResultTag::Ok);
-        assert!(err.is_none());
-        let parsed: Value = serde_json::from_str(&result_json).unwrap();
-        assert_eq!(parsed["value"], json!(4));
+    /// Emit dicts keyed by ints and bools as JSON objects with stringified
+    /// keys and a `__key_types__` entry, instead of `[k, v]` pairs. Values
+    /// from the host restore `__key_types__` keys only while this is on.
+    pub fn set_stringify_int_keys(&mut self, enabled: bool) {
+        self.options.conversion.stringify_int_keys = enabled;
     }
 
-    #[test]
-    fn test_run_error() {
-        let mut handle = MontyHandle::new("1/0".into(), vec![], None).unwrap();
-        let (tag, _, err) = handle.run();
-        assert_eq!(tag, MontyResultTag::Error);
-        assert!(err.is_some());
+    /// Emit `-0.0` as-is in results and external call arguments instead of
+    /// normalizing it to `0.0`.
+    pub fn set_preserve_negative_zero(&mut self, enabled: bool) {
+        self.options.conversion.preserve_negative_zero = enabled;
     }
 
-    #[test]
-    fn test_run_not_ready() {
-        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
-        handle.run(); // consume Ready state
-        let (tag, _, err) = handle.run();
-        assert_eq!(tag, MontyResultTag::Error);
-        assert!(err.unwrap().contains("not in Ready state"));
+    /// Choose how floats with no fractional part (`3.0`) are emitted in
+    /// results and external call arguments.
+    pub fn set_integral_float_policy(&mut self, policy: IntegralFloatPolicy) {
+        self.options.conversion.integral_float_policy = policy;
     }
 
-    #[test]
-    fn test_set_limits() {
-        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
-        handle.set_memory_limit(1024 * 1024);
-        handle.set_time_limit_ms(5000);
-        handle.set_stack_limit(100);
-        let (tag, _, _) = handle.run();
-        assert_eq!(tag, MontyResultTag::Ok);
+    /// Always include `print_output` in the result JSON (empty string when
+    /// nothing was printed). Off by default, which omits the key when empty.
+    pub fn set_always_emit_print(&mut self, enabled: bool) {
+        self.options.always_emit_print = enabled;
     }
 
-    #[test]
-    fn test_snapshot_restore() {
-        let handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
-        let bytes = handle.snapshot().unwrap();
-        assert!(!bytes.is_empty());
+    /// Include `value_repr`, the Python `repr()` of the result value, in
+    /// successful result JSON. Off by default.
+    pub fn set_value_repr(&mut self, enabled: bool) {
+        self.options.value_repr = enabled;
+    }
 
-        let mut restored = MontyHandle::restore(&bytes).unwrap();
-        let (tag, result_json, _) = restored.run();
-        assert_eq!(tag, MontyResultTag::Ok);
-        let parsed: Value = serde_json::from_str(&result_json).unwrap();
-        assert_eq!(parsed["value"], json!(4));
+    /// Pause iterative execution with `MontyProgressTag::OsCall` on an OS
+    /// call no registered handler answers, for the host to answer with
+    /// `resume_os_call`. Off by default: such a call ends the run with
+    /// `NotImplementedError`, as it always does under `run`.
+    pub fn set_pause_os_calls(&mut self, enabled: bool) {
+        self.options.pause_os_calls = enabled;
     }
 
-    #[test]
+    /// Return the handle to Ready so `run`/`start` can execute the program
+    /// again, clearing print output, usage, the external call count and the
+    /// event log.
+    ///
+    /// Outside Ready state the program is re-derived by recompiling the
+    /// retained source. Fails for a restored handle that has left Ready,
+    /// since it keeps no compiled program to return to.
+    pub fn reset(&mut self) -> Result<(), String> {
+        if !matches!(self.state, HandleState::Ready(_)) {
+            let Some(name) = &self.script_name else {
+                return Err("cannot reset a restored handle: compiled program not retained".into());
+            };
+            let (compiled, compile_ms) = compile_with_budget(
+                self.source.clone(),
+                name,
+                self.global_names(),
+                self.external_functions.clone(),
+                self.compile_timeout,
+            )
+            .map_err(|e| e.summary())?;
+            self.state = HandleState::Ready(compiled);
+            self.compile_ms = Some(compile_ms);
+            self.snapshot_cache.take();
+        }
+        self.clear_execution();
+        self.clear_output();
+        Ok(())
+    }
+
+    /// Empty the print buffer and reset usage to defaults, leaving the
+    /// execution state untouched.
+    pub fn clear_output(&mut self) {
+        self.print_output.clear();
+        self.print_output_truncated = false;
+        self.usage.reset();
+        self.usage_json = None;
+    }
+
+    /// Declare which externals the host services. Calls to any other
+    /// declared external raise `NotImplementedError` in the guest without
+    /// pausing. `None` (the default) services every declared external.
+    pub fn set_serviceable_externals(&mut self, names: Option<Vec<String>>) -> Result<(), String> {
+        if let Some(names) = &names
+            && let Some(unknown) = names.iter().find(|n| !self.external_functions.contains(n))
+        {
+            return Err(format!("external function not declared: {unknown}"));
+        }
+        self.options.serviceable_externals = names;
+        Ok(())
+    }
+
+    /// Replace the exception types reported as retryable. `None` restores
+    /// `DEFAULT_RETRYABLE_EXC_TYPES`. Errors the host injected stay
+    /// retryable either way.
+    pub fn set_retryable_exc_types(&mut self, names: Option<Vec<String>>) -> Result<(), String> {
+        self.options.retryable_exc_types = names
+            .map(|names| names.iter().map(|n| parse_exc_type(n)).collect())
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Answer OS calls of `kind` (the engine's function name, e.g.
+    /// `"os.getenv"`) with `handler` during iterative execution. The handle
+    /// resumes with its return value or exception instead of surfacing the
+    /// call. Replaces any handler already registered for `kind`.
+    pub fn register_os_handler(&mut self, kind: &str, handler: OsHandler) {
+        self.os_handlers.insert(kind.to_string(), handler);
+    }
+
+    /// Pre-load text for `input()` to read during iterative execution.
+    ///
+    /// The engine has no stdin, so `input` is declared as an external
+    /// function (recompiling, so only valid in Ready state unless already
+    /// declared) and answered by the handle: each call returns the next line
+    /// without its newline. Once the text is used up `input()` raises
+    /// `OSError("EOF when reading a line")`; the engine has no `EOFError`.
+    pub fn set_stdin(&mut self, data: String) -> Result<(), String> {
+        self.add_external(STDIN_EXTERNAL)?;
+        self.stdin = Some(data);
+        Ok(())
+    }
+
+    /// Answer every call to the external `name` with the fixed value
+    /// `value_json`, whatever its arguments, without pausing.
+    ///
+    /// Declares `name` as an external like `set_stdin` does (recompiling,
+    /// so only valid in Ready state unless already declared). Registering
+    /// the same name again replaces its value. Answered calls don't count
+    /// toward `ext_call_count`.
+    pub fn register_constant(&mut self, name: &str, value_json: &str) -> Result<(), String> {
+        let value: Value = serde_json::from_str(value_json).map_err(|e| {
+            self.fail(
+                MontyErrorCode::InvalidJson,
+                format!("invalid value JSON: {e}"),
+            )
+        })?;
+        let value =
+            json_to_monty_object_with(&value, &self.options.conversion.decode_options(None))
+                .map_err(|e| format!("invalid value: {e}"))?;
+        self.add_external(name)?;
+        self.constants.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Reject a resume value (from `resume`, `resume_many`, `resume_reader`,
+    /// `resume_os_call` or `resume_futures`) that would decode into more
+    /// than `n` values, before it is fully built. Every JSON value counts
+    /// once; object keys don't.
+    pub fn set_max_resume_elements(&mut self, n: usize) {
+        self.options.max_resume_elements = Some(n);
+    }
+
+    /// Stop buffering print output once `print_output` reaches `bytes`,
+    /// reporting `"print_output_truncated": true` in the result.
+    pub fn set_output_limit(&mut self, bytes: usize) {
+        self.options.output_limit = Some(bytes);
+    }
+
+    /// Route print output to `callback` as it is written instead of
+    /// buffering it into `print_output`. `None` restores buffering; either
+    /// change takes effect from the next execution step.
+    pub fn set_print_callback(&mut self, callback: Option<PrintCallback>) {
+        self.print_callback = callback;
+    }
+
+    /// Set (or clear, with `None`) the handle's free-form label.
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+    }
+
+    /// Set (or clear, with `None`) an opaque id added as `correlation_id`
+    /// to every result JSON, success or error.
+    pub fn set_correlation_id(&mut self, id: Option<String>) {
+        self.options.correlation_id = id;
+    }
+
+    /// The handle's label, if set.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Category of the last failed FFI call; `None` if it succeeded.
+    pub fn last_error_code(&self) -> MontyErrorCode {
+        self.last_error_code.get()
+    }
+
+    /// Record the category of a failure.
+    pub fn set_last_error_code(&self, code: MontyErrorCode) {
+        self.last_error_code.set(code);
+    }
+
+    /// Record a failed call as `InvalidArgument` unless its error site set
+    /// a more specific code (e.g. `WrongState` or `VmException`).
+    pub fn note_error(&self) {
+        if self.last_error_code.get() == MontyErrorCode::None {
+            self.last_error_code.set(MontyErrorCode::InvalidArgument);
+        }
+    }
+
+    /// Record `code` as the category of the failure described by `msg`,
+    /// returning `msg` for the error out-parameter.
+    fn fail(&self, code: MontyErrorCode, msg: impl Into<String>) -> String {
+        self.last_error_code.set(code);
+        msg.into()
+    }
+
+    /// Prefix a panic message captured at the FFI boundary with the label.
+    pub fn panic_message(&self, msg: String) -> String {
+        match &self.label {
+            Some(label) => format!("handle '{label}' panicked: {msg}"),
+            None => msg,
+        }
+    }
+
+    /// Set memory limit in bytes.
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        let limits = self.limits.get_or_insert_with(ResourceLimits::new);
+        limits.max_memory = Some(bytes);
+    }
+
+    /// Set time limit in milliseconds. Replaces any compute time limit.
+    pub fn set_time_limit_ms(&mut self, ms: u64) {
+        let limits = self.limits.get_or_insert_with(ResourceLimits::new);
+        limits.max_duration = Some(Duration::from_millis(ms));
+        self.compute_time_limit = None;
+    }
+
+    /// Set a time limit in milliseconds that only counts time the VM is
+    /// running. Time paused for the host (in Paused or Futures state) is
+    /// excluded, so a slow external answer can't trip the timeout.
+    /// Replaces any limit from `set_time_limit_ms`.
+    pub fn set_compute_time_limit_ms(&mut self, ms: u64) {
+        let limits = self.limits.get_or_insert_with(ResourceLimits::new);
+        limits.max_duration = None;
+        self.compute_time_limit = Some(Duration::from_millis(ms));
+    }
+
+    /// Set stack depth limit.
+    pub fn set_stack_limit(&mut self, depth: usize) {
+        let limits = self.limits.get_or_insert_with(ResourceLimits::new);
+        limits.max_recursion_depth = Some(depth);
+    }
+
+    /// Abort an execution with `RuntimeError` when it makes more than `max`
+    /// external function calls. Enforced by the handle, not the tracker.
+    pub fn set_max_external_calls(&mut self, max: u64) {
+        self.max_external_calls = Some(max);
+    }
+
+    /// Cap the JSON a pending external call's arguments may take. Arguments
+    /// past the cap are left out of the pending metadata, and
+    /// `pending_args_truncated` reports it; the call itself is unaffected.
+    pub fn set_max_call_args_bytes(&mut self, max: usize) {
+        self.max_call_args_bytes = Some(max);
+    }
+
+    /// External function calls handed to the host so far in the current
+    /// execution, including calls later resolved as futures.
+    pub fn ext_call_count(&self) -> u64 {
+        self.ext_call_count
+    }
+
+    /// Record each pause and how the host answered it, keeping the latest
+    /// `max_entries` events. Replaces any log recorded so far; the log is
+    /// cleared when a new execution starts.
+    pub fn enable_event_log(&mut self, max_entries: usize) {
+        self.event_log = Some(EventLog::new(max_entries));
+    }
+
+    /// The event log as `{"events": [...], "dropped": n}`, or `None` if it
+    /// isn't enabled. Each event is `{"call_id", "fn_name", "args",
+    /// "kwargs", "timestamp_ms"}`, plus `"os_call": true` for OS calls and,
+    /// once answered, one of `"result"`, `"error"` or `"future"` with
+    /// `"resumed_ms"`.
+    pub fn event_log_json(&self) -> Option<String> {
+        self.event_log.as_ref().map(|log| log.to_json().to_string())
+    }
+
+    /// Remove every resource limit, so the next run uses `NoLimitTracker`.
+    pub fn clear_limits(&mut self) {
+        self.limits = None;
+        self.compute_time_limit = None;
+        self.max_external_calls = None;
+        self.max_call_args_bytes = None;
+    }
+
+    /// Remove the memory limit.
+    pub fn clear_memory_limit(&mut self) {
+        self.clear_limit(|limits| limits.max_memory = None);
+    }
+
+    /// Remove the time limit, including a compute time limit.
+    pub fn clear_time_limit(&mut self) {
+        self.compute_time_limit = None;
+        self.clear_limit(|limits| limits.max_duration = None);
+    }
+
+    /// Restore the default stack depth limit (the 1000 frames
+    /// `NoLimitTracker` also enforces) rather than removing it outright.
+    pub fn clear_stack_limit(&mut self) {
+        self.clear_limit(|limits| {
+            limits.max_recursion_depth = ResourceLimits::new().max_recursion_depth;
+        });
+    }
+
+    /// Apply `clear` to the limits, dropping back to no limits once nothing
+    /// differs from the defaults.
+    fn clear_limit(&mut self, clear: impl FnOnce(&mut ResourceLimits)) {
+        let Some(limits) = self.limits.as_mut() else {
+            return;
+        };
+        clear(limits);
+        if limits.max_memory.is_none()
+            && limits.max_duration.is_none()
+            && limits.max_allocations.is_none()
+            && limits.max_recursion_depth == ResourceLimits::new().max_recursion_depth
+            && self.compute_time_limit.is_none()
+        {
+            self.limits = None;
+        }
+    }
+
+    /// The configured limits as a JSON object. Unset limits are `null`;
+    /// `max_recursion_depth` defaults to the engine's 1000 once any limit
+    /// is set. `max_compute_ms` is the `set_compute_time_limit_ms` limit and
+    /// `max_external_calls` and `max_call_args_bytes` the handle-enforced
+    /// caps.
+    pub fn limits_json(&self) -> String {
+        let limits = self.limits.as_ref();
+        let ms = |d: Duration| u64::try_from(d.as_millis()).unwrap_or(u64::MAX);
+        serde_json::json!({
+            "max_memory": limits.and_then(|l| l.max_memory),
+            "max_duration_ms": limits.and_then(|l| l.max_duration).map(ms),
+            "max_recursion_depth": limits.and_then(|l| l.max_recursion_depth),
+            "max_compute_ms": self.compute_time_limit.map(ms),
+            "max_external_calls": self.max_external_calls,
+            "max_call_args_bytes": self.max_call_args_bytes,
+        })
+        .to_string()
+    }
+
+    /// Set limits from a JSON object with any of the keys `limits_json`
+    /// reports; `null` leaves a limit as it is. Every entry is checked
+    /// before any is applied, so an unknown key or a bad value changes
+    /// nothing.
+    pub fn set_limits_json(&mut self, limits_json: &str) -> Result<(), String> {
+        let Value::Object(map) = serde_json::from_str(limits_json).map_err(|e| {
+            self.fail(
+                MontyErrorCode::InvalidJson,
+                format!("invalid limits JSON: {e}"),
+            )
+        })?
+        else {
+            return Err("limits JSON must be an object".into());
+        };
+        let mut updates = Vec::with_capacity(map.len());
+        for (key, value) in &map {
+            let is_size = match key.as_str() {
+                "max_memory" | "max_recursion_depth" | "max_call_args_bytes" => true,
+                "max_duration_ms" | "max_compute_ms" | "max_external_calls" => false,
+                _ => return Err(format!("unknown limit: {key}")),
+            };
+            if value.is_null() {
+                continue;
+            }
+            let Some(n) = value.as_u64() else {
+                return Err(format!("invalid value for limit '{key}': {value}"));
+            };
+            if is_size && usize::try_from(n).is_err() {
+                return Err(format!("limit '{key}' out of range"));
+            }
+            updates.push((key.as_str(), n));
+        }
+        for (key, n) in updates {
+            // Sizes were range-checked above.
+            let size = usize::try_from(n).unwrap_or(usize::MAX);
+            match key {
+                "max_memory" => self.set_memory_limit(size),
+                "max_duration_ms" => self.set_time_limit_ms(n),
+                "max_recursion_depth" => self.set_stack_limit(size),
+                "max_compute_ms" => self.set_compute_time_limit_ms(n),
+                "max_external_calls" => self.set_max_external_calls(n),
+                _ => self.set_max_call_args_bytes(size),
+            }
+        }
+        Ok(())
+    }
+
+    // --- private helpers ---
+
+    /// Forget what the previous execution left behind: injected errors,
+    /// gathered futures, the external call count and logged events.
+    fn clear_execution(&mut self) {
+        self.injected_excs.clear();
+        self.future_calls.clear();
+        self.ext_call_count = 0;
+        if let Some(log) = &mut self.event_log {
+            log.clear();
+        }
+    }
+
+    /// How host answers decode: the tags this handle writes, capped at
+    /// `max_resume_elements`.
+    fn decode_options(&self) -> DecodeOptions {
+        self.options
+            .conversion
+            .decode_options(self.options.max_resume_elements)
+    }
+
+    fn recompile(&mut self, external_functions: Vec<String>) -> Result<(), String> {
+        let HandleState::Ready(compiled) = &self.state else {
+            return Err(self.fail(MontyErrorCode::WrongState, "handle not in Ready state"));
+        };
+        let Some(name) = &self.script_name else {
+            return Err("cannot recompile a restored handle".into());
+        };
+        let (recompiled, compile_ms) = compile_with_budget(
+            compiled.code().to_string(),
+            name,
+            self.global_names(),
+            external_functions.clone(),
+            self.compile_timeout,
+        )
+        .map_err(|e| e.summary())?;
+        self.state = HandleState::Ready(recompiled);
+        self.compile_ms = Some(compile_ms);
+        self.external_functions = external_functions;
+        self.snapshot_cache.take();
+        Ok(())
+    }
+
+    fn global_names(&self) -> Vec<String> {
+        self.globals.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn global_values(&self) -> Vec<monty::MontyObject> {
+        self.globals
+            .iter()
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    fn run_snapshot_op<T: TrackerExt>(
+        &mut self,
+        f: impl FnOnce(&mut PrintWriter) -> Result<RunProgress<T>, MontyException>,
+    ) -> (MontyProgressTag, Option<String>) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.usage.add_host_wait(paused_at.elapsed());
+        }
+        let started = Instant::now();
+        let result = self
+            .collect_print(f)
+            .and_then(|progress| self.auto_dispatch(progress));
+        self.record_usage(started);
+        match result {
+            Ok(progress) => self.process_progress(progress),
+            Err(exc) => self.handle_exception(exc),
+        }
+    }
+
+    /// A token that stops the current execution at its next time check,
+    /// raising `KeyboardInterrupt`. It shares only the usage counters, so
+    /// other threads may use it while this handle runs.
+    pub fn interrupt_token(&self) -> MontyInterruptToken {
+        MontyInterruptToken::new(self.usage.clone())
+    }
+
+    /// An interrupt surfaces from the tracker as the error of the check it
+    /// failed; report it as `KeyboardInterrupt` instead.
+    fn interrupted(&self, exc: MontyException) -> MontyException {
+        if self.usage.stop_reason() == Some(StopReason::Interrupt)
+            && exc.exc_type() == StopReason::Interrupt.exc_type()
+        {
+            return MontyException::new(
+                monty::ExcType::KeyboardInterrupt,
+                Some("execution interrupted".into()),
+            );
+        }
+        exc
+    }
+
+    /// Wrap the tracker for a new execution, arming the compute limit.
+    fn metered<T>(&mut self, tracker: T) -> MeteredTracker<T> {
+        self.usage.set_compute_limit(self.compute_time_limit);
+        self.usage.clear_interrupt();
+        self.paused_at = None;
+        MeteredTracker::new(tracker, self.usage.clone())
+    }
+
+    /// Add the time since `started` to the usage counters and refresh the
+    /// usage reported in results. Host wait is measured from here.
+    fn record_usage(&mut self, started: Instant) {
+        self.usage.add_elapsed(started.elapsed());
+        self.usage_json = Some(self.usage.to_json().to_string());
+        self.paused_at = Some(Instant::now());
+    }
+
+    /// Run `f` with a print writer feeding the print callback if set, or
+    /// else appending to `print_output` up to the output limit.
+    fn collect_print<R>(&mut self, f: impl FnOnce(&mut PrintWriter) -> R) -> R {
+        if let Some(callback) = self.print_callback.as_mut() {
+            let mut writer = CallbackWriter(callback);
+            return f(&mut PrintWriter::Callback(&mut writer));
+        }
+        let mut writer = BufferWriter {
+            out: &mut self.print_output,
+            limit: self.options.output_limit,
+            truncated: &mut self.print_output_truncated,
+        };
+        f(&mut PrintWriter::Callback(&mut writer))
+    }
+
+    /// Resume past calls the crate answers itself, returning the first
+    /// progress the host has to handle.
+    fn auto_dispatch<T: TrackerExt>(
+        &mut self,
+        mut progress: RunProgress<T>,
+    ) -> Result<RunProgress<T>, MontyException> {
+        loop {
+            progress = match progress {
+                RunProgress::FunctionCall {
+                    function_name,
+                    args,
+                    method_call: false,
+                    state,
+                    ..
+                } if function_name == STDIN_EXTERNAL && self.stdin.is_some() => {
+                    let result = self.read_stdin_line(&args);
+                    self.collect_print(|print| state.run(result, print))?
+                }
+                RunProgress::FunctionCall {
+                    function_name,
+                    method_call: false,
+                    state,
+                    ..
+                } if self.constants.contains_key(&function_name) => {
+                    let value = self.constants[&function_name].clone();
+                    self.collect_print(|print| state.run(value, print))?
+                }
+                RunProgress::FunctionCall {
+                    function_name,
+                    method_call: false,
+                    state,
+                    ..
+                } if !self.is_serviceable(&function_name) => {
+                    let exc = MontyException::new(
+                        monty::ExcType::NotImplementedError,
+                        Some(format!(
+                            "external function not serviced by host: {function_name}"
+                        )),
+                    );
+                    self.collect_print(|print| state.run(exc, print))?
+                }
+                RunProgress::OsCall {
+                    function,
+                    args,
+                    kwargs,
+                    call_id,
+                    state,
+                } => {
+                    let Some(handler) = self.os_handlers.get_mut(&function.to_string()) else {
+                        if !self.options.pause_os_calls {
+                            return Err(MontyException::new(
+                                monty::ExcType::NotImplementedError,
+                                Some(format!("no handler for OS call: {function}")),
+                            ));
+                        }
+                        return Ok(RunProgress::OsCall {
+                            function,
+                            args,
+                            kwargs,
+                            call_id,
+                            state,
+                        });
+                    };
+                    let result = match handler(&args) {
+                        Ok(obj) => ExternalResult::Return(obj),
+                        Err(exc) => ExternalResult::Error(exc),
+                    };
+                    self.collect_print(|print| state.run(result, print))?
+                }
+                other => return Ok(other),
+            };
+        }
+    }
+
+    /// Finish a `run`: answer what `auto_dispatch` can, and fail on any
+    /// call that would need the host, since `run` cannot pause.
+    fn run_to_completion<T: TrackerExt>(
+        &mut self,
+        progress: Result<RunProgress<T>, MontyException>,
+    ) -> Result<monty::MontyObject, MontyException> {
+        let name = match progress.and_then(|progress| self.auto_dispatch(progress))? {
+            RunProgress::Complete(obj) => return Ok(obj),
+            RunProgress::FunctionCall { function_name, .. } => function_name,
+            RunProgress::OsCall { function, .. } => function.to_string(),
+            RunProgress::ResolveFutures(_) => "a future".into(),
+        };
+        Err(MontyException::new(
+            monty::ExcType::RuntimeError,
+            Some(format!(
+                "external call to '{name}' cannot be answered in run"
+            )),
+        ))
+    }
+
+    /// Answer an `input(prompt)` call from `stdin`, echoing the prompt to
+    /// print output.
+    fn read_stdin_line(&mut self, args: &[monty::MontyObject]) -> ExternalResult {
+        if let Some(prompt) = args.first() {
+            let prompt = match prompt {
+                monty::MontyObject::String(s) => s.clone(),
+                other => format!("{other}"),
+            };
+            if let Err(exc) = self.collect_print(|print| print.stdout_write(Cow::Owned(prompt))) {
+                return ExternalResult::Error(exc);
+            }
+        }
+        let stdin = self.stdin.get_or_insert_default();
+        if stdin.is_empty() {
+            return ExternalResult::Error(MontyException::new(
+                monty::ExcType::OSError,
+                Some("EOF when reading a line".into()),
+            ));
+        }
+        let line = match stdin.find('\n') {
+            Some(end) => {
+                let line = stdin[..end].to_string();
+                stdin.drain(..=end);
+                line
+            }
+            None => std::mem::take(stdin),
+        };
+        ExternalResult::Return(monty::MontyObject::String(line))
+    }
+
+    fn is_serviceable(&self, function_name: &str) -> bool {
+        self.options
+            .serviceable_externals
+            .as_ref()
+            .is_none_or(|names| names.iter().any(|n| n == function_name))
+    }
+
+    /// Resume the gathered futures; `injected` lists the errors among
+    /// `ext_results` that the host raised.
+    fn resolve_futures(
+        &mut self,
+        ext_results: Vec<(u32, ExternalResult)>,
+        injected: Vec<InjectedExc>,
+    ) -> (MontyProgressTag, Option<String>) {
+        if matches!(
+            self.state,
+            HandleState::FuturesLimited { .. } | HandleState::FuturesNoLimit { .. }
+        ) {
+            for (call_id, result) in &ext_results {
+                self.log_answer(*call_id, result);
+                self.future_calls.remove(call_id);
+            }
+        }
+        let state = std::mem::replace(&mut self.state, HandleState::Consumed);
+
+        match state {
+            HandleState::FuturesLimited { snapshot, .. } => {
+                self.injected_excs = injected;
+                self.run_snapshot_op(|print| snapshot.resume(ext_results, print))
+            }
+            HandleState::FuturesNoLimit { snapshot, .. } => {
+                self.injected_excs = injected;
+                self.run_snapshot_op(|print| snapshot.resume(ext_results, print))
+            }
+            other => {
+                self.state = other;
+                (
+                    MontyProgressTag::Error,
+                    Some(self.fail(MontyErrorCode::WrongState, "handle not in Futures state")),
+                )
+            }
+        }
+    }
+
+    /// Resume the pending call with `result`; `injected` marks an error the
+    /// host raised, as opposed to one decoded from a value.
+    fn resume_with_result(
+        &mut self,
+        result: ExternalResult,
+        injected: bool,
+    ) -> (MontyProgressTag, Option<String>) {
+        if let Some(call_id) = self.pending_call_id() {
+            self.log_answer(call_id, &result);
+        }
+        let injected = match &result {
+            ExternalResult::Error(exc) if injected => vec![InjectedExc::of(exc)],
+            _ => Vec::new(),
+        };
+        let state = std::mem::replace(&mut self.state, HandleState::Consumed);
+
+        match state {
+            HandleState::PausedLimited { snapshot, .. } => {
+                self.injected_excs = injected;
+                self.run_snapshot_op(|print| snapshot.run(result, print))
+            }
+            HandleState::PausedNoLimit { snapshot, .. } => {
+                self.injected_excs = injected;
+                self.run_snapshot_op(|print| snapshot.run(result, print))
+            }
+            other => {
+                self.state = other;
+                (
+                    MontyProgressTag::Error,
+                    Some(self.fail(MontyErrorCode::WrongState, "handle not in Paused state")),
+                )
+            }
+        }
+    }
+
+    fn process_progress<T: TrackerExt>(
+        &mut self,
+        progress: RunProgress<T>,
+    ) -> (MontyProgressTag, Option<String>) {
+        match progress {
+            RunProgress::Complete(obj) => match self.value_result_json(&obj) {
+                Ok(result_json) => {
+                    self.state = HandleState::Complete {
+                        result_json: result_json.into(),
+                        is_error: false,
+                        retryable: false,
+                    };
+                    (MontyProgressTag::Complete, None)
+                }
+                Err(exc) => self.handle_exception(exc),
+            },
+            RunProgress::FunctionCall {
+                function_name,
+                args,
+                kwargs,
+                call_id,
+                method_call,
+                state: snapshot,
+            } => {
+                if let Some(max) = self.max_external_calls
+                    && self.ext_call_count >= max
+                {
+                    return self.handle_exception(MontyException::new(
+                        monty::ExcType::RuntimeError,
+                        Some(format!("external call limit exceeded: {max}")),
+                    ));
+                }
+                self.ext_call_count += 1;
+                match build_pending_meta(
+                    function_name,
+                    args,
+                    kwargs,
+                    call_id,
+                    method_call,
+                    &self.options.conversion,
+                    self.max_call_args_bytes,
+                ) {
+                    Ok(meta) => {
+                        self.log_pause(&meta, false);
+                        self.state = T::into_paused(snapshot, meta);
+                        (MontyProgressTag::Pending, None)
+                    }
+                    Err(exc) => self.handle_exception(exc),
+                }
+            }
+            RunProgress::ResolveFutures(snapshot) => {
+                let call_ids_json = serde_json::to_string(snapshot.pending_call_ids())
+                    .unwrap_or_else(|_| "[]".into());
+                self.state = T::into_futures(snapshot, call_ids_json);
+                (MontyProgressTag::ResolveFutures, None)
+            }
+            RunProgress::OsCall {
+                function,
+                args,
+                kwargs,
+                call_id,
+                state: snapshot,
+            } => {
+                match build_pending_meta(
+                    function.to_string(),
+                    args,
+                    kwargs,
+                    call_id,
+                    false,
+                    &self.options.conversion,
+                    None,
+                ) {
+                    Ok(meta) => {
+                        self.log_pause(&meta, true);
+                        self.state = T::into_os_call(snapshot, meta);
+                        (MontyProgressTag::OsCall, None)
+                    }
+                    Err(exc) => self.handle_exception(exc),
+                }
+            }
+        }
+    }
+
+    /// Build the result JSON for a successful completion, or the exception to
+    /// report instead if the value breaks a conversion limit.
+    ///
+    /// The value is written straight into the result text rather than
+    /// through a `Value` tree, so a large result is held once.
+    fn value_result_json(&self, obj: &monty::MontyObject) -> Result<String, MontyException> {
+        let mut out = RESULT_VALUE_PREFIX.to_vec();
+        let js_safe = monty_object_to_writer(obj, &self.options.conversion, &mut out)
+            .map_err(conversion_exception)?;
+        let value_repr = self.options.value_repr.then(|| obj.to_string());
+        Ok(self.result_json(out, None, js_safe, value_repr))
+    }
+
+    /// Build the result JSON for a guest exception.
+    fn exception_result_json(&self, exc: &MontyException, retryable: bool) -> String {
+        let error = if self.options.minimal_errors {
+            monty_exception_to_minimal_json(exc)
+        } else {
+            monty_exception_to_json(exc)
+        };
+        let mut error = error;
+        error["retryable"] = Value::Bool(retryable);
+        if let Some(kind) = self.usage.limit_hit()
+            && kind.exc_type() == exc.exc_type()
+        {
+            error["limit_exceeded"] = Value::from(kind.as_str());
+        }
+        self.error_result_json(error)
+    }
+
+    /// Transient failures: a retryable exception type, or the exception the
+    /// host raised into the guest at the last resume, propagated unhandled.
+    fn is_retryable(&self, exc: &MontyException) -> bool {
+        self.options
+            .retryable_exc_types
+            .as_deref()
+            .unwrap_or(DEFAULT_RETRYABLE_EXC_TYPES)
+            .contains(&exc.exc_type())
+            || self.injected_excs.contains(&InjectedExc::of(exc))
+    }
+
+    /// Build the result JSON for a failed completion.
+    fn error_result_json(&self, error: Value) -> String {
+        let mut out = RESULT_VALUE_PREFIX.to_vec();
+        out.extend_from_slice(b"null");
+        self.result_json(out, Some(error), true, None)
+    }
+
+    /// Finish a result document whose `out` holds the `value` field.
+    fn result_json(
+        &self,
+        out: Vec<u8>,
+        error: Option<Value>,
+        js_safe: bool,
+        value_repr: Option<String>,
+    ) -> String {
+        #[cfg(test)]
+        RESULT_SERIALIZATIONS.with(|n| n.set(n.get() + 1));
+        let usage_json = self.usage_json.clone().unwrap_or_else(default_usage_json);
+        let mut map = build_result_fields(error, &usage_json, &self.print_output);
+        if self.options.always_emit_print {
+            map.entry("print_output")
+                .or_insert_with(|| Value::String(String::new()));
+        }
+        map.insert("js_safe".into(), Value::Bool(js_safe));
+        if let Some(repr) = value_repr {
+            map.insert("value_repr".into(), Value::String(repr));
+        }
+        if let Some(id) = &self.options.correlation_id {
+            map.insert("correlation_id".into(), Value::String(id.clone()));
+        }
+        if self.print_output_truncated {
+            map.insert("print_output_truncated".into(), Value::Bool(true));
+        }
+        if let Some(ms) = self.compile_ms
+            && let Some(usage) = map.get_mut("usage").and_then(Value::as_object_mut)
+        {
+            usage.insert("compile_ms".into(), serde_json::json!(ms));
+        }
+        finish_result_json(out, &map)
+    }
+
+    fn log_pause(&mut self, meta: &PendingMeta, os_call: bool) {
+        let Some(log) = self.event_log.as_mut() else {
+            return;
+        };
+        let parse = |json: Option<&str>| {
+            json.and_then(|j| serde_json::from_str(j).ok())
+                .unwrap_or(Value::Null)
+        };
+        log.record_pause(
+            meta.call_id,
+            &meta.fn_name,
+            parse(meta.args_json()),
+            parse(meta.kwargs_json()),
+            os_call,
+        );
+    }
+
+    fn log_answer(&mut self, call_id: u32, result: &ExternalResult) {
+        let Some(log) = self.event_log.as_mut() else {
+            return;
+        };
+        let (field, value) = match result {
+            ExternalResult::Return(obj) => (
+                "result",
+                monty_object_to_json_with(obj, &self.options.conversion).unwrap_or(Value::Null),
+            ),
+            ExternalResult::Error(exc) => ("error", monty_exception_to_minimal_json(exc)),
+            ExternalResult::Future => ("future", Value::Bool(true)),
+        };
+        log.record_answer(call_id, field, value);
+    }
+
+    /// Report a failed streamed decode of `input`: malformed JSON as
+    /// `InvalidJson`, JSON that doesn't decode to a value as an invalid
+    /// value.
+    fn decode_error(&self, e: serde_json::Error, input: &str) -> String {
+        if e.is_data() {
+            format!("invalid value: {e}")
+        } else {
+            self.fail(MontyErrorCode::InvalidJson, format!("invalid {input}: {e}"))
+        }
+    }
+
+    fn handle_exception(&mut self, exc: MontyException) -> (MontyProgressTag, Option<String>) {
+        self.last_error_code.set(MontyErrorCode::VmException);
+        let exc = self.interrupted(exc);
+        let retryable = self.is_retryable(&exc);
+        let result_json = self.exception_result_json(&exc, retryable);
+        let msg = exc.summary();
+        self.state = HandleState::Complete {
+            result_json: result_json.into(),
+            is_error: true,
+            retryable,
+        };
+        (MontyProgressTag::Error, Some(msg))
+    }
+}
+
+/// Parse a JSON array of positional arguments for `run_with_args`.
+fn parse_args_json(
+    args_json: &str,
+    options: &DecodeOptions,
+) -> Result<Vec<monty::MontyObject>, (MontyErrorCode, String)> {
+    let invalid = |msg: String| (MontyErrorCode::InvalidArgument, msg);
+    match serde_json::from_str::<Value>(args_json) {
+        Ok(Value::Array(items)) => items
+            .iter()
+            .map(|item| json_to_monty_object_with(item, options))
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid(format!("invalid args: {e}"))),
+        Ok(_) => Err(invalid("args_json must be a JSON array".into())),
+        Err(e) => Err((MontyErrorCode::InvalidJson, format!("invalid JSON: {e}"))),
+    }
+}
+
+/// Parse `resume_with_exception`'s JSON into an exception type and message.
+fn parse_exception_json(
+    exc_json: &str,
+) -> Result<(monty::ExcType, Option<String>), (MontyErrorCode, String)> {
+    let invalid = |msg: &str| (MontyErrorCode::InvalidArgument, msg.to_string());
+    let val: Value = serde_json::from_str(exc_json).map_err(|e| {
+        (
+            MontyErrorCode::InvalidJson,
+            format!("invalid exception JSON: {e}"),
+        )
+    })?;
+    let Some(obj) = val.as_object() else {
+        return Err(invalid("exception JSON must be an object"));
+    };
+    let Some(name) = obj.get("type").and_then(Value::as_str) else {
+        return Err(invalid("exception JSON needs a string \"type\""));
+    };
+    let exc_type = parse_exc_type(name).map_err(|e| invalid(&e))?;
+    let message = match (obj.get("message"), obj.get("args")) {
+        (Some(_), Some(_)) => {
+            return Err(invalid(
+                "exception JSON takes \"message\" or \"args\", not both",
+            ));
+        }
+        (Some(Value::String(message)), None) => Some(message.clone()),
+        (Some(Value::Null) | None, None) => None,
+        (None, Some(Value::Array(args))) => match args.as_slice() {
+            [] => None,
+            [Value::String(message)] => Some(message.clone()),
+            _ => {
+                return Err(invalid(
+                    "exception args beyond a single string message are not supported",
+                ));
+            }
+        },
+        _ => {
+            return Err(invalid(
+                "exception \"message\" must be a string and \"args\" an array",
+            ));
+        }
+    };
+    Ok((exc_type, message))
+}
+
+/// Convert a host-supplied JSON return value into an `ExternalResult`,
+/// mapping the "missing" sentinel to a `KeyError`.
+fn external_result(value: HostValue) -> ExternalResult {
+    match value {
+        HostValue::Value(obj) => ExternalResult::Return(obj),
+        HostValue::Missing => {
+            ExternalResult::Error(MontyException::new(monty::ExcType::KeyError, None))
+        }
+    }
+}
+
+/// Whether `name` is a plain Python identifier (safe to splice into source).
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Build a `PendingMeta` from a `FunctionCall` variant's fields.
+///
+/// Every argument is checked against the conversion limits here, so a
+/// violation fails the call at the pause as it did when arguments were
+/// encoded eagerly; without `max_args_bytes` nothing is encoded until the
+/// first read. With it, positional then keyword arguments (key and value)
+/// are kept up to the first whose JSON breaks the budget, and that one and
+/// every later one are dropped. Kept arguments are measured and checked in
+/// one encode into a `CappedWriter`, which stops at the budget; the argument
+/// that overflows it and the dropped ones are checked without encoding.
+fn build_pending_meta(
+    function_name: String,
+    args: Vec<monty::MontyObject>,
+    kwargs: Vec<(monty::MontyObject, monty::MontyObject)>,
+    call_id: u32,
+    method_call: bool,
+    options: &ConversionOptions,
+    max_args_bytes: Option<usize>,
+) -> Result<PendingMeta, MontyException> {
+    let mut budget = max_args_bytes;
+    let mut args_truncated = false;
+    let mut keep = |parts: &[&monty::MontyObject]| -> Result<bool, MontyException> {
+        if let Some(remaining) = budget.as_mut()
+            && !args_truncated
+        {
+            let mut writer = CappedWriter::new(*remaining);
+            let measured = parts
+                .iter()
+                .try_for_each(|obj| monty_object_to_writer(obj, options, &mut writer).map(drop));
+            match measured {
+                Ok(()) => {
+                    *remaining -= writer.written;
+                    return Ok(true);
+                }
+                Err(_) if writer.overflowed => args_truncated = true,
+                Err(message) => return Err(conversion_exception(message)),
+            }
+        }
+        for obj in parts {
+            check_conversion_limits(obj, options).map_err(conversion_exception)?;
+        }
+        Ok(budget.is_none())
+    };
+
+    let args_count = args.len();
+    let mut kept_args = Vec::with_capacity(args_count);
+    for obj in args {
+        if keep(&[&obj])? {
+            kept_args.push(obj);
+        }
+    }
+    let mut kept_kwargs = Vec::with_capacity(kwargs.len());
+    for (k, v) in kwargs {
+        if keep(&[&k, &v])? {
+            kept_kwargs.push((k, v));
+        }
+    }
+
+    Ok(PendingMeta {
+        fn_name: function_name,
+        args: kept_args,
+        kwargs: kept_kwargs,
+        call_id,
+        method_call,
+        options: options.clone(),
+        args_count,
+        args_truncated,
+        args_json: OnceCell::new(),
+        args_tagged: OnceCell::new(),
+        kwargs_json: OnceCell::new(),
+    })
+}
+
+/// Writer that only counts bytes, failing once more than `cap` are written.
+struct CappedWriter {
+    cap: usize,
+    written: usize,
+    overflowed: bool,
+}
+
+impl CappedWriter {
+    fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            written: 0,
+            overflowed: false,
+        }
+    }
+}
+
+impl Write for CappedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.len() > self.cap - self.written {
+            self.overflowed = true;
+            return Err(std::io::Error::other("argument cap exceeded"));
+        }
+        self.written += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Exception reported when a value breaks a `ConversionOptions` limit.
+fn conversion_exception(message: String) -> MontyException {
+    MontyException::new(monty::ExcType::MemoryError, Some(message))
+}
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+/// Check that `name` can be bound as a global, returning why not.
+fn check_global_name(name: &str) -> Result<(), &'static str> {
+    if !is_identifier(name) {
+        return Err("not a valid identifier");
+    }
+    if PYTHON_KEYWORDS.contains(&name) {
+        return Err("is a Python keyword");
+    }
+    if name.starts_with("__monty_") {
+        return Err("reserved for internal use");
+    }
+    Ok(())
+}
+
+/// Compile `code`, returning the program and the milliseconds it took.
+fn compile_timed(
+    code: String,
+    script_name: &str,
+    input_names: Vec<String>,
+    external_functions: Vec<String>,
+) -> Result<(MontyRun, f64), MontyException> {
+    let started = Instant::now();
+    let compiled = MontyRun::new(code, script_name, input_names, external_functions)?;
+    Ok((compiled, started.elapsed().as_secs_f64() * 1000.0))
+}
+
+/// Compile `code`, within `limit` if one is given.
+fn compile_with_budget(
+    code: String,
+    script_name: &str,
+    input_names: Vec<String>,
+    external_functions: Vec<String>,
+    limit: Option<Duration>,
+) -> Result<(MontyRun, f64), MontyException> {
+    match limit {
+        Some(limit) => compile_within(code, script_name, input_names, external_functions, limit),
+        None => compile_timed(code, script_name, input_names, external_functions),
+    }
+}
+
+/// Stack for `compile_within`'s worker: the parser recurses on nesting, so
+/// it gets as much room as a typical main thread rather than the 2 MiB
+/// spawned threads default to.
+const COMPILE_THREAD_STACK: usize = 8 << 20;
+
+/// Most `compile_within` workers alive at once, abandoned ones included.
+const MAX_COMPILE_WORKERS: usize = 8;
+
+/// `compile_within` workers currently alive.
+static COMPILE_WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+/// A slot among the `MAX_COMPILE_WORKERS`, freed on drop.
+struct CompileWorkerSlot;
+
+impl CompileWorkerSlot {
+    fn acquire() -> Option<Self> {
+        // Dropping the slot gives back the increment if it was one too many.
+        let slot = CompileWorkerSlot;
+        (COMPILE_WORKERS.fetch_add(1, Ordering::AcqRel) < MAX_COMPILE_WORKERS).then_some(slot)
+    }
+}
+
+impl Drop for CompileWorkerSlot {
+    fn drop(&mut self) {
+        COMPILE_WORKERS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Compile `code` on a worker thread, failing with `TimeoutError` once
+/// `limit` passes.
+///
+/// `MontyRun::new` has no cancellation hook, so a compile that runs over is
+/// abandoned, not stopped: the worker finishes in the background and drops
+/// the program, but the caller gets its error on time. Abandoned workers
+/// still hold one of the `MAX_COMPILE_WORKERS` slots, so a flood of
+/// pathological sources fails fast with `RuntimeError` instead of piling up
+/// threads.
+fn compile_within(
+    code: String,
+    script_name: &str,
+    input_names: Vec<String>,
+    external_functions: Vec<String>,
+    limit: Duration,
+) -> Result<(MontyRun, f64), MontyException> {
+    let Some(slot) = CompileWorkerSlot::acquire() else {
+        return Err(MontyException::new(
+            monty::ExcType::RuntimeError,
+            Some(format!(
+                "too many compiles in progress (max {MAX_COMPILE_WORKERS})"
+            )),
+        ));
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    let name = script_name.to_string();
+    std::thread::Builder::new()
+        .name("monty-compile".into())
+        .stack_size(COMPILE_THREAD_STACK)
+        .spawn(move || {
+            let _slot = slot;
+            // The receiver is gone if the caller already timed out.
+            let _ = tx.send(compile_timed(code, &name, input_names, external_functions));
+        })
+        .map_err(|e| {
+            MontyException::new(
+                monty::ExcType::RuntimeError,
+                Some(format!("failed to start compile thread: {e}")),
+            )
+        })?;
+    match rx.recv_timeout(limit) {
+        Ok(result) => result,
+        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Err(MontyException::new(
+            monty::ExcType::TimeoutError,
+            Some(format!("compile timed out after {} ms", limit.as_millis())),
+        )),
+        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => Err(MontyException::new(
+            monty::ExcType::RuntimeError,
+            Some("compile thread panicked".into()),
+        )),
+    }
+}
+
+fn default_usage_json() -> String {
+    r#"{"memory_bytes_used":0,"peak_memory_bytes":0,"final_memory_bytes":0,"time_elapsed_ms":0,"stack_depth_used":0,"peak_stack_depth":0}"#.into()
+}
+
+/// Start of every result document; the encoded value follows.
+const RESULT_VALUE_PREFIX: &[u8] = b"{\"value\":";
+
+/// The result fields other than `value`.
+fn build_result_fields(
+    error: Option<Value>,
+    usage_json: &str,
+    print_output: &str,
+) -> serde_json::Map<String, Value> {
+    let usage: Value = serde_json::from_str(usage_json).unwrap_or(serde_json::json!({
+        "memory_bytes_used": 0,
+        "peak_memory_bytes": 0,
+        "final_memory_bytes": 0,
+        "time_elapsed_ms": 0,
+        "stack_depth_used": 0,
+        "peak_stack_depth": 0,
+    }));
+    let mut result = serde_json::Map::new();
+    result.insert("usage".into(), usage);
+    if let Some(err) = error {
+        result.insert("error".into(), err);
+    }
+    if !print_output.is_empty() {
+        result.insert("print_output".into(), Value::String(print_output.into()));
+    }
+    result
+}
+
+/// Result JSON for an error raised before a handle exists to report it.
+fn standalone_error_result_json(error: Value) -> String {
+    let mut out = RESULT_VALUE_PREFIX.to_vec();
+    out.extend_from_slice(b"null");
+    let mut fields = build_result_fields(Some(error), &default_usage_json(), "");
+    fields.insert("js_safe".into(), Value::Bool(true));
+    finish_result_json(out, &fields)
+}
+
+/// Append `fields` to `out`, which holds `RESULT_VALUE_PREFIX` and the
+/// value, and close the document.
+fn finish_result_json(mut out: Vec<u8>, fields: &serde_json::Map<String, Value>) -> String {
+    for (key, field) in fields {
+        out.push(b',');
+        // Writing JSON into a Vec can't fail.
+        serde_json::to_writer(&mut out, key).unwrap_or_default();
+        out.push(b':');
+        serde_json::to_writer(&mut out, field).unwrap_or_default();
+    }
+    out.push(b'}');
+    String::from_utf8(out).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::ENCODER_RUNS;
+
+    #[test]
+    fn test_create_handle() {
+        let handle = MontyHandle::new("2 + 2".into(), vec![], None);
+        assert!(handle.is_ok());
+    }
+
+    #[test]
+    fn test_new_with_globals() {
+        let globals = vec![("x".to_string(), monty::MontyObject::Int(10))];
+        let mut handle =
+            MontyHandle::new_with_globals("x * 2".into(), vec![], None, globals).unwrap();
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["value"], json!(20));
+
+        handle.reset().unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Complete);
+    }
+
+    #[test]
+    fn test_run_with_args() {
+        let globals = vec![("x".to_string(), monty::MontyObject::None)];
+        let mut handle = MontyHandle::new_with_globals("x".into(), vec![], None, globals).unwrap();
+        for arg in ["5", r#""hi""#, "[1, 2]"] {
+            let (tag, result_json, err) = handle.run_with_args_json(&format!("[{arg}]"));
+            assert_eq!(tag, MontyResultTag::Ok, "{err:?}");
+            let parsed: Value = serde_json::from_str(&result_json).unwrap();
+            assert_eq!(parsed["value"], serde_json::from_str::<Value>(arg).unwrap());
+            handle.reset().unwrap();
+        }
+        assert_eq!(handle.globals[0].1, monty::MontyObject::None);
+
+        let (tag, _, err) = handle.run_with_args_json("[1, 2]");
+        assert_eq!(tag, MontyResultTag::Error);
+        assert_eq!(err.as_deref(), Some("expected 1 args, got 2"));
+        let (_, _, err) = handle.run_with_args_json("{}");
+        assert_eq!(err.as_deref(), Some("args_json must be a JSON array"));
+        assert!(matches!(handle.state, HandleState::Ready(_)));
+    }
+
+    #[test]
+    fn test_new_with_globals_rejects_bad_names() {
+        for name in ["1x", "a-b", "", "lambda", "__monty_call_args__"] {
+            let globals = vec![(name.to_string(), monty::MontyObject::None)];
+            let err = MontyHandle::new_with_globals("1".into(), vec![], None, globals)
+                .err()
+                .unwrap();
+            assert!(err.summary().contains("invalid global name"), "{name}");
+        }
+        let globals = vec![
+            ("x".to_string(), monty::MontyObject::Int(1)),
+            ("x".to_string(), monty::MontyObject::Int(2)),
+        ];
+        let err = MontyHandle::new_with_globals("x".into(), vec![], None, globals)
+            .err()
+            .unwrap();
+        assert!(err.summary().contains("more than once"));
+    }
+
+    #[test]
+    fn test_create_handle_syntax_error() {
+        let handle = MontyHandle::new("def".into(), vec![], None);
+        assert!(handle.is_err());
+    }
+
+    #[test]
+    fn test_run_simple() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        let (tag, result_json, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        assert!(err.is_none());
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["value"], json!(4));
+    }
+
+    #[test]
+    fn test_run_error() {
+        let mut handle = MontyHandle::new("1/0".into(), vec![], None).unwrap();
+        let (tag, _, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_large_result_serialized_once() {
+        let serializations = || RESULT_SERIALIZATIONS.with(Cell::get);
+        let mut handle = MontyHandle::new(
+            "[{'i': i, 's': str(i) * 10} for i in range(20000)]".into(),
+            vec![],
+            None,
+        )
+        .unwrap();
+        let before = serializations();
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        assert_eq!(serializations() - before, 1);
+        // The returned JSON and the stored result are one buffer.
+        assert!(std::ptr::eq(
+            result_json.as_ptr(),
+            handle.complete_result_json().unwrap().as_ptr()
+        ));
+
+        let mut handle = MontyHandle::new("1/0".into(), vec![], None).unwrap();
+        let before = serializations();
+        handle.run();
+        assert_eq!(serializations() - before, 1);
+    }
+
+    #[test]
+    fn test_run_not_ready() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        handle.run(); // consume Ready state
+        let (tag, _, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        assert!(err.unwrap().contains("not in Ready state"));
+    }
+
+    #[test]
+    fn test_print_callback_streams_output() {
+        use std::sync::Mutex;
+
+        let code = "print('a')\next_fn()\nprint('b')";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        let seen = Arc::new(Mutex::new(String::new()));
+        let sink = seen.clone();
+        handle.set_print_callback(Some(Box::new(move |text| {
+            sink.lock().unwrap().push_str(text)
+        })));
+
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(*seen.lock().unwrap(), "a\n");
+
+        // Cleared while paused: the rest is buffered again.
+        handle.set_print_callback(None);
+        let (tag, _) = handle.resume("null");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert_eq!(*seen.lock().unwrap(), "a\n");
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["print_output"], "b\n");
+    }
+
+    #[test]
+    fn test_output_limit_truncates() {
+        let code = "for i in range(1000):\n    print('line', i)\n1";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_output_limit(64);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["print_output"].as_str().unwrap().len(), 64);
+        assert!(
+            parsed["print_output"]
+                .as_str()
+                .unwrap()
+                .starts_with("line 0\n")
+        );
+        assert_eq!(parsed["print_output_truncated"], true);
+
+        handle.reset().unwrap();
+        handle.set_output_limit(1024 * 1024);
+        let (_, result_json, _) = handle.run();
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert!(parsed.get("print_output_truncated").is_none());
+    }
+
+    #[test]
+    fn test_output_limit_respects_char_boundaries() {
+        let mut out = String::new();
+        let mut truncated = false;
+        let mut writer = BufferWriter {
+            out: &mut out,
+            limit: Some(3),
+            truncated: &mut truncated,
+        };
+        writer.push("aé");
+        writer.push("é");
+        assert_eq!(out, "aé");
+        assert!(truncated);
+    }
+
+    #[test]
+    fn test_print_callback_run() {
+        use std::sync::Mutex;
+
+        let mut handle = MontyHandle::new("print('hi')\n1".into(), vec![], None).unwrap();
+        let seen = Arc::new(Mutex::new(String::new()));
+        let sink = seen.clone();
+        handle.set_print_callback(Some(Box::new(move |text| {
+            sink.lock().unwrap().push_str(text)
+        })));
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        assert_eq!(*seen.lock().unwrap(), "hi\n");
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert!(parsed.get("print_output").is_none());
+    }
+
+    #[test]
+    fn test_max_resume_elements() {
+        let mut handle =
+            MontyHandle::new("len(ext_fn())".into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_max_resume_elements(1000);
+        handle.start();
+
+        let huge = serde_json::to_string(&vec![0u8; 1_000_000]).unwrap();
+        let (tag, err) = handle.resume(&huge);
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("max_resume_elements"));
+        let (tag, err) = handle.resume_reader(huge.as_bytes());
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("max_resume_elements"));
+
+        // Rejected values leave the handle paused.
+        let (tag, err) = handle.resume(&serde_json::to_string(&vec![0u8; 999]).unwrap());
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], 999);
+    }
+
+    #[test]
+    fn test_resume_many_completes() {
+        let code = "a = ext_fn(1)\nb = ext_fn(2)\nc = ext_fn(3)\na + b + c";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+
+        let (tag, err) = handle.resume_many("[10, 20, 30]");
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        assert_eq!(handle.last_batch_consumed(), 3);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], json!(60));
+    }
+
+    #[test]
+    fn test_ext_call_count() {
+        let code = "a = ext_fn(1)\nb = ext_fn(2)\nc = ext_fn(3)\na + b + c";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        assert_eq!(handle.ext_call_count(), 0);
+        handle.start();
+        assert_eq!(handle.ext_call_count(), 1);
+        let (tag, _) = handle.resume_many("[10, 20, 30]");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert_eq!(handle.ext_call_count(), 3);
+    }
+
+    #[test]
+    fn test_max_external_calls() {
+        let code = "a = ext_fn(1)\nb = ext_fn(2)\nc = ext_fn(3)\na + b + c";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_max_external_calls(2);
+        let limits: Value = serde_json::from_str(&handle.limits_json()).unwrap();
+        assert_eq!(limits["max_external_calls"], 2);
+
+        handle.start();
+        let (tag, _) = handle.resume("10");
+        assert_eq!(tag, MontyProgressTag::Pending);
+        let (tag, err) = handle.resume("20");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("external call limit exceeded: 2"));
+        assert_eq!(handle.ext_call_count(), 2);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["error"]["exc_type"], "RuntimeError");
+
+        handle.clear_limits();
+        assert!(handle.max_external_calls.is_none());
+    }
+
+    #[test]
+    fn test_reset_clears_call_count_and_event_log() {
+        let code = "x = ext_fn(1) if go else 0\nx";
+        let globals = vec![("go".to_string(), monty::MontyObject::Bool(true))];
+        let mut handle =
+            MontyHandle::new_with_globals(code.into(), vec!["ext_fn".into()], None, globals)
+                .unwrap();
+        handle.enable_event_log(16);
+        handle.start();
+        assert_eq!(handle.resume("10").0, MontyProgressTag::Complete);
+        assert_eq!(handle.ext_call_count(), 1);
+
+        handle.reset().unwrap();
+        assert_eq!(handle.ext_call_count(), 0);
+        let log: Value = serde_json::from_str(&handle.event_log_json().unwrap()).unwrap();
+        assert_eq!(log["events"], json!([]));
+
+        handle.globals[0].1 = monty::MontyObject::Bool(false);
+        let (tag, _, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        assert_eq!(handle.ext_call_count(), 0);
+    }
+
+    #[test]
+    fn test_max_call_args_bytes() {
+        let code = "ext_fn(1, list(range(100000)), 'x')";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_max_call_args_bytes(64);
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_args_json(), Some("[1]"));
+        assert_eq!(handle.pending_fn_args_count(), Some(3));
+        assert_eq!(handle.pending_args_truncated(), Some(true));
+        assert!(handle.pending_fn_arg_tagged_json(2).is_none());
+        let (tag, _) = handle.resume("null");
+        assert_eq!(tag, MontyProgressTag::Complete);
+
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        assert_eq!(handle.pending_fn_args_count(), Some(3));
+        assert_eq!(handle.pending_args_truncated(), Some(false));
+    }
+
+    #[test]
+    fn test_max_call_args_bytes_counts_kwarg_keys() {
+        let code = "ext_fn(1, a=2, long_keyword_name_well_past_the_budget=3)";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_max_call_args_bytes(16);
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_args_json(), Some("[1]"));
+        assert_eq!(handle.pending_fn_kwargs_json(), Some(r#"{"a":2}"#));
+        assert_eq!(handle.pending_args_truncated(), Some(true));
+    }
+
+    #[test]
+    fn test_pending_args_encoded_lazily() {
+        let encoder_runs = || ENCODER_RUNS.with(Cell::get);
+        let code = "ext_fn(list(range(1000)), k=(1, 2))";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        let before = encoder_runs();
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        assert_eq!(encoder_runs() - before, 0);
+        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+        assert_eq!(handle.pending_fn_args_count(), Some(1));
+        let HandleState::PausedNoLimit { meta, .. } = &handle.state else {
+            panic!("expected PausedNoLimit");
+        };
+        assert!(meta.args_json.get().is_none());
+        assert!(meta.args_tagged.get().is_none());
+        assert!(meta.kwargs_json.get().is_none());
+
+        let args = handle.pending_fn_args_json().unwrap();
+        assert!(args.starts_with("[[0,1,2,"));
+        assert_eq!(encoder_runs() - before, 1);
+        assert_eq!(handle.pending_fn_kwargs_json(), Some(r#"{"k":[1,2]}"#));
+        let HandleState::PausedNoLimit { meta, .. } = &handle.state else {
+            panic!("expected PausedNoLimit");
+        };
+        assert!(meta.args_json.get().is_some());
+        assert!(meta.args_tagged.get().is_none());
+    }
+
+    #[test]
+    fn test_pending_args_use_options_from_pause() {
+        let code = "ext_fn((1, 2), 'abcdef')";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        handle.set_typed_containers(true);
+        handle.set_max_string_length(2);
+        assert_eq!(handle.pending_fn_args_json(), Some(r#"[[1,2],"abcdef"]"#));
+
+        // A nesting depth limit is checked at the pause too, not at the read.
+        let code = "ext_fn([[[1]]])";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.options.conversion.max_depth = 2;
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("conversion depth exceeded"));
+        assert_eq!(handle.complete_is_error(), Some(true));
+    }
+
+    #[test]
+    fn test_resume_with_big_integer_literal() {
+        let code = "x = ext_fn()\n(x == 1234567890123456789012345, x + 1)";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, err) = handle.resume("1234567890123456789012345");
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], json!([true, "1234567890123456789012346"]));
+    }
+
+    #[test]
+    fn test_resume_many_runs_out() {
+        let code = "a = ext_fn(1)\nb = ext_fn(2)\nc = ext_fn(3)\na + b + c";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, _) = handle.resume_many("[10]");
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.last_batch_consumed(), 1);
+        assert_eq!(handle.pending_fn_args_json(), Some("[2]"));
+
+        // Extra values past completion are left unconsumed.
+        let (tag, _) = handle.resume_many("[20, 30, 40]");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert_eq!(handle.last_batch_consumed(), 2);
+    }
+
+    #[test]
+    fn test_resume_many_wrong_state() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let (tag, err) = handle.resume_many("[1]");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("Paused"));
+        assert_eq!(handle.last_batch_consumed(), 0);
+        let (tag, _) = handle.resume_many("{}");
+        assert_eq!(tag, MontyProgressTag::Error);
+    }
+
+    #[test]
+    fn test_os_call_paused_for_host() {
+        let code = "import os\nos.getenv('HOME')";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_pause_os_calls(true);
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::OsCall, "{err:?}");
+        assert_eq!(handle.state_tag(), MontyStateTag::OsCall);
+        assert_eq!(handle.pending_os_call_name(), Some("os.getenv"));
+        assert_eq!(handle.pending_os_call_args_json(), Some("[\"HOME\"]"));
+        assert_eq!(handle.pending_fn_name(), None);
+
+        let (tag, _) = handle.resume("\"/nope\"");
+        assert_eq!(tag, MontyProgressTag::Error);
+
+        let (tag, err) = handle.resume_os_call("\"/home/guest\"");
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], "/home/guest");
+    }
+
+    #[test]
+    fn test_os_call_without_handler_errors() {
+        let code = "import os\nos.getenv('HOME')";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("no handler for OS call"));
+
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_pause_os_calls(true);
+        let (tag, _, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        assert!(err.unwrap().contains("cannot be answered in run"));
+    }
+
+    #[test]
+    fn test_os_handler_answers_os_call_in_run() {
+        let code = "import os\nos.getenv('HOME')";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.register_os_handler(
+            "os.getenv",
+            Box::new(|_| Ok(monty::MontyObject::String("/home/guest".into()))),
+        );
+        let (tag, result_json, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok, "{err:?}");
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["value"], "/home/guest");
+    }
+
+    #[test]
+    fn test_resume_os_call_wrong_state() {
+        let mut handle = MontyHandle::new("ext_fn(1)".into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, err) = handle.resume_os_call("null");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("OS call"));
+        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+    }
+
+    #[test]
+    fn test_os_handler_answers_os_call() {
+        let code = "import os\nos.getenv('HOME')";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.register_os_handler(
+            "os.getenv",
+            Box::new(|args| {
+                assert_eq!(args, [monty::MontyObject::String("HOME".into())]);
+                Ok(monty::MontyObject::String("/home/guest".into()))
+            }),
+        );
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], "/home/guest");
+    }
+
+    #[test]
+    fn test_timeout_is_retryable() {
+        let code = "i = 0\nwhile True:\n    i += 1\ni";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_time_limit_ms(1);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["error"]["exc_type"], "TimeoutError");
+        assert_eq!(parsed["error"]["retryable"], true);
+        assert_eq!(handle.complete_is_retryable(), Some(true));
+    }
+
+    #[test]
+    fn test_limit_exceeded_kind() {
+        let error_of = |mut handle: MontyHandle| {
+            let (tag, result_json, _) = handle.run();
+            assert_eq!(tag, MontyResultTag::Error);
+            serde_json::from_str::<Value>(&result_json).unwrap()["error"].take()
+        };
+
+        let code = "x = []\nwhile True:\n    x.append('y' * 1000)";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_memory_limit(64 * 1024);
+        let error = error_of(handle);
+        assert_eq!(error["exc_type"], "MemoryError");
+        assert_eq!(error["limit_exceeded"], "memory");
+
+        let code = "i = 0\nwhile True:\n    i += 1";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_time_limit_ms(1);
+        let error = error_of(handle);
+        assert_eq!(error["exc_type"], "TimeoutError");
+        assert_eq!(error["limit_exceeded"], "time");
+
+        let code = "def f(n):\n    return f(n + 1)\nf(0)";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_stack_limit(50);
+        let error = error_of(handle);
+        assert_eq!(error["exc_type"], "RecursionError");
+        assert_eq!(error["limit_exceeded"], "stack");
+
+        let code = "raise MemoryError('guest')";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_memory_limit(1024 * 1024);
+        assert!(error_of(handle).get("limit_exceeded").is_none());
+    }
+
+    #[test]
+    fn test_compute_time_limit_times_out() {
+        let code = "i = 0\nwhile True:\n    i += 1\ni";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_compute_time_limit_ms(1);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["error"]["exc_type"], "TimeoutError");
+    }
+
+    #[test]
+    fn test_compute_time_limit_excludes_host_wait() {
+        let code = "x = ext_fn(1)\ntotal = 0\nfor i in range(1000):\n    total += i\nx + total";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_compute_time_limit_ms(200);
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+
+        // A slow host answer longer than the whole compute budget.
+        std::thread::sleep(Duration::from_millis(400));
+        let (tag, err) = handle.resume("1");
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], 499_501);
+    }
+
+    #[test]
+    fn test_interrupt_token_stops_loop() {
+        let code = "i = 0\nwhile True:\n    i += 1\ni";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        let token = handle.interrupt_token();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            token.interrupt();
+        });
+        let (tag, result_json, _) = handle.run();
+        interrupter.join().unwrap();
+        assert_eq!(tag, MontyResultTag::Error);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["error"]["exc_type"], "KeyboardInterrupt");
+    }
+
+    #[test]
+    fn test_resume_with_exception() {
+        let code = "try:\n    r = ext_fn(1)\nexcept ValueError as e:\n    r = e.args\nr";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, err) =
+            handle.resume_with_exception(r#"{"type": "ValueError", "args": ["bad input"]}"#);
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], json!(["bad input"]));
+
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, _) = handle.resume_with_exception(r#"{"type": "ValueError"}"#);
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], json!([]));
+
+        // Rejected descriptions leave the handle paused.
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        for (exc_json, expected) in [
+            ("[]", "must be an object"),
+            (r#"{"type": "NoSuchError"}"#, "unknown exception type"),
+            (r#"{"type": "ValueError", "args": [1, 2]}"#, "not supported"),
+            (
+                r#"{"type": "ValueError", "message": "a", "args": []}"#,
+                "not both",
+            ),
+        ] {
+            let (tag, err) = handle.resume_with_exception(exc_json);
+            assert_eq!(tag, MontyProgressTag::Error);
+            assert!(err.unwrap().contains(expected), "{exc_json}");
+            assert_eq!(handle.state_tag(), MontyStateTag::Paused);
+        }
+        let (tag, _) = handle.resume_with_exception(r#"{"type": "KeyError", "message": "k"}"#);
+        assert_eq!(tag, MontyProgressTag::Error);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["error"]["exc_type"], "KeyError");
+    }
+
+    #[test]
+    fn test_error_sites_set_error_code() {
+        let code = "ext_fn(1)";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.resume("1");
+        assert_eq!(handle.last_error_code(), MontyErrorCode::WrongState);
+        handle.start();
+
+        handle.set_last_error_code(MontyErrorCode::None);
+        handle.resume_with_exception("{");
+        assert_eq!(handle.last_error_code(), MontyErrorCode::InvalidJson);
+
+        // Other rejections leave the code for the FFI layer to default.
+        handle.set_last_error_code(MontyErrorCode::None);
+        let (_, err) = handle.resume_with_exception(r#"{"type": "NoSuchError"}"#);
+        assert!(err.is_some());
+        assert_eq!(handle.last_error_code(), MontyErrorCode::None);
+        handle.note_error();
+        assert_eq!(handle.last_error_code(), MontyErrorCode::InvalidArgument);
+
+        // Codes come from the site, not the message text.
+        handle.set_last_error_code(MontyErrorCode::None);
+        let (_, err) = handle.resume_with_exception(r#"{"type": "ValueError", "args": [1, 2]}"#);
+        assert!(err.is_some());
+        assert_eq!(handle.last_error_code(), MontyErrorCode::None);
+        assert!(handle.try_clone().is_err());
+        assert_eq!(handle.last_error_code(), MontyErrorCode::WrongState);
+    }
+
+    #[test]
+    fn test_resume_with_typed_error() {
+        let code =
+            "try:\n    r = ext_fn(1)\nexcept ValueError as e:\n    r = 'caught: ' + str(e)\nr";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, err) = handle.resume_with_typed_error("ValueError", "bad input");
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], "caught: bad input");
+
+        // Other types get past `except ValueError`.
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, _) = handle.resume_with_typed_error("KeyError", "missing");
+        assert_eq!(tag, MontyProgressTag::Error);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["error"]["exc_type"], "KeyError");
+
+        // Unknown names raise RuntimeError.
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, _) = handle.resume_with_typed_error("NoSuchError", "?");
+        assert_eq!(tag, MontyProgressTag::Error);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["error"]["exc_type"], "RuntimeError");
+    }
+
+    #[test]
+    fn test_name_error_not_retryable() {
+        let mut handle = MontyHandle::new("undefined_name".into(), vec![], None).unwrap();
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["error"]["exc_type"], "NameError");
+        assert_eq!(parsed["error"]["retryable"], false);
+        assert_eq!(handle.complete_is_retryable(), Some(false));
+    }
+
+    #[test]
+    fn test_retryable_exc_types_override() {
+        let mut handle = MontyHandle::new("undefined_name".into(), vec![], None).unwrap();
+        handle
+            .set_retryable_exc_types(Some(vec!["NameError".into()]))
+            .unwrap();
+        handle.run();
+        assert_eq!(handle.complete_is_retryable(), Some(true));
+
+        let err = handle
+            .set_retryable_exc_types(Some(vec!["NotAnError".into()]))
+            .unwrap_err();
+        assert!(err.contains("unknown exception type"));
+    }
+
+    #[test]
+    fn test_injected_error_is_retryable() {
+        let mut handle = MontyHandle::new("ext_fn(1)".into(), vec!["ext_fn".into()], None).unwrap();
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let (tag, _) = handle.resume_with_error("upstream unavailable");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert_eq!(handle.complete_is_retryable(), Some(true));
+    }
+
+    #[test]
+    fn test_guest_error_of_injected_type_not_retryable() {
+        // The guest handles the injected error, then fails on its own with
+        // the same type; only the injected exception itself is retryable.
+        let code =
+            "try:\n    ext_fn(1)\nexcept RuntimeError:\n    pass\nraise RuntimeError('guest bug')";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let (tag, _) = handle.resume_with_error("upstream unavailable");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert_eq!(handle.complete_is_retryable(), Some(false));
+
+        // An injected error answered by a later plain resume is forgotten.
+        let code =
+            "try:\n    ext_fn(1)\nexcept ValueError:\n    pass\next_fn(2)\nraise ValueError('bad')";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let (tag, _) = handle.resume_with_typed_error("ValueError", "bad");
+        assert_eq!(tag, MontyProgressTag::Pending);
+        let (tag, _) = handle.resume("null");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert_eq!(handle.complete_is_retryable(), Some(false));
+    }
+
+    #[test]
+    fn test_label_in_panic_message() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let panic_msg = crate::error::catch_ffi_panic(|| panic!("boom")).unwrap_err();
+        assert_eq!(handle.panic_message(panic_msg.clone()), panic_msg);
+
+        handle.set_label(Some("tenant-42/report-gen".into()));
+        assert_eq!(handle.label(), Some("tenant-42/report-gen"));
+        assert_eq!(
+            handle.panic_message(panic_msg.clone()),
+            format!("handle 'tenant-42/report-gen' panicked: {panic_msg}")
+        );
+    }
+
+    #[test]
+    fn test_set_limits() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        handle.set_memory_limit(1024 * 1024);
+        handle.set_time_limit_ms(5000);
+        handle.set_stack_limit(100);
+        let (tag, _, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+    }
+
+    #[test]
+    fn test_limits_json() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let limits: Value = serde_json::from_str(&handle.limits_json()).unwrap();
+        assert_eq!(
+            limits,
+            json!({
+                "max_memory": null,
+                "max_duration_ms": null,
+                "max_recursion_depth": null,
+                "max_compute_ms": null,
+                "max_external_calls": null,
+                "max_call_args_bytes": null,
+            })
+        );
+
+        handle.set_memory_limit(4096);
+        handle.set_stack_limit(50);
+        let limits: Value = serde_json::from_str(&handle.limits_json()).unwrap();
+        assert_eq!(limits["max_memory"], 4096);
+        assert_eq!(limits["max_recursion_depth"], 50);
+        assert!(limits["max_duration_ms"].is_null());
+
+        handle.set_compute_time_limit_ms(250);
+        let limits: Value = serde_json::from_str(&handle.limits_json()).unwrap();
+        assert_eq!(limits["max_compute_ms"], 250);
+        assert!(limits["max_duration_ms"].is_null());
+        handle.set_time_limit_ms(1500);
+        let limits: Value = serde_json::from_str(&handle.limits_json()).unwrap();
+        assert_eq!(limits["max_duration_ms"], 1500);
+        assert!(limits["max_compute_ms"].is_null());
+    }
+
+    #[test]
+    fn test_set_limits_json() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let full = r#"{"max_memory": 4096, "max_duration_ms": 1500, "max_recursion_depth": 50}"#;
+        handle.set_limits_json(full).unwrap();
+        let limits: Value = serde_json::from_str(&handle.limits_json()).unwrap();
+        assert_eq!(limits["max_memory"], 4096);
+        assert_eq!(limits["max_duration_ms"], 1500);
+        assert_eq!(limits["max_recursion_depth"], 50);
+
+        // A subset leaves the other limits alone.
+        handle
+            .set_limits_json(r#"{"max_memory": 8192, "max_duration_ms": null}"#)
+            .unwrap();
+        let limits: Value = serde_json::from_str(&handle.limits_json()).unwrap();
+        assert_eq!(limits["max_memory"], 8192);
+        assert_eq!(limits["max_duration_ms"], 1500);
+        assert_eq!(limits["max_recursion_depth"], 50);
+    }
+
+    #[test]
+    fn test_set_limits_json_rejects_without_applying() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let before = handle.limits_json();
+        let err = handle
+            .set_limits_json(r#"{"max_memory": 4096, "max_memroy": 1}"#)
+            .unwrap_err();
+        assert_eq!(err, "unknown limit: max_memroy");
+        let err = handle
+            .set_limits_json(r#"{"max_memory": 4096, "max_recursion_depth": -1}"#)
+            .unwrap_err();
+        assert!(err.contains("invalid value for limit 'max_recursion_depth'"));
+        assert!(
+            handle
+                .set_limits_json("[1]")
+                .unwrap_err()
+                .contains("must be an object")
+        );
+        assert_eq!(handle.limits_json(), before);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        let bytes = handle.snapshot().unwrap();
+        assert!(!bytes.is_empty());
+
+        let mut restored = MontyHandle::restore(&bytes).unwrap();
+        let (tag, result_json, _) = restored.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["value"], json!(4));
+    }
+
+    #[test]
+    fn test_snapshot_size_matches_snapshot() {
+        let handle = MontyHandle::new("x = [1, 2, 3]\nsum(x)".into(), vec![], None).unwrap();
+        let size = handle.snapshot_size().unwrap();
+        assert_eq!(handle.snapshot_size().unwrap(), size);
+        let bytes = handle.snapshot().unwrap();
+        assert_eq!(bytes.len(), size);
+        // The cached buffer is handed out once; later calls serialize again.
+        assert_eq!(handle.snapshot().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_snapshot_size_invalidated_by_recompile() {
+        let mut handle = MontyHandle::new("ext_fn(1)".into(), vec![], None).unwrap();
+        handle.snapshot_size().unwrap();
+        handle.add_external("ext_fn").unwrap();
+        // The snapshot reflects the recompiled program, not the stale cache.
+        let bytes = handle.snapshot().unwrap();
+        let mut restored = MontyHandle::restore(&bytes).unwrap();
+        let (tag, _) = restored.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+    }
+
+    #[test]
+    fn test_snapshot_size_wrong_state() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        handle.run();
+        assert!(handle.snapshot_size().is_err());
+    }
+
+    #[test]
     fn test_snapshot_wrong_state() {
         let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
         handle.run();
@@ -698,240 +3947,1031 @@ mod tests {
         assert!(result.is_err());
     }
 
+    fn paused_round_trip(limited: bool) {
+        let mut handle =
+            MontyHandle::new("ext_fn(1, 2) + 10".into(), vec!["ext_fn".into()], None).unwrap();
+        if limited {
+            handle.set_memory_limit(1024 * 1024);
+        }
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+
+        let bytes = handle.snapshot().unwrap();
+        assert_eq!(handle.snapshot_size().unwrap(), bytes.len());
+        drop(handle);
+
+        let mut restored = MontyHandle::restore(&bytes).unwrap();
+        assert_eq!(restored.state_tag(), MontyStateTag::Paused);
+        assert_eq!(restored.pending_fn_name(), Some("ext_fn"));
+        assert_eq!(restored.pending_fn_args_json(), Some("[1,2]"));
+
+        let (tag, _) = restored.resume("5");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(restored.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], json!(15));
+    }
+
+    #[test]
+    fn test_snapshot_paused_keeps_config() {
+        let code = "a = ext_fn(base)\nb = ext_fn(2)\nc = ext_fn(3)\n(a, b, c, input(), PI)";
+        let externals = vec!["ext_fn".into(), "PI".into()];
+        let globals = vec![("base".to_string(), monty::MontyObject::Int(1))];
+        let mut handle =
+            MontyHandle::new_with_globals(code.into(), externals, None, globals).unwrap();
+        handle.set_memory_limit(1024 * 1024);
+        handle.set_compute_time_limit_ms(60_000);
+        handle.set_typed_containers(true);
+        handle.set_correlation_id(Some("req-1".into()));
+        handle.set_label(Some("job-9".into()));
+        handle.set_stdin("line".into()).unwrap();
+        handle.register_constant("PI", "3.5").unwrap();
+        handle.set_max_external_calls(2);
+        handle.set_max_call_args_bytes(64);
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        assert_eq!(handle.resume("1").0, MontyProgressTag::Pending);
+
+        let bytes = handle.snapshot().unwrap();
+        drop(handle);
+        let mut restored = MontyHandle::restore(&bytes).unwrap();
+        assert_eq!(restored.globals.len(), 1);
+        assert!(restored.limits.is_some());
+        assert_eq!(restored.compute_time_limit, Some(Duration::from_secs(60)));
+        assert_eq!(restored.label.as_deref(), Some("job-9"));
+        assert_eq!(restored.max_call_args_bytes, Some(64));
+
+        // The third call is past max_external_calls, counted across restore.
+        let (tag, _) = restored.resume("2");
+        assert_eq!(tag, MontyProgressTag::Error);
+        let result: Value = serde_json::from_str(restored.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["correlation_id"], "req-1");
+
+        let code = "(ext_fn(1), input(), PI)";
+        let externals = vec!["ext_fn".into(), "PI".into()];
+        let mut handle = MontyHandle::new(code.into(), externals, None).unwrap();
+        handle.set_typed_containers(true);
+        handle.set_stdin("line".into()).unwrap();
+        handle.register_constant("PI", "3.5").unwrap();
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let mut restored = MontyHandle::restore(&handle.snapshot().unwrap()).unwrap();
+        let (tag, err) = restored.resume("1");
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let result: Value = serde_json::from_str(restored.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], json!({"__tuple__": [1, "line", 3.5]}));
+
+        // The compute limit still excludes host wait after a restore.
+        let code = "x = ext_fn(1)\ntotal = 0\nfor i in range(1000):\n    total += i\nx + total";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_compute_time_limit_ms(200);
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let mut restored = MontyHandle::restore(&handle.snapshot().unwrap()).unwrap();
+        std::thread::sleep(Duration::from_millis(400));
+        let (tag, err) = restored.resume("1");
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let result: Value = serde_json::from_str(restored.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], 499_501);
+    }
+
+    #[test]
+    fn test_snapshot_paused_limited() {
+        paused_round_trip(true);
+    }
+
+    #[test]
+    fn test_snapshot_paused_no_limit() {
+        paused_round_trip(false);
+    }
+
+    #[test]
+    fn test_restore_paused_unknown_kind() {
+        let mut bytes = PAUSED_SNAPSHOT_MAGIC.to_vec();
+        bytes.push(b'?');
+        let err = MontyHandle::restore(&bytes).err().unwrap();
+        assert!(err.contains("unknown paused snapshot kind"));
+    }
+
+    #[test]
+    fn test_snapshot_header_versioned() {
+        let handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        let bytes = handle.snapshot().unwrap();
+        assert!(bytes.starts_with(SNAPSHOT_MAGIC));
+        let version_at = SNAPSHOT_MAGIC.len();
+        assert_eq!(
+            bytes[version_at..version_at + 4],
+            SNAPSHOT_FORMAT_VERSION.to_le_bytes()
+        );
+        assert!(MontyHandle::restore(&bytes).is_ok());
+
+        // Headerless bytes from before versioning still restore.
+        assert!(MontyHandle::restore(&bytes[version_at + 4..]).is_ok());
+
+        let err = MontyHandle::restore(&bytes[..version_at + 2])
+            .err()
+            .unwrap();
+        assert_eq!(err, "restore failed: snapshot header truncated");
+
+        let mut bumped = bytes.clone();
+        bumped[version_at..version_at + 4]
+            .copy_from_slice(&(SNAPSHOT_FORMAT_VERSION + 1).to_le_bytes());
+        let err = MontyHandle::restore(&bumped).err().unwrap();
+        assert_eq!(
+            err,
+            format!(
+                "snapshot version mismatch: got {}, expected {SNAPSHOT_FORMAT_VERSION}",
+                SNAPSHOT_FORMAT_VERSION + 1
+            )
+        );
+    }
+
+    #[test]
+    fn test_snapshot_codecs_round_trip() {
+        let code = format!("x = 0\n{}x", "x = x + 1\n".repeat(200));
+        let handle = MontyHandle::new(code, vec![], None).unwrap();
+        let plain = handle.snapshot().unwrap();
+        for codec in [
+            SnapshotCodec::None,
+            SnapshotCodec::Zstd,
+            SnapshotCodec::Gzip,
+        ] {
+            let bytes = handle.snapshot_with_codec(codec).unwrap();
+            if codec != SnapshotCodec::None {
+                assert!(bytes.len() < plain.len(), "{codec:?} did not shrink");
+            }
+            let mut restored = MontyHandle::restore(&bytes).unwrap();
+            let (tag, result_json, _) = restored.run();
+            assert_eq!(tag, MontyResultTag::Ok, "{codec:?}");
+            let parsed: Value = serde_json::from_str(&result_json).unwrap();
+            assert_eq!(parsed["value"], json!(200));
+        }
+    }
+
+    #[test]
+    fn test_restore_corrupt_compressed() {
+        let err = MontyHandle::restore(&[0x1F, 0x8B, 0, 0]).err().unwrap();
+        assert!(
+            err.starts_with("restore failed: snapshot decompression"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_decompress_stops_at_limit() {
+        let body = vec![0u8; 4096];
+        for codec in [SnapshotCodec::Zstd, SnapshotCodec::Gzip] {
+            let frame = compress_snapshot(body.clone(), codec).unwrap();
+            let err = decompress_snapshot(&frame, 1024).unwrap_err();
+            assert_eq!(
+                err, "restore failed: decompressed snapshot exceeds 1024 bytes",
+                "{codec:?}"
+            );
+            let out = decompress_snapshot(&frame, 4096).unwrap().unwrap();
+            assert_eq!(out, body, "{codec:?}");
+        }
+    }
+
+    #[test]
+    fn test_restore_rejects_nested_compression() {
+        let handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let inner = handle.snapshot_with_codec(SnapshotCodec::Gzip).unwrap();
+        let nested = compress_snapshot(inner, SnapshotCodec::Zstd).unwrap();
+        let err = MontyHandle::restore(&nested).err().unwrap();
+        assert_eq!(err, "restore failed: compressed snapshot has no header");
+    }
+
+    #[test]
+    fn test_restore_invalid_bytes() {
+        let result = MontyHandle::restore(&[0, 1, 2, 3]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_start_complete() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert!(err.is_none());
+        assert!(handle.complete_result_json().is_some());
+        assert_eq!(handle.complete_is_error(), Some(false));
+    }
+
+    #[test]
+    fn test_iterative_execution() {
+        let code = r#"
+result = ext_fn(42)
+result + 1
+"#;
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert!(err.is_none());
+        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+
+        let args: Value = serde_json::from_str(handle.pending_fn_args_json().unwrap()).unwrap();
+        assert_eq!(args, json!([42]));
+
+        // Resume with 100
+        let (tag, err) = handle.resume("100");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert!(err.is_none());
+
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], json!(101));
+    }
+
+    #[test]
+    fn test_resume_with_error() {
+        let code = r#"
+try:
+    result = ext_fn(1)
+except RuntimeError as e:
+    result = str(e)
+result
+"#;
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+
+        let (tag, _) = handle.resume_with_error("something went wrong");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert_eq!(handle.complete_is_error(), Some(false));
+
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert!(
+            result["value"]
+                .as_str()
+                .unwrap()
+                .contains("something went wrong")
+        );
+    }
+
+    #[test]
+    fn test_pending_accessors_wrong_state() {
+        let handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        assert!(handle.pending_fn_name().is_none());
+        assert!(handle.pending_fn_args_json().is_none());
+        assert!(handle.complete_result_json().is_none());
+        assert!(handle.complete_is_error().is_none());
+    }
+
+    #[test]
+    fn test_start_not_ready() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        handle.run();
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_resume_not_paused() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        let (tag, err) = handle.resume("42");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_resume_missing_vs_none() {
+        let code = "try:\n    v = get('k')\nexcept KeyError:\n    v = 'absent'\nv";
+
+        let mut handle = MontyHandle::new(code.into(), vec!["get".into()], None).unwrap();
+        handle.start();
+        let (tag, _) = handle.resume(r#"{"__monty_type__": "missing"}"#);
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], "absent");
+
+        let mut handle = MontyHandle::new(code.into(), vec!["get".into()], None).unwrap();
+        handle.start();
+        let (tag, _) = handle.resume("null");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], Value::Null);
+    }
+
+    #[test]
+    fn test_resume_reader() {
+        let mut handle =
+            MontyHandle::new("len(fetch())".into(), vec!["fetch".into()], None).unwrap();
+        handle.start();
+        let json = format!("[{}]", vec!["7"; 10_000].join(","));
+        let (tag, err) = handle.resume_reader(json.as_bytes());
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], 10_000);
+    }
+
+    #[test]
+    fn test_resume_reader_nesting_past_serde_limit() {
+        let mut handle =
+            MontyHandle::new("len(fetch())".into(), vec!["fetch".into()], None).unwrap();
+        handle.start();
+        let json = "[".repeat(300) + &"]".repeat(300);
+        let (tag, err) = handle.resume_reader(json.as_bytes());
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+    }
+
+    #[test]
+    fn test_resume_paths_stop_at_budget() {
+        let code = "a = ext_fn()\nb = ext_fn()\nc = ext_fn()\nlen(a) + len(b) + len(c)";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_max_resume_elements(10);
+        handle.start();
+
+        // The text after the 11th value is malformed: an error from the
+        // budget, not the syntax, shows decoding stopped before reaching it.
+        let oversized = format!("[{}", "0,".repeat(20));
+        let (tag, err) = handle.resume(&oversized);
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("max_resume_elements"));
+        let (tag, err) = handle.resume_many(&format!("[{oversized}"));
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("max_resume_elements"));
+        assert_eq!(handle.last_batch_consumed(), 0);
+        assert_eq!(handle.state_tag(), MontyStateTag::Paused);
+
+        // Values nest past serde_json's own 128-level limit.
+        let deep = "[".repeat(300) + &"]".repeat(300);
+        handle.set_max_resume_elements(1000);
+        let (tag, err) = handle.resume_many(&format!("[{deep}, {deep}, {deep}]"));
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+    }
+
     #[test]
-    fn test_restore_invalid_bytes() {
-        let result = MontyHandle::restore(&[0, 1, 2, 3]);
-        assert!(result.is_err());
+    fn test_resume_futures_stop_at_budget() {
+        let mut handle = MontyHandle::new(
+            async_code_gather().into(),
+            vec!["foo".into(), "bar".into()],
+            None,
+        )
+        .unwrap();
+        handle.set_max_resume_elements(10);
+        let (mut tag, _) = handle.start();
+        while tag == MontyProgressTag::Pending {
+            (tag, _) = handle.resume_as_future();
+        }
+        assert_eq!(tag, MontyProgressTag::ResolveFutures);
+        let ids: Vec<u32> =
+            serde_json::from_str(handle.pending_future_call_ids().unwrap()).unwrap();
+
+        let oversized = format!("{{\"{}\": [{}", ids[0], "0,".repeat(20));
+        let (tag, err) = handle.resume_futures(&oversized, "{}");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("max_resume_elements"));
+        assert_eq!(handle.state_tag(), MontyStateTag::Futures);
+
+        // Decoding nests past serde_json's 128-level limit; the guest then
+        // fails adding a list to an int.
+        let deep = "[".repeat(300) + &"]".repeat(300);
+        handle.set_max_resume_elements(1000);
+        let results = format!("{{\"{}\": {deep}, \"{}\": 1}}", ids[0], ids[1]);
+        let (tag, err) = handle.resume_futures(&results, "{}");
+        assert_eq!(tag, MontyProgressTag::Error, "{err:?}");
+        assert_eq!(handle.last_error_code(), MontyErrorCode::VmException);
     }
 
     #[test]
-    fn test_start_complete() {
+    fn test_resume_reader_invalid_json_keeps_state() {
+        let mut handle = MontyHandle::new("fetch()".into(), vec!["fetch".into()], None).unwrap();
+        handle.start();
+        let (tag, err) = handle.resume_reader("[1,".as_bytes());
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("invalid JSON"));
+        assert_eq!(handle.pending_fn_name(), Some("fetch"));
+    }
+
+    #[test]
+    fn test_resume_reader_not_paused() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let (tag, err) = handle.resume_reader("1".as_bytes());
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("not in Paused state"));
+    }
+
+    #[test]
+    fn test_resume_invalid_json() {
+        let code = "result = ext_fn(1)\nresult";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.start();
+        let (tag, err) = handle.resume("not valid json{");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("invalid JSON"));
+    }
+
+    use serde_json::json;
+
+    #[test]
+    fn test_iterative_no_limits() {
+        // Exercise the NoLimitTracker path through start/resume
+        let code = "result = ext_fn(10)\nresult + 5";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        // No limits set — uses NoLimitTracker
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert!(err.is_none());
+        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+
+        let (tag, err) = handle.resume("20");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert!(err.is_none());
+
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], json!(25));
+    }
+
+    #[test]
+    fn test_start_runtime_error() {
+        // Exception during start() — covers handle_exception via iterative path
+        let mut handle = MontyHandle::new("1/0".into(), vec![], None).unwrap();
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.is_some());
+        assert!(handle.complete_is_error() == Some(true));
+    }
+
+    #[test]
+    fn test_start_with_limits_complete() {
+        // LimitedTracker start that completes immediately
         let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        handle.set_memory_limit(10 * 1024 * 1024);
+        handle.set_time_limit_ms(5000);
         let (tag, err) = handle.start();
         assert_eq!(tag, MontyProgressTag::Complete);
         assert!(err.is_none());
-        assert!(handle.complete_result_json().is_some());
         assert_eq!(handle.complete_is_error(), Some(false));
     }
 
     #[test]
-    fn test_iterative_execution() {
-        let code = r#"
-result = ext_fn(42)
-result + 1
-"#;
+    fn test_iterative_with_limits() {
+        // Exercise the LimitedTracker path through start/resume
+        let code = "result = ext_fn(1)\nresult * 2";
         let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_memory_limit(10 * 1024 * 1024);
+        handle.set_time_limit_ms(5000);
         let (tag, err) = handle.start();
         assert_eq!(tag, MontyProgressTag::Pending);
         assert!(err.is_none());
-        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
-
-        let args: Value = serde_json::from_str(handle.pending_fn_args_json().unwrap()).unwrap();
-        assert_eq!(args, json!([42]));
 
-        // Resume with 100
-        let (tag, err) = handle.resume("100");
+        let (tag, err) = handle.resume("50");
         assert_eq!(tag, MontyProgressTag::Complete);
         assert!(err.is_none());
 
         let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
-        assert_eq!(result["value"], json!(101));
+        assert_eq!(result["value"], json!(100));
     }
 
     #[test]
-    fn test_resume_with_error() {
-        let code = r#"
-try:
-    result = ext_fn(1)
-except RuntimeError as e:
-    result = str(e)
-result
-"#;
+    fn test_run_with_limits_error() {
+        // Run with limits that triggers an exception
+        let mut handle = MontyHandle::new("1/0".into(), vec![], None).unwrap();
+        handle.set_memory_limit(10 * 1024 * 1024);
+        let (tag, _, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_multiple_ext_fn_calls() {
+        // Multiple pauses: Paused→Paused→Complete
+        let code = "a = ext_fn(1)\nb = ext_fn(2)\na + b";
         let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
         let (tag, _) = handle.start();
         assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
 
-        let (tag, _) = handle.resume_with_error("something went wrong");
+        let (tag, _) = handle.resume("10");
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+
+        let (tag, _) = handle.resume("20");
         assert_eq!(tag, MontyProgressTag::Complete);
-        assert_eq!(handle.complete_is_error(), Some(false));
 
         let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
-        assert!(
-            result["value"]
-                .as_str()
-                .unwrap()
-                .contains("something went wrong")
-        );
+        assert_eq!(result["value"], json!(30));
+    }
+
+    #[test]
+    fn test_usage_compile_ms() {
+        let code: String = (0..200)
+            .map(|i| format!("def f{i}(x):\n    return [x * n for n in range({i})]\n"))
+            .chain(std::iter::once("f199(2)[-1]".to_string()))
+            .collect();
+        let mut handle = MontyHandle::new(code, vec![], None).unwrap();
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["value"], 396);
+        assert!(parsed["usage"]["compile_ms"].as_f64().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_compile_budget_kept_and_workers_capped() {
+        let budget = Duration::from_secs(10);
+        let mut handle =
+            MontyHandle::new_with_compile_timeout("1 + 1".into(), vec![], None, vec![], budget)
+                .unwrap();
+        assert_eq!(handle.compile_timeout, Some(budget));
+
+        // With every worker slot taken, creates and recompiles under a budget
+        // fail at once instead of queueing threads.
+        let slots: Vec<_> = std::iter::from_fn(CompileWorkerSlot::acquire).collect();
+        assert_eq!(slots.len(), MAX_COMPILE_WORKERS);
+        let err = MontyHandle::new_with_compile_timeout("1".into(), vec![], None, vec![], budget)
+            .err()
+            .unwrap();
+        assert_eq!(err.exc_type(), monty::ExcType::RuntimeError);
+        assert!(err.summary().contains("too many compiles in progress"));
+        let err = handle.add_external("ext_fn").unwrap_err();
+        assert!(err.contains("too many compiles in progress"));
+        drop(slots);
+
+        handle.add_external("ext_fn").unwrap();
+        let (tag, _, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        handle.reset().unwrap();
+        assert_eq!(handle.try_clone().unwrap().compile_timeout, Some(budget));
+    }
+
+    const ALLOCATING_LOOP: &str =
+        "items = []\nfor i in range(1000):\n    items.append(str(i) * 10)\nlen(items)";
+
+    #[test]
+    fn test_clear_memory_limit_allows_allocation() {
+        let mut handle = MontyHandle::new(ALLOCATING_LOOP.into(), vec![], None).unwrap();
+        handle.set_memory_limit(1024);
+        let (tag, _, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+
+        handle.reset().unwrap();
+        handle.clear_memory_limit();
+        assert!(handle.limits.is_none());
+        let (tag, result_json, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok, "{err:?}");
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["value"], 1000);
+    }
+
+    #[test]
+    fn test_clear_limits_one_at_a_time() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        handle.set_memory_limit(4096);
+        handle.set_time_limit_ms(100);
+        handle.set_stack_limit(10);
+
+        handle.clear_stack_limit();
+        handle.clear_memory_limit();
+        let limits: Value = serde_json::from_str(&handle.limits_json()).unwrap();
+        assert_eq!(limits["max_recursion_depth"], 1000);
+        assert_eq!(limits["max_duration_ms"], 100);
+        handle.clear_time_limit();
+        assert!(handle.limits.is_none());
+
+        handle.set_compute_time_limit_ms(100);
+        handle.clear_limits();
+        assert!(handle.limits.is_none());
+        assert!(handle.compute_time_limit.is_none());
+    }
+
+    #[test]
+    fn test_run_reports_usage() {
+        let mut handle = MontyHandle::new(ALLOCATING_LOOP.into(), vec![], None).unwrap();
+        handle.set_memory_limit(64 * 1024 * 1024);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let usage = &serde_json::from_str::<Value>(&result_json).unwrap()["usage"];
+        assert!(usage["memory_bytes_used"].as_u64().unwrap() > 0);
+        assert!(usage["time_elapsed_ms"].as_u64().is_some());
+        assert!(usage["stack_depth_used"].as_u64().is_some());
+    }
+
+    #[test]
+    fn test_usage_reports_peaks() {
+        let code = "def build(n):\n    return [str(i) * 100 for i in range(n)]\n\
+                    items = build(2000)\nitems = None\n1";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let usage = &serde_json::from_str::<Value>(&result_json).unwrap()["usage"];
+        let peak = usage["peak_memory_bytes"].as_u64().unwrap();
+        let last = usage["final_memory_bytes"].as_u64().unwrap();
+        assert!(peak > last, "peak {peak} should exceed final {last}");
+        assert_eq!(usage["memory_bytes_used"], usage["peak_memory_bytes"]);
+        assert!(usage["peak_stack_depth"].as_u64().unwrap() > 0);
+        assert_eq!(usage["stack_depth_used"], usage["peak_stack_depth"]);
+    }
+
+    #[test]
+    fn test_start_reports_usage_without_limits() {
+        let code = format!("{ALLOCATING_LOOP}\next_fn(len(items))");
+        let mut handle = MontyHandle::new(code, vec!["ext_fn".into()], None).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        let (tag, _) = handle.resume("1");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert!(result["usage"]["memory_bytes_used"].as_u64().unwrap() > 0);
+        assert!(result["usage"]["time_elapsed_ms"].as_u64().is_some());
+
+        handle.clear_output();
+        assert_eq!(handle.usage.to_json()["memory_bytes_used"], 0);
+    }
+
+    #[test]
+    fn test_usage_json_while_paused() {
+        let code = format!("{ALLOCATING_LOOP}\nx = ext_fn(1)\n{ALLOCATING_LOOP}");
+        let mut handle = MontyHandle::new(code, vec!["ext_fn".into()], None).unwrap();
+        assert_eq!(handle.usage_json(), "{}");
+
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        let paused: Value = serde_json::from_str(handle.usage_json()).unwrap();
+        assert!(paused["memory_bytes_used"].as_u64().unwrap() > 0);
+
+        let (tag, _) = handle.resume("1");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let done: Value = serde_json::from_str(handle.usage_json()).unwrap();
+        for key in ["memory_bytes_used", "time_elapsed_ms"] {
+            assert!(done[key].as_u64().unwrap() >= paused[key].as_u64().unwrap());
+        }
+
+        handle.clear_output();
+        assert_eq!(handle.usage_json(), "{}");
+    }
+
+    #[test]
+    fn test_default_usage_json() {
+        let usage: Value = serde_json::from_str(&default_usage_json()).unwrap();
+        assert_eq!(usage["memory_bytes_used"], 0);
+        assert_eq!(usage["peak_memory_bytes"], 0);
+        assert_eq!(usage["final_memory_bytes"], 0);
+        assert_eq!(usage["time_elapsed_ms"], 0);
+        assert_eq!(usage["stack_depth_used"], 0);
+        assert_eq!(usage["peak_stack_depth"], 0);
+    }
+
+    /// Finish a result document holding `value` through the handle's own
+    /// `result_json`, as a completed run would.
+    fn build_result_json(handle: &MontyHandle, value: Value, error: Option<Value>) -> String {
+        let mut out = RESULT_VALUE_PREFIX.to_vec();
+        serde_json::to_writer(&mut out, &value).unwrap();
+        handle.result_json(out, error, true, None)
+    }
+
+    #[test]
+    fn test_build_result_json_ok() {
+        let handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let result = build_result_json(&handle, json!(42), None);
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["value"], 42);
+        assert!(parsed.get("error").is_none());
+        assert!(parsed.get("print_output").is_none());
+        assert!(parsed["usage"].is_object());
+    }
+
+    #[test]
+    fn test_build_result_json_error() {
+        let handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let result = handle.error_result_json(json!({"message": "boom"}));
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert!(parsed["value"].is_null());
+        assert_eq!(parsed["error"]["message"], "boom");
+    }
+
+    #[test]
+    fn test_build_result_json_with_print_output() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        handle.print_output.push_str("hello world\n");
+        let result = build_result_json(&handle, json!(42), None);
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(parsed["value"], 42);
+        assert_eq!(parsed["print_output"], "hello world\n");
+    }
+
+    #[test]
+    fn test_build_result_json_empty_print_output_omitted() {
+        let handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let result = build_result_json(&handle, json!(42), None);
+        let parsed: Value = serde_json::from_str(&result).unwrap();
+        assert!(parsed.get("print_output").is_none());
+    }
+
+    #[test]
+    fn test_result_js_safe_plain_value() {
+        let mut handle = MontyHandle::new("[1, 2.5, 'x']".into(), vec![], None).unwrap();
+        let (_, result_json, _) = handle.run();
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["js_safe"], true);
+    }
+
+    #[test]
+    fn test_result_js_safe_large_int() {
+        let mut handle = MontyHandle::new("2 ** 64".into(), vec![], None).unwrap();
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["js_safe"], false);
+    }
+
+    #[test]
+    fn test_result_js_safe_nan() {
+        let mut handle = MontyHandle::new("float('nan')".into(), vec![], None).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], "NaN");
+        assert_eq!(parsed["js_safe"], false);
+    }
+
+    #[test]
+    fn test_minimal_errors_omits_traceback() {
+        let code = "def f():\n    raise ValueError('bad')\nf()";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_minimal_errors(true);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        let error = parsed["error"].as_object().unwrap();
+        assert_eq!(error["exc_type"], "ValueError");
+        assert_eq!(error["message"], "ValueError: bad");
+        assert!(error.get("traceback").is_none());
+        assert!(error.get("line_number").is_none());
+    }
+
+    #[test]
+    fn test_minimal_errors_disabled_keeps_traceback() {
+        let code = "def f():\n    raise ValueError('bad')\nf()";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Error);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert!(parsed["error"]["traceback"].is_array());
+    }
+
+    #[test]
+    fn test_max_string_length_result() {
+        let mut handle = MontyHandle::new("'x' * 11".into(), vec![], None).unwrap();
+        handle.set_max_string_length(10);
+        let (tag, result_json, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        assert!(err.unwrap().contains("exceeds max_string_length of 10"));
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["error"]["exc_type"], "MemoryError");
+
+        let mut handle = MontyHandle::new("'x' * 10".into(), vec![], None).unwrap();
+        handle.set_max_string_length(10);
+        let (tag, _, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
     }
 
     #[test]
-    fn test_pending_accessors_wrong_state() {
-        let handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
-        assert!(handle.pending_fn_name().is_none());
-        assert!(handle.pending_fn_args_json().is_none());
-        assert!(handle.complete_result_json().is_none());
-        assert!(handle.complete_is_error().is_none());
+    fn test_max_string_length_external_arg() {
+        let mut handle =
+            MontyHandle::new("send('y' * 11)".into(), vec!["send".into()], None).unwrap();
+        handle.set_max_string_length(10);
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("exceeds max_string_length"));
+        assert_eq!(handle.complete_is_error(), Some(true));
+
+        let code = "try:\n    send('y' * 11)\nexcept MemoryError:\n    pass";
+        let mut handle = MontyHandle::new(code.into(), vec!["send".into()], None).unwrap();
+        handle.set_max_string_length(10);
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Error);
     }
 
     #[test]
-    fn test_start_not_ready() {
-        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
-        handle.run();
+    fn test_max_string_length_kwarg_name() {
+        let code = "send(**{'k' * 11: 1})";
+        let mut handle = MontyHandle::new(code.into(), vec!["send".into()], None).unwrap();
+        handle.set_max_string_length(10);
         let (tag, err) = handle.start();
         assert_eq!(tag, MontyProgressTag::Error);
-        assert!(err.is_some());
+        assert!(err.unwrap().contains("exceeds max_string_length"));
     }
 
     #[test]
-    fn test_resume_not_paused() {
-        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
-        let (tag, err) = handle.resume("42");
-        assert_eq!(tag, MontyProgressTag::Error);
-        assert!(err.is_some());
+    fn test_typed_containers_result() {
+        let mut handle = MontyHandle::new("(1, [2, (3,)])".into(), vec![], None).unwrap();
+        handle.set_typed_containers(true);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(
+            parsed["value"],
+            json!({"__tuple__": [1, [2, {"__tuple__": [3]}]]})
+        );
     }
 
     #[test]
-    fn test_resume_invalid_json() {
-        let code = "result = ext_fn(1)\nresult";
-        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
-        handle.start();
-        let (tag, err) = handle.resume("not valid json{");
-        assert_eq!(tag, MontyProgressTag::Error);
-        assert!(err.unwrap().contains("invalid JSON"));
+    fn test_resume_with_tagged_bytes() {
+        let mut handle = MontyHandle::new("ext_fn(1)".into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_typed_containers(true);
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let (tag, err) = handle.resume(r#"{"__bytes__": "aGk="}"#);
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], json!({"__bytes__": "aGk="}));
     }
 
-    use serde_json::json;
+    #[test]
+    fn test_resume_with_non_finite_floats() {
+        let code = "x = ext_fn(1)\n[isinstance(x, float), x != x, x > 1e308, x < -1e308]";
+        for (tag, expected) in [
+            ("NaN", json!([true, true, false, false])),
+            ("Infinity", json!([true, false, true, false])),
+            ("-Infinity", json!([true, false, false, true])),
+        ] {
+            let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+            handle.set_typed_containers(true);
+            assert_eq!(handle.start().0, MontyProgressTag::Pending);
+            let (progress, err) = handle.resume(&format!(r#"{{"__float__": "{tag}"}}"#));
+            assert_eq!(progress, MontyProgressTag::Complete, "{err:?}");
+            let parsed: Value =
+                serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+            assert_eq!(parsed["value"], expected, "{tag}");
+        }
+    }
 
     #[test]
-    fn test_iterative_no_limits() {
-        // Exercise the NoLimitTracker path through start/resume
-        let code = "result = ext_fn(10)\nresult + 5";
+    fn test_resume_with_tagged_bigint() {
+        let code = "x = ext_fn(1)\nx + 1";
         let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
-        // No limits set — uses NoLimitTracker
-        let (tag, err) = handle.start();
-        assert_eq!(tag, MontyProgressTag::Pending);
-        assert!(err.is_none());
-        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
-
-        let (tag, err) = handle.resume("20");
-        assert_eq!(tag, MontyProgressTag::Complete);
-        assert!(err.is_none());
-
-        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
-        assert_eq!(result["value"], json!(25));
+        handle.set_typed_containers(true);
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let (tag, err) = handle.resume(r#"{"__bigint__": "999999999999999999999"}"#);
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], "1000000000000000000000");
     }
 
     #[test]
-    fn test_start_runtime_error() {
-        // Exception during start() — covers handle_exception via iterative path
-        let mut handle = MontyHandle::new("1/0".into(), vec![], None).unwrap();
-        let (tag, err) = handle.start();
-        assert_eq!(tag, MontyProgressTag::Error);
-        assert!(err.is_some());
-        assert!(handle.complete_is_error() == Some(true));
+    fn test_resume_with_tagged_dict() {
+        let code = "d = ext_fn(1)\n[d[1], d['a'], d[(1, 2)]]";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_typed_containers(true);
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let value = r#"{"__dict__": [[1, "one"], ["a", 2], [{"__tuple__": [1, 2]}, "pair"]]}"#;
+        let (tag, err) = handle.resume(value);
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], json!(["one", 2, "pair"]));
     }
 
     #[test]
-    fn test_start_with_limits_complete() {
-        // LimitedTracker start that completes immediately
-        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
-        handle.set_memory_limit(10 * 1024 * 1024);
-        handle.set_time_limit_ms(5000);
-        let (tag, err) = handle.start();
-        assert_eq!(tag, MontyProgressTag::Complete);
-        assert!(err.is_none());
-        assert_eq!(handle.complete_is_error(), Some(false));
+    fn test_typed_cycle_result() {
+        let mut handle = MontyHandle::new("x = [1]\nx.append(x)\nx".into(), vec![], None).unwrap();
+        handle.set_typed_containers(true);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        let cycle = &parsed["value"][1]["__cycle__"];
+        assert!(cycle["id"].is_u64());
+        assert!(cycle["repr"].is_string());
     }
 
     #[test]
-    fn test_iterative_with_limits() {
-        // Exercise the LimitedTracker path through start/resume
-        let code = "result = ext_fn(1)\nresult * 2";
-        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
-        handle.set_memory_limit(10 * 1024 * 1024);
-        handle.set_time_limit_ms(5000);
-        let (tag, err) = handle.start();
-        assert_eq!(tag, MontyProgressTag::Pending);
-        assert!(err.is_none());
-
-        let (tag, err) = handle.resume("50");
-        assert_eq!(tag, MontyProgressTag::Complete);
-        assert!(err.is_none());
+    fn test_typed_type_result() {
+        let mut handle = MontyHandle::new("type(42)".into(), vec![], None).unwrap();
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["value"], "<class 'int'>");
 
-        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
-        assert_eq!(result["value"], json!(100));
+        let mut handle = MontyHandle::new("type(42)".into(), vec![], None).unwrap();
+        handle.set_typed_containers(true);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(
+            parsed["value"],
+            json!({"__type__": {"name": "int", "repr": "<class 'int'>"}})
+        );
     }
 
     #[test]
-    fn test_run_with_limits_error() {
-        // Run with limits that triggers an exception
-        let mut handle = MontyHandle::new("1/0".into(), vec![], None).unwrap();
-        handle.set_memory_limit(10 * 1024 * 1024);
-        let (tag, _, err) = handle.run();
+    fn test_result_depth_exceeded() {
+        let code = "x = 1\nfor _ in range(2000):\n    x = [x]\nx";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        // Each encoded level takes several KiB of stack in debug builds, so
+        // the default limit would overflow a test thread first.
+        handle.options.conversion.max_depth = 100;
+        let (tag, result_json, err) = handle.run();
         assert_eq!(tag, MontyResultTag::Error);
-        assert!(err.is_some());
+        assert!(err.unwrap().contains("conversion depth exceeded"));
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert!(parsed["value"].is_null());
+        assert!(
+            parsed["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("conversion depth exceeded")
+        );
     }
 
     #[test]
-    fn test_multiple_ext_fn_calls() {
-        // Multiple pauses: Paused→Paused→Complete
-        let code = "a = ext_fn(1)\nb = ext_fn(2)\na + b";
+    fn test_resume_tags_ignored_without_typed_containers() {
+        let code = "d = ext_fn(1)\n[isinstance(d, dict), d['__tuple__']]";
         let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
-        let (tag, _) = handle.start();
-        assert_eq!(tag, MontyProgressTag::Pending);
-        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let (tag, err) = handle.resume(r#"{"__tuple__": [1, 2]}"#);
+        assert_eq!(tag, MontyProgressTag::Complete, "{err:?}");
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"], json!([true, [1, 2]]));
+    }
 
-        let (tag, _) = handle.resume("10");
-        assert_eq!(tag, MontyProgressTag::Pending);
-        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+    #[test]
+    fn test_resume_with_invalid_bytes_rejected() {
+        let mut handle = MontyHandle::new("ext_fn(1)".into(), vec!["ext_fn".into()], None).unwrap();
+        handle.set_typed_containers(true);
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        let (tag, err) = handle.resume(r#"{"__bytes__": [300]}"#);
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().starts_with("invalid value:"));
+        assert!(handle.pending_fn_name().is_some());
+    }
 
-        let (tag, _) = handle.resume("20");
-        assert_eq!(tag, MontyProgressTag::Complete);
+    #[test]
+    fn test_negative_zero_result() {
+        let mut handle = MontyHandle::new("-0.0".into(), vec![], None).unwrap();
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        assert!(result_json.contains(r#""value":0.0"#));
 
-        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
-        assert_eq!(result["value"], json!(30));
+        let mut handle = MontyHandle::new("-0.0".into(), vec![], None).unwrap();
+        handle.set_preserve_negative_zero(true);
+        let (_, result_json, _) = handle.run();
+        assert!(result_json.contains(r#""value":-0.0"#));
     }
 
     #[test]
-    fn test_default_usage_json() {
-        let usage: Value = serde_json::from_str(&default_usage_json()).unwrap();
-        assert_eq!(usage["memory_bytes_used"], 0);
-        assert_eq!(usage["time_elapsed_ms"], 0);
-        assert_eq!(usage["stack_depth_used"], 0);
+    fn test_max_collection_width_result() {
+        let mut handle = MontyHandle::new("list(range(1000))".into(), vec![], None).unwrap();
+        handle.set_max_collection_width(10);
+        let (tag, result_json, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        let items = parsed["value"].as_array().unwrap();
+        assert_eq!(items.len(), 11);
+        assert_eq!(items[9], 9);
+        assert_eq!(items[10], serde_json::json!({"__monty_elided__": 990}));
     }
 
     #[test]
-    fn test_build_result_json_ok() {
-        let result = build_result_json(json!(42), None, &default_usage_json(), "");
-        let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed["value"], 42);
-        assert!(parsed.get("error").is_none());
+    fn test_always_emit_print_disabled_by_default() {
+        let mut handle = MontyHandle::new("1 + 1".into(), vec![], None).unwrap();
+        let (_, result_json, _) = handle.run();
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
         assert!(parsed.get("print_output").is_none());
-        assert!(parsed["usage"].is_object());
     }
 
     #[test]
-    fn test_build_result_json_error() {
-        let err = json!({"message": "boom"});
-        let result = build_result_json(Value::Null, Some(err), &default_usage_json(), "");
-        let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert!(parsed["value"].is_null());
-        assert_eq!(parsed["error"]["message"], "boom");
+    fn test_always_emit_print_enabled() {
+        let mut handle = MontyHandle::new("1 + 1".into(), vec![], None).unwrap();
+        handle.set_always_emit_print(true);
+        let (_, result_json, _) = handle.run();
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["print_output"], "");
     }
 
     #[test]
-    fn test_build_result_json_with_print_output() {
-        let result = build_result_json(json!(42), None, &default_usage_json(), "hello world\n");
-        let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert_eq!(parsed["value"], 42);
-        assert_eq!(parsed["print_output"], "hello world\n");
+    fn test_always_emit_print_keeps_output() {
+        let mut handle = MontyHandle::new("print('hi')".into(), vec![], None).unwrap();
+        handle.set_always_emit_print(true);
+        let (_, result_json, _) = handle.run();
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["print_output"], "hi\n");
     }
 
     #[test]
-    fn test_build_result_json_empty_print_output_omitted() {
-        let result = build_result_json(json!(42), None, &default_usage_json(), "");
-        let parsed: Value = serde_json::from_str(&result).unwrap();
-        assert!(parsed.get("print_output").is_none());
+    fn test_correlation_id_in_results() {
+        for (code, is_error) in [("1 + 1", false), ("1 / 0", true)] {
+            let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+            handle.set_correlation_id(Some("req-7f3a".into()));
+            let (_, result_json, _) = handle.run();
+            let parsed: Value = serde_json::from_str(&result_json).unwrap();
+            assert_eq!(parsed["correlation_id"], "req-7f3a");
+            assert_eq!(parsed.get("error").is_some(), is_error);
+        }
+
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let (_, result_json, _) = handle.run();
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert!(parsed.get("correlation_id").is_none());
+    }
+
+    #[test]
+    fn test_value_repr() {
+        let mut handle = MontyHandle::new("(1, 2)".into(), vec![], None).unwrap();
+        let (_, result_json, _) = handle.run();
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert!(parsed.get("value_repr").is_none());
+
+        let mut handle = MontyHandle::new("(1, 2)".into(), vec![], None).unwrap();
+        handle.set_value_repr(true);
+        let (_, result_json, _) = handle.run();
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["value"], serde_json::json!([1, 2]));
+        assert_eq!(parsed["value_repr"], "(1, 2)");
     }
 
     #[test]
@@ -1080,45 +5120,207 @@ result
     }
 
     #[test]
-    fn test_run_error_with_limits_captures_print() {
-        let code = "print('lim_err')\n1/0";
-        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
-        handle.set_memory_limit(10 * 1024 * 1024);
-        let (tag, result_json, err) = handle.run();
+    fn test_run_error_with_limits_captures_print() {
+        let code = "print('lim_err')\n1/0";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_memory_limit(10 * 1024 * 1024);
+        let (tag, result_json, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        assert!(err.is_some());
+        let parsed: Value = serde_json::from_str(&result_json).unwrap();
+        assert_eq!(parsed["print_output"], "lim_err\n");
+    }
+
+    // --- M7A.2: New accessor tests ---
+
+    #[test]
+    fn test_pending_kwargs_empty() {
+        let code = "result = ext_fn(42)\nresult";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_kwargs_json(), Some("{}"));
+        assert_eq!(handle.pending_call_id(), Some(0));
+        assert_eq!(handle.pending_method_call(), Some(false));
+    }
+
+    #[test]
+    fn test_pending_fn_args_tagged() {
+        let code = "ext_fn((1, 2), {3}, [4])";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        assert!(handle.pending_fn_args_count().is_none());
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+
+        assert_eq!(handle.pending_fn_args_json(), Some("[[1,2],[3],[4]]"));
+        assert_eq!(handle.pending_fn_args_count(), Some(3));
+        assert_eq!(
+            handle.pending_fn_arg_tagged_json(0),
+            Some(r#"{"__tuple__":[1,2]}"#)
+        );
+        assert_eq!(
+            handle.pending_fn_arg_tagged_json(1),
+            Some(r#"{"__set__":[3]}"#)
+        );
+        assert_eq!(handle.pending_fn_arg_tagged_json(2), Some("[4]"));
+        assert!(handle.pending_fn_arg_tagged_json(3).is_none());
+    }
+
+    #[test]
+    fn test_pending_call_id_increments() {
+        let code = "a = ext_fn(1)\nb = ext_fn(2)\na + b";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        let first_id = handle.pending_call_id().unwrap();
+
+        let (tag, _) = handle.resume("10");
+        assert_eq!(tag, MontyProgressTag::Pending);
+        let second_id = handle.pending_call_id().unwrap();
+        assert!(
+            second_id > first_id,
+            "call_id should increment: {second_id} > {first_id}"
+        );
+    }
+
+    #[test]
+    fn test_resume_into_nested_call() {
+        let code = "outer(inner(1), k=inner(2))";
+        let mut handle =
+            MontyHandle::new(code.into(), vec!["inner".into(), "outer".into()], None).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("inner"));
+        assert_eq!(handle.pending_fn_args_json(), Some("[1]"));
+        let mut last_id = handle.pending_call_id().unwrap();
+
+        let (tag, _) = handle.resume("10");
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("inner"));
+        assert_eq!(handle.pending_fn_args_json(), Some("[2]"));
+        let id = handle.pending_call_id().unwrap();
+        assert!(id > last_id, "call_id should advance: {id} > {last_id}");
+        last_id = id;
+
+        // The second value goes straight into `outer`, with no bytecode of
+        // its own in between.
+        let (tag, _) = handle.resume(r#"{"x": 1}"#);
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("outer"));
+        assert_eq!(handle.pending_fn_args_json(), Some("[10]"));
+        assert_eq!(handle.pending_fn_kwargs_json(), Some(r#"{"k":{"x":1}}"#));
+        let id = handle.pending_call_id().unwrap();
+        assert!(id > last_id, "call_id should advance: {id} > {last_id}");
+
+        let (tag, _) = handle.resume("42");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], 42);
+    }
+
+    #[test]
+    fn test_resume_many_through_nested_calls() {
+        let mut handle = MontyHandle::new(
+            "a(b(c()))".into(),
+            vec!["a".into(), "b".into(), "c".into()],
+            None,
+        )
+        .unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("c"));
+
+        let (tag, _) = handle.resume_many("[1, 2]");
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.last_batch_consumed(), 2);
+        assert_eq!(handle.pending_fn_name(), Some("a"));
+        assert_eq!(handle.pending_fn_args_json(), Some("[2]"));
+
+        let (tag, _) = handle.resume_many("[3, 4]");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert_eq!(handle.last_batch_consumed(), 1);
+    }
+
+    #[test]
+    fn test_eval_errors_become_results() {
+        let (tag, json) = MontyHandle::eval("x = = 1".into(), vec![], None, None);
         assert_eq!(tag, MontyResultTag::Error);
-        assert!(err.is_some());
-        let parsed: Value = serde_json::from_str(&result_json).unwrap();
-        assert_eq!(parsed["print_output"], "lim_err\n");
-    }
+        let result: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(result["error"]["exc_type"], "SyntaxError");
+        assert_eq!(result["error"]["retryable"], false);
 
-    // --- M7A.2: New accessor tests ---
+        let (tag, json) = MontyHandle::eval("1".into(), vec![], None, Some(r#"{"max_cpu": 1}"#));
+        assert_eq!(tag, MontyResultTag::Error);
+        let result: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(result["error"]["exc_type"], "ValueError");
+        assert!(
+            result["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("unknown limit: max_cpu")
+        );
+
+        let limits = r#"{"max_recursion_depth": 20, "max_memory": null}"#;
+        let code = "def f(n):\n    return f(n + 1)\nf(0)";
+        let (tag, json) = MontyHandle::eval(code.into(), vec![], None, Some(limits));
+        assert_eq!(tag, MontyResultTag::Error);
+        let result: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(result["error"]["exc_type"], "RecursionError");
+    }
 
     #[test]
-    fn test_pending_kwargs_empty() {
-        let code = "result = ext_fn(42)\nresult";
-        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
-        let (tag, _) = handle.start();
-        assert_eq!(tag, MontyProgressTag::Pending);
-        assert_eq!(handle.pending_fn_kwargs_json(), Some("{}"));
-        assert_eq!(handle.pending_call_id(), Some(0));
-        assert_eq!(handle.pending_method_call(), Some(false));
+    fn test_eval_panic_result() {
+        let json = MontyHandle::eval_panic_result_json("boom (at src/vm.rs:1:1)".into());
+        let result: Value = serde_json::from_str(&json).unwrap();
+        assert!(result["value"].is_null());
+        assert_eq!(result["error"]["exc_type"], "RuntimeError");
+        assert!(
+            result["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("boom (at src/vm.rs:1:1)")
+        );
+        assert_eq!(result["error"]["retryable"], false);
     }
 
     #[test]
-    fn test_pending_call_id_increments() {
-        let code = "a = ext_fn(1)\nb = ext_fn(2)\na + b";
+    fn test_event_log_records_answers() {
+        let code = "a = ext_fn(1)\nb = ext_fn(2, k='x')\na + b";
         let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        assert!(handle.event_log_json().is_none());
+        handle.enable_event_log(16);
+
         let (tag, _) = handle.start();
         assert_eq!(tag, MontyProgressTag::Pending);
         let first_id = handle.pending_call_id().unwrap();
-
         let (tag, _) = handle.resume("10");
         assert_eq!(tag, MontyProgressTag::Pending);
         let second_id = handle.pending_call_id().unwrap();
-        assert!(
-            second_id > first_id,
-            "call_id should increment: {second_id} > {first_id}"
-        );
+        let (tag, _) = handle.resume("32");
+        assert_eq!(tag, MontyProgressTag::Complete);
+
+        let log: Value = serde_json::from_str(&handle.event_log_json().unwrap()).unwrap();
+        assert_eq!(log["dropped"], 0);
+        let events = log["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["call_id"], first_id);
+        assert_eq!(events[0]["fn_name"], "ext_fn");
+        assert_eq!(events[0]["args"], serde_json::json!([1]));
+        assert_eq!(events[0]["result"], 10);
+        assert_eq!(events[1]["call_id"], second_id);
+        assert_eq!(events[1]["args"], serde_json::json!([2]));
+        assert_eq!(events[1]["kwargs"], serde_json::json!({"k": "x"}));
+        assert_eq!(events[1]["result"], 32);
+        assert!(events[1]["timestamp_ms"].as_u64().unwrap() > 0);
+
+        handle.reset().unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        handle.resume_with_error("boom");
+        let log: Value = serde_json::from_str(&handle.event_log_json().unwrap()).unwrap();
+        let events = log["events"].as_array().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["error"]["exc_type"], "RuntimeError");
     }
 
     #[test]
@@ -1217,6 +5419,273 @@ outer()
         assert_eq!(traceback[0]["filename"], "test.py");
     }
 
+    #[test]
+    fn test_add_external_between_runs() {
+        let code = "result = ext_fn(1)\nresult";
+
+        // Without the declaration the name is unresolved at runtime.
+        let mut first = MontyHandle::new(code.into(), vec![], None).unwrap();
+        let (tag, _, _) = first.run();
+        assert_eq!(tag, MontyResultTag::Error);
+
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.add_external("ext_fn").unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+
+        let (tag, _) = handle.resume("7");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], 7);
+    }
+
+    #[test]
+    fn test_remove_external() {
+        let code = "result = ext_fn(1)\nresult";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle.remove_external("ext_fn").unwrap();
+        let (tag, err) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.is_some());
+    }
+
+    #[test]
+    fn test_external_functions_tracks_changes() {
+        let mut handle =
+            MontyHandle::new("1".into(), vec!["foo".into(), "bar".into()], None).unwrap();
+        assert_eq!(handle.external_functions(), ["foo", "bar"]);
+        handle.remove_external("foo").unwrap();
+        handle.add_external("baz").unwrap();
+        assert_eq!(handle.external_functions(), ["bar", "baz"]);
+    }
+
+    #[test]
+    fn test_add_external_run_add_run() {
+        let mut handle = MontyHandle::new("a(1)".into(), vec![], None).unwrap();
+        handle.add_external("a").unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("a"));
+        let (tag, _) = handle.resume("1");
+        assert_eq!(tag, MontyProgressTag::Complete);
+
+        // Externals can only change in Ready state, so reset in between.
+        handle.reset().unwrap();
+        handle.add_external("b").unwrap();
+        assert_eq!(handle.external_functions(), ["a", "b"]);
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("a"));
+        let (tag, _) = handle.resume("2");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], 2);
+    }
+
+    #[test]
+    fn test_add_external_rejects_non_identifier() {
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        for bad in ["", "1abc", "a-b", "a b", "x)\nimport os"] {
+            let err = handle.add_external(bad).unwrap_err();
+            assert!(err.starts_with("invalid external function name"), "{err}");
+        }
+        assert!(handle.external_functions().is_empty());
+    }
+
+    #[test]
+    fn test_remove_external_drops_constant_and_serviceable() {
+        let mut handle = MontyHandle::new("1".into(), vec!["ext_fn".into()], None).unwrap();
+        handle.register_constant("flag", "true").unwrap();
+        handle.set_stdin("line".into()).unwrap();
+        handle
+            .set_serviceable_externals(Some(vec!["ext_fn".into(), "flag".into()]))
+            .unwrap();
+
+        handle.remove_external("flag").unwrap();
+        handle.remove_external("input").unwrap();
+        assert!(handle.constants.is_empty());
+        assert!(handle.stdin.is_none());
+        assert_eq!(
+            handle.options.serviceable_externals.as_deref(),
+            Some(&["ext_fn".to_string()][..])
+        );
+    }
+
+    #[test]
+    fn test_remove_external_not_declared() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        let err = handle.remove_external("missing").unwrap_err();
+        assert!(err.contains("not declared"));
+    }
+
+    #[test]
+    fn test_set_stdin_feeds_input() {
+        let code = "x = input()\nx.upper()";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_stdin("hello".into()).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], "HELLO");
+    }
+
+    #[test]
+    fn test_set_stdin_lines_and_eof() {
+        let code = "a = input('name? ')\nb = input()\ntry:\n  input()\nexcept OSError as e:\n  c = str(e)\n[a, b, c]";
+        let mut handle = MontyHandle::new(code.into(), vec![], None).unwrap();
+        handle.set_stdin("ada\nlovelace\n".into()).unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(
+            result["value"],
+            serde_json::json!(["ada", "lovelace", "EOF when reading a line"])
+        );
+        assert_eq!(result["print_output"], "name? ");
+
+        // Refilling works once the external is declared, even outside Ready.
+        assert!(handle.set_stdin("more".into()).is_ok());
+    }
+
+    #[test]
+    fn test_register_constant() {
+        let code = "cfg = get_config()\nx = ext_fn(1)\n[get_config(2)['debug'], x]";
+        let mut handle = MontyHandle::new(code.into(), vec!["ext_fn".into()], None).unwrap();
+        handle
+            .register_constant("get_config", r#"{"debug": true}"#)
+            .unwrap();
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("ext_fn"));
+        assert_eq!(handle.ext_call_count(), 1);
+        let (tag, _) = handle.resume("7");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], serde_json::json!([true, 7]));
+
+        let mut handle = MontyHandle::new("1".into(), vec![], None).unwrap();
+        let err = handle.register_constant("flag", "{").unwrap_err();
+        assert!(err.starts_with("invalid value JSON: "));
+        assert!(handle.constants.is_empty());
+    }
+
+    #[test]
+    fn test_add_external_not_ready() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        handle.run();
+        let err = handle.add_external("ext_fn").unwrap_err();
+        assert!(err.contains("not in Ready state"));
+    }
+
+    #[test]
+    fn test_add_external_restored_handle() {
+        let handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        let bytes = handle.snapshot().unwrap();
+        let mut restored = MontyHandle::restore(&bytes).unwrap();
+        let err = restored.add_external("ext_fn").unwrap_err();
+        assert!(err.contains("restored"));
+    }
+
+    #[test]
+    fn test_serviceable_externals() {
+        let code = "try:\n    b = missing()\nexcept NotImplementedError as e:\n    b = str(e)\n[present(), b]";
+        let mut handle =
+            MontyHandle::new(code.into(), vec!["present".into(), "missing".into()], None).unwrap();
+        handle
+            .set_serviceable_externals(Some(vec!["present".into()]))
+            .unwrap();
+
+        // `missing` is answered by the crate; only `present` pauses.
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.pending_fn_name(), Some("present"));
+        let (tag, _) = handle.resume("1");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let parsed: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(parsed["value"][0], 1);
+        assert!(
+            parsed["value"][1]
+                .as_str()
+                .unwrap()
+                .contains("not serviced by host: missing")
+        );
+    }
+
+    #[test]
+    fn test_serviceable_externals_undeclared() {
+        let mut handle = MontyHandle::new("1".into(), vec!["a".into()], None).unwrap();
+        let err = handle
+            .set_serviceable_externals(Some(vec!["b".into()]))
+            .unwrap_err();
+        assert_eq!(err, "external function not declared: b");
+    }
+
+    #[test]
+    fn test_try_clone_runs_independently() {
+        let code = "x = fetch(1)\nprint(x)\nx";
+        let mut handle = MontyHandle::new(code.into(), vec!["fetch".into()], None).unwrap();
+        handle.set_time_limit_ms(5000);
+        let mut clone = handle.try_clone().unwrap();
+        assert!(clone.limits.is_some());
+
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+        assert_eq!(clone.start().0, MontyProgressTag::Pending);
+        assert_eq!(handle.resume("1").0, MontyProgressTag::Complete);
+        assert_eq!(clone.resume("2").0, MontyProgressTag::Complete);
+
+        let original: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        let cloned: Value = serde_json::from_str(clone.complete_result_json().unwrap()).unwrap();
+        assert_eq!(original["value"], 1);
+        assert_eq!(original["print_output"], "1\n");
+        assert_eq!(cloned["value"], 2);
+        assert_eq!(cloned["print_output"], "2\n");
+    }
+
+    #[test]
+    fn test_try_clone_requires_ready() {
+        let mut handle = MontyHandle::new("print('hi')".into(), vec![], None).unwrap();
+        handle.run();
+        assert_eq!(
+            handle.try_clone().err().unwrap(),
+            "can only clone in Ready state"
+        );
+    }
+
+    #[test]
+    fn test_reset_runs_again() {
+        let code = "x = fetch(1)\nprint(x)\nx * 2";
+        let mut handle = MontyHandle::new(code.into(), vec!["fetch".into()], None).unwrap();
+        for value in [10, 20] {
+            assert_eq!(handle.start().0, MontyProgressTag::Pending);
+            assert_eq!(
+                handle.resume(&value.to_string()).0,
+                MontyProgressTag::Complete
+            );
+            let parsed: Value =
+                serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+            assert_eq!(parsed["value"], value * 2);
+            assert_eq!(parsed["print_output"], format!("{value}\n"));
+            handle.reset().unwrap();
+        }
+
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        handle.reset().unwrap();
+        assert_eq!(handle.start().0, MontyProgressTag::Pending);
+    }
+
+    #[test]
+    fn test_reset_restored_handle() {
+        let handle = MontyHandle::new("1 + 1".into(), vec![], None).unwrap();
+        let bytes = handle.snapshot().unwrap();
+        let mut restored = MontyHandle::restore(&bytes).unwrap();
+        restored.reset().unwrap();
+        assert_eq!(restored.run().0, MontyResultTag::Ok);
+        let err = restored.reset().unwrap_err();
+        assert!(err.contains("compiled program not retained"));
+    }
+
     // --- M13: Async/Futures tests ---
 
     fn async_code_single() -> &'static str {
@@ -1250,6 +5719,48 @@ outer()
         assert_eq!(result["value"], "response_x");
     }
 
+    #[test]
+    fn test_state_tag_walks_lifecycle() {
+        let mut handle =
+            MontyHandle::new(async_code_single().into(), vec!["fetch".into()], None).unwrap();
+        assert_eq!(handle.state_tag(), MontyStateTag::Ready);
+
+        let (tag, _) = handle.start();
+        assert_eq!(tag, MontyProgressTag::Pending);
+        assert_eq!(handle.state_tag(), MontyStateTag::Paused);
+
+        let (tag, _) = handle.resume_as_future();
+        assert_eq!(tag, MontyProgressTag::ResolveFutures);
+        assert_eq!(handle.state_tag(), MontyStateTag::Futures);
+
+        let ids: Vec<u32> =
+            serde_json::from_str(handle.pending_future_call_ids().unwrap()).unwrap();
+        let results = format!("{{\"{}\":\"ok\"}}", ids[0]);
+        let (tag, _) = handle.resume_futures(&results, "{}");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        assert_eq!(handle.state_tag(), MontyStateTag::Complete);
+
+        handle.state = HandleState::Consumed;
+        assert_eq!(handle.state_tag(), MontyStateTag::Consumed);
+    }
+
+    #[test]
+    fn test_is_consumed_after_run() {
+        let mut handle = MontyHandle::new("1 + 1".into(), vec![], None).unwrap();
+        assert!(!handle.is_consumed());
+        let (tag, _, _) = handle.run();
+        assert_eq!(tag, MontyResultTag::Ok);
+        assert!(handle.is_consumed());
+
+        let (tag, _, err) = handle.run();
+        assert_eq!(tag, MontyResultTag::Error);
+        assert_eq!(err.as_deref(), Some("handle not in Ready state"));
+        assert!(handle.is_consumed());
+
+        handle.reset().unwrap();
+        assert!(!handle.is_consumed());
+    }
+
     #[test]
     fn test_async_gather_via_handle() {
         let mut handle = MontyHandle::new(
@@ -1305,6 +5816,79 @@ outer()
         assert_eq!(handle.complete_is_error(), Some(true));
     }
 
+    #[test]
+    fn test_fail_all_futures_three_way_gather() {
+        let code = "import asyncio\n\nasync def main():\n  return await asyncio.gather(a(), b(), c())\n\nawait main()";
+        let mut handle =
+            MontyHandle::new(code.into(), vec!["a".into(), "b".into(), "c".into()], None).unwrap();
+
+        let (mut tag, _) = handle.start();
+        while tag == MontyProgressTag::Pending {
+            (tag, _) = handle.resume_as_future();
+        }
+        assert_eq!(tag, MontyProgressTag::ResolveFutures);
+        let ids: Vec<u32> =
+            serde_json::from_str(handle.pending_future_call_ids().unwrap()).unwrap();
+        assert_eq!(ids.len(), 3);
+
+        let (tag, _) = handle.fail_all_futures("ConnectionError", "connection dropped");
+        assert_eq!(tag, MontyProgressTag::Error);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["error"]["exc_type"], "ConnectionError");
+        assert!(
+            result["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("connection dropped")
+        );
+    }
+
+    #[test]
+    fn test_fail_all_futures_unknown_type() {
+        let mut handle =
+            MontyHandle::new(async_code_single().into(), vec!["fetch".into()], None).unwrap();
+        handle.start();
+        handle.resume_as_future();
+        let (tag, err) = handle.fail_all_futures("BogusError", "x");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("unknown exception type"));
+        // The handle is left in Futures state.
+        assert!(handle.pending_future_call_ids().is_some());
+    }
+
+    #[test]
+    fn test_fail_all_futures_wrong_state() {
+        let mut handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
+        let (tag, err) = handle.fail_all_futures("ValueError", "x");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("not in Futures state"));
+    }
+
+    #[test]
+    fn test_pending_futures_json_gather() {
+        let code = "import asyncio\n\nasync def main():\n  return await asyncio.gather(foo(1), bar(2))\n\nawait main()";
+        let mut handle =
+            MontyHandle::new(code.into(), vec!["foo".into(), "bar".into()], None).unwrap();
+        assert!(handle.pending_futures_json().is_none());
+
+        let (mut tag, _) = handle.start();
+        while tag == MontyProgressTag::Pending {
+            (tag, _) = handle.resume_as_future();
+        }
+        assert_eq!(tag, MontyProgressTag::ResolveFutures);
+        let ids: Vec<u32> =
+            serde_json::from_str(handle.pending_future_call_ids().unwrap()).unwrap();
+        let futures: Value = serde_json::from_str(&handle.pending_futures_json().unwrap()).unwrap();
+        assert_eq!(futures.as_array().unwrap().len(), 2);
+        assert_eq!(futures[0]["call_id"], ids[0]);
+        assert_eq!(futures[0]["fn_name"], "foo");
+        assert_eq!(futures[0]["args"], serde_json::json!([1]));
+        assert_eq!(futures[0]["kwargs"], serde_json::json!({}));
+        assert_eq!(futures[1]["call_id"], ids[1]);
+        assert_eq!(futures[1]["fn_name"], "bar");
+        assert_eq!(futures[1]["args"], serde_json::json!([2]));
+    }
+
     #[test]
     fn test_async_future_call_ids_wrong_state() {
         let handle = MontyHandle::new("2 + 2".into(), vec![], None).unwrap();
@@ -1327,6 +5911,71 @@ outer()
         assert!(err.unwrap().contains("not in Paused state"));
     }
 
+    #[test]
+    fn test_resume_futures_partial() {
+        let mut handle = MontyHandle::new(
+            async_code_gather().into(),
+            vec!["foo".into(), "bar".into()],
+            None,
+        )
+        .unwrap();
+        let (mut tag, _) = handle.start();
+        while tag == MontyProgressTag::Pending {
+            (tag, _) = handle.resume_as_future();
+        }
+        assert_eq!(tag, MontyProgressTag::ResolveFutures);
+        let ids: Vec<u32> =
+            serde_json::from_str(handle.pending_future_call_ids().unwrap()).unwrap();
+
+        let (tag, _) = handle.resume_futures(&format!("{{\"{}\":10}}", ids[0]), "{}");
+        assert_eq!(tag, MontyProgressTag::ResolveFutures);
+        let remaining: Vec<u32> =
+            serde_json::from_str(handle.pending_future_call_ids().unwrap()).unwrap();
+        assert_eq!(remaining, vec![ids[1]]);
+        // Resolved calls are dropped from the recorded metadata.
+        assert_eq!(
+            handle.future_calls.keys().collect::<Vec<_>>(),
+            vec![&ids[1]]
+        );
+        let futures: Value = serde_json::from_str(&handle.pending_futures_json().unwrap()).unwrap();
+        assert_eq!(futures.as_array().unwrap().len(), 1);
+        assert_eq!(futures[0]["call_id"], ids[1]);
+
+        let (tag, err) = handle.resume_futures(&format!("{{\"{}\":1}}", ids[0]), "{}");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("unknown call_id"));
+
+        let (tag, _) = handle.resume_futures(&format!("{{\"{}\":32}}", ids[1]), "{}");
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], 42);
+    }
+
+    #[test]
+    fn test_resume_futures_with_timeouts() {
+        let code = "import asyncio\n\nasync def guarded(coro):\n  try:\n    return await coro\n  except TimeoutError:\n    return 'timed out'\n\nasync def main():\n  return await asyncio.gather(guarded(foo()), guarded(bar()))\n\nawait main()";
+        let mut handle =
+            MontyHandle::new(code.into(), vec!["foo".into(), "bar".into()], None).unwrap();
+        let (mut tag, _) = handle.start();
+        while tag == MontyProgressTag::Pending {
+            (tag, _) = handle.resume_as_future();
+        }
+        assert_eq!(tag, MontyProgressTag::ResolveFutures);
+        let ids: Vec<u32> =
+            serde_json::from_str(handle.pending_future_call_ids().unwrap()).unwrap();
+
+        let (tag, err) = handle.resume_futures_with_timeouts("{}", "{}", "[999]");
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(err.unwrap().contains("unknown call_id"));
+
+        let results = format!("{{\"{}\":\"ok\"}}", ids[0]);
+        let timeouts = format!("[{}]", ids[1]);
+        let (tag, _) = handle.resume_futures_with_timeouts(&results, "{}", &timeouts);
+        assert_eq!(tag, MontyProgressTag::Complete);
+        let result: Value = serde_json::from_str(handle.complete_result_json().unwrap()).unwrap();
+        assert_eq!(result["value"], serde_json::json!(["ok", "timed out"]));
+    }
+
     #[test]
     fn test_resume_futures_invalid_json() {
         let mut handle =
@@ -2,17 +2,92 @@
 
 mod convert;
 mod error;
+mod event_log;
 mod handle;
+mod usage;
 
-pub use handle::{MontyHandle, MontyProgressTag, MontyResultTag};
+pub use error::MontyErrorCode;
+pub use handle::{MontyHandle, MontyProgressTag, MontyResultTag, MontyStateTag, SnapshotCodec};
+pub use usage::MontyInterruptToken;
 
-use std::ffi::{c_char, c_int};
+use std::ffi::{c_char, c_int, c_void};
+use std::io::{self, BufReader, Read};
 use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use error::{catch_ffi_panic, parse_c_str, to_c_string};
+use convert::IntegralFloatPolicy;
+use error::{catch_ffi_panic, monty_exception_to_json, parse_c_str, to_c_string};
+
+/// Error reported when a call finds the handle claimed by another call.
+const HANDLE_IN_USE: &str = "handle already in use";
+
+/// Addresses of the handles an FFI call currently holds. Kept outside the
+/// handles so checking a claim never reads through a pointer another call
+/// may hold as `&mut`.
+static CLAIMED_HANDLES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// A handle's claim, released on drop.
+struct HandleClaim(usize);
+
+impl HandleClaim {
+    /// Claim `handle` for the calling FFI function. Returns `None` if another
+    /// call already holds it: a reentrant one from a callback, or one on
+    /// another thread.
+    fn take(handle: *const MontyHandle) -> Option<Self> {
+        let addr = handle as usize;
+        let mut claimed = CLAIMED_HANDLES.lock().unwrap_or_else(|e| e.into_inner());
+        if claimed.contains(&addr) {
+            return None;
+        }
+        claimed.push(addr);
+        Some(HandleClaim(addr))
+    }
+}
+
+impl Drop for HandleClaim {
+    fn drop(&mut self) {
+        let mut claimed = CLAIMED_HANDLES.lock().unwrap_or_else(|e| e.into_inner());
+        claimed.retain(|&addr| addr != self.0);
+    }
+}
+
+/// Read a non-NULL, unclaimed handle through `f`, holding the claim for the
+/// call. Returns `None` without calling `f` otherwise, so an accessor never
+/// borrows a handle that a running call holds mutably.
+///
+/// # Safety
+/// `handle` must be NULL or a valid handle.
+unsafe fn with_handle_ref<R>(
+    handle: *const MontyHandle,
+    f: impl FnOnce(&MontyHandle) -> R,
+) -> Option<R> {
+    if handle.is_null() {
+        return None;
+    }
+    let _claim = HandleClaim::take(handle)?;
+    Some(f(unsafe { &*handle }))
+}
+
+/// Run `f` on a non-NULL, unclaimed handle, holding the claim for the call.
+/// Returns `None` without calling `f` otherwise.
+///
+/// # Safety
+/// `handle` must be NULL or a valid handle.
+unsafe fn with_handle<R>(
+    handle: *mut MontyHandle,
+    f: impl FnOnce(&mut MontyHandle) -> R,
+) -> Option<R> {
+    if handle.is_null() {
+        return None;
+    }
+    let _claim = HandleClaim::take(handle)?;
+    Some(f(unsafe { &mut *handle }))
+}
 
 /// Common FFI wrapper for functions returning `MontyProgressTag`.
-/// Handles: handle null check, panic boundary, error out-parameter.
+/// Handles: handle null check, in-use guard, panic boundary, error
+/// out-parameter, error code.
 macro_rules! ffi_progress {
     ($handle:expr, $out_error:expr, |$h:ident| $body:expr) => {{
         if $handle.is_null() {
@@ -21,9 +96,19 @@ macro_rules! ffi_progress {
             }
             return MontyProgressTag::Error;
         }
+        let Some(_claim) = HandleClaim::take($handle) else {
+            if !$out_error.is_null() {
+                unsafe { *$out_error = to_c_string(HANDLE_IN_USE) };
+            }
+            return MontyProgressTag::Error;
+        };
         let $h = unsafe { &mut *$handle };
+        $h.set_last_error_code(MontyErrorCode::None);
         match catch_ffi_panic(|| $body) {
             Ok((tag, err)) => {
+                if tag == MontyProgressTag::Error {
+                    $h.note_error();
+                }
                 if !$out_error.is_null() {
                     match err {
                         Some(ref msg) => unsafe { *$out_error = to_c_string(msg) },
@@ -33,8 +118,9 @@ macro_rules! ffi_progress {
                 tag
             }
             Err(panic_msg) => {
+                $h.set_last_error_code(MontyErrorCode::Panic);
                 if !$out_error.is_null() {
-                    unsafe { *$out_error = to_c_string(&panic_msg) };
+                    unsafe { *$out_error = to_c_string(&$h.panic_message(panic_msg)) };
                 }
                 MontyProgressTag::Error
             }
@@ -42,6 +128,179 @@ macro_rules! ffi_progress {
     }};
 }
 
+/// Common FFI wrapper for functions returning `MontyResultTag` from a
+/// `Result<(), String>` body.
+/// Handles: handle null check, in-use guard, panic boundary, error
+/// out-parameter, error code.
+macro_rules! ffi_result {
+    ($handle:expr, $out_error:expr, |$h:ident| $body:expr) => {{
+        if $handle.is_null() {
+            if !$out_error.is_null() {
+                unsafe { *$out_error = to_c_string("handle is NULL") };
+            }
+            return MontyResultTag::Error;
+        }
+        let Some(_claim) = HandleClaim::take($handle) else {
+            if !$out_error.is_null() {
+                unsafe { *$out_error = to_c_string(HANDLE_IN_USE) };
+            }
+            return MontyResultTag::Error;
+        };
+        let $h = unsafe { &mut *$handle };
+        $h.set_last_error_code(MontyErrorCode::None);
+        match catch_ffi_panic(|| $body) {
+            Ok(Ok(())) => {
+                if !$out_error.is_null() {
+                    unsafe { *$out_error = ptr::null_mut() };
+                }
+                MontyResultTag::Ok
+            }
+            Ok(Err(msg)) => {
+                $h.note_error();
+                if !$out_error.is_null() {
+                    unsafe { *$out_error = to_c_string(&msg) };
+                }
+                MontyResultTag::Error
+            }
+            Err(panic_msg) => {
+                $h.set_last_error_code(MontyErrorCode::Panic);
+                if !$out_error.is_null() {
+                    unsafe { *$out_error = to_c_string(&$h.panic_message(panic_msg)) };
+                }
+                MontyResultTag::Error
+            }
+        }
+    }};
+}
+
+/// Record a rejected argument on the handle (if any), then return `ret`.
+///
+/// # Safety
+/// `handle` must be NULL or a valid handle.
+unsafe fn arg_error<R>(handle: *const MontyHandle, code: MontyErrorCode, ret: R) -> R {
+    if !handle.is_null()
+        && let Some(_claim) = HandleClaim::take(handle)
+    {
+        unsafe { &*handle }.set_last_error_code(code);
+    }
+    ret
+}
+
+// ---------------------------------------------------------------------------
+// Library info
+// ---------------------------------------------------------------------------
+
+/// Version of the C ABI in `dart_monty.h`. Bumped when an existing function
+/// changes signature or behavior; additions are listed in
+/// `MONTY_CAPABILITIES` instead.
+pub const MONTY_ABI_VERSION: u32 = 2;
+
+/// Features a binding can check for before using them, each with the
+/// functions it adds or changes.
+const MONTY_CAPABILITIES: &[(&str, &[&str])] = &[
+    (
+        "async",
+        &[
+            "monty_resume_as_future",
+            "monty_pending_future_call_ids",
+            "monty_resume_futures",
+        ],
+    ),
+    ("partial-futures", &["monty_resume_futures"]),
+    ("future-timeouts", &["monty_resume_futures_with_timeouts"]),
+    ("fail-all-futures", &["monty_fail_all_futures"]),
+    ("pending-futures-info", &["monty_pending_futures_json"]),
+    ("snapshot-paused", &["monty_snapshot", "monty_restore"]),
+    ("snapshot-codecs", &["monty_snapshot_ex"]),
+    (
+        "os-call",
+        &[
+            "monty_pending_os_call_name",
+            "monty_pending_os_call_args_json",
+            "monty_resume_os_call",
+        ],
+    ),
+    ("os-call-pausing", &["monty_set_pause_os_calls"]),
+    ("typed-conversions", &["monty_set_typed_containers"]),
+    (
+        "tagged-args",
+        &[
+            "monty_pending_fn_args_count",
+            "monty_pending_fn_arg_tagged_json",
+        ],
+    ),
+    (
+        "globals",
+        &["monty_create_with_globals", "monty_run_with_args"],
+    ),
+    (
+        "batch-resume",
+        &["monty_resume_many", "monty_last_batch_consumed"],
+    ),
+    ("streamed-resume", &["monty_resume_reader"]),
+    ("print-callback", &["monty_set_print_callback"]),
+    ("stdin", &["monty_set_stdin"]),
+    (
+        "interrupt-token",
+        &[
+            "monty_interrupt_token",
+            "monty_token_interrupt",
+            "monty_token_free",
+        ],
+    ),
+    ("compute-time-limit", &["monty_set_compute_time_limit_ms"]),
+    ("error-codes", &["monty_last_error_code"]),
+    (
+        "limits-json",
+        &["monty_get_limits_json", "monty_set_limits_json"],
+    ),
+    ("value-repr", &["monty_set_value_repr"]),
+    (
+        "integral-float-policy",
+        &["monty_set_integral_float_policy"],
+    ),
+    ("resume-with-exception", &["monty_resume_with_exception"]),
+    ("register-constant", &["monty_register_constant"]),
+    ("eval", &["monty_eval"]),
+    ("validate", &["monty_validate"]),
+    (
+        "event-log",
+        &["monty_enable_event_log", "monty_event_log_json"],
+    ),
+    ("create-ex", &["monty_create_ex"]),
+    (
+        "max-external-calls",
+        &["monty_set_max_external_calls", "monty_ext_call_count"],
+    ),
+    (
+        "call-args-cap",
+        &[
+            "monty_set_max_call_args_bytes",
+            "monty_pending_args_truncated",
+        ],
+    ),
+    ("stringify-int-keys", &["monty_set_stringify_int_keys"]),
+];
+
+/// Get the C ABI version of the loaded library (`MONTY_ABI_VERSION`).
+#[unsafe(no_mangle)]
+pub extern "C" fn monty_abi_version() -> u32 {
+    MONTY_ABI_VERSION
+}
+
+/// Describe the loaded library as a JSON object:
+/// `{"abi_version", "crate_version", "capabilities": [...]}`.
+/// Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn monty_capabilities_json() -> *mut c_char {
+    let info = serde_json::json!({
+        "abi_version": MONTY_ABI_VERSION,
+        "crate_version": env!("CARGO_PKG_VERSION"),
+        "capabilities": MONTY_CAPABILITIES.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+    });
+    to_c_string(&info.to_string())
+}
+
 // ---------------------------------------------------------------------------
 // Lifecycle
 // ---------------------------------------------------------------------------
@@ -60,37 +319,137 @@ pub unsafe extern "C" fn monty_create(
     ext_fns: *const c_char,
     script_name: *const c_char,
     out_error: *mut *mut c_char,
+) -> *mut MontyHandle {
+    unsafe { monty_create_with_globals(code, ext_fns, script_name, ptr::null(), out_error) }
+}
+
+/// Create a new `MontyHandle` with host values bound as Python globals.
+///
+/// - `globals_json`: NUL-terminated JSON object mapping global names to
+///   values (or NULL for none). Names must be valid, non-keyword
+///   identifiers.
+///
+/// Other parameters and the return value are as for `monty_create`.
+/// `monty_create_ex` also reports compile errors as JSON.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_create_with_globals(
+    code: *const c_char,
+    ext_fns: *const c_char,
+    script_name: *const c_char,
+    globals_json: *const c_char,
+    out_error: *mut *mut c_char,
+) -> *mut MontyHandle {
+    unsafe {
+        create_handle(
+            code,
+            ext_fns,
+            script_name,
+            globals_json,
+            None,
+            out_error,
+            ptr::null_mut(),
+        )
+    }
+}
+
+/// Create a new `MontyHandle` with every creation option.
+///
+/// - `globals_json`: as for `monty_create_with_globals` (or NULL).
+/// - `compile_timeout_ms`: fail if compilation takes longer (0 for no
+///   limit). A compile that runs over fails with `out_error` set to
+///   `"TimeoutError: compile timed out after N ms"`. The engine can't
+///   interrupt compilation, so it finishes on a background thread and is
+///   discarded; the call itself returns on time. At most 8 compile threads
+///   run at once, abandoned ones included; past that, creation fails at
+///   once with a `RuntimeError`. Later recompiles of the handle
+///   (`monty_add_external`, `monty_reset`, ...) get the same budget.
+/// - `out_error_json`: when creation fails with a Python exception (e.g. a
+///   `SyntaxError`), receives it as the same JSON object runtime errors
+///   carry under `"error"`, with `line_number`/`column_number` (caller
+///   frees). May be NULL. Left untouched for other failures; `out_error`
+///   always gets the plain message.
+///
+/// Other parameters and the return value are as for `monty_create`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_create_ex(
+    code: *const c_char,
+    ext_fns: *const c_char,
+    script_name: *const c_char,
+    globals_json: *const c_char,
+    compile_timeout_ms: u64,
+    out_error: *mut *mut c_char,
+    out_error_json: *mut *mut c_char,
+) -> *mut MontyHandle {
+    let timeout = (compile_timeout_ms > 0).then(|| Duration::from_millis(compile_timeout_ms));
+    unsafe {
+        create_handle(
+            code,
+            ext_fns,
+            script_name,
+            globals_json,
+            timeout,
+            out_error,
+            out_error_json,
+        )
+    }
+}
+
+/// Shared body of the `monty_create*` functions.
+unsafe fn create_handle(
+    code: *const c_char,
+    ext_fns: *const c_char,
+    script_name: *const c_char,
+    globals_json: *const c_char,
+    compile_timeout: Option<Duration>,
+    out_error: *mut *mut c_char,
+    out_error_json: *mut *mut c_char,
 ) -> *mut MontyHandle {
     let code_str = match unsafe { parse_c_str(code, "code", out_error) } {
         Ok(s) => s.to_string(),
-        Err(()) => return ptr::null_mut(),
+        Err(_) => return ptr::null_mut(),
     };
 
-    let ext_fn_list = if ext_fns.is_null() {
-        vec![]
-    } else {
-        match unsafe { parse_c_str(ext_fns, "ext_fns", out_error) } {
-            Ok("") => vec![],
-            Ok(s) => s.split(',').map(|f| f.trim().to_string()).collect(),
-            Err(()) => return ptr::null_mut(),
-        }
+    let Ok(ext_fn_list) = (unsafe { parse_ext_fns(ext_fns, out_error) }) else {
+        return ptr::null_mut();
+    };
+    let Ok(name) = (unsafe { parse_script_name(script_name, out_error) }) else {
+        return ptr::null_mut();
     };
 
-    let name = if script_name.is_null() {
-        None
+    let globals = if globals_json.is_null() {
+        vec![]
     } else {
-        match unsafe { parse_c_str(script_name, "script_name", out_error) } {
-            Ok(s) => Some(s.to_string()),
-            Err(()) => return ptr::null_mut(),
+        let parsed = match unsafe { parse_c_str(globals_json, "globals_json", out_error) } {
+            Ok(s) => parse_globals(s),
+            Err(_) => return ptr::null_mut(),
+        };
+        match parsed {
+            Ok(globals) => globals,
+            Err(msg) => {
+                if !out_error.is_null() {
+                    unsafe { *out_error = to_c_string(&msg) };
+                }
+                return ptr::null_mut();
+            }
         }
     };
 
-    match catch_ffi_panic(|| MontyHandle::new(code_str, ext_fn_list, name)) {
+    let created = catch_ffi_panic(|| match compile_timeout {
+        Some(limit) => {
+            MontyHandle::new_with_compile_timeout(code_str, ext_fn_list, name, globals, limit)
+        }
+        None => MontyHandle::new_with_globals(code_str, ext_fn_list, name, globals),
+    });
+    match created {
         Ok(Ok(handle)) => Box::into_raw(Box::new(handle)),
         Ok(Err(exc)) => {
             if !out_error.is_null() {
                 unsafe { *out_error = to_c_string(&exc.summary()) };
             }
+            if !out_error_json.is_null() {
+                let json = monty_exception_to_json(&exc).to_string();
+                unsafe { *out_error_json = to_c_string(&json) };
+            }
             ptr::null_mut()
         }
         Err(panic_msg) => {
@@ -102,14 +461,360 @@ pub unsafe extern "C" fn monty_create(
     }
 }
 
-/// Free a `MontyHandle`. Safe to call with NULL.
+/// Parse a comma-separated list of external function names (NULL for none).
+///
+/// # Safety
+/// `ext_fns` must be NULL or a valid NUL-terminated C string.
+unsafe fn parse_ext_fns(
+    ext_fns: *const c_char,
+    out_error: *mut *mut c_char,
+) -> Result<Vec<String>, MontyErrorCode> {
+    if ext_fns.is_null() {
+        return Ok(vec![]);
+    }
+    match unsafe { parse_c_str(ext_fns, "ext_fns", out_error) }? {
+        "" => Ok(vec![]),
+        s => Ok(s.split(',').map(|f| f.trim().to_string()).collect()),
+    }
+}
+
+/// Parse an optional script name (NULL for the default).
+///
+/// # Safety
+/// `script_name` must be NULL or a valid NUL-terminated C string.
+unsafe fn parse_script_name(
+    script_name: *const c_char,
+    out_error: *mut *mut c_char,
+) -> Result<Option<String>, MontyErrorCode> {
+    if script_name.is_null() {
+        return Ok(None);
+    }
+    unsafe { parse_c_str(script_name, "script_name", out_error) }.map(|s| Some(s.to_string()))
+}
+
+/// Parse a JSON object into global name/value pairs.
+fn parse_globals(json: &str) -> Result<Vec<(String, monty::MontyObject)>, String> {
+    let serde_json::Value::Object(map) =
+        serde_json::from_str(json).map_err(|e| format!("invalid globals_json: {e}"))?
+    else {
+        return Err("globals_json must be a JSON object".into());
+    };
+    map.into_iter()
+        .map(|(name, value)| {
+            let obj = convert::json_to_monty_object(&value)
+                .map_err(|e| format!("invalid value for global '{name}': {e}"))?;
+            Ok((name, obj))
+        })
+        .collect()
+}
+
+/// Free a `MontyHandle`. Safe to call with NULL. Does nothing while
+/// another call holds the handle.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_free(handle: *mut MontyHandle) {
-    if !handle.is_null() {
+    if handle.is_null() {
+        return;
+    }
+    if let Some(claim) = HandleClaim::take(handle) {
+        // Released first: once freed, the address may go to a new handle.
+        drop(claim);
         drop(unsafe { Box::from_raw(handle) });
     }
 }
 
+/// Set a free-form label identifying the handle in panic messages.
+/// Pass NULL to clear it. Safe to call with a NULL handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_label(handle: *mut MontyHandle, label: *const c_char) {
+    if handle.is_null() {
+        return;
+    }
+    let label = if label.is_null() {
+        None
+    } else {
+        match unsafe { parse_c_str(label, "label", ptr::null_mut()) } {
+            Ok(s) => Some(s.to_string()),
+            Err(_) => return,
+        }
+    };
+    unsafe { with_handle(handle, |h| h.set_label(label)) };
+}
+
+/// Set an opaque id embedded as `"correlation_id"` in every result JSON the
+/// handle produces, success or error. Pass NULL to clear it. Safe to call
+/// with a NULL handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_correlation_id(handle: *mut MontyHandle, id: *const c_char) {
+    if handle.is_null() {
+        return;
+    }
+    let id = if id.is_null() {
+        None
+    } else {
+        match unsafe { parse_c_str(id, "id", ptr::null_mut()) } {
+            Ok(s) => Some(s.to_string()),
+            Err(_) => return,
+        }
+    };
+    unsafe { with_handle(handle, |h| h.set_correlation_id(id)) };
+}
+
+/// Get the handle's label, or NULL if none is set.
+/// Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_get_label(handle: *const MontyHandle) -> *mut c_char {
+    unsafe {
+        with_handle_ref(handle, |h| match h.label() {
+            Some(label) => to_c_string(label),
+            None => ptr::null_mut(),
+        })
+    }
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Empty the accumulated print output and reset usage to defaults without
+/// changing the handle's execution state. Safe to call with NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_clear_output(handle: *mut MontyHandle) {
+    unsafe { with_handle(handle, |h| h.clear_output()) };
+}
+
+/// Return the handle to Ready so `monty_run`/`monty_start` can execute the
+/// same program again, clearing print output, usage, the external call
+/// count and the event log.
+///
+/// - `out_error`: receives an error message on failure (caller frees).
+///
+/// Fails for a restored handle that has left Ready state, since it keeps no
+/// compiled program to return to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_reset(
+    handle: *mut MontyHandle,
+    out_error: *mut *mut c_char,
+) -> MontyResultTag {
+    ffi_result!(handle, out_error, |h| h.reset())
+}
+
+/// Duplicate a handle in Ready state. The clone keeps the limits, options,
+/// label and externals but starts with empty print output.
+///
+/// - `out_error`: receives an error message on failure (caller frees).
+///
+/// Returns a new handle (free with `monty_free`), or NULL on failure.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_clone(
+    handle: *const MontyHandle,
+    out_error: *mut *mut c_char,
+) -> *mut MontyHandle {
+    if handle.is_null() {
+        if !out_error.is_null() {
+            unsafe { *out_error = to_c_string("handle is NULL") };
+        }
+        return ptr::null_mut();
+    }
+    let Some(_claim) = HandleClaim::take(handle) else {
+        if !out_error.is_null() {
+            unsafe { *out_error = to_c_string(HANDLE_IN_USE) };
+        }
+        return ptr::null_mut();
+    };
+    let h = unsafe { &*handle };
+    h.set_last_error_code(MontyErrorCode::None);
+    let result = match catch_ffi_panic(|| h.try_clone()) {
+        Ok(result) => result.inspect_err(|_| h.note_error()),
+        Err(panic_msg) => {
+            h.set_last_error_code(MontyErrorCode::Panic);
+            Err(h.panic_message(panic_msg))
+        }
+    };
+    match result {
+        Ok(clone) => {
+            if !out_error.is_null() {
+                unsafe { *out_error = ptr::null_mut() };
+            }
+            Box::into_raw(Box::new(clone))
+        }
+        Err(msg) => {
+            if !out_error.is_null() {
+                unsafe { *out_error = to_c_string(&msg) };
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// External functions
+// ---------------------------------------------------------------------------
+
+/// Declare an additional external function on a handle in Ready state.
+///
+/// Recompiles the program with the updated external set.
+///
+/// - `name`: NUL-terminated external function name.
+/// - `out_error`: receives an error message on failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_add_external(
+    handle: *mut MontyHandle,
+    name: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyResultTag {
+    let name_str = match unsafe { parse_c_str(name, "name", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+    };
+    ffi_result!(handle, out_error, |h| h.add_external(name_str))
+}
+
+/// Remove a declared external function from a handle in Ready state.
+///
+/// - `name`: NUL-terminated external function name.
+/// - `out_error`: receives an error message on failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_remove_external(
+    handle: *mut MontyHandle,
+    name: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyResultTag {
+    let name_str = match unsafe { parse_c_str(name, "name", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+    };
+    ffi_result!(handle, out_error, |h| h.remove_external(name_str))
+}
+
+/// Pre-load text for `input()` to read, a line per call.
+///
+/// Declares `input` as an external (recompiling, so only valid in Ready
+/// state unless already declared) that the handle answers from `data`.
+///
+/// - `data`: NUL-terminated UTF-8 text.
+/// - `out_error`: receives an error message on failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_stdin(
+    handle: *mut MontyHandle,
+    data: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyResultTag {
+    let data_str = match unsafe { parse_c_str(data, "data", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+    };
+    ffi_result!(handle, out_error, |h| h.set_stdin(data_str.to_string()))
+}
+
+/// Answer every call to the external `name` with a fixed value, without
+/// pausing.
+///
+/// Declares `name` as an external (recompiling, so only valid in Ready
+/// state unless already declared). Registering a name again replaces its
+/// value.
+///
+/// - `name`: NUL-terminated function name.
+/// - `value_json`: NUL-terminated JSON return value, decoded like a resume
+///   value.
+/// - `out_error`: receives an error message on failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_register_constant(
+    handle: *mut MontyHandle,
+    name: *const c_char,
+    value_json: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyResultTag {
+    let name_str = match unsafe { parse_c_str(name, "name", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+    };
+    let value_str = match unsafe { parse_c_str(value_json, "value_json", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+    };
+    ffi_result!(handle, out_error, |h| h
+        .register_constant(name_str, value_str))
+}
+
+/// Declare which externals the host services.
+///
+/// - `csv`: NUL-terminated comma-separated names, each already declared, or
+///   NULL to service every declared external (the default).
+/// - `out_error`: receives an error message on failure (caller frees).
+///
+/// Guest calls to a declared external outside this set raise
+/// `NotImplementedError` without pausing.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_serviceable_externals(
+    handle: *mut MontyHandle,
+    csv: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyResultTag {
+    let names = if csv.is_null() {
+        None
+    } else {
+        match unsafe { parse_c_str(csv, "csv", out_error) } {
+            Ok("") => Some(vec![]),
+            Ok(s) => Some(s.split(',').map(|f| f.trim().to_string()).collect()),
+            Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+        }
+    };
+    ffi_result!(handle, out_error, |h| h.set_serviceable_externals(names))
+}
+
+/// Get the declared external function names as a JSON array string.
+/// Caller frees with `monty_string_free`. Returns NULL for a NULL or in-use handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_ext_fn_names(handle: *const MontyHandle) -> *mut c_char {
+    unsafe {
+        with_handle_ref(handle, |h| {
+            let json =
+                serde_json::to_string(h.external_functions()).unwrap_or_else(|_| "[]".into());
+            to_c_string(&json)
+        })
+    }
+    .unwrap_or(ptr::null_mut())
+}
+
+// ---------------------------------------------------------------------------
+// Validation
+// ---------------------------------------------------------------------------
+
+/// Compile Python code without creating a handle or running anything.
+///
+/// - `code`, `ext_fns`, `script_name`: as for `monty_create`.
+/// - `out_diagnostics_json`: receives a JSON array of diagnostics, `[]` when
+///   the code compiles (caller frees). May be NULL.
+///
+/// Returns 0 if clean, 1 if there are diagnostics, or -1 if an argument is
+/// NULL or not UTF-8 (`out_diagnostics_json` is then left untouched).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_validate(
+    code: *const c_char,
+    ext_fns: *const c_char,
+    script_name: *const c_char,
+    out_diagnostics_json: *mut *mut c_char,
+) -> c_int {
+    let Ok(code_str) = (unsafe { parse_c_str(code, "code", ptr::null_mut()) }) else {
+        return -1;
+    };
+    let Ok(ext_fn_list) = (unsafe { parse_ext_fns(ext_fns, ptr::null_mut()) }) else {
+        return -1;
+    };
+    let Ok(name) = (unsafe { parse_script_name(script_name, ptr::null_mut()) }) else {
+        return -1;
+    };
+
+    let Ok(diagnostics) =
+        catch_ffi_panic(|| MontyHandle::validate(code_str.to_string(), ext_fn_list, name))
+    else {
+        return -1;
+    };
+    let status = c_int::from(!diagnostics.is_empty());
+    if !out_diagnostics_json.is_null() {
+        let json = serde_json::Value::Array(diagnostics).to_string();
+        unsafe { *out_diagnostics_json = to_c_string(&json) };
+    }
+    status
+}
+
 // ---------------------------------------------------------------------------
 // Execution: run to completion
 // ---------------------------------------------------------------------------
@@ -126,6 +831,96 @@ pub unsafe extern "C" fn monty_run(
     handle: *mut MontyHandle,
     result_json: *mut *mut c_char,
     error_msg: *mut *mut c_char,
+) -> MontyResultTag {
+    unsafe { run_to_completion(handle, result_json, error_msg, MontyHandle::run) }
+}
+
+/// Run Python code to completion with `args_json` in place of the values of
+/// the globals given to `monty_create_with_globals`, matched positionally.
+///
+/// - `args_json`: NUL-terminated JSON array, one value per global.
+/// - `result_json`, `error_msg`: as for `monty_run`.
+///
+/// Returns `MONTY_RESULT_OK` or `MONTY_RESULT_ERROR`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_run_with_args(
+    handle: *mut MontyHandle,
+    args_json: *const c_char,
+    result_json: *mut *mut c_char,
+    error_msg: *mut *mut c_char,
+) -> MontyResultTag {
+    let args_str = match unsafe { parse_c_str(args_json, "args_json", error_msg) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+    };
+    unsafe {
+        run_to_completion(handle, result_json, error_msg, |h| {
+            h.run_with_args_json(args_str)
+        })
+    }
+}
+
+/// Create a handle, run it to completion and free it, all in one call, for
+/// code that needs no answers from the host.
+///
+/// - `code`, `ext_fns`, `script_name`: as for `monty_create`.
+/// - `limits_json`: NUL-terminated JSON object with any of the keys
+///   `monty_get_limits_json` reports (or NULL for none).
+/// - `out_result_json`: receives the result JSON (caller frees with
+///   `monty_string_free`). Compile errors, a bad `limits_json` and calls to
+///   declared externals come back as error results; an external call ends
+///   the run with `RuntimeError` rather than pausing, and a panic becomes a
+///   `RuntimeError` carrying the panic message. May be NULL.
+///
+/// Returns `MONTY_RESULT_OK` or `MONTY_RESULT_ERROR`. If an argument is NULL
+/// or not UTF-8, `out_result_json` is left untouched.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_eval(
+    code: *const c_char,
+    ext_fns: *const c_char,
+    script_name: *const c_char,
+    limits_json: *const c_char,
+    out_result_json: *mut *mut c_char,
+) -> MontyResultTag {
+    let Ok(code_str) = (unsafe { parse_c_str(code, "code", ptr::null_mut()) }) else {
+        return MontyResultTag::Error;
+    };
+    let Ok(ext_fn_list) = (unsafe { parse_ext_fns(ext_fns, ptr::null_mut()) }) else {
+        return MontyResultTag::Error;
+    };
+    let Ok(name) = (unsafe { parse_script_name(script_name, ptr::null_mut()) }) else {
+        return MontyResultTag::Error;
+    };
+    let limits = if limits_json.is_null() {
+        None
+    } else {
+        match unsafe { parse_c_str(limits_json, "limits_json", ptr::null_mut()) } {
+            Ok(s) => Some(s),
+            Err(_) => return MontyResultTag::Error,
+        }
+    };
+
+    let (tag, json) = match catch_ffi_panic(|| {
+        MontyHandle::eval(code_str.to_string(), ext_fn_list, name, limits)
+    }) {
+        Ok(outcome) => outcome,
+        Err(msg) => (
+            MontyResultTag::Error,
+            MontyHandle::eval_panic_result_json(msg),
+        ),
+    };
+    if !out_result_json.is_null() {
+        unsafe { *out_result_json = to_c_string(&json) };
+    }
+    tag
+}
+
+/// Shared body of `monty_run` and `monty_run_with_args`.
+unsafe fn run_to_completion(
+    handle: *mut MontyHandle,
+    result_json: *mut *mut c_char,
+    error_msg: *mut *mut c_char,
+    run: impl FnOnce(&mut MontyHandle) -> (MontyResultTag, Arc<str>, Option<String>),
 ) -> MontyResultTag {
     if handle.is_null() {
         if !error_msg.is_null() {
@@ -134,10 +929,20 @@ pub unsafe extern "C" fn monty_run(
         return MontyResultTag::Error;
     }
 
+    let Some(_claim) = HandleClaim::take(handle) else {
+        if !error_msg.is_null() {
+            unsafe { *error_msg = to_c_string(HANDLE_IN_USE) };
+        }
+        return MontyResultTag::Error;
+    };
     let h = unsafe { &mut *handle };
+    h.set_last_error_code(MontyErrorCode::None);
 
-    match catch_ffi_panic(|| h.run()) {
+    match catch_ffi_panic(|| run(h)) {
         Ok((tag, json, err)) => {
+            if tag == MontyResultTag::Error {
+                h.note_error();
+            }
             if !result_json.is_null() {
                 unsafe { *result_json = to_c_string(&json) };
             }
@@ -150,8 +955,9 @@ pub unsafe extern "C" fn monty_run(
             tag
         }
         Err(panic_msg) => {
+            h.set_last_error_code(MontyErrorCode::Panic);
             if !error_msg.is_null() {
-                unsafe { *error_msg = to_c_string(&panic_msg) };
+                unsafe { *error_msg = to_c_string(&h.panic_message(panic_msg)) };
             }
             MontyResultTag::Error
         }
@@ -187,7 +993,7 @@ pub unsafe extern "C" fn monty_resume(
 ) -> MontyProgressTag {
     let json_str = match unsafe { parse_c_str(value_json, "value_json", out_error) } {
         Ok(s) => s,
-        Err(()) => return MontyProgressTag::Error,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
     };
     ffi_progress!(handle, out_error, |h| h.resume(json_str))
 }
@@ -204,11 +1010,152 @@ pub unsafe extern "C" fn monty_resume_with_error(
 ) -> MontyProgressTag {
     let msg = match unsafe { parse_c_str(error_message, "error_message", out_error) } {
         Ok(s) => s,
-        Err(()) => return MontyProgressTag::Error,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
     };
     ffi_progress!(handle, out_error, |h| h.resume_with_error(msg))
 }
 
+/// Resume by raising a typed exception at the pending call.
+///
+/// - `exc_type`: NUL-terminated Python exception class name (e.g. `"ValueError"`);
+///   unknown names raise `RuntimeError`.
+/// - `message`: NUL-terminated exception message.
+/// - `out_error`: receives an error message on FFI failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_resume_with_typed_error(
+    handle: *mut MontyHandle,
+    exc_type: *const c_char,
+    message: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyProgressTag {
+    let exc_type_str = match unsafe { parse_c_str(exc_type, "exc_type", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    let message_str = match unsafe { parse_c_str(message, "message", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    ffi_progress!(handle, out_error, |h| h
+        .resume_with_typed_error(exc_type_str, message_str))
+}
+
+/// Resume by raising an exception described as JSON.
+///
+/// - `exc_json`: NUL-terminated `{"type": "ValueError", "message": "..."}`;
+///   `"args": ["..."]` may replace `message`. The engine keeps only a type
+///   and message, so richer `args` and unknown types are rejected.
+/// - `out_error`: receives an error message on failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_resume_with_exception(
+    handle: *mut MontyHandle,
+    exc_json: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyProgressTag {
+    let exc_json = match unsafe { parse_c_str(exc_json, "exc_json", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    ffi_progress!(handle, out_error, |h| h.resume_with_exception(exc_json))
+}
+
+/// Resume an OS call (e.g. `os.getenv`) with the host's result (JSON string).
+///
+/// - `value_json`: NUL-terminated JSON value to return to Python.
+/// - `out_error`: receives an error message on failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_resume_os_call(
+    handle: *mut MontyHandle,
+    value_json: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyProgressTag {
+    let json_str = match unsafe { parse_c_str(value_json, "value_json", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    ffi_progress!(handle, out_error, |h| h.resume_os_call(json_str))
+}
+
+/// Resume with each value of a JSON array in turn, answering successive
+/// external calls in one FFI call.
+///
+/// - `values_json`: NUL-terminated JSON array of return values.
+/// - `out_error`: receives an error message on failure (caller frees).
+///
+/// Stops at the first progress other than another pending call. Returns
+/// `Pending` if the values run out first; `monty_last_batch_consumed` says
+/// how many were used.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_resume_many(
+    handle: *mut MontyHandle,
+    values_json: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyProgressTag {
+    let json_str = match unsafe { parse_c_str(values_json, "values_json", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    ffi_progress!(handle, out_error, |h| h.resume_many(json_str))
+}
+
+/// Number of values the last `monty_resume_many` call consumed. Returns 0
+/// for a NULL or in-use handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_last_batch_consumed(handle: *const MontyHandle) -> usize {
+    unsafe { with_handle_ref(handle, |h| h.last_batch_consumed()) }.unwrap_or(0)
+}
+
+/// Pull callback feeding `monty_resume_reader`.
+///
+/// Writes up to `cap` bytes of JSON into `buf` and returns the number
+/// written, `0` at end of input, or a negative value on error.
+pub type MontyReadCallback =
+    unsafe extern "C" fn(user_data: *mut c_void, buf: *mut u8, cap: usize) -> isize;
+
+/// Adapts a `MontyReadCallback` to `std::io::Read`.
+struct CallbackReader {
+    read_cb: MontyReadCallback,
+    user_data: *mut c_void,
+}
+
+impl Read for CallbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { (self.read_cb)(self.user_data, buf.as_mut_ptr(), buf.len()) };
+        match usize::try_from(n) {
+            Ok(n) if n <= buf.len() => Ok(n),
+            Ok(_) => Err(io::Error::other("read callback overran the buffer")),
+            Err(_) => Err(io::Error::other("read callback failed")),
+        }
+    }
+}
+
+/// Resume execution with a return value streamed as JSON through `read_cb`.
+///
+/// - `read_cb`: called repeatedly on this thread until it returns `0` (end
+///   of input) or a negative value (aborts with an error). It must not call
+///   back into the handle.
+/// - `user_data`: passed through to `read_cb` unchanged.
+/// - `out_error`: receives an error message on failure (caller frees).
+///
+/// The value is decoded incrementally, so the full JSON text is never held
+/// in memory. Otherwise behaves like `monty_resume`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_resume_reader(
+    handle: *mut MontyHandle,
+    read_cb: Option<MontyReadCallback>,
+    user_data: *mut c_void,
+    out_error: *mut *mut c_char,
+) -> MontyProgressTag {
+    let Some(read_cb) = read_cb else {
+        if !out_error.is_null() {
+            unsafe { *out_error = to_c_string("read_cb is NULL") };
+        }
+        return MontyProgressTag::Error;
+    };
+    let reader = BufReader::with_capacity(64 * 1024, CallbackReader { read_cb, user_data });
+    ffi_progress!(handle, out_error, |h| h.resume_reader(reader))
+}
+
 // ---------------------------------------------------------------------------
 // Async / Futures
 // ---------------------------------------------------------------------------
@@ -232,14 +1179,28 @@ pub unsafe extern "C" fn monty_resume_as_future(
 /// Caller frees with `monty_string_free`.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_pending_future_call_ids(handle: *const MontyHandle) -> *mut c_char {
-    if handle.is_null() {
-        return ptr::null_mut();
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_future_call_ids() {
+            Some(json) => to_c_string(json),
+            None => ptr::null_mut(),
+        })
     }
-    let h = unsafe { &*handle };
-    match h.pending_future_call_ids() {
-        Some(json) => to_c_string(json),
-        None => ptr::null_mut(),
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Describe the pending futures as a JSON array of
+/// `{"call_id", "fn_name", "args", "kwargs"}` objects.
+/// Only valid when handle is in RESOLVE_FUTURES state.
+/// Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_pending_futures_json(handle: *const MontyHandle) -> *mut c_char {
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_futures_json() {
+            Some(json) => to_c_string(&json),
+            None => ptr::null_mut(),
+        })
     }
+    .unwrap_or(ptr::null_mut())
 }
 
 /// Resume futures with results and errors.
@@ -247,6 +1208,9 @@ pub unsafe extern "C" fn monty_pending_future_call_ids(handle: *const MontyHandl
 /// - `results_json`: JSON object `{"call_id": value, ...}` (string keys)
 /// - `errors_json`: JSON object `{"call_id": "error_msg", ...}` (string keys)
 /// - `out_error`: receives an error message on failure (caller frees).
+///
+/// A subset of the pending call ids may be given; the handle then returns
+/// `MONTY_PROGRESS_RESOLVE_FUTURES` again while any remain blocked.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_resume_futures(
     handle: *mut MontyHandle,
@@ -256,16 +1220,72 @@ pub unsafe extern "C" fn monty_resume_futures(
 ) -> MontyProgressTag {
     let results_str = match unsafe { parse_c_str(results_json, "results_json", out_error) } {
         Ok(s) => s,
-        Err(()) => return MontyProgressTag::Error,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
     };
     let errors_str = match unsafe { parse_c_str(errors_json, "errors_json", out_error) } {
         Ok(s) => s,
-        Err(()) => return MontyProgressTag::Error,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
     };
     ffi_progress!(handle, out_error, |h| h
         .resume_futures(results_str, errors_str))
 }
 
+/// Resume futures like `monty_resume_futures`, raising `TimeoutError` in the
+/// guest for each call id in `timeouts_json`.
+///
+/// - `timeouts_json`: JSON array of call ids, e.g. `[3, 4]`
+/// - `out_error`: receives an error message on failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_resume_futures_with_timeouts(
+    handle: *mut MontyHandle,
+    results_json: *const c_char,
+    errors_json: *const c_char,
+    timeouts_json: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyProgressTag {
+    let results_str = match unsafe { parse_c_str(results_json, "results_json", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    let errors_str = match unsafe { parse_c_str(errors_json, "errors_json", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    let timeouts_str = match unsafe { parse_c_str(timeouts_json, "timeouts_json", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    ffi_progress!(handle, out_error, |h| h.resume_futures_with_timeouts(
+        results_str,
+        errors_str,
+        timeouts_str
+    ))
+}
+
+/// Fail every pending future with the same typed exception.
+///
+/// - `exc_type`: NUL-terminated Python exception class name (e.g. `"ConnectionError"`).
+/// - `message`: NUL-terminated exception message.
+/// - `out_error`: receives an error message on failure (caller frees).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_fail_all_futures(
+    handle: *mut MontyHandle,
+    exc_type: *const c_char,
+    message: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyProgressTag {
+    let exc_type_str = match unsafe { parse_c_str(exc_type, "exc_type", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    let message_str = match unsafe { parse_c_str(message, "message", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyProgressTag::Error) },
+    };
+    ffi_progress!(handle, out_error, |h| h
+        .fail_all_futures(exc_type_str, message_str))
+}
+
 // ---------------------------------------------------------------------------
 // State accessors
 // ---------------------------------------------------------------------------
@@ -274,28 +1294,62 @@ pub unsafe extern "C" fn monty_resume_futures(
 /// returned `MONTY_PROGRESS_PENDING`). Caller frees with `monty_string_free`.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_pending_fn_name(handle: *const MontyHandle) -> *mut c_char {
-    if handle.is_null() {
-        return ptr::null_mut();
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_fn_name() {
+            Some(name) => to_c_string(name),
+            None => ptr::null_mut(),
+        })
+    }
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Get the pending function arguments as a JSON array string.
+/// Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_pending_fn_args_json(handle: *const MontyHandle) -> *mut c_char {
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_fn_args_json() {
+            Some(json) => to_c_string(json),
+            None => ptr::null_mut(),
+        })
     }
-    let h = unsafe { &*handle };
-    match h.pending_fn_name() {
-        Some(name) => to_c_string(name),
-        None => ptr::null_mut(),
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Get the number of positional arguments of the pending call, counting any
+/// dropped under `monty_set_max_call_args_bytes`.
+/// Returns -1 if the handle is NULL, in use, or not paused at a function call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_pending_fn_args_count(handle: *const MontyHandle) -> c_int {
+    unsafe {
+        with_handle_ref(handle, |h| {
+            h.pending_fn_args_count()
+                .map_or(-1, |n| c_int::try_from(n).unwrap_or(c_int::MAX))
+        })
     }
+    .unwrap_or(-1)
 }
 
-/// Get the pending function arguments as a JSON array string.
+/// Get one pending argument as JSON with container types tagged
+/// (`{"__tuple__": [...]}`, `{"__set__": [...]}`, ...).
+/// Returns NULL if the handle is NULL, in use, not paused, `index` is out of range
+/// or the argument was dropped under `monty_set_max_call_args_bytes`.
 /// Caller frees with `monty_string_free`.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn monty_pending_fn_args_json(handle: *const MontyHandle) -> *mut c_char {
-    if handle.is_null() {
+pub unsafe extern "C" fn monty_pending_fn_arg_tagged_json(
+    handle: *const MontyHandle,
+    index: c_int,
+) -> *mut c_char {
+    let Ok(index) = usize::try_from(index) else {
         return ptr::null_mut();
+    };
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_fn_arg_tagged_json(index) {
+            Some(json) => to_c_string(json),
+            None => ptr::null_mut(),
+        })
     }
-    let h = unsafe { &*handle };
-    match h.pending_fn_args_json() {
-        Some(json) => to_c_string(json),
-        None => ptr::null_mut(),
-    }
+    .unwrap_or(ptr::null_mut())
 }
 
 /// Get the pending function keyword arguments as a JSON object string.
@@ -303,69 +1357,171 @@ pub unsafe extern "C" fn monty_pending_fn_args_json(handle: *const MontyHandle)
 /// Caller frees with `monty_string_free`.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_pending_fn_kwargs_json(handle: *const MontyHandle) -> *mut c_char {
-    if handle.is_null() {
-        return ptr::null_mut();
-    }
-    let h = unsafe { &*handle };
-    match h.pending_fn_kwargs_json() {
-        Some(json) => to_c_string(json),
-        None => ptr::null_mut(),
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_fn_kwargs_json() {
+            Some(json) => to_c_string(json),
+            None => ptr::null_mut(),
+        })
     }
+    .unwrap_or(ptr::null_mut())
 }
 
 /// Get the pending call ID (monotonically increasing per-execution).
 /// Returns the call ID, or `u32::MAX` if not in Paused state.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_pending_call_id(handle: *const MontyHandle) -> u32 {
-    if handle.is_null() {
-        return u32::MAX;
-    }
-    let h = unsafe { &*handle };
-    h.pending_call_id().unwrap_or(u32::MAX)
+    unsafe { with_handle_ref(handle, |h| h.pending_call_id().unwrap_or(u32::MAX)) }
+        .unwrap_or(u32::MAX)
 }
 
 /// Whether the pending call is a method call (`obj.method()` vs `func()`).
 /// Returns 1 for method call, 0 for function call, -1 if not in Paused state.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_pending_method_call(handle: *const MontyHandle) -> c_int {
-    if handle.is_null() {
-        return -1;
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_method_call() {
+            Some(true) => 1,
+            Some(false) => 0,
+            None => -1,
+        })
     }
-    let h = unsafe { &*handle };
-    match h.pending_method_call() {
-        Some(true) => 1,
-        Some(false) => 0,
-        None => -1,
+    .unwrap_or(-1)
+}
+
+/// Whether arguments of the pending call were dropped under
+/// `monty_set_max_call_args_bytes`.
+/// Returns 1 if truncated, 0 if not, -1 if not in Paused state.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_pending_args_truncated(handle: *const MontyHandle) -> c_int {
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_args_truncated() {
+            Some(true) => 1,
+            Some(false) => 0,
+            None => -1,
+        })
+    }
+    .unwrap_or(-1)
+}
+
+/// Get the pending OS call's function name (e.g. `"os.getenv"`).
+/// Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_pending_os_call_name(handle: *const MontyHandle) -> *mut c_char {
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_os_call_name() {
+            Some(name) => to_c_string(name),
+            None => ptr::null_mut(),
+        })
+    }
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Get the pending OS call's arguments as a JSON array string.
+/// Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_pending_os_call_args_json(
+    handle: *const MontyHandle,
+) -> *mut c_char {
+    unsafe {
+        with_handle_ref(handle, |h| match h.pending_os_call_args_json() {
+            Some(json) => to_c_string(json),
+            None => ptr::null_mut(),
+        })
+    }
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Get the usage accumulated by the current execution as a JSON object
+/// string, in any state. Returns `"{}"` if nothing has run yet.
+/// Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_usage_json(handle: *const MontyHandle) -> *mut c_char {
+    unsafe { with_handle_ref(handle, |h| to_c_string(h.usage_json())) }.unwrap_or(ptr::null_mut())
+}
+
+/// Record each pause and how it was answered, keeping the latest
+/// `max_entries` events. Replaces any earlier log. No-op on NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_enable_event_log(handle: *mut MontyHandle, max_entries: usize) {
+    unsafe { with_handle(handle, |h| h.enable_event_log(max_entries)) };
+}
+
+/// Get the event log as a JSON object string. Returns NULL if the handle is
+/// NULL or the log isn't enabled. Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_event_log_json(handle: *const MontyHandle) -> *mut c_char {
+    unsafe {
+        with_handle_ref(handle, |h| match h.event_log_json() {
+            Some(json) => to_c_string(&json),
+            None => ptr::null_mut(),
+        })
     }
+    .unwrap_or(ptr::null_mut())
 }
 
 /// Get the completed result as a JSON string.
 /// Caller frees with `monty_string_free`.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_complete_result_json(handle: *const MontyHandle) -> *mut c_char {
-    if handle.is_null() {
-        return ptr::null_mut();
-    }
-    let h = unsafe { &*handle };
-    match h.complete_result_json() {
-        Some(json) => to_c_string(json),
-        None => ptr::null_mut(),
+    unsafe {
+        with_handle_ref(handle, |h| match h.complete_result_json() {
+            Some(json) => to_c_string(json),
+            None => ptr::null_mut(),
+        })
     }
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Current lifecycle state of the handle as a `MontyStateTag` value
+/// (0=Ready, 1=Paused, 2=Futures, 3=Complete, 4=Consumed, 5=OsCall).
+/// Returns -1 for a NULL or in-use handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_state(handle: *const MontyHandle) -> c_int {
+    unsafe { with_handle_ref(handle, |h| h.state_tag() as c_int) }.unwrap_or(-1)
+}
+
+/// Whether the handle has spent its program (Complete or Consumed state), so
+/// it cannot run again until `monty_reset`. Returns 1 if consumed, 0 if not,
+/// -1 for a NULL or in-use handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_is_consumed(handle: *const MontyHandle) -> c_int {
+    unsafe { with_handle_ref(handle, |h| c_int::from(h.is_consumed())) }.unwrap_or(-1)
+}
+
+/// Category of the last failed call on the handle as a `MontyErrorCode`
+/// value (0 if the last call succeeded). Set by calls that report failures
+/// through an error out-parameter. Returns -1 for a NULL or in-use handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_last_error_code(handle: *const MontyHandle) -> c_int {
+    unsafe { with_handle_ref(handle, |h| h.last_error_code() as c_int) }.unwrap_or(-1)
 }
 
 /// Whether the completed result is an error. Returns 1 for error, 0 for success,
 /// -1 if not in Complete state.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_complete_is_error(handle: *const MontyHandle) -> c_int {
-    if handle.is_null() {
-        return -1;
+    unsafe {
+        with_handle_ref(handle, |h| match h.complete_is_error() {
+            Some(true) => 1,
+            Some(false) => 0,
+            None => -1,
+        })
     }
-    let h = unsafe { &*handle };
-    match h.complete_is_error() {
-        Some(true) => 1,
-        Some(false) => 0,
-        None => -1,
+    .unwrap_or(-1)
+}
+
+/// Whether the completed result is a retryable error. Returns 1 if retryable,
+/// 0 if not (including success), -1 if not in Complete state.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_complete_is_retryable(handle: *const MontyHandle) -> c_int {
+    unsafe {
+        with_handle_ref(handle, |h| match h.complete_is_retryable() {
+            Some(true) => 1,
+            Some(false) => 0,
+            None => -1,
+        })
     }
+    .unwrap_or(-1)
 }
 
 // ---------------------------------------------------------------------------
@@ -374,6 +1530,11 @@ pub unsafe extern "C" fn monty_complete_is_error(handle: *const MontyHandle) ->
 
 /// Serialize the compiled code to a byte buffer. Caller frees with `monty_bytes_free`.
 ///
+/// A handle paused at an external function call is serialized with its VM
+/// state and settings (globals, limits, options, label, stdin, constants);
+/// `monty_restore` then yields a handle ready for `monty_resume`. Callbacks,
+/// OS call handlers and the event log must be set again.
+///
 /// - `out_len`: receives the byte count.
 ///
 /// Returns a heap-allocated byte buffer, or NULL on error.
@@ -385,16 +1546,73 @@ pub unsafe extern "C" fn monty_snapshot(
     if handle.is_null() || out_len.is_null() {
         return ptr::null_mut();
     }
+    let Some(_claim) = HandleClaim::take(handle) else {
+        return ptr::null_mut();
+    };
+    let h = unsafe { &*handle };
+    match catch_ffi_panic(|| h.snapshot()) {
+        Ok(Ok(bytes)) => {
+            let len = bytes.len();
+            let boxed = bytes.into_boxed_slice();
+            let ptr = Box::into_raw(boxed) as *mut u8;
+            unsafe { *out_len = len };
+            ptr
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Like `monty_snapshot`, compressed with `codec`: 0 none, 1 zstd, 2 gzip.
+/// `monty_restore` detects the codec. Caller frees with `monty_bytes_free`.
+///
+/// Returns NULL on error or for an unknown codec.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_snapshot_ex(
+    handle: *const MontyHandle,
+    codec: c_int,
+    out_len: *mut usize,
+) -> *mut u8 {
+    if handle.is_null() || out_len.is_null() {
+        return ptr::null_mut();
+    }
+    let Some(codec) = SnapshotCodec::from_c_int(codec) else {
+        return ptr::null_mut();
+    };
+    let Some(_claim) = HandleClaim::take(handle) else {
+        return ptr::null_mut();
+    };
     let h = unsafe { &*handle };
-    match h.snapshot() {
-        Ok(bytes) => {
+    match catch_ffi_panic(|| h.snapshot_with_codec(codec)) {
+        Ok(Ok(bytes)) => {
             let len = bytes.len();
             let boxed = bytes.into_boxed_slice();
             let ptr = Box::into_raw(boxed) as *mut u8;
             unsafe { *out_len = len };
             ptr
         }
-        Err(_) => ptr::null_mut(),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Get the byte length `monty_snapshot` would return.
+///
+/// The bytes are serialized once and cached, so a following `monty_snapshot`
+/// returns them without serializing again.
+///
+/// Returns the length, or -1 if the handle is NULL, in use, not in Ready or
+/// Paused state, or serialization fails.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_snapshot_size(handle: *const MontyHandle) -> i64 {
+    if handle.is_null() {
+        return -1;
+    }
+    let Some(_claim) = HandleClaim::take(handle) else {
+        return -1;
+    };
+    let h = unsafe { &*handle };
+    match catch_ffi_panic(|| h.snapshot_size()) {
+        Ok(Ok(len)) => i64::try_from(len).unwrap_or(-1),
+        _ => -1,
     }
 }
 
@@ -419,9 +1637,9 @@ pub unsafe extern "C" fn monty_restore(
     }
 
     let bytes = unsafe { std::slice::from_raw_parts(data, len) };
-    match MontyHandle::restore(bytes) {
-        Ok(handle) => Box::into_raw(Box::new(handle)),
-        Err(msg) => {
+    match catch_ffi_panic(|| MontyHandle::restore(bytes)) {
+        Ok(Ok(handle)) => Box::into_raw(Box::new(handle)),
+        Ok(Err(msg)) | Err(msg) => {
             if !out_error.is_null() {
                 unsafe { *out_error = to_c_string(&msg) };
             }
@@ -437,24 +1655,340 @@ pub unsafe extern "C" fn monty_restore(
 /// Set the memory limit in bytes. Must be called before `monty_run` or `monty_start`.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_set_memory_limit(handle: *mut MontyHandle, bytes: usize) {
-    if !handle.is_null() {
-        unsafe { &mut *handle }.set_memory_limit(bytes);
-    }
+    unsafe { with_handle(handle, |h| h.set_memory_limit(bytes)) };
 }
 
 /// Set the execution time limit in milliseconds.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_set_time_limit_ms(handle: *mut MontyHandle, ms: u64) {
-    if !handle.is_null() {
-        unsafe { &mut *handle }.set_time_limit_ms(ms);
+    unsafe { with_handle(handle, |h| h.set_time_limit_ms(ms)) };
+}
+
+/// Set a time limit in milliseconds that excludes time spent paused for
+/// the host. Replaces any limit from `monty_set_time_limit_ms`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_compute_time_limit_ms(handle: *mut MontyHandle, ms: u64) {
+    unsafe { with_handle(handle, |h| h.set_compute_time_limit_ms(ms)) };
+}
+
+/// Create a token that interrupts the handle's executions. Caller frees
+/// with `monty_token_free`. Returns NULL if `handle` is NULL.
+///
+/// Create the token before handing the handle to the thread that runs it:
+/// the token shares only the handle's usage counters, so it may be used
+/// from any thread, even after the handle is freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_interrupt_token(
+    handle: *const MontyHandle,
+) -> *mut MontyInterruptToken {
+    unsafe { with_handle_ref(handle, |h| Box::into_raw(Box::new(h.interrupt_token()))) }
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Ask the current execution of the token's handle to stop at its next
+/// time check, raising `KeyboardInterrupt` (an ordinary error result).
+/// Cleared when a new execution starts. No-op on NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_token_interrupt(token: *const MontyInterruptToken) {
+    if !token.is_null() {
+        unsafe { &*token }.interrupt();
+    }
+}
+
+/// Free a token from `monty_interrupt_token`. Safe to call with NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_token_free(token: *mut MontyInterruptToken) {
+    if !token.is_null() {
+        drop(unsafe { Box::from_raw(token) });
     }
 }
 
 /// Set the stack depth limit.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_set_stack_limit(handle: *mut MontyHandle, depth: usize) {
-    if !handle.is_null() {
-        unsafe { &mut *handle }.set_stack_limit(depth);
+    unsafe { with_handle(handle, |h| h.set_stack_limit(depth)) };
+}
+
+/// Abort an execution with `RuntimeError` once it makes more than `max`
+/// external function calls. No-op on NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_max_external_calls(handle: *mut MontyHandle, max: u64) {
+    unsafe { with_handle(handle, |h| h.set_max_external_calls(max)) };
+}
+
+/// Cap the JSON bytes a pending call's arguments may take in its metadata;
+/// the first argument past the cap and every later one are dropped. No-op
+/// on NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_max_call_args_bytes(handle: *mut MontyHandle, max: usize) {
+    unsafe { with_handle(handle, |h| h.set_max_call_args_bytes(max)) };
+}
+
+/// External function calls handed to the host in the current execution.
+/// Returns 0 for a NULL or in-use handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_ext_call_count(handle: *const MontyHandle) -> u64 {
+    unsafe { with_handle_ref(handle, |h| h.ext_call_count()) }.unwrap_or(0)
+}
+
+/// Remove every resource limit. No-op on NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_clear_limits(handle: *mut MontyHandle) {
+    unsafe { with_handle(handle, |h| h.clear_limits()) };
+}
+
+/// Remove the memory limit. No-op on NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_clear_memory_limit(handle: *mut MontyHandle) {
+    unsafe { with_handle(handle, |h| h.clear_memory_limit()) };
+}
+
+/// Remove the time limit (wall or compute). No-op on NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_clear_time_limit(handle: *mut MontyHandle) {
+    unsafe { with_handle(handle, |h| h.clear_time_limit()) };
+}
+
+/// Restore the default stack depth limit (1000). No-op on NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_clear_stack_limit(handle: *mut MontyHandle) {
+    unsafe { with_handle(handle, |h| h.clear_stack_limit()) };
+}
+
+/// Get the configured limits as a JSON object string:
+/// `{"max_memory", "max_duration_ms", "max_recursion_depth", "max_compute_ms"}`,
+/// each a number or `null` if unset. Returns NULL for a NULL or in-use handle.
+/// Caller frees with `monty_string_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_get_limits_json(handle: *const MontyHandle) -> *mut c_char {
+    unsafe { with_handle_ref(handle, |h| to_c_string(&h.limits_json())) }.unwrap_or(ptr::null_mut())
+}
+
+/// Set several limits at once from a JSON object with any of the keys
+/// `monty_get_limits_json` reports; `null` leaves a limit as it is.
+///
+/// - `limits_json`: NUL-terminated JSON object, e.g.
+///   `{"max_memory": 1048576, "max_duration_ms": 500}`.
+/// - `out_error`: receives an error message on failure (caller frees).
+///
+/// Nothing is applied if any key is unknown or any value is not a
+/// non-negative integer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_limits_json(
+    handle: *mut MontyHandle,
+    limits_json: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyResultTag {
+    let json_str = match unsafe { parse_c_str(limits_json, "limits_json", out_error) } {
+        Ok(s) => s,
+        Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+    };
+    ffi_result!(handle, out_error, |h| h.set_limits_json(json_str))
+}
+
+/// Cap the length in characters of any single string in a result or external
+/// call argument, keyword names included. Enforced at serialization time,
+/// since the engine has no per-object string cap: the run fails with a
+/// host-side serialization error (reported as `MemoryError`) that the guest
+/// cannot catch.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_max_string_length(handle: *mut MontyHandle, chars: usize) {
+    unsafe { with_handle(handle, |h| h.set_max_string_length(chars)) };
+}
+
+/// Cap buffered print output at `bytes`. Output past the cap is dropped and
+/// the result JSON reports `"print_output_truncated": true`. Unlimited by
+/// default.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_output_limit(handle: *mut MontyHandle, bytes: usize) {
+    unsafe { with_handle(handle, |h| h.set_output_limit(bytes)) };
+}
+
+/// Truncate any single collection in a result or external call argument to
+/// `n` elements, ending it with a `{"__monty_elided__": remaining}` marker.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_max_collection_width(handle: *mut MontyHandle, n: usize) {
+    unsafe { with_handle(handle, |h| h.set_max_collection_width(n)) };
+}
+
+/// Reject any single resume value that would decode into more than `n`
+/// values (each JSON value counts once), before it is fully built.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_max_resume_elements(handle: *mut MontyHandle, n: usize) {
+    unsafe { with_handle(handle, |h| h.set_max_resume_elements(n)) };
+}
+
+// ---------------------------------------------------------------------------
+// Result options
+// ---------------------------------------------------------------------------
+
+/// Always include `print_output` in result JSON (empty string when nothing
+/// was printed). `enabled` is non-zero to enable; default omits the key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_always_emit_print(handle: *mut MontyHandle, enabled: c_int) {
+    unsafe { with_handle(handle, |h| h.set_always_emit_print(enabled != 0)) };
+}
+
+/// Include `value_repr`, the Python `repr()` of the result value, in
+/// successful result JSON. `enabled` is non-zero to enable; default omits it.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_value_repr(handle: *mut MontyHandle, enabled: c_int) {
+    unsafe { with_handle(handle, |h| h.set_value_repr(enabled != 0)) };
+}
+
+/// Pause with `MONTY_PROGRESS_OS_CALL` on an OS call no registered handler
+/// answers, for the host to answer with `monty_resume_os_call`. `enabled`
+/// is non-zero to enable; by default such a call ends the run with
+/// `NotImplementedError`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_pause_os_calls(handle: *mut MontyHandle, enabled: c_int) {
+    unsafe { with_handle(handle, |h| h.set_pause_os_calls(enabled != 0)) };
+}
+
+/// Print callback for `monty_set_print_callback`.
+///
+/// Receives `len` bytes of UTF-8 print output at `text`, which is not
+/// NUL-terminated and is only valid for the duration of the call.
+pub type MontyPrintCallback =
+    unsafe extern "C" fn(text: *const c_char, len: usize, user_data: *mut c_void);
+
+/// A C print callback with its user data, forwarded on the executing thread.
+struct FfiPrintCallback {
+    cb: MontyPrintCallback,
+    user_data: *mut c_void,
+}
+
+// The callback only runs inside monty_* calls on the handle, on the caller's
+// thread, so moving it with the handle is up to the host like any handle use.
+unsafe impl Send for FfiPrintCallback {}
+
+impl FfiPrintCallback {
+    fn write(&self, text: &str) {
+        unsafe { (self.cb)(text.as_ptr().cast(), text.len(), self.user_data) };
+    }
+}
+
+/// Stream print output to `cb` as the guest writes it, instead of
+/// buffering it into the result's `print_output`.
+///
+/// - `cb`: called with each output chunk, or NULL to restore buffering.
+///   Calls into the handle from `cb`, accessors included, are refused.
+/// - `user_data`: passed through to `cb` unchanged.
+///
+/// Takes effect from the next `monty_run`/`monty_start`/`monty_resume*`
+/// call, so it may be changed while paused. Safe to call with NULL handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_print_callback(
+    handle: *mut MontyHandle,
+    cb: Option<MontyPrintCallback>,
+    user_data: *mut c_void,
+) {
+    if handle.is_null() {
+        return;
+    }
+    let callback = cb.map(|cb| {
+        let sink = FfiPrintCallback { cb, user_data };
+        Box::new(move |text: &str| sink.write(text)) as handle::PrintCallback
+    });
+    unsafe { with_handle(handle, |h| h.set_print_callback(callback)) };
+}
+
+/// Replace the exception types reported as `"retryable"` in error JSON.
+///
+/// - `csv`: NUL-terminated comma-separated exception class names (e.g.
+///   `"TimeoutError,ConnectionError"`), or NULL to restore the default
+///   (`TimeoutError`).
+/// - `out_error`: receives an error message on failure (caller frees).
+///
+/// An error the host injected at the last resume (`monty_resume_with_error`,
+/// futures errors and timeouts, `monty_fail_all_futures`) is retryable
+/// regardless, when that exception itself ends the run.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_retryable_exc_types(
+    handle: *mut MontyHandle,
+    csv: *const c_char,
+    out_error: *mut *mut c_char,
+) -> MontyResultTag {
+    let names = if csv.is_null() {
+        None
+    } else {
+        match unsafe { parse_c_str(csv, "csv", out_error) } {
+            Ok("") => Some(vec![]),
+            Ok(s) => Some(s.split(',').map(|t| t.trim().to_string()).collect()),
+            Err(code) => return unsafe { arg_error(handle, code, MontyResultTag::Error) },
+        }
+    };
+    ffi_result!(handle, out_error, |h| h.set_retryable_exc_types(names))
+}
+
+/// Tag tuples, sets, frozensets, bytes, non-finite floats, integers beyond
+/// i64 and non-string-keyed dicts as `{"__tuple__": [...]}`,
+/// `{"__set__": [...]}`, `{"__frozenset__": [...]}`,
+/// `{"__bytes__": "<base64>"}`, `{"__float__": "NaN"}`,
+/// `{"__bigint__": "<digits>"}` and `{"__dict__": [[k, v], ...]}` in result
+/// JSON and external call arguments. `enabled` is non-zero to enable; default emits
+/// plain arrays and strings. Host values decode the tags only while enabled.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_typed_containers(handle: *mut MontyHandle, enabled: c_int) {
+    unsafe { with_handle(handle, |h| h.set_typed_containers(enabled != 0)) };
+}
+
+/// Emit int- and bool-keyed dicts as JSON objects with stringified keys and
+/// a `__key_types__` entry recording them. `enabled` is non-zero to enable;
+/// default emits `[k, v]` pairs. Host values restore the keys only while
+/// enabled.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_stringify_int_keys(handle: *mut MontyHandle, enabled: c_int) {
+    unsafe { with_handle(handle, |h| h.set_stringify_int_keys(enabled != 0)) };
+}
+
+/// Emit `-0.0` as-is in result JSON and external call arguments. `enabled`
+/// is non-zero to enable; default normalizes it to `0.0`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_preserve_negative_zero(
+    handle: *mut MontyHandle,
+    enabled: c_int,
+) {
+    unsafe { with_handle(handle, |h| h.set_preserve_negative_zero(enabled != 0)) };
+}
+
+/// Choose how floats with no fractional part (`3.0`) are emitted in result
+/// JSON and external call arguments: `0` as a number (`3.0`, the default),
+/// `1` tagged (`{"__float__": 3.0}`), `2` as an integer (`3`). Unknown
+/// values are ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_integral_float_policy(handle: *mut MontyHandle, policy: c_int) {
+    if let Some(policy) = IntegralFloatPolicy::from_c_int(policy) {
+        unsafe { with_handle(handle, |h| h.set_integral_float_policy(policy)) };
+    }
+}
+
+/// Emit only `exc_type` and `message` for guest exceptions, skipping
+/// traceback serialization. `enabled` is non-zero to enable; default keeps
+/// full tracebacks.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_set_minimal_errors(handle: *mut MontyHandle, enabled: c_int) {
+    unsafe { with_handle(handle, |h| h.set_minimal_errors(enabled != 0)) };
+}
+
+// ---------------------------------------------------------------------------
+// Value helpers
+// ---------------------------------------------------------------------------
+
+/// Estimate the memory footprint in bytes of a JSON value once converted
+/// to a Python object, e.g. to check a resume value against a memory limit.
+///
+/// - `json`: NUL-terminated JSON value.
+///
+/// Returns the estimate, or -1 if `json` is NULL or not valid JSON.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_estimate_value_memory(json: *const c_char) -> i64 {
+    let Ok(json_str) = (unsafe { parse_c_str(json, "json", ptr::null_mut()) }) else {
+        return -1;
+    };
+    match catch_ffi_panic(|| serde_json::from_str::<serde_json::Value>(json_str)) {
+        Ok(Ok(val)) => i64::try_from(convert::estimate_json_memory(&val)).unwrap_or(i64::MAX),
+        _ => -1,
     }
 }
 
@@ -470,6 +2004,20 @@ pub unsafe extern "C" fn monty_string_free(ptr: *mut c_char) {
     }
 }
 
+/// Free `count` C strings returned by `monty_*` functions in one call.
+///
+/// NULL entries are skipped, as is a NULL `ptrs` array. The array itself
+/// belongs to the caller.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn monty_string_free_many(ptrs: *const *mut c_char, count: usize) {
+    if ptrs.is_null() {
+        return;
+    }
+    for &ptr in unsafe { std::slice::from_raw_parts(ptrs, count) } {
+        unsafe { monty_string_free(ptr) };
+    }
+}
+
 /// Free a byte buffer returned by `monty_snapshot`.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn monty_bytes_free(ptr: *mut u8, len: usize) {
@@ -477,3 +2025,100 @@ pub unsafe extern "C" fn monty_bytes_free(ptr: *mut u8, len: usize) {
         drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Functions every ABI version has, announced by no capability.
+    const UNANNOUNCED: &[&str] = &[
+        "monty_abi_version",
+        "monty_capabilities_json",
+        "monty_create",
+        "monty_free",
+        "monty_set_label",
+        "monty_set_correlation_id",
+        "monty_get_label",
+        "monty_clear_output",
+        "monty_reset",
+        "monty_clone",
+        "monty_add_external",
+        "monty_remove_external",
+        "monty_set_serviceable_externals",
+        "monty_ext_fn_names",
+        "monty_run",
+        "monty_start",
+        "monty_resume",
+        "monty_resume_with_error",
+        "monty_resume_with_typed_error",
+        "monty_pending_fn_name",
+        "monty_pending_fn_args_json",
+        "monty_pending_fn_kwargs_json",
+        "monty_pending_call_id",
+        "monty_pending_method_call",
+        "monty_usage_json",
+        "monty_complete_result_json",
+        "monty_state",
+        "monty_is_consumed",
+        "monty_complete_is_error",
+        "monty_complete_is_retryable",
+        "monty_snapshot_size",
+        "monty_set_memory_limit",
+        "monty_set_time_limit_ms",
+        "monty_set_stack_limit",
+        "monty_clear_limits",
+        "monty_clear_memory_limit",
+        "monty_clear_time_limit",
+        "monty_clear_stack_limit",
+        "monty_set_max_string_length",
+        "monty_set_output_limit",
+        "monty_set_max_collection_width",
+        "monty_set_max_resume_elements",
+        "monty_set_always_emit_print",
+        "monty_set_retryable_exc_types",
+        "monty_set_preserve_negative_zero",
+        "monty_set_minimal_errors",
+        "monty_estimate_value_memory",
+        "monty_string_free",
+        "monty_string_free_many",
+        "monty_bytes_free",
+    ];
+
+    /// Names of the `extern "C"` functions this file exports.
+    fn exported_symbols() -> Vec<&'static str> {
+        include_str!("lib.rs")
+            .lines()
+            .filter_map(|line| line.split_once("extern \"C\" fn ")?.1.split_once('('))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn test_capability_functions_are_exported() {
+        let exported = exported_symbols();
+        let header = include_str!("../include/dart_monty.h");
+        for (name, functions) in MONTY_CAPABILITIES {
+            assert!(!functions.is_empty(), "{name}");
+            for function in *functions {
+                assert!(exported.contains(function), "{name}: {function}");
+                let declared = [" ", "*"]
+                    .iter()
+                    .any(|prefix| header.contains(&format!("{prefix}{function}(")));
+                assert!(declared, "{name}: {function} not in dart_monty.h");
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_export_is_announced() {
+        for symbol in exported_symbols() {
+            let announced = MONTY_CAPABILITIES
+                .iter()
+                .any(|(_, functions)| functions.contains(&symbol));
+            assert!(
+                announced || UNANNOUNCED.contains(&symbol),
+                "{symbol} is in no capability"
+            );
+        }
+    }
+}
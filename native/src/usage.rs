@@ -0,0 +1,409 @@
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use monty::{
+    ExcType, LimitedTracker, NoLimitTracker, ResourceError, ResourceLimits, ResourceTracker,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+/// Resource usage of one execution, shared between a handle and the
+/// tracker running its code. Also carries the interrupt flag, which other
+/// threads may set through an `MontyInterruptToken` while the code runs.
+#[derive(Debug, Default)]
+pub struct UsageStats {
+    memory: AtomicUsize,
+    peak_memory: AtomicUsize,
+    peak_depth: AtomicUsize,
+    elapsed_us: AtomicU64,
+    /// Time spent paused for the host, which a compute limit doesn't count.
+    host_wait_us: AtomicU64,
+    /// Compute time limit in microseconds; 0 when unset.
+    compute_limit_us: AtomicU64,
+    interrupted: AtomicBool,
+    /// Why the tracker last aborted: a `LimitKind` as its `u8`,
+    /// `STOPPED_BY_INTERRUPT`, or 0 for none.
+    stopped: AtomicU8,
+}
+
+/// `UsageStats::stopped` value for an execution stopped by an interrupt.
+const STOPPED_BY_INTERRUPT: u8 = 4;
+
+/// Why a tracker check aborted the current execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// A resource limit tripped.
+    Limit(LimitKind),
+    /// The host requested an interrupt.
+    Interrupt,
+}
+
+impl StopReason {
+    /// Exception type the engine raises for this stop.
+    pub fn exc_type(self) -> ExcType {
+        match self {
+            Self::Limit(kind) => kind.exc_type(),
+            Self::Interrupt => ExcType::RecursionError,
+        }
+    }
+}
+
+/// Resource limit a tracker check failed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    /// The memory or allocation count limit.
+    Memory = 1,
+    /// The time or compute time limit.
+    Time = 2,
+    /// The recursion depth limit.
+    Stack = 3,
+}
+
+impl LimitKind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Memory),
+            2 => Some(Self::Time),
+            3 => Some(Self::Stack),
+            _ => None,
+        }
+    }
+
+    /// Name reported as `limit_exceeded` in error JSON.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Time => "time",
+            Self::Stack => "stack",
+        }
+    }
+
+    /// Exception type the engine raises when this limit trips.
+    pub fn exc_type(self) -> ExcType {
+        match self {
+            Self::Memory => ExcType::MemoryError,
+            Self::Time => ExcType::TimeoutError,
+            Self::Stack => ExcType::RecursionError,
+        }
+    }
+}
+
+impl UsageStats {
+    /// Zero every counter, keeping trackers that share these stats attached.
+    pub fn reset(&self) {
+        self.memory.store(0, Ordering::Relaxed);
+        self.peak_memory.store(0, Ordering::Relaxed);
+        self.peak_depth.store(0, Ordering::Relaxed);
+        self.elapsed_us.store(0, Ordering::Relaxed);
+        self.host_wait_us.store(0, Ordering::Relaxed);
+        self.stopped.store(0, Ordering::Relaxed);
+    }
+
+    /// Why a tracker check last aborted this execution.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        match self.stopped.load(Ordering::Relaxed) {
+            STOPPED_BY_INTERRUPT => Some(StopReason::Interrupt),
+            value => LimitKind::from_u8(value).map(StopReason::Limit),
+        }
+    }
+
+    /// The limit a tracker check last failed on in this execution.
+    pub fn limit_hit(&self) -> Option<LimitKind> {
+        match self.stop_reason() {
+            Some(StopReason::Limit(kind)) => Some(kind),
+            _ => None,
+        }
+    }
+
+    /// Pass through a tracker check's result, recording `kind` if it failed.
+    fn checked(
+        &self,
+        kind: LimitKind,
+        result: Result<(), ResourceError>,
+    ) -> Result<(), ResourceError> {
+        if result.is_err() {
+            self.stopped.store(kind as u8, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Set the compute time limit enforced by trackers sharing these stats.
+    pub fn set_compute_limit(&self, limit: Option<Duration>) {
+        let us = limit.map_or(0, |d| duration_us(d).max(1));
+        self.compute_limit_us.store(us, Ordering::Relaxed);
+    }
+
+    /// Ask the running execution to stop at its next time check.
+    pub fn request_interrupt(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    /// Drop a pending interrupt request, as a new execution starts.
+    pub fn clear_interrupt(&self) {
+        self.interrupted.store(false, Ordering::Relaxed);
+    }
+
+    /// Add wall time spent paused waiting for the host.
+    pub fn add_host_wait(&self, waited: Duration) {
+        self.host_wait_us
+            .fetch_add(duration_us(waited), Ordering::Relaxed);
+    }
+
+    /// Wall-clock budget for the current execution: the compute limit plus
+    /// time spent waiting on the host. `None` without a compute limit.
+    fn compute_budget(&self) -> Option<Duration> {
+        match self.compute_limit_us.load(Ordering::Relaxed) {
+            0 => None,
+            limit => {
+                let waited = self.host_wait_us.load(Ordering::Relaxed);
+                Some(Duration::from_micros(limit.saturating_add(waited)))
+            }
+        }
+    }
+
+    /// Add wall time spent executing guest code.
+    pub fn add_elapsed(&self, elapsed: Duration) {
+        self.elapsed_us
+            .fetch_add(duration_us(elapsed), Ordering::Relaxed);
+    }
+
+    /// The `usage` object reported in result JSON. `memory_bytes_used` and
+    /// `stack_depth_used` are peaks, repeated as `peak_memory_bytes` and
+    /// `peak_stack_depth` to set them apart from `final_memory_bytes`, the
+    /// bytes still live.
+    pub fn to_json(&self) -> Value {
+        let peak_memory = self.peak_memory.load(Ordering::Relaxed);
+        let peak_depth = self.peak_depth.load(Ordering::Relaxed);
+        json!({
+            "memory_bytes_used": peak_memory,
+            "peak_memory_bytes": peak_memory,
+            "final_memory_bytes": self.memory.load(Ordering::Relaxed),
+            "time_elapsed_ms": self.elapsed_us.load(Ordering::Relaxed) / 1000,
+            "stack_depth_used": peak_depth,
+            "peak_stack_depth": peak_depth,
+        })
+    }
+
+    fn allocated(&self, size: usize) {
+        let memory = self.memory.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak_memory.fetch_max(memory, Ordering::Relaxed);
+    }
+
+    fn freed(&self, size: usize) {
+        // Saturate: a restored snapshot frees memory counted before it was taken.
+        let memory = self.memory.load(Ordering::Relaxed);
+        self.memory
+            .store(memory.saturating_sub(size), Ordering::Relaxed);
+    }
+}
+
+/// The error a check fails with once an interrupt is requested.
+///
+/// `ResourceError` has no public constructor, so this is the depth error of
+/// a tracker that allows no depth at all, which fails on every call. The
+/// stats record `StopReason::Interrupt` alongside it, so the handle can
+/// tell it from a real recursion limit.
+fn interrupt_error() -> Result<(), ResourceError> {
+    LimitedTracker::new(ResourceLimits::new().max_recursion_depth(Some(0))).check_recursion_depth(1)
+}
+
+/// Interrupts a handle's executions from any thread, without touching the
+/// handle itself: it shares only the handle's `UsageStats`. Outlives the
+/// handle harmlessly; interrupting after it is gone does nothing.
+#[derive(Debug, Clone)]
+pub struct MontyInterruptToken(Arc<UsageStats>);
+
+impl MontyInterruptToken {
+    pub fn new(stats: Arc<UsageStats>) -> Self {
+        Self(stats)
+    }
+
+    /// Stop the current execution at its next time check, raising
+    /// `KeyboardInterrupt`.
+    pub fn interrupt(&self) {
+        self.0.request_interrupt();
+    }
+}
+
+fn duration_us(d: Duration) -> u64 {
+    u64::try_from(d.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Trackers whose time limit can be moved after they start.
+pub trait Rearm {
+    fn rearm(&mut self, max_duration: Duration);
+}
+
+impl Rearm for LimitedTracker {
+    fn rearm(&mut self, max_duration: Duration) {
+        self.set_max_duration(max_duration);
+    }
+}
+
+impl Rearm for NoLimitTracker {
+    fn rearm(&mut self, _max_duration: Duration) {}
+}
+
+/// Wraps a `ResourceTracker`, recording memory and stack depth into
+/// `UsageStats` while the inner tracker enforces its limits.
+///
+/// With a compute limit in the stats, the inner tracker's time limit is
+/// pushed back by the time spent waiting on the host before each check.
+/// The inner tracker sits in a `RefCell` because `check_time` only gets
+/// `&self`.
+///
+/// Serializes as the inner tracker alone; restored trackers start with
+/// fresh stats.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MeteredTracker<T> {
+    inner: RefCell<T>,
+    #[serde(skip)]
+    stats: Arc<UsageStats>,
+    /// Time limit last applied to `inner` for the compute limit.
+    #[serde(skip)]
+    armed: Cell<Option<Duration>>,
+}
+
+impl<T> MeteredTracker<T> {
+    pub fn new(inner: T, stats: Arc<UsageStats>) -> Self {
+        Self {
+            inner: RefCell::new(inner),
+            stats,
+            armed: Cell::new(None),
+        }
+    }
+
+    pub fn stats(&self) -> &Arc<UsageStats> {
+        &self.stats
+    }
+}
+
+impl<T: ResourceTracker + Rearm> ResourceTracker for MeteredTracker<T> {
+    fn on_allocate(&mut self, get_size: impl FnOnce() -> usize) -> Result<(), ResourceError> {
+        let size = get_size();
+        let result = self.inner.get_mut().on_allocate(|| size);
+        self.stats.checked(LimitKind::Memory, result)?;
+        self.stats.allocated(size);
+        Ok(())
+    }
+
+    fn on_free(&mut self, get_size: impl FnOnce() -> usize) {
+        let size = get_size();
+        self.inner.get_mut().on_free(|| size);
+        self.stats.freed(size);
+    }
+
+    fn check_time(&self) -> Result<(), ResourceError> {
+        if self.stats.interrupted.load(Ordering::Relaxed) {
+            self.stats
+                .stopped
+                .store(STOPPED_BY_INTERRUPT, Ordering::Relaxed);
+            return interrupt_error();
+        }
+        let budget = self.stats.compute_budget();
+        if budget.is_some() && budget != self.armed.get() {
+            self.inner.borrow_mut().rearm(budget.unwrap_or_default());
+            self.armed.set(budget);
+        }
+        let result = self.inner.borrow().check_time();
+        self.stats.checked(LimitKind::Time, result)
+    }
+
+    fn check_recursion_depth(&self, depth: usize) -> Result<(), ResourceError> {
+        let result = self.inner.borrow().check_recursion_depth(depth);
+        self.stats.checked(LimitKind::Stack, result)?;
+        self.stats.peak_depth.fetch_max(depth, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn check_large_result(&self, estimated_bytes: usize) -> Result<(), ResourceError> {
+        self.inner.borrow().check_large_result(estimated_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metered_tracker_records_peak() {
+        let stats = Arc::new(UsageStats::default());
+        let mut tracker = MeteredTracker::new(NoLimitTracker, stats.clone());
+        tracker.on_allocate(|| 100).unwrap();
+        tracker.on_allocate(|| 50).unwrap();
+        tracker.on_free(|| 100);
+        tracker.on_allocate(|| 20).unwrap();
+        tracker.check_recursion_depth(7).unwrap();
+        tracker.check_recursion_depth(3).unwrap();
+
+        let usage = stats.to_json();
+        assert_eq!(usage["memory_bytes_used"], 150);
+        assert_eq!(usage["stack_depth_used"], 7);
+        assert_eq!(usage["peak_memory_bytes"], 150);
+        assert_eq!(usage["peak_stack_depth"], 7);
+        assert_eq!(usage["final_memory_bytes"], 70);
+
+        stats.reset();
+        assert_eq!(stats.to_json()["memory_bytes_used"], 0);
+    }
+
+    #[test]
+    fn test_compute_budget_adds_host_wait() {
+        let stats = UsageStats::default();
+        assert_eq!(stats.compute_budget(), None);
+        stats.set_compute_limit(Some(Duration::from_millis(50)));
+        stats.add_host_wait(Duration::from_millis(200));
+        assert_eq!(stats.compute_budget(), Some(Duration::from_millis(250)));
+        stats.reset();
+        assert_eq!(stats.compute_budget(), Some(Duration::from_millis(50)));
+        stats.set_compute_limit(None);
+        assert_eq!(stats.compute_budget(), None);
+    }
+
+    #[test]
+    fn test_compute_limit_rearms_inner() {
+        let stats = Arc::new(UsageStats::default());
+        stats.set_compute_limit(Some(Duration::from_secs(5)));
+        let tracker = MeteredTracker::new(LimitedTracker::new(ResourceLimits::new()), stats);
+        tracker.check_time().unwrap();
+        assert_eq!(tracker.armed.get(), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_interrupt_fails_time_check() {
+        let stats = Arc::new(UsageStats::default());
+        let tracker = MeteredTracker::new(NoLimitTracker, stats.clone());
+        tracker.check_time().unwrap();
+        MontyInterruptToken::new(stats.clone()).interrupt();
+        assert!(tracker.check_time().is_err());
+        assert_eq!(stats.stop_reason(), Some(StopReason::Interrupt));
+        assert_eq!(stats.limit_hit(), None);
+        stats.clear_interrupt();
+        tracker.check_time().unwrap();
+    }
+
+    #[test]
+    fn test_limit_hit_recorded() {
+        let stats = Arc::new(UsageStats::default());
+        let limits = ResourceLimits::new().max_recursion_depth(Some(10));
+        let tracker = MeteredTracker::new(LimitedTracker::new(limits), stats.clone());
+        tracker.check_recursion_depth(5).unwrap();
+        assert_eq!(stats.limit_hit(), None);
+        assert!(tracker.check_recursion_depth(11).is_err());
+        assert_eq!(stats.limit_hit(), Some(LimitKind::Stack));
+        stats.reset();
+        assert_eq!(stats.limit_hit(), None);
+    }
+
+    #[test]
+    fn test_free_saturates() {
+        let stats = Arc::new(UsageStats::default());
+        let mut tracker = MeteredTracker::new(NoLimitTracker, stats.clone());
+        tracker.on_free(|| 10);
+        tracker.on_allocate(|| 5).unwrap();
+        assert_eq!(stats.to_json()["memory_bytes_used"], 5);
+    }
+}
@@ -1,4 +1,4 @@
-use std::ffi::{CStr, CString, c_char};
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
 use std::ptr;
 
 use dart_monty_native::*;
@@ -134,10 +134,16 @@ fn null_safety() {
     // monty_complete_is_error with NULL
     assert_eq!(unsafe { monty_complete_is_error(ptr::null()) }, -1);
 
+    // monty_state with NULL
+    assert_eq!(unsafe { monty_state(ptr::null()) }, -1);
+    assert_eq!(unsafe { monty_is_consumed(ptr::null()) }, -1);
+
     // monty_snapshot with NULL
     let mut len: usize = 0;
     let p = unsafe { monty_snapshot(ptr::null(), &mut len) };
     assert!(p.is_null());
+    let p = unsafe { monty_snapshot_ex(ptr::null(), 1, &mut len) };
+    assert!(p.is_null());
 
     // monty_snapshot with NULL out_len
     let code2 = CString::new("1+1").unwrap();
@@ -160,10 +166,18 @@ fn null_safety() {
     // monty_set_* with NULL handle
     unsafe { monty_set_memory_limit(ptr::null_mut(), 1024) };
     unsafe { monty_set_time_limit_ms(ptr::null_mut(), 1000) };
+    unsafe { monty_set_compute_time_limit_ms(ptr::null_mut(), 1000) };
+    unsafe { monty_clear_limits(ptr::null_mut()) };
+    unsafe { monty_clear_memory_limit(ptr::null_mut()) };
+    unsafe { monty_clear_time_limit(ptr::null_mut()) };
+    unsafe { monty_clear_stack_limit(ptr::null_mut()) };
     unsafe { monty_set_stack_limit(ptr::null_mut(), 100) };
+    unsafe { monty_set_value_repr(ptr::null_mut(), 1) };
+    unsafe { monty_set_integral_float_policy(ptr::null_mut(), 2) };
 
     // monty_string_free with NULL
     unsafe { monty_string_free(ptr::null_mut()) };
+    unsafe { monty_string_free_many(ptr::null(), 3) };
 
     // monty_bytes_free with NULL
     unsafe { monty_bytes_free(ptr::null_mut(), 0) };
@@ -399,6 +413,173 @@ fn create_error_with_null_out_error() {
     assert!(handle.is_null());
 }
 
+#[test]
+fn create_syntax_error_reports_location_json() {
+    let code = c("x = 1\ny = = 2");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let mut out_error_json: *mut c_char = ptr::null_mut();
+    let handle = unsafe {
+        monty_create_ex(
+            code.as_ptr(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+            0,
+            &mut out_error,
+            &mut out_error_json,
+        )
+    };
+    assert!(handle.is_null());
+    assert!(!unsafe { read_c_string(out_error) }.is_empty());
+
+    let error: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(out_error_json) }).unwrap();
+    assert_eq!(error["exc_type"], "SyntaxError");
+    assert_eq!(error["line_number"], 2);
+    assert!(error["column_number"].as_u64().unwrap() >= 1);
+}
+
+#[test]
+fn create_ex_compile_timeout() {
+    let source: String = (0..200_000)
+        .map(|i| format!("x{i} = [{i}, {{'k': {i}}}]\n"))
+        .collect();
+    let code = c(&source);
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe {
+        monty_create_ex(
+            code.as_ptr(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+            1,
+            &mut out_error,
+            ptr::null_mut(),
+        )
+    };
+    assert!(handle.is_null());
+    assert_eq!(
+        unsafe { read_c_string(out_error) },
+        "TimeoutError: compile timed out after 1 ms"
+    );
+
+    // A generous budget, or none at all, compiles as usual.
+    let small = c("1 + 1");
+    for timeout_ms in [10_000, 0] {
+        let handle = unsafe {
+            monty_create_ex(
+                small.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                timeout_ms,
+                &mut out_error,
+                ptr::null_mut(),
+            )
+        };
+        assert!(!handle.is_null());
+        unsafe { monty_free(handle) };
+    }
+}
+
+#[test]
+fn validate_reports_diagnostics() {
+    let clean = c("def f(x):\n    return ext_fn(x)\nf(1)");
+    let ext_fns = c("ext_fn");
+    let mut diagnostics: *mut c_char = ptr::null_mut();
+    let status = unsafe {
+        monty_validate(
+            clean.as_ptr(),
+            ext_fns.as_ptr(),
+            ptr::null(),
+            &mut diagnostics,
+        )
+    };
+    assert_eq!(status, 0);
+    assert_eq!(unsafe { read_c_string(diagnostics) }, "[]");
+
+    let broken = c("x = 1\ny = = 2");
+    let name = c("lint.py");
+    let status = unsafe {
+        monty_validate(
+            broken.as_ptr(),
+            ptr::null(),
+            name.as_ptr(),
+            &mut diagnostics,
+        )
+    };
+    assert_eq!(status, 1);
+    let parsed: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(diagnostics) }).unwrap();
+    assert_eq!(parsed.as_array().unwrap().len(), 1);
+    assert_eq!(parsed[0]["severity"], "error");
+    assert_eq!(parsed[0]["exc_type"], "SyntaxError");
+    assert_eq!(parsed[0]["filename"], "lint.py");
+    assert_eq!(parsed[0]["line"], 2);
+
+    let status = unsafe { monty_validate(ptr::null(), ptr::null(), ptr::null(), &mut diagnostics) };
+    assert_eq!(status, -1);
+}
+
+#[test]
+fn eval_in_one_call() {
+    let code = c("2 + 2");
+    let limits = c(r#"{"max_memory": 1048576}"#);
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let tag = unsafe {
+        monty_eval(
+            code.as_ptr(),
+            ptr::null(),
+            ptr::null(),
+            limits.as_ptr(),
+            &mut result_json,
+        )
+    };
+    assert_eq!(tag, MontyResultTag::Ok);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+    assert_eq!(result["value"], 4);
+    assert!(result.get("error").is_none());
+
+    let tag = unsafe {
+        monty_eval(
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+            &mut result_json,
+        )
+    };
+    assert_eq!(tag, MontyResultTag::Error);
+}
+
+#[test]
+fn eval_rejects_external_calls() {
+    let code = c("x = fetch(1)\nx + 1");
+    let ext_fns = c("fetch");
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let tag = unsafe {
+        monty_eval(
+            code.as_ptr(),
+            ext_fns.as_ptr(),
+            ptr::null(),
+            ptr::null(),
+            &mut result_json,
+        )
+    };
+    assert_eq!(tag, MontyResultTag::Error);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+    assert!(result["value"].is_null());
+    assert_eq!(result["error"]["exc_type"], "RuntimeError");
+    assert!(
+        result["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("'fetch'")
+    );
+}
+
 // ---------------------------------------------------------------------------
 // 24. complete accessors after run via FFI
 // ---------------------------------------------------------------------------
@@ -1062,6 +1243,15 @@ fn async_null_safety_via_ffi() {
         if !out3.is_null() {
             unsafe { monty_string_free(out3) };
         }
+
+        // monty_resume_futures_with_timeouts with NULL timeouts_json
+        let mut out4: *mut c_char = ptr::null_mut();
+        let tag = unsafe {
+            monty_resume_futures_with_timeouts(h, r.as_ptr(), e.as_ptr(), ptr::null(), &mut out4)
+        };
+        assert_eq!(tag, MontyProgressTag::Error);
+        assert!(!out4.is_null());
+        unsafe { monty_string_free(out4) };
         unsafe { monty_free(h) };
     }
 }
@@ -1179,6 +1369,38 @@ fn snapshot_round_trip_via_ffi() {
     unsafe { monty_free(restored) };
 }
 
+#[test]
+fn paused_snapshot_round_trip_via_ffi() {
+    let code = c("ext_fn(3) * 2");
+    let ext = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+
+    let mut snap_len: usize = 0;
+    let snap_ptr = unsafe { monty_snapshot(handle, &mut snap_len) };
+    assert!(!snap_ptr.is_null());
+    unsafe { monty_free(handle) };
+
+    let restored = unsafe { monty_restore(snap_ptr, snap_len, &mut out_error) };
+    assert!(!restored.is_null());
+    unsafe { monty_bytes_free(snap_ptr, snap_len) };
+
+    let name = unsafe { read_c_string(monty_pending_fn_name(restored)) };
+    assert_eq!(name, "ext_fn");
+    let value = c("21");
+    let tag = unsafe { monty_resume(restored, value.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_complete_result_json(restored)) })
+            .unwrap();
+    assert_eq!(result["value"], 42);
+
+    unsafe { monty_free(restored) };
+}
+
 // ---------------------------------------------------------------------------
 // FFI Boundary: Resource limit enforcement (memory + time)
 // Only way to verify limits trigger errors through C FFI wrappers.
@@ -1331,3 +1553,1163 @@ fn async_gather_via_ffi() {
     }
     unsafe { monty_free(handle) };
 }
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Pending future descriptors
+// ---------------------------------------------------------------------------
+
+#[test]
+fn pending_futures_json_via_ffi() {
+    let code = c(
+        "import asyncio\n\nasync def main():\n  return await asyncio.gather(foo(1), bar(2))\n\nawait main()",
+    );
+    let ext_fns = c("foo,bar");
+    let mut out_error: *mut c_char = ptr::null_mut();
+
+    let handle =
+        unsafe { monty_create(code.as_ptr(), ext_fns.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    assert!(unsafe { monty_pending_futures_json(handle) }.is_null());
+    assert!(unsafe { monty_pending_futures_json(ptr::null()) }.is_null());
+
+    let mut tag = unsafe { monty_start(handle, &mut out_error) };
+    while tag == MontyProgressTag::Pending {
+        tag = unsafe { monty_resume_as_future(handle, &mut out_error) };
+    }
+    assert_eq!(tag, MontyProgressTag::ResolveFutures);
+
+    let json = unsafe { read_c_string(monty_pending_futures_json(handle)) };
+    let futures: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(futures[0]["fn_name"], "foo");
+    assert_eq!(futures[0]["args"], serde_json::json!([1]));
+    assert_eq!(futures[1]["fn_name"], "bar");
+    assert_eq!(futures[1]["args"], serde_json::json!([2]));
+
+    let results = CString::new(format!(
+        "{{\"{}\":10,\"{}\":32}}",
+        futures[0]["call_id"], futures[1]["call_id"]
+    ))
+    .unwrap();
+    let errors = c("{}");
+    let tag =
+        unsafe { monty_resume_futures(handle, results.as_ptr(), errors.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+
+    if !out_error.is_null() {
+        unsafe { monty_string_free(out_error) };
+    }
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Declare an external between create and start
+// ---------------------------------------------------------------------------
+
+#[test]
+fn add_external_via_ffi() {
+    let code = c("result = ext_fn(1)\nresult");
+    let name = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let tag = unsafe { monty_add_external(handle, name.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    assert!(out_error.is_null());
+
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+    let fn_name = unsafe { read_c_string(monty_pending_fn_name(handle)) };
+    assert_eq!(fn_name, "ext_fn");
+
+    // Externals are fixed once execution has started.
+    let tag = unsafe { monty_remove_external(handle, name.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    let msg = unsafe { read_c_string(out_error) };
+    assert!(msg.contains("not in Ready state"));
+
+    let tag = unsafe { monty_add_external(ptr::null_mut(), name.as_ptr(), ptr::null_mut()) };
+    assert_eq!(tag, MontyResultTag::Error);
+
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Estimate value memory
+// ---------------------------------------------------------------------------
+
+#[test]
+fn estimate_value_memory_via_ffi() {
+    let scalar = c("1");
+    let nested = c(r#"{"items": [1, 2, 3], "name": "widget"}"#);
+    let invalid = c("not json");
+
+    let scalar_size = unsafe { monty_estimate_value_memory(scalar.as_ptr()) };
+    let nested_size = unsafe { monty_estimate_value_memory(nested.as_ptr()) };
+    assert!(scalar_size > 0);
+    assert!(nested_size > scalar_size);
+
+    assert_eq!(unsafe { monty_estimate_value_memory(invalid.as_ptr()) }, -1);
+    assert_eq!(unsafe { monty_estimate_value_memory(ptr::null()) }, -1);
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Snapshot size matches the snapshot buffer
+// ---------------------------------------------------------------------------
+
+#[test]
+fn snapshot_size_via_ffi() {
+    let code = c("values = [1, 2, 3]\nsum(values)");
+    let mut out_error: *mut c_char = ptr::null_mut();
+
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let size = unsafe { monty_snapshot_size(handle) };
+    assert!(size > 0);
+
+    let mut snap_len: usize = 0;
+    let snap_ptr = unsafe { monty_snapshot(handle, &mut snap_len) };
+    assert!(!snap_ptr.is_null());
+    assert_eq!(snap_len as i64, size);
+    unsafe { monty_bytes_free(snap_ptr, snap_len) };
+
+    assert_eq!(unsafe { monty_snapshot_size(ptr::null()) }, -1);
+
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Unserviceable externals fail without pausing
+// ---------------------------------------------------------------------------
+
+#[test]
+fn serviceable_externals_via_ffi() {
+    let code = c(
+        "try:\n    missing()\n    r = 'no error'\nexcept NotImplementedError:\n    r = 'raised'\nr",
+    );
+    let ext_fns = c("present,missing");
+    let serviceable = c("present");
+    let undeclared = c("present,other");
+    let mut out_error: *mut c_char = ptr::null_mut();
+
+    let handle =
+        unsafe { monty_create(code.as_ptr(), ext_fns.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let tag =
+        unsafe { monty_set_serviceable_externals(handle, undeclared.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    let msg = unsafe { read_c_string(out_error) };
+    assert!(msg.contains("not declared: other"));
+
+    let tag =
+        unsafe { monty_set_serviceable_externals(handle, serviceable.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Ok);
+
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    let result_str = unsafe { read_c_string(monty_complete_result_json(handle)) };
+    let result: serde_json::Value = serde_json::from_str(&result_str).unwrap();
+    assert_eq!(result["value"], "raised");
+
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Streamed resume value through a pull callback
+// ---------------------------------------------------------------------------
+
+struct ByteSource {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+unsafe extern "C" fn read_from_source(
+    user_data: *mut std::ffi::c_void,
+    buf: *mut u8,
+    cap: usize,
+) -> isize {
+    let source = unsafe { &mut *(user_data as *mut ByteSource) };
+    let n = cap.min(source.data.len() - source.pos);
+    unsafe { ptr::copy_nonoverlapping(source.data[source.pos..].as_ptr(), buf, n) };
+    source.pos += n;
+    n as isize
+}
+
+#[test]
+fn resume_reader_large_array_via_ffi() {
+    let code = c("items = fetch()\n[len(items), items[-1]]");
+    let ext_fns = c("fetch");
+    let mut out_error: *mut c_char = ptr::null_mut();
+
+    let handle =
+        unsafe { monty_create(code.as_ptr(), ext_fns.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+
+    let items: Vec<String> = (0..200_000).map(|i| i.to_string()).collect();
+    let mut source = ByteSource {
+        data: format!("[{}]", items.join(",")).into_bytes(),
+        pos: 0,
+    };
+    let tag = unsafe {
+        monty_resume_reader(
+            handle,
+            Some(read_from_source),
+            &mut source as *mut ByteSource as *mut std::ffi::c_void,
+            &mut out_error,
+        )
+    };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    assert_eq!(source.pos, source.data.len());
+
+    let result_str = unsafe { read_c_string(monty_complete_result_json(handle)) };
+    let result: serde_json::Value = serde_json::from_str(&result_str).unwrap();
+    assert_eq!(result["value"], serde_json::json!([200_000, 199_999]));
+
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Handle labels
+// ---------------------------------------------------------------------------
+
+#[test]
+fn label_via_ffi() {
+    let code = c("1");
+    let label = c("tenant-42/report-gen");
+    let mut out_error: *mut c_char = ptr::null_mut();
+
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    assert!(unsafe { monty_get_label(handle) }.is_null());
+
+    unsafe { monty_set_label(handle, label.as_ptr()) };
+    let got = unsafe { read_c_string(monty_get_label(handle)) };
+    assert_eq!(got, "tenant-42/report-gen");
+
+    unsafe { monty_set_label(handle, ptr::null()) };
+    assert!(unsafe { monty_get_label(handle) }.is_null());
+    assert!(unsafe { monty_get_label(ptr::null()) }.is_null());
+
+    unsafe { monty_free(handle) };
+}
+
+#[test]
+fn correlation_id_via_ffi() {
+    let code = c("raise ValueError('bad')");
+    let id = c("req-1234");
+    let mut out_error: *mut c_char = ptr::null_mut();
+
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    unsafe { monty_set_correlation_id(handle, id.as_ptr()) };
+    unsafe { monty_set_correlation_id(ptr::null_mut(), id.as_ptr()) };
+
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let mut error_msg: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_run(handle, &mut result_json, &mut error_msg) };
+    assert_eq!(tag, MontyResultTag::Error);
+    unsafe { monty_string_free(error_msg) };
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+    assert_eq!(result["correlation_id"], "req-1234");
+    assert_eq!(result["error"]["exc_type"], "ValueError");
+
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Reset
+// ---------------------------------------------------------------------------
+
+#[test]
+fn reset_via_ffi() {
+    let code = c("21 * 2");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    for _ in 0..2 {
+        let mut result_json: *mut c_char = ptr::null_mut();
+        let mut error_msg: *mut c_char = ptr::null_mut();
+        let tag = unsafe { monty_run(handle, &mut result_json, &mut error_msg) };
+        assert_eq!(tag, MontyResultTag::Ok);
+        let result: serde_json::Value =
+            serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+        assert_eq!(result["value"], 42);
+
+        let tag = unsafe { monty_reset(handle, &mut out_error) };
+        assert_eq!(tag, MontyResultTag::Ok);
+        assert!(out_error.is_null());
+    }
+
+    assert_eq!(
+        unsafe { monty_reset(ptr::null_mut(), &mut out_error) },
+        MontyResultTag::Error
+    );
+    unsafe { monty_string_free(out_error) };
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Clone
+// ---------------------------------------------------------------------------
+
+#[test]
+fn clone_via_ffi() {
+    let code = c("print('run')\n6 * 7");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let clone = unsafe { monty_clone(handle, &mut out_error) };
+    assert!(!clone.is_null());
+    assert!(out_error.is_null());
+
+    for h in [handle, clone] {
+        let mut result_json: *mut c_char = ptr::null_mut();
+        let mut error_msg: *mut c_char = ptr::null_mut();
+        let tag = unsafe { monty_run(h, &mut result_json, &mut error_msg) };
+        assert_eq!(tag, MontyResultTag::Ok);
+        let result: serde_json::Value =
+            serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+        assert_eq!(result["value"], 42);
+        assert_eq!(result["print_output"], "run\n");
+    }
+
+    let again = unsafe { monty_clone(handle, &mut out_error) };
+    assert!(again.is_null());
+    let err = unsafe { read_c_string(out_error) };
+    assert!(err.contains("Ready state"));
+
+    unsafe { monty_free(clone) };
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: State
+// ---------------------------------------------------------------------------
+
+#[test]
+fn state_walks_lifecycle_via_ffi() {
+    let code = c("async def main():\n  return await fetch('x')\n\nawait main()");
+    let ext = c("fetch");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    assert_eq!(
+        unsafe { monty_state(handle) },
+        MontyStateTag::Ready as c_int
+    );
+
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+    assert_eq!(
+        unsafe { monty_state(handle) },
+        MontyStateTag::Paused as c_int
+    );
+
+    let tag = unsafe { monty_resume_as_future(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::ResolveFutures);
+    assert_eq!(
+        unsafe { monty_state(handle) },
+        MontyStateTag::Futures as c_int
+    );
+
+    let ids: Vec<u32> =
+        serde_json::from_str(&unsafe { read_c_string(monty_pending_future_call_ids(handle)) })
+            .unwrap();
+    let results = c(&format!("{{\"{}\":\"ok\"}}", ids[0]));
+    let errors = c("{}");
+    let tag =
+        unsafe { monty_resume_futures(handle, results.as_ptr(), errors.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    assert_eq!(
+        unsafe { monty_state(handle) },
+        MontyStateTag::Complete as c_int
+    );
+
+    unsafe { monty_free(handle) };
+}
+
+#[test]
+fn second_run_reports_consumed() {
+    let code = c("2 + 2");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    assert_eq!(unsafe { monty_is_consumed(handle) }, 0);
+
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let mut error_msg: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_run(handle, &mut result_json, &mut error_msg) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    unsafe { monty_string_free(result_json) };
+
+    let tag = unsafe { monty_run(handle, &mut result_json, &mut error_msg) };
+    assert_eq!(tag, MontyResultTag::Error);
+    assert_eq!(
+        unsafe { read_c_string(error_msg) },
+        "handle not in Ready state"
+    );
+    unsafe { monty_string_free(result_json) };
+    assert_eq!(unsafe { monty_is_consumed(handle) }, 1);
+
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: OS calls
+// ---------------------------------------------------------------------------
+
+#[test]
+fn os_call_via_ffi() {
+    let code = c("import os\nos.getenv('HOME')");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    unsafe { monty_set_pause_os_calls(handle, 1) };
+
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::OsCall);
+    assert_eq!(
+        unsafe { monty_state(handle) },
+        MontyStateTag::OsCall as c_int
+    );
+    let name = unsafe { read_c_string(monty_pending_os_call_name(handle)) };
+    assert_eq!(name, "os.getenv");
+    let args = unsafe { read_c_string(monty_pending_os_call_args_json(handle)) };
+    assert_eq!(args, r#"["HOME"]"#);
+
+    let value = c(r#""/home/guest""#);
+    let tag = unsafe { monty_resume_os_call(handle, value.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_complete_result_json(handle)) })
+            .unwrap();
+    assert_eq!(result["value"], "/home/guest");
+
+    assert!(unsafe { monty_pending_os_call_name(ptr::null()) }.is_null());
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Usage
+// ---------------------------------------------------------------------------
+
+#[test]
+fn usage_json_via_ffi() {
+    let code = c("data = [str(i) for i in range(500)]\next_fn(len(data))");
+    let ext = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    assert_eq!(unsafe { read_c_string(monty_usage_json(handle)) }, "{}");
+
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+    let paused: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_usage_json(handle)) }).unwrap();
+
+    let value = c("1");
+    let tag = unsafe { monty_resume(handle, value.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    let done: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_usage_json(handle)) }).unwrap();
+    for key in ["memory_bytes_used", "time_elapsed_ms"] {
+        assert!(done[key].as_u64().unwrap() >= paused[key].as_u64().unwrap());
+    }
+
+    assert!(unsafe { monty_usage_json(ptr::null()) }.is_null());
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Globals
+// ---------------------------------------------------------------------------
+
+#[test]
+fn create_with_globals_via_ffi() {
+    let code = c("x * 2");
+    let globals = c(r#"{"x": 10}"#);
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe {
+        monty_create_with_globals(
+            code.as_ptr(),
+            ptr::null(),
+            ptr::null(),
+            globals.as_ptr(),
+            &mut out_error,
+        )
+    };
+    assert!(!handle.is_null());
+
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let mut error_msg: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_run(handle, &mut result_json, &mut error_msg) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+    assert_eq!(result["value"], 20);
+    unsafe { monty_free(handle) };
+
+    for bad in [r#"{"class": 1}"#, "[1, 2]", "{"] {
+        let globals = c(bad);
+        let handle = unsafe {
+            monty_create_with_globals(
+                code.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                globals.as_ptr(),
+                &mut out_error,
+            )
+        };
+        assert!(handle.is_null(), "{bad}");
+        assert!(!unsafe { read_c_string(out_error) }.is_empty());
+    }
+}
+
+#[test]
+fn run_with_args_via_ffi() {
+    let code = c("x");
+    let globals = c(r#"{"x": null}"#);
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe {
+        monty_create_with_globals(
+            code.as_ptr(),
+            ptr::null(),
+            ptr::null(),
+            globals.as_ptr(),
+            &mut out_error,
+        )
+    };
+    assert!(!handle.is_null());
+
+    let args = c(r#"["echo"]"#);
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let mut error_msg: *mut c_char = ptr::null_mut();
+    let tag =
+        unsafe { monty_run_with_args(handle, args.as_ptr(), &mut result_json, &mut error_msg) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+    assert_eq!(result["value"], "echo");
+
+    let tag = unsafe { monty_run_with_args(handle, ptr::null(), ptr::null_mut(), &mut error_msg) };
+    assert_eq!(tag, MontyResultTag::Error);
+    assert_eq!(unsafe { read_c_string(error_msg) }, "args_json is NULL");
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: External function names
+// ---------------------------------------------------------------------------
+
+#[test]
+fn ext_fn_names_via_ffi() {
+    let code = c("1");
+    let ext = c("foo,bar");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    let names = unsafe { read_c_string(monty_ext_fn_names(handle)) };
+    assert_eq!(names, r#"["foo","bar"]"#);
+    assert!(unsafe { monty_ext_fn_names(ptr::null()) }.is_null());
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Print callback
+// ---------------------------------------------------------------------------
+
+unsafe extern "C" fn collect_print(text: *const c_char, len: usize, user_data: *mut c_void) {
+    let out = unsafe { &mut *(user_data as *mut String) };
+    let bytes = unsafe { std::slice::from_raw_parts(text as *const u8, len) };
+    out.push_str(std::str::from_utf8(bytes).unwrap());
+}
+
+/// Print callback that calls back into the handle it is printing for.
+unsafe extern "C" fn reenter_on_print(_text: *const c_char, _len: usize, user_data: *mut c_void) {
+    let (handle, seen) = unsafe { &mut *(user_data as *mut (*mut MontyHandle, Vec<String>)) };
+    let value = c("null");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_resume(*handle, value.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Error);
+    seen.push(unsafe { read_c_string(out_error) });
+
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_run(*handle, &mut result_json, &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    seen.push(unsafe { read_c_string(out_error) });
+}
+
+#[test]
+fn reentrant_call_rejected() {
+    let code = c("print('x')\next_fn()");
+    let ext = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let mut state: (*mut MontyHandle, Vec<String>) = (handle, Vec::new());
+    unsafe {
+        monty_set_print_callback(
+            handle,
+            Some(reenter_on_print),
+            &mut state as *mut _ as *mut c_void,
+        )
+    };
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+    assert_eq!(state.1, ["handle already in use", "handle already in use"]);
+
+    // The claim is released once the outer call returns.
+    unsafe { monty_set_print_callback(handle, None, ptr::null_mut()) };
+    let value = c("null");
+    let tag = unsafe { monty_resume(handle, value.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    unsafe { monty_free(handle) };
+}
+
+/// Print callback that tries to reset and free the handle it is printing for.
+unsafe extern "C" fn reset_and_free_on_print(
+    _text: *const c_char,
+    _len: usize,
+    user_data: *mut c_void,
+) {
+    let (handle, seen) = unsafe { &mut *(user_data as *mut (*mut MontyHandle, Vec<String>)) };
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_reset(*handle, &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    seen.push(unsafe { read_c_string(out_error) });
+
+    let limits = c(r#"{"max_memory": 1}"#);
+    let tag = unsafe { monty_set_limits_json(*handle, limits.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    seen.push(unsafe { read_c_string(out_error) });
+
+    // Ignored while the handle is claimed; the outer call keeps running.
+    unsafe { monty_set_memory_limit(*handle, 1) };
+    unsafe { monty_free(*handle) };
+}
+
+#[test]
+fn reentrant_reset_and_free_rejected() {
+    let code = c("print('x')\n1 + 2");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let mut state: (*mut MontyHandle, Vec<String>) = (handle, Vec::new());
+    unsafe {
+        monty_set_print_callback(
+            handle,
+            Some(reset_and_free_on_print),
+            &mut state as *mut _ as *mut c_void,
+        )
+    };
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_run(handle, &mut result_json, &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+    assert_eq!(result["value"], 3);
+    assert_eq!(state.1, ["handle already in use", "handle already in use"]);
+
+    // The handle survived the reentrant free and its limits are unchanged.
+    let limits: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_get_limits_json(handle)) }).unwrap();
+    assert!(limits["max_memory"].is_null());
+    unsafe { monty_free(handle) };
+}
+
+unsafe extern "C" fn read_accessors_on_print(
+    _text: *const c_char,
+    _len: usize,
+    user_data: *mut c_void,
+) {
+    let (handle, seen) = unsafe { &mut *(user_data as *mut (*mut MontyHandle, Vec<String>)) };
+    let handle = *handle;
+    // The running call holds the handle mutably, so accessors must not read it.
+    assert!(unsafe { monty_pending_fn_name(handle) }.is_null());
+    assert!(unsafe { monty_usage_json(handle) }.is_null());
+    assert!(unsafe { monty_get_limits_json(handle) }.is_null());
+    assert_eq!(unsafe { monty_state(handle) }, -1);
+    assert_eq!(unsafe { monty_last_error_code(handle) }, -1);
+    assert_eq!(unsafe { monty_pending_call_id(handle) }, u32::MAX);
+    seen.push("refused".into());
+}
+
+#[test]
+fn reentrant_accessors_rejected() {
+    let code = c("print('x')\n1 + 2");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let mut state: (*mut MontyHandle, Vec<String>) = (handle, Vec::new());
+    unsafe {
+        monty_set_print_callback(
+            handle,
+            Some(read_accessors_on_print),
+            &mut state as *mut _ as *mut c_void,
+        )
+    };
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_run(handle, &mut result_json, &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    unsafe { monty_string_free(result_json) };
+    assert_eq!(state.1, ["refused"]);
+
+    // Released once the run returns.
+    assert_ne!(unsafe { monty_state(handle) }, -1);
+    let usage = unsafe { monty_usage_json(handle) };
+    assert!(!usage.is_null());
+    unsafe { monty_string_free(usage) };
+    unsafe { monty_free(handle) };
+}
+
+#[test]
+fn print_callback_via_ffi() {
+    let code = c("print('a')\next_fn()\nprint('b')");
+    let ext = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let mut seen = String::new();
+    unsafe {
+        monty_set_print_callback(
+            handle,
+            Some(collect_print),
+            &mut seen as *mut String as *mut c_void,
+        )
+    };
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+    assert_eq!(seen, "a\n");
+
+    unsafe { monty_set_print_callback(handle, None, ptr::null_mut()) };
+    let value = c("null");
+    let tag = unsafe { monty_resume(handle, value.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    assert_eq!(seen, "a\n");
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_complete_result_json(handle)) })
+            .unwrap();
+    assert_eq!(result["print_output"], "b\n");
+
+    unsafe { monty_set_print_callback(ptr::null_mut(), None, ptr::null_mut()) };
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Output limit
+// ---------------------------------------------------------------------------
+
+#[test]
+fn output_limit_via_ffi() {
+    let code = c("for i in range(500):\n    print(i)\n1");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    unsafe { monty_set_output_limit(handle, 10) };
+
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let mut error_msg: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_run(handle, &mut result_json, &mut error_msg) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+    assert_eq!(result["print_output"], "0\n1\n2\n3\n4\n");
+    assert_eq!(result["print_output_truncated"], true);
+
+    unsafe { monty_set_output_limit(ptr::null_mut(), 10) };
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Batch resume
+// ---------------------------------------------------------------------------
+
+#[test]
+fn resume_many_via_ffi() {
+    let code = c("a = ext_fn(1)\nb = ext_fn(2)\nc = ext_fn(3)\n[a, b, c]");
+    let ext = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+
+    let values = c(r#"["x", "y", "z"]"#);
+    let tag = unsafe { monty_resume_many(handle, values.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    assert_eq!(unsafe { monty_last_batch_consumed(handle) }, 3);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_complete_result_json(handle)) })
+            .unwrap();
+    assert_eq!(result["value"], serde_json::json!(["x", "y", "z"]));
+
+    assert_eq!(unsafe { monty_last_batch_consumed(ptr::null()) }, 0);
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Interrupt
+// ---------------------------------------------------------------------------
+
+#[test]
+fn request_interrupt_from_another_thread() {
+    let code = c("i = 0\nwhile True:\n    i += 1\ni");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let token = unsafe { monty_interrupt_token(handle) };
+    assert!(!token.is_null());
+    // Raw pointers aren't Send; pass the address instead.
+    let addr = token as usize;
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        unsafe { monty_token_interrupt(addr as *const MontyInterruptToken) };
+    });
+
+    let started = std::time::Instant::now();
+    let mut result_json: *mut c_char = ptr::null_mut();
+    let mut error_msg: *mut c_char = ptr::null_mut();
+    let tag = unsafe { monty_run(handle, &mut result_json, &mut error_msg) };
+    interrupter.join().unwrap();
+    assert_eq!(tag, MontyResultTag::Error);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(result_json) }).unwrap();
+    assert_eq!(result["error"]["exc_type"], "KeyboardInterrupt");
+    unsafe { read_c_string(error_msg) };
+
+    unsafe { monty_free(handle) };
+    // The token outlives the handle.
+    unsafe { monty_token_interrupt(token) };
+    unsafe { monty_token_free(token) };
+
+    assert!(unsafe { monty_interrupt_token(ptr::null()) }.is_null());
+    unsafe { monty_token_interrupt(ptr::null()) };
+    unsafe { monty_token_free(ptr::null_mut()) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Typed resume errors
+// ---------------------------------------------------------------------------
+
+#[test]
+fn resume_with_typed_error_via_ffi() {
+    let code = c("try:\n    r = ext_fn()\nexcept TimeoutError:\n    r = 'retry'\nr");
+    let ext = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    assert_eq!(
+        unsafe { monty_start(handle, &mut out_error) },
+        MontyProgressTag::Pending
+    );
+
+    let exc_type = c("TimeoutError");
+    let message = c("upstream too slow");
+    let tag = unsafe {
+        monty_resume_with_typed_error(handle, exc_type.as_ptr(), message.as_ptr(), &mut out_error)
+    };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_complete_result_json(handle)) })
+            .unwrap();
+    assert_eq!(result["value"], "retry");
+
+    let tag = unsafe {
+        monty_resume_with_typed_error(handle, ptr::null(), message.as_ptr(), &mut out_error)
+    };
+    assert_eq!(tag, MontyProgressTag::Error);
+    assert!(!unsafe { read_c_string(out_error) }.is_empty());
+    let tag = unsafe { monty_resume_with_exception(handle, ptr::null(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Error);
+    assert_eq!(unsafe { read_c_string(out_error) }, "exc_json is NULL");
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Error codes
+// ---------------------------------------------------------------------------
+
+#[test]
+fn last_error_code_per_failure_mode() {
+    let code = c("ext_fn(1)");
+    let ext = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ext.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    assert_eq!(
+        unsafe { monty_last_error_code(handle) },
+        MontyErrorCode::None as c_int
+    );
+    let last_code = || unsafe { monty_last_error_code(handle) };
+
+    // Resuming a Ready handle.
+    let value = c("1");
+    let tag = unsafe { monty_resume(handle, value.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Error);
+    unsafe { read_c_string(out_error) };
+    assert_eq!(last_code(), MontyErrorCode::WrongState as c_int);
+
+    assert_eq!(
+        unsafe { monty_start(handle, &mut out_error) },
+        MontyProgressTag::Pending
+    );
+    assert_eq!(last_code(), MontyErrorCode::None as c_int);
+
+    let bad_json = c("{");
+    unsafe { monty_resume(handle, bad_json.as_ptr(), &mut out_error) };
+    unsafe { read_c_string(out_error) };
+    assert_eq!(last_code(), MontyErrorCode::InvalidJson as c_int);
+
+    unsafe { monty_resume(handle, ptr::null(), &mut out_error) };
+    unsafe { read_c_string(out_error) };
+    assert_eq!(last_code(), MontyErrorCode::NullArgument as c_int);
+
+    let non_utf8 = CString::new(vec![0xff, 0xfe]).unwrap();
+    unsafe { monty_resume(handle, non_utf8.as_ptr(), &mut out_error) };
+    unsafe { read_c_string(out_error) };
+    assert_eq!(last_code(), MontyErrorCode::NonUtf8 as c_int);
+
+    // The handle is still paused after rejected arguments.
+    let message = c("boom");
+    let tag = unsafe { monty_resume_with_error(handle, message.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Error);
+    unsafe { read_c_string(out_error) };
+    assert_eq!(last_code(), MontyErrorCode::VmException as c_int);
+    unsafe { monty_free(handle) };
+
+    // A panic inside the library.
+    let code = c("import os\nos.getenv('HOME')");
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    unsafe { &mut *handle }.register_os_handler("os.getenv", Box::new(|_| panic!("handler bug")));
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Error);
+    assert!(unsafe { read_c_string(out_error) }.contains("handler bug"));
+    assert_eq!(
+        unsafe { monty_last_error_code(handle) },
+        MontyErrorCode::Panic as c_int
+    );
+    unsafe { monty_free(handle) };
+
+    assert_eq!(unsafe { monty_last_error_code(ptr::null()) }, -1);
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Limits read-back
+// ---------------------------------------------------------------------------
+
+#[test]
+fn get_limits_json_via_ffi() {
+    let code = c("1");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    unsafe { monty_set_memory_limit(handle, 1024 * 1024) };
+    unsafe { monty_set_time_limit_ms(handle, 2000) };
+    let limits: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_get_limits_json(handle)) }).unwrap();
+    assert_eq!(limits["max_memory"], 1024 * 1024);
+    assert_eq!(limits["max_duration_ms"], 2000);
+    assert!(limits["max_compute_ms"].is_null());
+
+    unsafe { monty_clear_time_limit(handle) };
+    let limits: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_get_limits_json(handle)) }).unwrap();
+    assert!(limits["max_duration_ms"].is_null());
+    unsafe { monty_clear_limits(handle) };
+    let limits: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_get_limits_json(handle)) }).unwrap();
+    assert!(limits["max_memory"].is_null());
+
+    unsafe { monty_set_max_external_calls(handle, 5) };
+    let limits: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_get_limits_json(handle)) }).unwrap();
+    assert_eq!(limits["max_external_calls"], 5);
+    assert_eq!(unsafe { monty_ext_call_count(handle) }, 0);
+    assert_eq!(unsafe { monty_ext_call_count(ptr::null()) }, 0);
+
+    assert!(unsafe { monty_get_limits_json(ptr::null()) }.is_null());
+    unsafe { monty_free(handle) };
+}
+
+#[test]
+fn set_limits_json_via_ffi() {
+    let code = c("1");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let limits = c(r#"{"max_memory": 2048, "max_recursion_depth": 30}"#);
+    let tag = unsafe { monty_set_limits_json(handle, limits.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    assert!(out_error.is_null());
+    let read: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_get_limits_json(handle)) }).unwrap();
+    assert_eq!(read["max_memory"], 2048);
+    assert_eq!(read["max_recursion_depth"], 30);
+
+    let typo = c(r#"{"max_duration": 100}"#);
+    let tag = unsafe { monty_set_limits_json(handle, typo.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    assert_eq!(
+        unsafe { read_c_string(out_error) },
+        "unknown limit: max_duration"
+    );
+
+    let tag = unsafe { monty_set_limits_json(handle, ptr::null(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    assert_eq!(unsafe { read_c_string(out_error) }, "limits_json is NULL");
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Tagged pending arguments
+// ---------------------------------------------------------------------------
+
+#[test]
+fn pending_fn_arg_tagged_json_via_ffi() {
+    let code = c("ext_fn((1, 2), {3})");
+    let ext_fns = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle =
+        unsafe { monty_create(code.as_ptr(), ext_fns.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    assert_eq!(unsafe { monty_pending_fn_args_count(handle) }, -1);
+
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+    assert_eq!(unsafe { monty_pending_fn_args_count(handle) }, 2);
+
+    let tuple: serde_json::Value = serde_json::from_str(&unsafe {
+        read_c_string(monty_pending_fn_arg_tagged_json(handle, 0))
+    })
+    .unwrap();
+    assert_eq!(tuple, serde_json::json!({"__tuple__": [1, 2]}));
+    let set: serde_json::Value = serde_json::from_str(&unsafe {
+        read_c_string(monty_pending_fn_arg_tagged_json(handle, 1))
+    })
+    .unwrap();
+    assert_eq!(set, serde_json::json!({"__set__": [3]}));
+    assert!(unsafe { monty_pending_fn_arg_tagged_json(handle, 2) }.is_null());
+    assert!(unsafe { monty_pending_fn_arg_tagged_json(handle, -1) }.is_null());
+
+    assert_eq!(unsafe { monty_pending_fn_args_count(ptr::null()) }, -1);
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Stdin
+// ---------------------------------------------------------------------------
+
+#[test]
+fn set_stdin_via_ffi() {
+    let code = c("x = input()\nx.upper()");
+    let data = c("hello");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let tag = unsafe { monty_set_stdin(handle, data.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_complete_result_json(handle)) })
+            .unwrap();
+    assert_eq!(result["value"], "HELLO");
+
+    let tag = unsafe { monty_set_stdin(handle, ptr::null(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    assert!(!out_error.is_null());
+    unsafe { monty_string_free(out_error) };
+    unsafe { monty_free(handle) };
+}
+
+#[test]
+fn register_constant_via_ffi() {
+    let code = c("cfg = get_config()\ncfg['debug']");
+    let name = c("get_config");
+    let value = c(r#"{"debug": true}"#);
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle = unsafe { monty_create(code.as_ptr(), ptr::null(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+
+    let tag =
+        unsafe { monty_register_constant(handle, name.as_ptr(), value.as_ptr(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Ok);
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Complete);
+    let result: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_complete_result_json(handle)) })
+            .unwrap();
+    assert_eq!(result["value"], true);
+
+    let tag =
+        unsafe { monty_register_constant(handle, name.as_ptr(), ptr::null(), &mut out_error) };
+    assert_eq!(tag, MontyResultTag::Error);
+    assert_eq!(unsafe { read_c_string(out_error) }, "value_json is NULL");
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Bulk string free
+// ---------------------------------------------------------------------------
+
+#[test]
+fn string_free_many_via_ffi() {
+    let code = c("ext_fn(1, k=2)");
+    let ext_fns = c("ext_fn");
+    let mut out_error: *mut c_char = ptr::null_mut();
+    let handle =
+        unsafe { monty_create(code.as_ptr(), ext_fns.as_ptr(), ptr::null(), &mut out_error) };
+    assert!(!handle.is_null());
+    let tag = unsafe { monty_start(handle, &mut out_error) };
+    assert_eq!(tag, MontyProgressTag::Pending);
+
+    let strings = [
+        unsafe { monty_pending_fn_name(handle) },
+        unsafe { monty_pending_fn_args_json(handle) },
+        ptr::null_mut(),
+        unsafe { monty_pending_fn_kwargs_json(handle) },
+    ];
+    assert_eq!(strings.iter().filter(|p| !p.is_null()).count(), 3);
+    unsafe { monty_string_free_many(strings.as_ptr(), strings.len()) };
+    unsafe { monty_string_free_many(strings.as_ptr(), 0) };
+
+    unsafe { monty_free(handle) };
+}
+
+// ---------------------------------------------------------------------------
+// FFI Boundary: Library info
+// ---------------------------------------------------------------------------
+
+#[test]
+fn capabilities_via_ffi() {
+    let version = monty_abi_version();
+    assert_eq!(version, MONTY_ABI_VERSION);
+
+    let info: serde_json::Value =
+        serde_json::from_str(&unsafe { read_c_string(monty_capabilities_json()) }).unwrap();
+    assert_eq!(info["abi_version"], version);
+    let capabilities = info["capabilities"].as_array().unwrap();
+    assert!(capabilities.contains(&serde_json::json!("async")));
+    assert!(capabilities.contains(&serde_json::json!("snapshot-paused")));
+    assert!(capabilities.contains(&serde_json::json!("interrupt-token")));
+    assert!(capabilities.contains(&serde_json::json!("fail-all-futures")));
+    assert!(!capabilities.contains(&serde_json::json!("interrupt")));
+}